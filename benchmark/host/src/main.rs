@@ -5,7 +5,9 @@ mod imports;
 use std::sync::Arc;
 use std::sync::atomic::AtomicI32;
 use std::time::Instant;
+use bc_hostcall::module_api::cache::ModuleCache;
 use bc_hostcall::module_api::module::WasmModule;
+use bc_hostcall::module_api::wasmtime::Engine;
 use exports::*;
 use imports::*;
 
@@ -13,9 +15,11 @@ pub type Error = Box<dyn std::error::Error + Send + Sync>;
 pub type Result<T> = std::result::Result<T, Error>;
 
 async fn prepare_module(wasm: &str) -> WasmModule {
-    // 加载模块
+    // 加载模块，编译结果缓存在当前用户的缓存目录中，重复运行时无需重新编译
+    let cache = ModuleCache::with_user_dir(Engine::default()).unwrap();
+    let compiled = cache.load_file(wasm).unwrap();
     let mut module = WasmModule::new();
-    module.init(wasm, init_exports()).unwrap();
+    module.init_module(&compiled, __bc_module_export()).unwrap();
 
     // 启动
     module.start().await;
@@ -0,0 +1,43 @@
+//! 旧的参数及报文编码方案，仅作为性能对比的基准
+
+use serde::{Deserialize, Serialize};
+
+use rpc::abi;
+
+#[derive(Serialize, Deserialize)]
+struct InnerArgs {
+    arg_buffers: Vec<Vec<u8>>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LegacyMessage<'a> {
+    seq_no: u64,
+    func: abi::FunctionIdent,
+    deadline: Option<u64>,
+    #[serde(with = "serde_bytes")]
+    pub data: &'a [u8],
+}
+
+/// 逐个序列化参数后，再序列化参数数组
+pub fn build_args<A: Serialize + ?Sized, B: Serialize + ?Sized>(a: &A, b: &B) -> Vec<u8> {
+    let args = InnerArgs {
+        arg_buffers: vec![rmp_serde::to_vec(a).unwrap(), rmp_serde::to_vec(b).unwrap()],
+    };
+    rmp_serde::to_vec(&args).unwrap()
+}
+
+/// 把参数作为字节数组再次序列化到报文中
+pub fn make_request(seq_no: u64, func: abi::FunctionIdent, args: &[u8]) -> Vec<u8> {
+    let msg = LegacyMessage { seq_no, func, deadline: None, data: args };
+    rmp_serde::to_vec(&msg).unwrap()
+}
+
+pub fn decode_message(bytes: &[u8]) -> LegacyMessage<'_> {
+    rmp_serde::from_slice(bytes).unwrap()
+}
+
+/// 反序列化整个参数数组后，再反序列化其中的参数
+pub fn get_arg<T: for<'de> Deserialize<'de>>(bytes: &[u8], index: usize) -> T {
+    let args: InnerArgs = rmp_serde::from_slice(bytes).unwrap();
+    rmp_serde::from_slice(&args.arg_buffers[index]).unwrap()
+}
@@ -0,0 +1,61 @@
+//! 调用参数编解码的性能测试
+//!
+//! 对比旧的三次序列化方案（逐个序列化参数、序列化参数数组、序列化整个报文）与当前的单次序列化方案。
+
+mod legacy;
+
+use std::time::Instant;
+
+use rpc::{abi, RpcMessage, RpcRequestCtx};
+use serialize::{Args, ArgsBuilder, SerializeCtx};
+
+/// 以当前方案完成一次调用请求的编码与解码
+fn current_once(payload: &str) -> usize {
+    let ctx = SerializeCtx::new();
+
+    // 编码
+    let args = ArgsBuilder::new(&ctx)
+        .push(&payload).unwrap()
+        .push(&123i32).unwrap()
+        .build().unwrap();
+    let msg = RpcRequestCtx::new(0, &ctx, &())
+        .make_request(abi::FunctionIdent::new("test"), args).unwrap();
+
+    // 解码
+    let (ctx, msg) = RpcMessage::decode(&msg).unwrap();
+    let args = Args::from_bytes(&ctx, msg.data()).unwrap();
+    args.get::<String>(0).unwrap().len()
+}
+
+/// 以旧方案完成一次调用请求的编码与解码
+fn legacy_once(payload: &str) -> usize {
+    // 编码
+    let args = legacy::build_args(payload, &123i32);
+    let msg = legacy::make_request(0, abi::FunctionIdent::new("test"), &args);
+
+    // 解码
+    let msg = legacy::decode_message(&msg);
+    legacy::get_arg::<String>(msg.data, 0).len()
+}
+
+fn benchmark(name: &str, payload: &str, total: u32, f: fn(&str) -> usize) -> u128 {
+    let start = Instant::now();
+    for _ in 0..total {
+        assert_eq!(payload.len(), f(payload));
+    }
+    let elapsed = start.elapsed().as_nanos();
+    println!("{} {}B: {}ns", name, payload.len(), elapsed / total as u128);
+    elapsed
+}
+
+fn main() {
+    let sizes = [512, 1 << 10, 5 << 10, 10 << 10, 100 << 10, 1 << 20, 10 << 20];
+    let total = 50;
+
+    for size in sizes {
+        let payload = "x".repeat(size);
+        let legacy = benchmark("legacy ", &payload, total, legacy_once);
+        let current = benchmark("current", &payload, total, current_once);
+        println!("speedup {}B: {:.2}x", size, legacy as f64 / current.max(1) as f64);
+    }
+}
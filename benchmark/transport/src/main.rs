@@ -0,0 +1,65 @@
+//! 底层消息传递方式的性能测试
+//!
+//! Host 向 WASM 发送消息，WASM 将消息原样发回。对比每条消息一次函数调用的方式与环形缓冲区方式。
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use low_level::host::{LowLevelCtx, Transport};
+use wasmtime::{Engine, Linker, Module, Store};
+use wasmtime_wasi::sync::WasiCtxBuilder;
+use wasmtime_wasi::WasiCtx;
+
+const GUEST: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../../modules/low-level/tests/guest/guest.wasm");
+
+/// 加载开启回显的 WASM 模块，返回上下文与已接收消息的计数
+fn prepare(transport: Transport) -> (Arc<LowLevelCtx<WasiCtx>>, Arc<AtomicUsize>) {
+    let engine = Engine::default();
+    let mut linker = Linker::new(&engine);
+    wasmtime_wasi::add_to_linker(&mut linker, |s| s).unwrap();
+    let mut store = Store::new(&engine, WasiCtxBuilder::new().build());
+    let module = Module::from_file(&engine, GUEST).unwrap();
+
+    let received = Arc::new(AtomicUsize::new(0));
+    let mut ctx = LowLevelCtx::new();
+    ctx.set_transport(transport);
+    let counter = received.clone();
+    ctx.set_message_callback(move |_| {
+        counter.fetch_add(1, Ordering::Relaxed);
+    });
+    let ctx = Arc::new(ctx);
+    ctx.clone().add_to_linker(&mut linker).unwrap();
+
+    let instance = linker.instantiate(&mut store, &module).unwrap();
+    ctx.attach(&mut store, &instance).unwrap();
+    instance.get_typed_func::<u32, (), _>(&mut store, "set_echo").unwrap()
+        .call(&mut store, 1).unwrap();
+    ctx.move_store(store);
+    ctx.wasm_main().unwrap();
+
+    (ctx, received)
+}
+
+fn benchmark(name: &str, transport: Transport, size: usize, total: usize) {
+    let (ctx, received) = prepare(transport);
+    let msg = vec![0x42u8; size];
+
+    let start = Instant::now();
+    for _ in 0..total {
+        ctx.send_message_to_wasm(&msg).unwrap();
+    }
+    let elapsed = start.elapsed().as_nanos();
+    assert_eq!(total, received.load(Ordering::Relaxed));
+    println!("{} {}B: {}ns", name, size, elapsed / total as u128);
+}
+
+fn main() {
+    let sizes = [16, 128, 512, 1 << 10, 5 << 10, 10 << 10, 100 << 10];
+    let total = 10000;
+
+    for size in sizes {
+        benchmark("call", Transport::Call, size, total);
+        benchmark("ring", Transport::ring(), size, total);
+    }
+}
@@ -1,51 +1,137 @@
 use std::cell::Cell;
 use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{Arc, Mutex};
 use std::task::Waker;
+use std::time::Duration;
 
 use tokio;
 use tokio::sync::Notify;
+use tokio::task::JoinHandle;
 
-use low_level::host::LowLevelCtx;
-use rpc::{abi, RpcEndCtx, RpcNode, RpcResponseCtx, RpcSeqNo};
+use low_level::host::{LowLevelCtx, Resource, TrapInfo};
+use rpc::{abi, Message, RpcCallResult, RpcDeadline, RpcEndCtx, RpcError, RpcErrorCode, RpcImports, RpcMessage, RpcNode,
+          RpcRequestCtx, RpcResponseCtx, RpcSeqNo};
 use serialize::SerializeCtx;
 
 use crate::future::{AsyncRequestFuture, HandleRxFuture, HandleTxFuture};
-use crate::Result;
+use crate::{Error, Result};
 
 /// 接受消息后的动作
 pub enum ResultAction {
     /// 唤醒某个 Future
     Wake(Waker),
     /// 返回结果
-    Response(Vec<u8>),
-    /// 转发结果
-    ForwardResult(abi::LinkHint, abi::FunctionIdent),
+    Response(RpcCallResult),
+    /// 把结果转发给调用方模块实例，并换回调用方的原始序列号。请求带有截止时间时附带超时任务，结果返回后中止
+    ForwardResult(Arc<AsyncCtx>, RpcSeqNo, abi::FunctionIdent, Option<JoinHandle<()>>),
+    /// 请求已被取消，在截止时间前丢弃之后到达的结果
+    Discard(RpcDeadline),
+}
+
+/// 模块被毒化（停止运行）的原因
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Poison {
+    /// 超出 CPU 预算
+    Cpu,
+    /// 超出资源限制
+    Resource(Resource),
+    /// WASM 执行中发生 Trap 或其他低层错误
+    Trap(TrapInfo),
+}
+
+impl From<&low_level::Error> for Poison {
+    fn from(e: &low_level::Error) -> Self {
+        match e {
+            low_level::Error::CpuExhausted => Poison::Cpu,
+            low_level::Error::ResourceLimit(resource) => Poison::Resource(*resource),
+            e => Poison::Trap(e.into()),
+        }
+    }
+}
+
+impl From<Poison> for RpcError {
+    fn from(poison: Poison) -> Self {
+        let code = match poison {
+            Poison::Cpu => RpcErrorCode::ResourceExhausted,
+            Poison::Resource(_) => RpcErrorCode::LimitExceeded,
+            Poison::Trap(_) => RpcErrorCode::Trapped,
+        };
+        RpcError::new(code, Error::from(poison).to_string())
+    }
+}
+
+/// 模块的生命周期事件，宿主可以据此重启或卸载模块
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LifecycleEvent {
+    /// 模块被毒化而停止运行，其异步任务已经结束
+    Poisoned(Poison),
+    /// 模块被 `kill` 结束
+    Killed,
+}
+
+type MessageQueue = Mutex<Cell<VecDeque<Vec<u8>>>>;
+
+static FORWARD_REQUEST_NUM: AtomicU32 = AtomicU32::new(0);
+
+/// 卸载时检查请求是否都已返回的间隔
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// 为转发的请求分配序列号
+///
+/// 同一模块的多个实例使用相同的 nonce，发出的序列号可能重复，因此转发时统一换用 Host 分配的序列号。
+fn next_forward_seq_no() -> RpcSeqNo {
+    let request_num = FORWARD_REQUEST_NUM.fetch_add(1, Ordering::Relaxed);
+    ((abi::FORWARD_NONCE as u64) << 32) + request_num as u64
 }
 
 pub type CtxResolveCallback =
 dyn Fn(abi::LinkHint) -> Result<Arc<AsyncCtx>> + Send + Sync;
 
+pub type LifecycleCallback = dyn Fn(LifecycleEvent) + Send + Sync;
+
 /// 模块的异步上下文，主要维护围绕两个队列驱动的异步任务
 pub struct AsyncCtx {
     // 初始化提醒
     pub init_notify: Notify,
 
     /// 收到待处理的队列
-    pub tx_queue: Mutex<Cell<VecDeque<Vec<u8>>>>,
+    pub tx_queue: MessageQueue,
     pub tx_waker: Mutex<Cell<Option<Waker>>>,
 
     /// 要发送给 WASM 的队列
-    pub rx_queue: Mutex<Cell<VecDeque<Vec<u8>>>>,
+    pub rx_queue: MessageQueue,
     pub rx_waker: Mutex<Cell<Option<Waker>>>,
 
     /// 是否仍然存活
     alive: Mutex<Cell<bool>>,
 
+    /// 是否正在卸载，卸载期间不再接受新的请求
+    draining: Mutex<Cell<bool>>,
+
+    /// 队列中的消息数上限，仅限制新的请求与 WASM 发送的消息
+    max_pending: Mutex<Cell<Option<usize>>>,
+
+    /// 模块被毒化的原因
+    poison: Mutex<Option<Poison>>,
+
+    /// 生命周期事件的回调
+    lifecycle_cb: Mutex<Cell<Option<Arc<LifecycleCallback>>>>,
+
     pub rpc_ctx: Mutex<Cell<Option<RpcNode<Arc<Self>>>>>,
 
+    /// 与模块协商确定的序列化上下文。转发时需读取其他模块的格式，因此单独保存，不必锁定其 RpcNode
+    serialize_ctx: Mutex<Cell<SerializeCtx>>,
+
     pub tx_action: Mutex<Cell<HashMap<RpcSeqNo, ResultAction>>>,
 
+    /// 本模块发出、已转发的请求：原始序列号 -> (目标模块实例, 转发时使用的序列号)
+    forwarded: Mutex<Cell<HashMap<RpcSeqNo, (Arc<AsyncCtx>, RpcSeqNo)>>>,
+
+    /// 正在运行的 Host 导出函数异步任务，用于响应取消消息
+    export_tasks: Mutex<Cell<HashMap<RpcSeqNo, JoinHandle<()>>>>,
+
     /// 解析其他模块异步上下文的回调
     resolve_cb: Mutex<Cell<Option<Box<CtxResolveCallback>>>>,
 
@@ -61,8 +147,15 @@ impl AsyncCtx {
             rx_queue: Mutex::new(Cell::new(VecDeque::new())),
             rx_waker: Mutex::new(Cell::new(None)),
             alive: Mutex::new(Cell::new(true)),
+            draining: Mutex::new(Cell::new(false)),
+            max_pending: Mutex::new(Cell::new(None)),
+            poison: Mutex::new(None),
+            lifecycle_cb: Mutex::new(Cell::new(None)),
             rpc_ctx: Mutex::new(Cell::new(None)),
+            serialize_ctx: Mutex::new(Cell::new(SerializeCtx::default())),
             tx_action: Mutex::new(Cell::new(HashMap::new())),
+            forwarded: Mutex::new(Cell::new(HashMap::new())),
+            export_tasks: Mutex::new(Cell::new(HashMap::new())),
             resolve_cb: Mutex::new(Cell::new(None)),
             peer_hint: Mutex::new(None),
         }
@@ -75,6 +168,21 @@ impl AsyncCtx {
         *resolve_cb.get_mut() = Some(Box::new(cb));
     }
 
+    pub fn set_lifecycle_cb<CB>(&self, cb: CB)
+        where CB: Fn(LifecycleEvent) + Send + Sync + 'static,
+    {
+        let mut lifecycle_cb = self.lifecycle_cb.lock().unwrap();
+        *lifecycle_cb.get_mut() = Some(Arc::new(cb));
+    }
+
+    fn emit(&self, event: LifecycleEvent) {
+        // 回调中可能再次操作本上下文（如 `kill`），因此不持有锁
+        let cb = self.lifecycle_cb.lock().unwrap().get_mut().clone();
+        if let Some(cb) = cb {
+            cb(event);
+        }
+    }
+
     pub fn set_peer_hint(&self, hint: abi::LinkHint) {
         let mut peer_hint = self.peer_hint.lock().unwrap();
         *peer_hint = Some(hint);
@@ -93,7 +201,10 @@ impl AsyncCtx {
         // 唤醒 tx_wake
         {
             let mut waker = self.tx_waker.lock().unwrap();
-            waker.get_mut().as_ref().unwrap().wake_by_ref();
+            // 异步任务尚未启动时无需唤醒，消息会在启动后被处理
+            if let Some(waker) = waker.get_mut().as_ref() {
+                waker.wake_by_ref();
+            }
         }
     }
 
@@ -106,10 +217,63 @@ impl AsyncCtx {
         // 唤醒 rx_wake
         {
             let mut waker = self.rx_waker.lock().unwrap();
-            waker.get_mut().as_ref().unwrap().wake_by_ref();
+            // 异步任务尚未启动时无需唤醒，消息会在启动后被处理
+            if let Some(waker) = waker.get_mut().as_ref() {
+                waker.wake_by_ref();
+            }
         }
     }
 
+    /// 设置队列中的消息数上限
+    pub fn set_max_pending_messages(&self, max: Option<usize>) {
+        self.max_pending.lock().unwrap().set(max);
+    }
+
+    /// 检查队列中的消息数是否已达上限
+    fn check_pending(&self, queue: &MessageQueue) -> Result<()> {
+        if let Some(max) = self.max_pending.lock().unwrap().get() {
+            let mut queue = queue.lock().unwrap();
+            if queue.get_mut().len() >= max {
+                return Err(Error::QueueFull(max));
+            }
+        }
+        Ok(())
+    }
+
+    /// 压入 WASM 发送的报文，tx_queue 中的消息数达到上限时拒绝新的请求
+    ///
+    /// 被拒绝的请求以 `RpcErrorCode::QueueFull` 的错误结果回送给模块。调用结果、取消等其他报文
+    /// 不受上限限制，以免等待中的请求无法结束。
+    pub fn accept_tx(&self, msg: &[u8]) {
+        if let Err(e) = self.check_pending(&self.tx_queue) {
+            if let Ok((ser_ctx, req)) = RpcMessage::decode(msg) {
+                if let Message::Request = req.message() {
+                    let error = RpcError::new(RpcErrorCode::QueueFull, e.to_string());
+                    let resp = RpcResponseCtx::new(req.seq_no(), &ser_ctx, &());
+                    match resp.make_error(req.func().clone(), error) {
+                        Ok(resp_msg) => self.push_rx(resp_msg),
+                        Err(e) => eprintln!("[AsyncCtx]: make_error error: {:?}, discard!", e),
+                    }
+                    return;
+                }
+            }
+        }
+        self.push_tx(msg.to_vec());
+    }
+
+    /// 压入新的请求，rx_queue 中的消息数达到上限时以 `Error::QueueFull` 失败，模块正在卸载时以
+    /// `Error::ShuttingDown` 失败
+    ///
+    /// 调用结果与取消消息仍使用 `push_rx` 压入，不受上限限制，以免等待中的请求无法结束。
+    pub fn try_push_rx(&self, msg: Vec<u8>) -> Result<()> {
+        if self.draining() {
+            return Err(Error::ShuttingDown);
+        }
+        self.check_pending(&self.rx_queue)?;
+        self.push_rx(msg);
+        Ok(())
+    }
+
     pub fn push_action(&self, seq_no: RpcSeqNo, action: ResultAction) {
         let mut tx_action = self.tx_action.lock().unwrap();
         tx_action.get_mut().insert(seq_no, action);
@@ -120,31 +284,216 @@ impl AsyncCtx {
         tx_action.get_mut().remove(&seq_no)
     }
 
+    /// 记录转发调用的超时任务。结果已经返回或已超时时直接中止任务
+    fn set_deadline_task(&self, seq_no: RpcSeqNo, task: JoinHandle<()>) {
+        let mut tx_action = self.tx_action.lock().unwrap();
+        match tx_action.get_mut().get_mut(&seq_no) {
+            Some(ResultAction::ForwardResult(_, _, _, deadline)) => *deadline = Some(task),
+            _ => task.abort(),
+        }
+    }
+
+    fn push_forwarded(&self, seq_no: RpcSeqNo, dest_ctx: Arc<AsyncCtx>, forward_seq_no: RpcSeqNo) {
+        let mut forwarded = self.forwarded.lock().unwrap();
+        forwarded.get_mut().insert(seq_no, (dest_ctx, forward_seq_no));
+    }
+
+    fn get_forwarded(&self, seq_no: RpcSeqNo) -> Option<(Arc<AsyncCtx>, RpcSeqNo)> {
+        let mut forwarded = self.forwarded.lock().unwrap();
+        forwarded.get_mut().get(&seq_no).cloned()
+    }
+
+    fn take_forwarded(&self, seq_no: RpcSeqNo) -> Option<(Arc<AsyncCtx>, RpcSeqNo)> {
+        let mut forwarded = self.forwarded.lock().unwrap();
+        forwarded.get_mut().remove(&seq_no)
+    }
+
+    /// 模块实例的负载，即等待本模块返回结果的请求数与等待发送至本模块的消息数之和
+    pub fn load(&self) -> usize {
+        let waiting = {
+            let mut tx_action = self.tx_action.lock().unwrap();
+            tx_action.get_mut().values()
+                .filter(|action| matches!(action, ResultAction::Wake(_) | ResultAction::ForwardResult(..)))
+                .count()
+        };
+        let mut rx_queue = self.rx_queue.lock().unwrap();
+        waiting + rx_queue.get_mut().len()
+    }
+
+    /// 放弃等待请求的结果
+    ///
+    /// 若仍在等待结果，则以 `ResultAction::Discard` 替换并返回原有的动作，之后到达的结果将被丢弃；
+    /// 若结果已经到达，则直接将其移除。丢弃动作在收到对端的结果或超过 `rpc::DISCARD_TIMEOUT` 后移除。
+    pub fn discard_action(&self, seq_no: RpcSeqNo) -> Option<ResultAction> {
+        let mut tx_action = self.tx_action.lock().unwrap();
+        let tx_action = tx_action.get_mut();
+        tx_action.retain(|_, action| !matches!(action, ResultAction::Discard(until) if rpc::expired(*until)));
+        match tx_action.remove(&seq_no) {
+            Some(action @ ResultAction::Wake(_)) | Some(action @ ResultAction::ForwardResult(..)) => {
+                tx_action.insert(seq_no, ResultAction::Discard(rpc::deadline_after(rpc::DISCARD_TIMEOUT)));
+                Some(action)
+            }
+            Some(action @ ResultAction::Discard(_)) => {
+                tx_action.insert(seq_no, action);
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// 启动 Host 导出函数的异步任务，任务可以被调用方的取消消息中止
+    ///
+    /// 任务结束后回送其产生的返回报文；若任务已被取消或未能生成报文，则不再回送。
+    pub fn spawn_export<F>(self: Arc<Self>, seq_no: RpcSeqNo, future: F)
+        where F: Future<Output=rpc::Result<Vec<u8>>> + Send + 'static,
+    {
+        // 持有锁直到登记完成，保证任务结束时总能找到自身
+        let mut export_tasks = self.export_tasks.lock().unwrap();
+        let ctx = self.clone();
+        let handle = tokio::spawn(async move {
+            let msg = future.await;
+
+            let running = {
+                let mut export_tasks = ctx.export_tasks.lock().unwrap();
+                export_tasks.get_mut().remove(&seq_no).is_some()
+            };
+            match msg {
+                Ok(msg) if running => ctx.push_rx(msg),
+                Ok(_) => {}
+                Err(e) => eprintln!("[AsyncCtx]: export error: {:?}, discard!", e),
+            }
+        });
+        export_tasks.get_mut().insert(seq_no, handle);
+    }
+
+    /// 中止 Host 导出函数的异步任务，并向调用方回送取消的错误结果
+    fn abort_export(&self, ser_ctx: &SerializeCtx, seq_no: RpcSeqNo, func: abi::FunctionIdent) -> rpc::Result<()> {
+        let handle = {
+            let mut export_tasks = self.export_tasks.lock().unwrap();
+            export_tasks.get_mut().remove(&seq_no)
+        };
+
+        // 任务已经结束时，其结果已经回送，无需处理
+        if let Some(handle) = handle {
+            handle.abort();
+
+            let error = RpcError::new(RpcErrorCode::Cancelled, format!("{:?} cancelled", func));
+            let resp = RpcResponseCtx::new(seq_no, ser_ctx, &());
+            self.push_rx(resp.make_error(func, error)?);
+        }
+        Ok(())
+    }
+
     fn forward_action_cb(ctx: &RpcEndCtx<Arc<Self>>, func: abi::FunctionIdent, raw_msg: &[u8]) -> rpc::Result<()> {
-        let mut resolve_cb = ctx.data().resolve_cb.lock().unwrap();
-        let resolve_cb = resolve_cb.get_mut().as_ref()
-            .ok_or(format!("`resolve_cb` not set, cannot forward!"))?;
+        let caller_ctx = ctx.data();
+        let seq_no = ctx.seq_no();
+
+        // 只有 Host 可以请求模块导出状态
+        if func.name == abi::EXPORT_STATE {
+            let error = RpcError::new(RpcErrorCode::PermissionDenied,
+                                      format!("{:?} is reserved for the host", func));
+            let resp = RpcResponseCtx::new(seq_no, ctx.serialize_ctx(), &());
+            caller_ctx.push_rx(resp.make_error(func, error)?);
+            return Ok(());
+        }
 
         // 解析链接的目标模块
-        let link_hint = &func.hint;
-        let dest_ctx: Arc<AsyncCtx> = resolve_cb(link_hint.clone())?;
+        let dest_ctx = caller_ctx.resolve(func.hint.clone())?;
 
-        // 把消息转发到目标模块的 rx_queue
-        dest_ctx.push_rx(raw_msg.to_vec());
+        // 目标模块已经停止运行或正在卸载时，直接回送错误结果
+        let error = match dest_ctx.poisoned() {
+            Some(poison) => Some(poison.into()),
+            None if dest_ctx.draining() => {
+                Some(RpcError::new(RpcErrorCode::Unavailable, Error::ShuttingDown.to_string()))
+            }
+            None => None,
+        };
+        if let Some(error) = error {
+            let resp = RpcResponseCtx::new(seq_no, ctx.serialize_ctx(), &());
+            caller_ctx.push_rx(resp.make_error(func, error)?);
+            return Ok(());
+        }
 
-        // 设置返回动作
-        let me = ctx.data().peer_hint()
-            .ok_or(format!("`peer_hint` not set, cannot forward!"))?;
-        dest_ctx.push_action(ctx.seq_no(),
-                             ResultAction::ForwardResult(me, func.clone()));
+        // 换用 Host 分配的序列号，并转码为目标模块协商确定的序列化格式
+        let forward_seq_no = next_forward_seq_no();
+        let (ser_ctx, mut msg) = RpcMessage::decode(raw_msg)?;
+        msg.set_seq_no(forward_seq_no);
+        let msg = msg.transcode(&ser_ctx, &dest_ctx.serialize_ctx())?;
+
+        // 设置返回动作，之后把消息转发到目标模块的 rx_queue
+        dest_ctx.push_action(forward_seq_no,
+                             ResultAction::ForwardResult(caller_ctx.clone(), seq_no, func.clone(), None));
+        caller_ctx.push_forwarded(seq_no, dest_ctx.clone(), forward_seq_no);
+        if let Err(e) = dest_ctx.try_push_rx(msg) {
+            dest_ctx.take_action(forward_seq_no);
+            caller_ctx.take_forwarded(seq_no);
+            return Err(e.into());
+        }
+
+        // 请求带有截止时间时，在剩余的时间预算耗尽后清理返回动作，并向调用方回送超时的错误结果
+        if let Some(deadline) = ctx.deadline() {
+            let ser_ctx = *ctx.serialize_ctx();
+            let task_ctx = dest_ctx.clone();
+            let task = tokio::spawn(async move {
+                let dest_ctx = task_ctx;
+                if let Some(remaining) = rpc::remaining(deadline) {
+                    tokio::time::sleep(remaining).await;
+                }
+
+                // 结果已经返回时无需处理
+                if let Some(ResultAction::ForwardResult(caller_ctx, seq_no, func, _)) =
+                    dest_ctx.discard_action(forward_seq_no) {
+                    caller_ctx.take_forwarded(seq_no);
+
+                    // 通知目标模块取消调用，其迟到的结果将被丢弃
+                    match RpcRequestCtx::new(forward_seq_no, &ser_ctx, &()).make_cancel(func.clone()) {
+                        Ok(msg) => dest_ctx.push_rx(msg),
+                        Err(e) => eprintln!("[AsyncCtx]: make_cancel error: {:?}, discard!", e),
+                    }
+
+                    // 向调用方回送超时的错误结果
+                    let error = RpcError::new(RpcErrorCode::DeadlineExceeded,
+                                              format!("{:?} not returned before deadline", func));
+                    let resp = RpcResponseCtx::new(seq_no, &ser_ctx, &());
+                    match resp.make_error(func, error) {
+                        Ok(msg) => caller_ctx.push_rx(msg),
+                        Err(e) => eprintln!("[AsyncCtx]: make_error error: {:?}, discard!", e),
+                    }
+                }
+            });
+            dest_ctx.set_deadline_task(forward_seq_no, task);
+        }
 
         Ok(())
     }
 
-    fn return_action_cb(ctx: &RpcEndCtx<Arc<Self>>, res: Vec<u8>) -> rpc::Result<()> {
+    fn cancel_action_cb(ctx: &RpcEndCtx<Arc<Self>>, func: abi::FunctionIdent, _raw_msg: &[u8]) -> rpc::Result<()> {
+        let seq_no = ctx.seq_no();
+
+        // 调用 Host 导出的函数，中止对应的异步任务
+        if func.hint == abi::LinkHint::Host {
+            return ctx.data().abort_export(ctx.serialize_ctx(), seq_no, func);
+        }
+
+        // 转发的调用，找到请求被转发到的模块实例
+        let (dest_ctx, forward_seq_no) = match ctx.data().get_forwarded(seq_no) {
+            Some(forwarded) => forwarded,
+            // 结果已经返回
+            None => return Ok(()),
+        };
+
+        // 目标模块仍未返回结果，通知其取消调用。返回动作保持不变，目标模块回送的取消错误结果
+        // （或迟到的结果）照常转发给调用方，调用方收到后移除其丢弃动作
+        let msg = RpcRequestCtx::new(forward_seq_no, ctx.serialize_ctx(), &()).make_cancel(func)?;
+        dest_ctx.push_rx(msg);
+
+        Ok(())
+    }
+
+    fn return_action_cb(ctx: &RpcEndCtx<Arc<Self>>, res: RpcCallResult) -> rpc::Result<()> {
         let seq_no = ctx.seq_no();
         let action = ctx.data().take_action(seq_no)
-            .ok_or(format!("seq_no {} not found", seq_no))?;
+            .ok_or(Error::UnknownSeqNo(seq_no))?;
 
         match action {
             ResultAction::Wake(waker) => {
@@ -155,26 +504,38 @@ impl AsyncCtx {
                 waker.wake();
                 Ok(())
             }
-            ResultAction::ForwardResult(link_hint, func) => {
-                // 转发结果动作
-                let mut resolve_cb = ctx.data().resolve_cb.lock().unwrap();
-                let resolve_cb = resolve_cb.get_mut().as_ref()
-                    .ok_or(format!("`resolve_cb` not set, cannot forward result!"))?;
-
-                // 解析目标模块
-                let dest_ctx: Arc<AsyncCtx> = resolve_cb(link_hint)?;
-
-                // 拼接返回消息。此处因为 API 设计的考虑，因此暂时通过此种方法拼接。
-                let ser_ctx = SerializeCtx::new();
-                let resp = RpcResponseCtx::new(ctx.seq_no(), &ser_ctx, &());
-                let resp_msg = resp.make_response(func, res)?;
-
-                // 把消息转发到目标模块的 rx_queue
-                dest_ctx.push_rx(resp_msg);
+            ResultAction::ForwardResult(caller_ctx, caller_seq_no, func, deadline) => {
+                // 转发结果动作，结果已经返回，无需再等待超时
+                if let Some(deadline) = deadline {
+                    deadline.abort();
+                }
+                caller_ctx.take_forwarded(caller_seq_no);
+
+                // 拼接返回消息，把返回值转码为调用方协商确定的序列化格式，并换回调用方的原始序列号。
+                // 无法转码时沿用调用结果的序列化格式，调用方按报文的格式标记解析。
+                // 此处因为 API 设计的考虑，因此暂时通过此种方法拼接。
+                let caller_ser_ctx = caller_ctx.serialize_ctx();
+                let (ser_ctx, res) = match res {
+                    Ok(data) => match ctx.serialize_ctx().transcode(&caller_ser_ctx, &data) {
+                        Ok(data) => (caller_ser_ctx, Ok(data)),
+                        Err(serialize::Error::Untranscodable(_, _)) => (*ctx.serialize_ctx(), Ok(data)),
+                        Err(e) => return Err(e.into()),
+                    },
+                    Err(error) => (caller_ser_ctx, Err(error)),
+                };
+                let resp = RpcResponseCtx::new(caller_seq_no, &ser_ctx, &());
+                let resp_msg = resp.make_result(func, res)?;
+
+                // 把消息转发到调用方模块实例的 rx_queue
+                caller_ctx.push_rx(resp_msg);
 
                 Ok(())
             }
-            _ => Err(format!("seq_no {}: action not support", seq_no).into()),
+            ResultAction::Discard(_) => {
+                // 请求已被取消，丢弃迟到的结果
+                Ok(())
+            }
+            _ => Err(Error::UnexpectedAction(seq_no).into()),
         }
     }
 
@@ -182,11 +543,28 @@ impl AsyncCtx {
         // 添加返回回调
         rpc_node.set_forward_cb(Self::forward_action_cb);
         rpc_node.set_result_cb(Self::return_action_cb);
+        rpc_node.set_cancel_cb(Self::cancel_action_cb);
+        rpc_node.set_reply_cb(|ctx, msg| {
+            ctx.data().push_rx(msg);
+            Ok(())
+        });
         // 记录引用
+        self.serialize_ctx.lock().unwrap().set(rpc_node.serialize_ctx());
         let mut rpc_ctx = self.rpc_ctx.lock().unwrap();
         rpc_ctx.get_mut().replace(rpc_node);
     }
 
+    /// 以 RpcNode 处理模块发送的报文
+    ///
+    /// 握手或切换格式后记录协商确定的序列化上下文，之后读取格式时无需锁定 RpcNode。
+    pub fn handle_message(&self, msg: &[u8]) -> rpc::Result<()> {
+        let mut rpc_ctx = self.rpc_ctx.lock().unwrap();
+        let rpc_node = rpc_ctx.get_mut().as_ref().unwrap();
+        let ret = rpc_node.handle_message(msg);
+        self.serialize_ctx.lock().unwrap().set(rpc_node.serialize_ctx());
+        ret
+    }
+
     pub fn bind_low_level<T>(self: Arc<Self>, ll_ctx: &mut LowLevelCtx<T>)
         where T: Send + Sync + 'static,
     {
@@ -194,11 +572,14 @@ impl AsyncCtx {
         ll_ctx.set_message_callback(move |msg| {
             let that = self.as_ref();
             if that.prepared() {
-                that.push_tx(msg.to_vec());
+                // `msg` 仅在回调期间有效，因此在此复制一次。
+                // 之后导出函数的参数直接借用队列中的报文，不再复制
+                that.accept_tx(msg);
             } else {
-                // 异步未就绪，同步处理
-                let mut rpc_ctx = that.rpc_ctx.lock().unwrap();
-                rpc_ctx.get_mut().as_ref().unwrap().handle_message(msg).unwrap();
+                // 异步未就绪，同步处理。报文来自模块，处理失败时丢弃而不影响 Host
+                if let Err(e) = that.handle_message(msg) {
+                    eprintln!("[AsyncCtx]: handle_message error: {:?}, discard!", e);
+                }
             }
         });
     }
@@ -222,15 +603,52 @@ impl AsyncCtx {
         }
     }
 
+    /// 当前用于发送报文的序列化上下文，在与模块握手后为双方协商确定的格式
+    ///
+    /// 不锁定 RpcNode，可以在其他模块（或本模块）处理报文的回调中调用。
+    pub fn serialize_ctx(&self) -> SerializeCtx {
+        self.serialize_ctx.lock().unwrap().get()
+    }
+
+    /// 限制模块调用的导入表，见 `RpcNode::get_peer_imports`
+    ///
+    /// 调用导入表中未声明的函数以 `RpcErrorCode::UndeclaredImport` 失败。
+    pub fn peer_imports(&self) -> Option<RpcImports> {
+        let mut rpc_ctx = self.rpc_ctx.lock().unwrap();
+        rpc_ctx.get_mut().as_ref()
+            .and_then(|rpc_ctx| rpc_ctx.get_peer_imports())
+    }
+
     /// 异步调用 API
     pub fn request_api(self: Arc<Self>, func: abi::FunctionIdent, args: Vec<u8>) -> AsyncRequestFuture {
+        self.request_api_with_deadline(func, args, None)
+    }
+
+    /// 异步调用 API，超过 `timeout` 仍未返回时以 `Error::Timeout` 结束
+    pub fn request_api_timeout(self: Arc<Self>,
+                               func: abi::FunctionIdent,
+                               args: Vec<u8>,
+                               timeout: Duration,
+    ) -> AsyncRequestFuture {
+        self.request_api_with_deadline(func, args, Some(rpc::deadline_after(timeout)))
+    }
+
+    /// 异步调用 API，超过截止时间仍未返回时以 `Error::Timeout` 结束
+    ///
+    /// 截止时间随请求报文传递，经由其他模块转发的调用同样遵守剩余的时间预算。
+    pub fn request_api_with_deadline(self: Arc<Self>,
+                                     func: abi::FunctionIdent,
+                                     args: Vec<u8>,
+                                     deadline: Option<RpcDeadline>,
+    ) -> AsyncRequestFuture {
         let mut rpc_ctx = self.rpc_ctx.lock().unwrap();
         let req = rpc_ctx.get_mut().as_ref().unwrap().request();
 
         // 序列化
-        let msg = req.make_request(func, args).unwrap();
+        let cancel_msg = req.make_cancel(func.clone()).unwrap();
+        let msg = req.make_request_with_deadline(func, args, deadline).unwrap();
 
-        AsyncRequestFuture::new(self.clone(), req.seq_no(), msg)
+        AsyncRequestFuture::new(self.clone(), req.seq_no(), msg, cancel_msg, deadline)
     }
 
     pub fn alive(&self) -> bool {
@@ -238,6 +656,62 @@ impl AsyncCtx {
     }
 
     pub fn kill(&self) {
+        self.halt();
+        self.emit(LifecycleEvent::Killed);
+    }
+
+    /// 是否正在卸载
+    pub fn draining(&self) -> bool {
+        self.draining.lock().unwrap().get()
+    }
+
+    /// 停止接受新的请求，最多等待 `timeout` 让已接受的请求返回结果，返回是否都在超时前返回
+    ///
+    /// 模块继续运行，可以通过 `resume` 恢复接受请求。
+    pub async fn drain(&self, timeout: Duration) -> bool {
+        self.draining.lock().unwrap().set(true);
+
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            // 模块已经停止运行时，等待中的请求已经结束
+            if self.load() == 0 || !self.alive() {
+                return true;
+            }
+            if tokio::time::Instant::now() >= deadline {
+                return false;
+            }
+            tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+        }
+    }
+
+    /// 恢复接受新的请求，用于撤销 `drain`
+    pub fn resume(&self) {
+        self.draining.lock().unwrap().set(false);
+    }
+
+    /// 卸载模块：停止接受新的请求，最多等待 `timeout` 让已接受的请求返回结果，之后结束模块
+    ///
+    /// 返回已接受的请求是否都在超时前返回。超时仍未返回的请求以 `Error::ShuttingDown` 失败。
+    pub async fn shutdown(&self, timeout: Duration) -> bool {
+        let drained = self.drain(timeout).await;
+        if !drained {
+            self.fail_pending(RpcError::new(RpcErrorCode::Unavailable, Error::ShuttingDown.to_string()));
+        }
+        self.kill();
+        drained
+    }
+
+    /// 标记为死亡，异步任务已经启动时将其唤醒使之结束
+    fn halt(&self) {
+        if self.prepared() {
+            self.stop();
+        } else {
+            self.alive.lock().unwrap().set(false);
+        }
+    }
+
+    /// 标记为死亡，并唤醒异步任务使之结束
+    fn stop(&self) {
         self.alive.lock().unwrap().set(false);
         // 唤醒 tx_wake
         {
@@ -251,6 +725,78 @@ impl AsyncCtx {
         }
     }
 
+    /// 模块被毒化的原因，模块正常运行时为 `None`
+    pub fn poisoned(&self) -> Option<Poison> {
+        self.poison.lock().unwrap().clone()
+    }
+
+    /// 毒化模块：以错误结束所有等待中的请求，标记为死亡并发出 `LifecycleEvent::Poisoned`
+    ///
+    /// 之后对本模块的请求与转发都将直接失败。
+    pub fn poison(&self, poison: Poison) {
+        *self.poison.lock().unwrap() = Some(poison.clone());
+        self.fail_pending(RpcError::from(poison.clone()));
+        self.halt();
+        self.emit(LifecycleEvent::Poisoned(poison));
+    }
+
+    /// 以 `error` 结束所有等待本模块返回结果的请求，并放弃本模块转发出的请求
+    fn fail_pending(&self, error: RpcError) {
+        // 取出等待结果的动作
+        let waiting = {
+            let mut tx_action = self.tx_action.lock().unwrap();
+            let tx_action = tx_action.get_mut();
+            let (waiting, rest): (HashMap<_, _>, HashMap<_, _>) = tx_action.drain()
+                .partition(|(_, action)| matches!(action, ResultAction::Wake(_) | ResultAction::ForwardResult(..)));
+            // 停止运行的模块不会再回送结果，丢弃动作一并移除
+            *tx_action = rest.into_iter()
+                .filter(|(_, action)| !matches!(action, ResultAction::Discard(_)))
+                .collect();
+            waiting
+        };
+
+        for (seq_no, action) in waiting {
+            match action {
+                ResultAction::Wake(waker) => {
+                    self.push_action(seq_no, ResultAction::Response(Err(error.clone())));
+                    waker.wake();
+                }
+                ResultAction::ForwardResult(caller_ctx, caller_seq_no, func, deadline) => {
+                    // 向转发调用的调用方模块实例回送错误结果
+                    if let Some(deadline) = deadline {
+                        deadline.abort();
+                    }
+                    caller_ctx.take_forwarded(caller_seq_no);
+                    let resp = RpcResponseCtx::new(caller_seq_no, &caller_ctx.serialize_ctx(), &());
+                    match resp.make_error(func, error.clone()) {
+                        Ok(msg) => caller_ctx.push_rx(msg),
+                        Err(e) => eprintln!("[AsyncCtx]: reply error: {:?}, discard!", e),
+                    }
+                }
+                _ => unreachable!(),
+            }
+        }
+
+        // 本模块转发出的请求不再需要结果
+        let forwarded: Vec<_> = {
+            let mut forwarded = self.forwarded.lock().unwrap();
+            forwarded.get_mut().drain().map(|(_, forwarded)| forwarded).collect()
+        };
+        for (dest_ctx, forward_seq_no) in forwarded {
+            if let Some(ResultAction::ForwardResult(_, _, _, Some(deadline))) = dest_ctx.discard_action(forward_seq_no) {
+                deadline.abort();
+            }
+        }
+    }
+
+    /// 解析链接提示对应模块的异步上下文
+    fn resolve(&self, link_hint: abi::LinkHint) -> Result<Arc<AsyncCtx>> {
+        let mut resolve_cb = self.resolve_cb.lock().unwrap();
+        let resolve_cb = resolve_cb.get_mut().as_ref()
+            .ok_or(Error::Unconfigured("resolve_cb"))?;
+        resolve_cb(link_hint)
+    }
+
     pub fn prepared(&self) -> bool {
         // 检查 tx_waker
         {
@@ -0,0 +1,130 @@
+//! 异步接口模块的错误类型
+
+use std::fmt;
+
+use low_level::host::{Resource, TrapInfo};
+use rpc::{abi, RpcError, RpcErrorCode, RpcSeqNo};
+
+use crate::ctx::Poison;
+
+/// 异步调用中可能发生的错误
+#[derive(Debug)]
+pub enum Error {
+    /// RPC 通信失败，或对端回送了错误结果
+    Rpc(rpc::Error),
+    /// 低层传输失败
+    Transport(low_level::Error),
+    /// 参数或返回值的序列化失败
+    Serialize(serialize::Error),
+    /// 异步上下文缺少所需的配置
+    Unconfigured(&'static str),
+    /// 找不到链接提示对应的模块
+    ModuleNotFound(abi::LinkHint),
+    /// 模块调用了自身导出的函数
+    CircularImport(abi::LinkHint),
+    /// 收到的调用结果没有对应的请求
+    UnknownSeqNo(RpcSeqNo),
+    /// 调用结果对应的动作不受支持
+    UnexpectedAction(RpcSeqNo),
+    /// 调用请求超过截止时间仍未返回
+    Timeout(RpcSeqNo),
+    /// 模块超出 CPU 预算而停止运行
+    CpuExhausted,
+    /// 模块超出资源限制而停止运行
+    ResourceLimit(Resource),
+    /// 模块执行时发生 Trap 而停止运行
+    Trap(TrapInfo),
+    /// 队列中的消息数已达上限
+    QueueFull(usize),
+    /// 模块正在卸载，不再接受新的请求，或在卸载前未能返回结果
+    ShuttingDown,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Rpc(e) => write!(f, "{}", e),
+            Error::Transport(e) => write!(f, "transport error: {}", e),
+            Error::Serialize(e) => write!(f, "{}", e),
+            Error::Unconfigured(name) => write!(f, "`{}` not set", name),
+            Error::ModuleNotFound(hint) => write!(f, "Failed to resolve module with hint: {:?}", hint),
+            Error::CircularImport(hint) => write!(f, "Circular import: {:?}", hint),
+            Error::UnknownSeqNo(seq_no) => write!(f, "seq_no {} not found", seq_no),
+            Error::UnexpectedAction(seq_no) => write!(f, "seq_no {}: action not support", seq_no),
+            Error::Timeout(seq_no) => write!(f, "seq_no {}: deadline exceeded", seq_no),
+            Error::CpuExhausted => write!(f, "module exceeded its CPU budget"),
+            Error::ResourceLimit(resource) => write!(f, "resource limit exceeded: {}", resource),
+            Error::Trap(trap) => write!(f, "module trapped: {}", trap),
+            Error::QueueFull(max) => write!(f, "too many pending messages (max {})", max),
+            Error::ShuttingDown => write!(f, "module is shutting down"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Rpc(e) => Some(e),
+            Error::Transport(e) => Some(e),
+            Error::Serialize(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<rpc::Error> for Error {
+    fn from(e: rpc::Error) -> Self {
+        Error::Rpc(e)
+    }
+}
+
+impl From<RpcError> for Error {
+    fn from(e: RpcError) -> Self {
+        Error::Rpc(e.into())
+    }
+}
+
+impl From<low_level::Error> for Error {
+    fn from(e: low_level::Error) -> Self {
+        match e {
+            low_level::Error::CpuExhausted => Error::CpuExhausted,
+            low_level::Error::ResourceLimit(resource) => Error::ResourceLimit(resource),
+            e => Error::Transport(e),
+        }
+    }
+}
+
+impl From<Poison> for Error {
+    fn from(poison: Poison) -> Self {
+        match poison {
+            Poison::Cpu => Error::CpuExhausted,
+            Poison::Resource(resource) => Error::ResourceLimit(resource),
+            Poison::Trap(trap) => Error::Trap(trap),
+        }
+    }
+}
+
+impl From<serialize::Error> for Error {
+    fn from(e: serialize::Error) -> Self {
+        Error::Serialize(e)
+    }
+}
+
+impl From<Error> for rpc::Error {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Rpc(e) => e,
+            Error::Transport(e) => e.into(),
+            Error::Serialize(e) => e.into(),
+            e @ Error::Timeout(_) => RpcError::new(RpcErrorCode::DeadlineExceeded, e.to_string()).into(),
+            e @ Error::CpuExhausted => RpcError::new(RpcErrorCode::ResourceExhausted, e.to_string()).into(),
+            e @ Error::ResourceLimit(_) => RpcError::new(RpcErrorCode::LimitExceeded, e.to_string()).into(),
+            e @ Error::Trap(_) => RpcError::new(RpcErrorCode::Trapped, e.to_string()).into(),
+            e @ Error::ShuttingDown => RpcError::new(RpcErrorCode::Unavailable, e.to_string()).into(),
+            e @ Error::QueueFull(_) => RpcError::new(RpcErrorCode::QueueFull, e.to_string()).into(),
+            e => rpc::Error::callback(e),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -4,10 +4,12 @@ use std::pin::Pin;
 use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
 
+use tokio::time::Sleep;
+
 use low_level::host::LowLevelCtx;
-use rpc::RpcSeqNo;
+use rpc::{RpcDeadline, RpcErrorCode, RpcSeqNo};
 
-use crate::ctx::{AsyncCtx, ResultAction};
+use crate::ctx::{AsyncCtx, Poison, ResultAction};
 
 /// 处理接受队列信息、进行转发及调用的异步任务
 pub struct HandleTxFuture {
@@ -41,10 +43,8 @@ impl Future for HandleTxFuture {
             // 检查 tx 是否空，处理消息
             {
                 let mut tx_queue = self.ctx.tx_queue.lock().unwrap();
-                let mut rpc_ctx = self.ctx.rpc_ctx.lock().unwrap();
                 for msg in tx_queue.get_mut().iter() {
-                    let ret = rpc_ctx.get_mut().as_ref().unwrap().handle_message(msg);
-                    if let Err(e) = ret {
+                    if let Err(e) = self.ctx.handle_message(msg) {
                         eprintln!("[HandleTxFuture]: handle_message error: {:?}, discard!", e);
                     }
                 }
@@ -68,6 +68,9 @@ pub struct HandleRxFuture<T>
 
     /// 低层接口
     ll_ctx: Arc<LowLevelCtx<T>>,
+
+    /// 异步模式下正在执行的 WASM 调用
+    running: Option<Pin<Box<dyn Future<Output=low_level::Result<()>> + Send>>>,
 }
 
 impl<T> HandleRxFuture<T>
@@ -77,6 +80,7 @@ impl<T> HandleRxFuture<T>
         HandleRxFuture {
             ctx,
             ll_ctx,
+            running: None,
         }
     }
 }
@@ -86,7 +90,7 @@ impl<T> Future for HandleRxFuture<T>
 {
     type Output = ();
 
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         // 更新 waker
         {
             let waker = self.ctx.rx_waker.lock().unwrap();
@@ -100,19 +104,28 @@ impl<T> Future for HandleRxFuture<T>
         if alive {
             // 存活，正常操作
 
-            // 检查 rx_queue 是否空，并将其消息发送至 WASM
-            {
-                let mut rx_queue = self.ctx.rx_queue.lock().unwrap();
-                for msg in rx_queue.get_mut().iter() {
-                    self.ll_ctx.send_message_to_wasm(msg).unwrap();
+            // 异步模式下，WASM 的执行可能让出，因此保存正在执行的调用并在之后继续
+            let result = if self.ll_ctx.async_mode() {
+                match self.poll_async(cx) {
+                    Poll::Ready(result) => result,
+                    Poll::Pending => return Poll::Pending,
+                }
+            } else {
+                self.run()
+            };
+
+            // WASM 执行失败时毒化模块，之后不再运行
+            match result {
+                Ok(()) => Poll::Pending,
+                // 执行期间模块已被结束（如卸载时回收了 Store），不视为模块的错误
+                Err(_) if !self.ctx.alive() => Poll::Ready(()),
+                Err(e) => {
+                    let poison = Poison::from(&e);
+                    eprintln!("[HandleRxFuture]: {}, stop!", crate::Error::from(poison.clone()));
+                    self.ctx.poison(poison);
+                    Poll::Ready(())
                 }
-                rx_queue.get_mut().clear();
             }
-
-            // 运行 WASM 模块
-            self.ll_ctx.wasm_poll().unwrap();
-
-            Poll::Pending
         } else {
             // 死亡，直接返回
             Poll::Ready(())
@@ -120,33 +133,133 @@ impl<T> Future for HandleRxFuture<T>
     }
 }
 
+impl<T> HandleRxFuture<T>
+    where T: Send + Sync + 'static,
+{
+    /// 把 rx_queue 中的消息通过一次调用发送至 WASM，并运行 WASM 模块
+    fn run(&self) -> low_level::Result<()> {
+        {
+            let mut rx_queue = self.ctx.rx_queue.lock().unwrap();
+            let rx_queue = rx_queue.get_mut();
+            if !rx_queue.is_empty() {
+                self.ll_ctx.send_messages_to_wasm(rx_queue.iter().map(Vec::as_slice))?;
+                rx_queue.clear();
+            }
+        }
+
+        self.ll_ctx.wasm_poll()
+    }
+
+    fn poll_async(&mut self, cx: &mut Context<'_>) -> Poll<low_level::Result<()>> {
+        // 没有正在执行的调用时，取出 rx_queue 中的消息，发送至 WASM 并运行 WASM 模块
+        let running = self.running.get_or_insert_with(|| {
+            let messages = {
+                let mut rx_queue = self.ctx.rx_queue.lock().unwrap();
+                std::mem::take(rx_queue.get_mut())
+            };
+            let ll_ctx = self.ll_ctx.clone();
+            Box::pin(async move {
+                if !messages.is_empty() {
+                    ll_ctx.send_messages_to_wasm_async(messages.iter().map(Vec::as_slice)).await?;
+                }
+                ll_ctx.wasm_poll_async().await
+            })
+        });
+
+        let result = match running.as_mut().poll(cx) {
+            Poll::Ready(result) => result,
+            Poll::Pending => return Poll::Pending,
+        };
+        self.running = None;
+
+        // 执行期间收到的消息需要再次调用 WASM 发送
+        let mut rx_queue = self.ctx.rx_queue.lock().unwrap();
+        if !rx_queue.get_mut().is_empty() {
+            cx.waker().wake_by_ref();
+        }
+
+        Poll::Ready(result)
+    }
+}
+
 // 异步请求 API 的包装
 pub struct AsyncRequestFuture {
     ctx: Arc<AsyncCtx>,
     seq_no: RpcSeqNo,
     msg: Mutex<Cell<Option<Vec<u8>>>>,
     triggered: Mutex<Cell<bool>>,
+    /// 取消请求的报文，请求结束后置空
+    cancel_msg: Mutex<Cell<Option<Vec<u8>>>>,
+    deadline: Option<RpcDeadline>,
+    /// 截止时间的定时器，在第一次调用时创建
+    timer: Option<Pin<Box<Sleep>>>,
+    /// 是否不受卸载与待发送消息数上限的限制
+    forced: bool,
 }
 
 impl AsyncRequestFuture {
-    pub fn new(ctx: Arc<AsyncCtx>, seq_no: RpcSeqNo, msg: Vec<u8>) -> Self {
+    pub fn new(ctx: Arc<AsyncCtx>,
+               seq_no: RpcSeqNo,
+               msg: Vec<u8>,
+               cancel_msg: Vec<u8>,
+               deadline: Option<RpcDeadline>,
+    ) -> Self {
         AsyncRequestFuture {
             ctx,
             seq_no,
             msg: Mutex::new(Cell::new(Some(msg))),
             triggered: Mutex::new(Cell::new(false)),
+            cancel_msg: Mutex::new(Cell::new(Some(cancel_msg))),
+            deadline,
+            timer: None,
+            forced: false,
+        }
+    }
+
+    /// 排空或队列已满时仍然发送请求，用于 Host 在排空期间发出的内部请求，如导出模块状态
+    pub fn forced(mut self) -> Self {
+        self.forced = true;
+        self
+    }
+
+    /// 取消仍在等待结果的请求：丢弃之后到达的结果，并通知对端中止调用
+    fn cancel(&self) {
+        let cancel_msg = {
+            let cancel_msg = self.cancel_msg.lock().unwrap();
+            cancel_msg.take()
+        };
+        let cancel_msg = match cancel_msg {
+            Some(cancel_msg) => cancel_msg,
+            None => return,
+        };
+
+        // 请求尚未发送或结果已经到达时，无需通知对端
+        if let Some(ResultAction::Wake(_)) = self.ctx.discard_action(self.seq_no) {
+            self.ctx.push_rx(cancel_msg);
         }
     }
 }
 
+impl Drop for AsyncRequestFuture {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
 impl Future for AsyncRequestFuture {
     type Output = crate::Result<Vec<u8>>;
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         // 检查是否有结果
         let action = self.ctx.take_action(self.seq_no);
 
         match action {
             None => {
+                // 模块已经停止运行，请求不会有结果
+                if let Some(poison) = self.ctx.poisoned() {
+                    self.cancel_msg.lock().unwrap().take();
+                    return Poll::Ready(Err(poison.into()));
+                }
+
                 let triggered = self.triggered.lock().unwrap();
                 if !triggered.get() {
                     // 第一次调用
@@ -155,27 +268,57 @@ impl Future for AsyncRequestFuture {
                     let waker = cx.waker().clone();
                     self.ctx.push_action(self.seq_no, ResultAction::Wake(waker));
 
-                    // 发送请求
-                    {
-                        let msg = self.msg.lock().unwrap();
-                        self.ctx.push_rx(msg.take().unwrap());
+                    // 发送请求，待发送的消息过多时放弃请求
+                    let ret = {
+                        let msg = self.msg.lock().unwrap().take().unwrap();
+                        if self.forced {
+                            self.ctx.push_rx(msg);
+                            Ok(())
+                        } else {
+                            self.ctx.try_push_rx(msg)
+                        }
+                    };
+                    if let Err(e) = ret {
+                        self.ctx.take_action(self.seq_no);
+                        self.cancel_msg.lock().unwrap().take();
+                        return Poll::Ready(Err(e));
                     }
 
                     // 设置触发标志
                     triggered.set(true);
                 }
-                Poll::Pending
             }
-            Some(ResultAction::Response(msg)) => {
-                // 获取结果
-                Poll::Ready(Ok(msg))
+            Some(ResultAction::Response(result)) => {
+                // 获取结果，请求已经结束，无需再取消
+                self.cancel_msg.lock().unwrap().take();
+                return Poll::Ready(result.map_err(|e| match (e.code, self.ctx.poisoned()) {
+                    (RpcErrorCode::ResourceExhausted, _) => crate::Error::CpuExhausted,
+                    (RpcErrorCode::Unavailable, _) => crate::Error::ShuttingDown,
+                    (RpcErrorCode::LimitExceeded, Some(poison @ Poison::Resource(_)))
+                    | (RpcErrorCode::Trapped, Some(poison @ Poison::Trap(_))) => poison.into(),
+                    _ => e.into(),
+                }));
             }
             Some(action) => {
                 // 不支持的结果类型，放回
                 self.ctx.push_action(self.seq_no, action);
-                Poll::Pending
             }
         }
+
+        // 检查是否超过截止时间
+        if let Some(deadline) = self.deadline {
+            let timer = self.timer.get_or_insert_with(|| {
+                let remaining = rpc::remaining(deadline).unwrap_or_default();
+                Box::pin(tokio::time::sleep(remaining))
+            });
+            if timer.as_mut().poll(cx).is_ready() {
+                // 取消请求，迟到的结果将被丢弃
+                self.cancel();
+                return Poll::Ready(Err(crate::Error::Timeout(self.seq_no)));
+            }
+        }
+
+        Poll::Pending
     }
 }
 
@@ -183,10 +326,11 @@ impl Future for AsyncRequestFuture {
 mod tests {
     use std::sync::Arc;
 
-    use low_level::host::LowLevelCtx;
-    use rpc::{abi, RpcNode};
-    use serialize::{ArgsBuilder, SerializeCtx};
+    use low_level::host::{LowLevelCtx, TrapInfo};
+    use rpc::{abi, RpcError, RpcNode, RpcRequestCtx, RpcResponseCtx};
+    use serialize::{Args, ArgsBuilder, SerializeCtx, SerializeFormat};
 
+    use crate::ctx::LifecycleEvent;
     use crate::tests::*;
 
     use super::*;
@@ -249,7 +393,7 @@ mod tests {
     }
 
     async fn wasm_export_to_host(ctx: Arc<AsyncCtx>, param: String) -> crate::Result<String> {
-        let ser_ctx = SerializeCtx::new();
+        let ser_ctx = ctx.serialize_ctx();
         // 函数标识符
         let mut func = abi::FunctionIdent::new("wasm_export_to_host");
         func.set_hint(abi::LinkHint::BcModule("integrate-wasm".to_string()));
@@ -264,6 +408,386 @@ mod tests {
         Ok(result)
     }
 
+    /// 测试调用请求超时
+    #[tokio::test]
+    async fn test_request_timeout() {
+        let ctx = unstarted_ctx();
+
+        // 调用函数
+        let func = abi::FunctionIdent::new("never_return");
+        let ret = ctx.clone()
+            .request_api_timeout(func, vec![], std::time::Duration::from_millis(50))
+            .await;
+
+        // 检查结果，返回动作应当被替换为丢弃动作，并发送了取消消息
+        assert!(matches!(ret, Err(crate::Error::Timeout(_))));
+        {
+            let mut tx_action = ctx.tx_action.lock().unwrap();
+            let tx_action = tx_action.get_mut();
+            assert_eq!(1, tx_action.len());
+            assert!(tx_action.values().all(|action| matches!(action, ResultAction::Discard(_))));
+        }
+        let mut rx_queue = ctx.rx_queue.lock().unwrap();
+        assert_eq!(2, rx_queue.get_mut().len());
+    }
+
+    /// 测试模块超出 CPU 预算后结束等待中与之后的请求
+    #[tokio::test]
+    async fn test_request_exhausted() {
+        let ctx = unstarted_ctx();
+
+        // 发送请求后模块停止运行
+        let func = abi::FunctionIdent::new("never_return");
+        let request = tokio::spawn(ctx.clone().request_api(func.clone(), vec![]));
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        ctx.poison(Poison::Cpu);
+        assert!(matches!(request.await.unwrap(), Err(crate::Error::CpuExhausted)));
+
+        // 之后的请求直接失败
+        let ret = ctx.clone().request_api(func, vec![]).await;
+        assert!(matches!(ret, Err(crate::Error::CpuExhausted)));
+    }
+
+    /// 测试模块发生 Trap 后被毒化，并发出生命周期事件
+    #[tokio::test]
+    async fn test_request_trapped() {
+        let ctx = unstarted_ctx();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let events_cb = events.clone();
+        ctx.set_lifecycle_cb(move |event| events_cb.lock().unwrap().push(event));
+
+        // 发送请求后模块发生 Trap
+        let func = abi::FunctionIdent::new("never_return");
+        let request = tokio::spawn(ctx.clone().request_api(func, vec![]));
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        let trap = TrapInfo {
+            reason: "wasm `unreachable` instruction executed".to_string(),
+            backtrace: vec!["guest.wasm!abort".to_string()],
+        };
+        ctx.poison(Poison::Trap(trap.clone()));
+
+        // 请求以 Trap 失败，模块已经死亡
+        match request.await.unwrap() {
+            Err(crate::Error::Trap(actual)) => assert_eq!(trap, actual),
+            ret => panic!("unexpected result: {:?}", ret),
+        }
+        assert!(!ctx.alive());
+        assert_eq!(vec![LifecycleEvent::Poisoned(Poison::Trap(trap))], *events.lock().unwrap());
+    }
+
+    /// 测试待发送的消息数达到上限时请求失败
+    #[tokio::test]
+    async fn test_request_queue_full() {
+        let ctx = unstarted_ctx();
+        ctx.set_max_pending_messages(Some(1));
+
+        // 第一个请求可以发送
+        let func = abi::FunctionIdent::new("never_return");
+        let ret = tokio::time::timeout(std::time::Duration::from_millis(50),
+                                       ctx.clone().request_api(func.clone(), vec![])).await;
+        assert!(ret.is_err());
+
+        // 取消消息不受上限限制，之后的请求失败
+        let ret = ctx.clone().request_api(func, vec![]).await;
+        assert!(matches!(ret, Err(crate::Error::QueueFull(1))));
+        let mut rx_queue = ctx.rx_queue.lock().unwrap();
+        assert_eq!(2, rx_queue.get_mut().len());
+    }
+
+    /// 测试模块发送的消息数达到上限时，新的请求以错误结果回送，调用结果仍然被接收
+    #[tokio::test]
+    async fn test_accept_tx_queue_full() {
+        let ctx = unstarted_ctx();
+        ctx.set_max_pending_messages(Some(1));
+
+        let ser_ctx = SerializeCtx::new();
+        let func = abi::FunctionIdent::new("host_func");
+        let req = RpcRequestCtx::new(1, &ser_ctx, &()).make_request(func.clone(), vec![]).unwrap();
+        ctx.accept_tx(&req);
+        assert!(ctx.rx_queue.lock().unwrap().get_mut().is_empty());
+
+        // 队列已满，请求被拒绝
+        let req = RpcRequestCtx::new(2, &ser_ctx, &()).make_request(func.clone(), vec![]).unwrap();
+        ctx.accept_tx(&req);
+        let resp = pop_rx(&ctx);
+        let (_, resp) = rpc::RpcMessage::decode(&resp).unwrap();
+        assert_eq!(2, resp.seq_no());
+        match resp.message() {
+            rpc::Message::Error(error) => assert_eq!(RpcErrorCode::QueueFull, error.code),
+            message => panic!("unexpected message: {:?}", message),
+        }
+
+        // 调用结果不受上限限制
+        let resp = RpcResponseCtx::new(3, &ser_ctx, &()).make_response(func, vec![]).unwrap();
+        ctx.accept_tx(&resp);
+        assert_eq!(2, ctx.tx_queue.lock().unwrap().get_mut().len());
+    }
+
+    /// 测试丢弃调用请求时发送取消消息
+    #[tokio::test]
+    async fn test_request_drop() {
+        let ctx = unstarted_ctx();
+
+        // 发送请求后放弃等待
+        let func = abi::FunctionIdent::new("never_return");
+        let ret = tokio::time::timeout(std::time::Duration::from_millis(50),
+                                       ctx.clone().request_api(func, vec![])).await;
+        assert!(ret.is_err());
+
+        // 检查结果，请求与取消消息均已发送
+        let mut rx_queue = ctx.rx_queue.lock().unwrap();
+        let rx_queue = rx_queue.get_mut();
+        assert_eq!(2, rx_queue.len());
+        let (ser_ctx, cancel) = rpc::RpcMessage::decode(&rx_queue[1]).unwrap();
+        assert!(matches!(cancel.message(), rpc::Message::Cancel));
+
+        // 迟到的结果将被丢弃
+        let resp = RpcResponseCtx::new(cancel.seq_no(), &ser_ctx, &())
+            .make_response(cancel.func().clone(), vec![]).unwrap();
+        handle(&ctx, &resp);
+        let mut tx_action = ctx.tx_action.lock().unwrap();
+        assert!(tx_action.get_mut().is_empty());
+    }
+
+    /// 测试卸载模块时，超时仍未返回的请求失败，且不再接受新的请求
+    #[tokio::test]
+    async fn test_shutdown() {
+        let ctx = unstarted_ctx();
+
+        let func = abi::FunctionIdent::new("never_return");
+        let request = tokio::spawn(ctx.clone().request_api(func.clone(), vec![]));
+        tokio::task::yield_now().await;
+        assert_eq!(2, ctx.load());
+
+        // 等待超时，等待中的请求失败
+        assert!(!ctx.shutdown(std::time::Duration::from_millis(50)).await);
+        assert!(matches!(request.await.unwrap(), Err(crate::Error::ShuttingDown)));
+        assert!(!ctx.alive());
+
+        // 新的请求直接失败
+        let ret = ctx.clone().request_api(func, vec![]).await;
+        assert!(matches!(ret, Err(crate::Error::ShuttingDown)));
+    }
+
+    /// 测试来自同一模块多个实例、序列号相同的转发请求，结果能够返回各自的调用方实例
+    #[tokio::test]
+    async fn test_forward_same_seq_no() {
+        let dest = unstarted_ctx();
+        let callers = [unstarted_ctx(), unstarted_ctx()];
+        for caller in &callers {
+            let dest = dest.clone();
+            caller.set_resolve_cb(move |_| Ok(dest.clone()));
+        }
+
+        // 两个调用方实例以相同的序列号发出请求
+        let ser_ctx = SerializeCtx::new();
+        let mut func = abi::FunctionIdent::new("dest_func");
+        func.set_hint(abi::LinkHint::BcModule("dest".to_string()));
+        let req = RpcRequestCtx::new(7, &ser_ctx, &()).make_request(func.clone(), vec![]).unwrap();
+        for caller in &callers {
+            handle(caller, &req);
+        }
+        // 每个请求在队列中等待发送，同时等待返回结果
+        assert_eq!(4, dest.load());
+
+        // 转发后的序列号互不相同
+        let forwarded = [pop_rx(&dest), pop_rx(&dest)];
+        let seq_nos: Vec<_> = forwarded.iter()
+            .map(|msg| rpc::RpcMessage::decode(msg).unwrap().1.seq_no())
+            .collect();
+        assert_ne!(seq_nos[0], seq_nos[1]);
+
+        // 以不同的结果逆序返回
+        for (i, seq_no) in seq_nos.iter().enumerate().rev() {
+            let resp = RpcResponseCtx::new(*seq_no, &ser_ctx, &())
+                .make_response(func.clone(), vec![i as u8]).unwrap();
+            handle(&dest, &resp);
+        }
+        assert_eq!(0, dest.load());
+
+        // 结果换回原始序列号，并返回各自的调用方实例
+        for (i, caller) in callers.iter().enumerate() {
+            let resp = pop_rx(caller);
+            let (_, resp) = rpc::RpcMessage::decode(&resp).unwrap();
+            assert_eq!(7, resp.seq_no());
+            assert_eq!(&[i as u8], resp.data());
+        }
+    }
+
+    /// 测试在使用不同序列化格式的模块之间转发时，参数与返回值被转码为各自协商确定的格式
+    #[tokio::test]
+    async fn test_forward_transcode() {
+        let json = SerializeCtx::with_format(SerializeFormat::Json);
+        let msgpack = SerializeCtx::with_format(SerializeFormat::MessagePack);
+        let dest = Arc::new(AsyncCtx::new());
+        dest.bind_rpc(RpcNode::new(msgpack, 0, dest.clone()));
+        let caller = Arc::new(AsyncCtx::new());
+        caller.bind_rpc(RpcNode::new(json, 0, caller.clone()));
+        let dest_cb = dest.clone();
+        caller.set_resolve_cb(move |_| Ok(dest_cb.clone()));
+
+        // 调用方以 JSON 格式发出请求
+        let mut func = abi::FunctionIdent::new("dest_func");
+        func.set_hint(abi::LinkHint::BcModule("dest".to_string()));
+        let args = ArgsBuilder::new(&json).push(&"hello".to_string()).unwrap().build().unwrap();
+        let req = RpcRequestCtx::new(7, &json, &()).make_request(func.clone(), args).unwrap();
+        handle(&caller, &req);
+
+        // 目标模块收到 MessagePack 格式的请求
+        let forwarded = pop_rx(&dest);
+        let (ser_ctx, msg) = rpc::RpcMessage::decode(&forwarded).unwrap();
+        assert_eq!(SerializeFormat::MessagePack, ser_ctx.format());
+        let args = Args::from_bytes(&msgpack, msg.data()).unwrap();
+        assert_eq!("hello", args.get::<String>(0).unwrap());
+
+        // 目标模块以 MessagePack 格式返回，调用方收到 JSON 格式的结果
+        let result = msgpack.serialize(&"world".to_string()).unwrap();
+        let resp = RpcResponseCtx::new(msg.seq_no(), &msgpack, &())
+            .make_response(func, result).unwrap();
+        handle(&dest, &resp);
+        let resp = pop_rx(&caller);
+        let (ser_ctx, resp) = rpc::RpcMessage::decode(&resp).unwrap();
+        assert_eq!(SerializeFormat::Json, ser_ctx.format());
+        assert_eq!(7, resp.seq_no());
+        assert_eq!("world", json.deserialize::<String>(resp.data()).unwrap());
+    }
+
+    /// 测试带有截止时间的转发调用按时返回结果后，超时任务随之中止
+    #[tokio::test]
+    async fn test_forward_deadline_abort() {
+        let (caller, dest) = forwarding_pair();
+
+        let ser_ctx = SerializeCtx::new();
+        let mut func = abi::FunctionIdent::new("dest_func");
+        func.set_hint(abi::LinkHint::BcModule("dest".to_string()));
+        let deadline = rpc::deadline_after(std::time::Duration::from_secs(60));
+        let req = RpcRequestCtx::new(7, &ser_ctx, &())
+            .make_request_with_deadline(func.clone(), vec![], Some(deadline)).unwrap();
+        handle(&caller, &req);
+
+        // 调用方的转发记录与超时任务各持有目标模块的一个引用
+        let waiting = Arc::strong_count(&dest);
+        let seq_no = rpc::RpcMessage::decode(&pop_rx(&dest)).unwrap().1.seq_no();
+        let resp = RpcResponseCtx::new(seq_no, &ser_ctx, &()).make_response(func, vec![]).unwrap();
+        handle(&dest, &resp);
+
+        // 让运行时回收被中止的任务
+        tokio::task::yield_now().await;
+        assert_eq!(waiting - 2, Arc::strong_count(&dest));
+        assert_eq!(1, caller.rx_queue.lock().unwrap().get_mut().len());
+    }
+
+    /// 测试取消转发的调用后，目标模块回送的取消错误结果转发给调用方，双方都不再留有返回动作
+    #[tokio::test]
+    async fn test_forward_cancel() {
+        let (caller, dest) = forwarding_pair();
+
+        let ser_ctx = SerializeCtx::new();
+        let mut func = abi::FunctionIdent::new("dest_func");
+        func.set_hint(abi::LinkHint::BcModule("dest".to_string()));
+        let req = RpcRequestCtx::new(7, &ser_ctx, &());
+        handle(&caller, &req.make_request(func.clone(), vec![]).unwrap());
+        let forward_seq_no = rpc::RpcMessage::decode(&pop_rx(&dest)).unwrap().1.seq_no();
+
+        // 调用方取消调用，目标模块收到取消消息
+        handle(&caller, &req.make_cancel(func.clone()).unwrap());
+        let (_, cancel) = rpc::RpcMessage::decode(&pop_rx(&dest)).unwrap();
+        assert_eq!(forward_seq_no, cancel.seq_no());
+
+        // 目标模块回送取消的错误结果，调用方以原始序列号收到
+        let error = RpcError::new(RpcErrorCode::Cancelled, "cancelled");
+        let resp = RpcResponseCtx::new(forward_seq_no, &ser_ctx, &()).make_error(func, error).unwrap();
+        handle(&dest, &resp);
+        let (_, resp) = rpc::RpcMessage::decode(&pop_rx(&caller)).unwrap();
+        assert_eq!(7, resp.seq_no());
+        assert!(matches!(resp.message(), rpc::Message::Error(error) if error.code == RpcErrorCode::Cancelled));
+
+        assert!(dest.tx_action.lock().unwrap().get_mut().is_empty());
+        assert!(caller.forwarded.lock().unwrap().get_mut().is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_forward_mutual() {
+        // 两个模块同时互相转发，处理各自的报文时不锁定对方的 RpcNode
+        let (a, b) = (unstarted_ctx(), unstarted_ctx());
+        let b_weak = Arc::downgrade(&b);
+        a.set_resolve_cb(move |_| Ok(b_weak.upgrade().unwrap()));
+        let a_weak = Arc::downgrade(&a);
+        b.set_resolve_cb(move |_| Ok(a_weak.upgrade().unwrap()));
+        tokio::spawn(HandleTxFuture::new(a.clone()));
+        tokio::spawn(HandleTxFuture::new(b.clone()));
+
+        let ser_ctx = SerializeCtx::new();
+        let mut func = abi::FunctionIdent::new("peer_func");
+        func.set_hint(abi::LinkHint::BcModule("peer".to_string()));
+        const REQUESTS: u64 = 100;
+        for seq_no in 0..REQUESTS {
+            let req = RpcRequestCtx::new(seq_no, &ser_ctx, &()).make_request(func.clone(), vec![]).unwrap();
+            a.push_tx(req.clone());
+            b.push_tx(req);
+        }
+
+        // 双方的请求都被转发至对方
+        for ctx in [&a, &b] {
+            for _ in 0..REQUESTS {
+                let (_, req) = rpc::RpcMessage::decode(&wait_rx(ctx).await).unwrap();
+                assert!(matches!(req.message(), rpc::Message::Request));
+            }
+        }
+
+        a.kill();
+        b.kill();
+    }
+
+    #[tokio::test]
+    async fn test_forward_self() {
+        // 模块经由链接提示调用自身的导出函数
+        let ctx = unstarted_ctx();
+        let ctx_weak = Arc::downgrade(&ctx);
+        ctx.set_resolve_cb(move |_| Ok(ctx_weak.upgrade().unwrap()));
+        tokio::spawn(HandleTxFuture::new(ctx.clone()));
+
+        let ser_ctx = SerializeCtx::new();
+        let mut func = abi::FunctionIdent::new("self_func");
+        func.set_hint(abi::LinkHint::BcModule("self".to_string()));
+        let req = RpcRequestCtx::new(7, &ser_ctx, &()).make_request(func.clone(), vec![]).unwrap();
+        ctx.push_tx(req);
+        let (_, forwarded) = rpc::RpcMessage::decode(&wait_rx(&ctx).await).unwrap();
+        assert!(matches!(forwarded.message(), rpc::Message::Request));
+        assert_ne!(7, forwarded.seq_no());
+
+        // 以转发的序列号返回结果，模块以原始序列号收到
+        let resp = RpcResponseCtx::new(forwarded.seq_no(), &ser_ctx, &())
+            .make_response(func, vec![1]).unwrap();
+        ctx.push_tx(resp);
+        let (_, resp) = rpc::RpcMessage::decode(&wait_rx(&ctx).await).unwrap();
+        assert_eq!(7, resp.seq_no());
+        assert_eq!(&[1u8], resp.data());
+
+        ctx.kill();
+    }
+
+    /// 测试其他模块不能经由转发请求模块导出状态
+    #[tokio::test]
+    async fn test_forward_export_state() {
+        let (caller, dest) = forwarding_pair();
+
+        // 请求目标模块的保留导出函数
+        let ser_ctx = SerializeCtx::new();
+        let mut func = abi::FunctionIdent::new(abi::EXPORT_STATE);
+        func.set_hint(abi::LinkHint::BcModule("dest".to_string()));
+        let req = RpcRequestCtx::new(7, &ser_ctx, &()).make_request(func, vec![]).unwrap();
+        handle(&caller, &req);
+
+        // 请求没有被转发，调用方收到拒绝的错误结果
+        assert_eq!(0, dest.load());
+        let (_, resp) = rpc::RpcMessage::decode(&pop_rx(&caller)).unwrap();
+        match resp.message() {
+            rpc::Message::Error(error) => assert_eq!(rpc::RpcErrorCode::PermissionDenied, error.code),
+            message => panic!("unexpected message: {:?}", message),
+        }
+    }
+
     /// 测试 `HandleTxFuture`
     #[tokio::test]
     async fn test_async_call() {
@@ -1,21 +1,25 @@
 #![cfg(not(target_arch = "wasm32"))]
 
+pub use error::*;
+
 pub mod ctx;
 pub mod future;
-
-// FIXME: 此处的错误类型仅仅是最简单，可用于容纳任何错误的类型。而实际上好的错误类型
-//        应该囊括更加细节的错误信息。此处仅为适应短时间的开发需求而临时设计。
-pub type Error = Box<dyn std::error::Error + Send + Sync>;
-
-pub type Result<T> = std::result::Result<T, Error>;
+mod error;
 
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+
     use wasmtime::*;
     use wasmtime_wasi::sync::WasiCtxBuilder;
     use wasmtime_wasi::WasiCtx;
 
+    use rpc::RpcNode;
+    use serialize::SerializeCtx;
+
+    use crate::ctx::AsyncCtx;
+
     pub struct Context<T> {
         pub store: Store<T>,
         pub module: Module,
@@ -41,5 +45,44 @@ mod tests {
 
         Context { store, module, linker }
     }
-}
 
+    /// 绑定了 RpcNode 的异步上下文。不启动异步任务，因此请求不会有结果，消息也不会被取走
+    pub fn unstarted_ctx() -> Arc<AsyncCtx> {
+        let ctx = Arc::new(AsyncCtx::new());
+        ctx.bind_rpc(RpcNode::new(SerializeCtx::new(), 0, ctx.clone()));
+        ctx
+    }
+
+    /// 未启动的调用方与目标模块，调用方的请求总是被转发至目标模块
+    pub fn forwarding_pair() -> (Arc<AsyncCtx>, Arc<AsyncCtx>) {
+        let caller = unstarted_ctx();
+        let dest = unstarted_ctx();
+        let dest_cb = dest.clone();
+        caller.set_resolve_cb(move |_| Ok(dest_cb.clone()));
+        (caller, dest)
+    }
+
+    /// 以 `ctx` 的 RpcNode 处理模块发出的消息
+    pub fn handle(ctx: &Arc<AsyncCtx>, msg: &[u8]) {
+        ctx.handle_message(msg).unwrap();
+    }
+
+    /// 取出等待发送至模块的第一条消息
+    pub fn pop_rx(ctx: &Arc<AsyncCtx>) -> Vec<u8> {
+        let mut rx_queue = ctx.rx_queue.lock().unwrap();
+        rx_queue.get_mut().pop_front().unwrap()
+    }
+
+    /// 等待异步任务向模块发送消息，取出第一条
+    pub async fn wait_rx(ctx: &Arc<AsyncCtx>) -> Vec<u8> {
+        let wait = async {
+            loop {
+                if let Some(msg) = ctx.rx_queue.lock().unwrap().get_mut().pop_front() {
+                    return msg;
+                }
+                tokio::task::yield_now().await;
+            }
+        };
+        tokio::time::timeout(std::time::Duration::from_secs(5), wait).await.unwrap()
+    }
+}
@@ -0,0 +1,66 @@
+//! WASM 内异步运行时的错误类型
+
+use std::fmt;
+
+use rpc::{RpcError, RpcErrorCode, RpcSeqNo};
+
+/// 异步运行时中可能发生的错误
+#[derive(Debug)]
+pub enum Error {
+    /// RPC 通信失败，或对端回送了错误结果
+    Rpc(rpc::Error),
+    /// 参数或返回值的序列化失败
+    Serialize(serialize::Error),
+    /// 调用请求超过截止时间仍未返回
+    Timeout(RpcSeqNo),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Rpc(e) => write!(f, "{}", e),
+            Error::Serialize(e) => write!(f, "{}", e),
+            Error::Timeout(seq_no) => write!(f, "seq_no {}: deadline exceeded", seq_no),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Rpc(e) => Some(e),
+            Error::Serialize(e) => Some(e),
+            Error::Timeout(_) => None,
+        }
+    }
+}
+
+impl From<rpc::Error> for Error {
+    fn from(e: rpc::Error) -> Self {
+        Error::Rpc(e)
+    }
+}
+
+impl From<RpcError> for Error {
+    fn from(e: RpcError) -> Self {
+        Error::Rpc(e.into())
+    }
+}
+
+impl From<serialize::Error> for Error {
+    fn from(e: serialize::Error) -> Self {
+        Error::Serialize(e)
+    }
+}
+
+impl From<Error> for rpc::Error {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Rpc(e) => e,
+            Error::Serialize(e) => e.into(),
+            e @ Error::Timeout(_) => RpcError::new(RpcErrorCode::DeadlineExceeded, e.to_string()).into(),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
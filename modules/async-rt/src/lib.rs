@@ -2,16 +2,13 @@ extern crate core;
 
 use std::future::Future;
 
+pub use error::*;
+
+mod error;
 mod queue;
 mod task;
 pub mod rt;
 
-// FIXME: 此处的错误类型仅仅是最简单，可用于容纳任何错误的类型。而实际上好的错误类型
-//        应该囊括更加细节的错误信息。此处仅为适应短时间的开发需求而临时设计。
-pub type Error = Box<dyn std::error::Error + Send + Sync>;
-
-pub type Result<T> = std::result::Result<T, Error>;
-
 /// 将异步任务送入本地队列执行
 #[inline]
 pub fn spawn_local<F>(future: F)
@@ -23,11 +20,13 @@ pub fn spawn_local<F>(future: F)
 
 #[cfg(test)]
 mod tests {
-    use std::cell::Cell;
+    use std::cell::{Cell, RefCell};
     use std::rc::Rc;
 
     use crate::queue::QUEUE;
+    use crate::rt::{WasmAsyncRequestFuture, WasmReturnAction, CTX};
     use crate::spawn_local;
+    use crate::task::Task;
 
     #[test]
     fn test_future() {
@@ -46,4 +45,61 @@ mod tests {
 
         assert_eq!(cnt.get(), 1);
     }
+
+    #[test]
+    fn test_abort() {
+        let cnt = Rc::new(Cell::new(0));
+
+        let ccnt = cnt.clone();
+        let task = Task::spawn(Box::pin(async move {
+            ccnt.set(ccnt.get() + 1);
+        }));
+
+        // 中止后任务不再运行，其中的 Future 被立即丢弃
+        task.abort();
+        assert_eq!(Rc::strong_count(&cnt), 1);
+
+        QUEUE.with(|queue| {
+            queue.run_all();
+        });
+
+        assert_eq!(cnt.get(), 0);
+    }
+
+    #[test]
+    fn test_abort_while_running() {
+        let cnt = Rc::new(Cell::new(0));
+        let slot: Rc<RefCell<Option<Rc<Task>>>> = Rc::new(RefCell::new(None));
+
+        let ccnt = cnt.clone();
+        let cslot = slot.clone();
+        let task = Task::spawn(Box::pin(async move {
+            // 在运行中中止自身，Future 在本次 poll 结束后被丢弃
+            cslot.borrow_mut().take().unwrap().abort();
+            ccnt.set(ccnt.get() + 1);
+            std::future::pending::<()>().await;
+        }));
+        slot.borrow_mut().replace(task);
+
+        QUEUE.with(|queue| {
+            queue.run_all();
+        });
+
+        assert_eq!(cnt.get(), 1);
+        assert_eq!(Rc::strong_count(&cnt), 1);
+    }
+
+    #[test]
+    fn test_drop_unpolled_response() {
+        // 结果已经到达但请求未被再次 poll 即被丢弃，结果随之移除，且无需通知对端
+        let request = WasmAsyncRequestFuture::new(7, vec![], vec![], None);
+        CTX.with(|rt_ctx| {
+            rt_ctx.return_actions.borrow_mut().insert(7, WasmReturnAction::Response(Ok(vec![1])));
+        });
+        drop(request);
+
+        CTX.with(|rt_ctx| {
+            assert!(rt_ctx.return_actions.borrow().is_empty());
+        });
+    }
 }
@@ -2,22 +2,31 @@ use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::future::Future;
 use std::pin::Pin;
+use std::rc::Rc;
 use std::task::{Context, Poll, Waker};
+use std::time::Duration;
 
 use low_level::set_message_callback;
-use rpc::{abi, RpcEndCtx, RpcNode, RpcSeqNo};
+use rpc::{abi, RpcCallResult, RpcDeadline, RpcEndCtx, RpcError, RpcErrorCode, RpcExports, RpcNode, RpcResponseCtx, RpcSeqNo};
 use rpc::adapter::{SendMessageAdapter, WasmSendMessageAdapter};
+use serialize::SerializeCtx;
+
+use crate::task::Task;
 
 /// WASM 内部的运行时上下文
 pub struct WasmRtCtx {
     pub rpc_ctx: RefCell<Option<RpcNode<WasmSendMessageAdapter>>>,
     pub return_actions: RefCell<HashMap<RpcSeqNo, WasmReturnAction>>,
+    /// 正在运行的导出函数异步任务，用于响应取消消息
+    export_tasks: RefCell<HashMap<RpcSeqNo, Rc<Task>>>,
 }
 
 /// 返回动作
 pub enum WasmReturnAction {
     Wake(Waker),
-    Response(Vec<u8>),
+    Response(RpcCallResult),
+    /// 请求已被取消，在截止时间前丢弃之后到达的结果
+    Discard(RpcDeadline),
 }
 
 impl WasmRtCtx {
@@ -25,6 +34,7 @@ impl WasmRtCtx {
         WasmRtCtx {
             rpc_ctx: RefCell::new(None),
             return_actions: RefCell::new(HashMap::new()),
+            export_tasks: RefCell::new(HashMap::new()),
         }
     }
 }
@@ -47,7 +57,7 @@ fn host_message_handler(msg: &[u8]) {
 set_message_callback!(host_message_handler);
 
 /// WASM 侧的返回消息回调
-pub fn result_message_cb(ctx: &RpcEndCtx<WasmSendMessageAdapter>, res: Vec<u8>) -> rpc::Result<()> {
+pub fn result_message_cb(ctx: &RpcEndCtx<WasmSendMessageAdapter>, res: RpcCallResult) -> rpc::Result<()> {
     CTX.with(|rt_ctx| {
         // 唤醒调用结果的等待者
         let mut return_actions = rt_ctx.return_actions.borrow_mut();
@@ -59,6 +69,9 @@ pub fn result_message_cb(ctx: &RpcEndCtx<WasmSendMessageAdapter>, res: Vec<u8>)
                 // 唤醒 Future
                 waker.wake();
             }
+            Some(WasmReturnAction::Discard(_)) | None => {
+                // 请求已被取消，丢弃迟到的结果
+            }
             _ => {
                 panic!("未知的返回动作");
             }
@@ -67,10 +80,112 @@ pub fn result_message_cb(ctx: &RpcEndCtx<WasmSendMessageAdapter>, res: Vec<u8>)
     Ok(())
 }
 
+/// WASM 侧的取消消息回调，中止对应的导出函数任务并回送取消的错误结果
+pub fn cancel_message_cb(ctx: &RpcEndCtx<WasmSendMessageAdapter>, func: abi::FunctionIdent, _raw_msg: &[u8]) -> rpc::Result<()> {
+    let task = CTX.with(|rt_ctx| {
+        rt_ctx.export_tasks.borrow_mut().remove(&ctx.seq_no())
+    });
+
+    // 任务已经结束时，其结果已经回送，无需处理
+    if let Some(task) = task {
+        // 中止任务。任务中尚未返回的请求将在析构时继续向下游发送取消消息
+        task.abort();
+
+        let error = RpcError::new(RpcErrorCode::Cancelled, format!("{:?} cancelled", func));
+        let msg = RpcResponseCtx::new(ctx.seq_no(), ctx.serialize_ctx(), &())
+            .make_error(func, error)?;
+        ctx.data().send_message(&msg)?;
+    }
+    Ok(())
+}
+
+/// 启动导出函数的异步任务，任务可以被调用方的取消消息中止
+///
+/// 任务结束后回送其产生的返回报文；若任务已被取消或未能生成报文，则不再回送。
+pub fn spawn_export<F>(seq_no: RpcSeqNo, future: F)
+    where
+        F: Future<Output=rpc::Result<Vec<u8>>> + 'static,
+{
+    let task = Task::spawn(Box::pin(async move {
+        let msg = future.await;
+
+        let running = CTX.with(|rt_ctx| {
+            rt_ctx.export_tasks.borrow_mut().remove(&seq_no).is_some()
+        });
+        match msg {
+            Ok(msg) if running => WasmSendMessageAdapter::new().send_message(&msg).unwrap(),
+            Ok(_) => {}
+            Err(e) => eprintln!("[WasmRtCtx]: export error: {:?}, discard!", e),
+        }
+    }));
+
+    // 任务在下一次 poll 时才会运行，因此此处登记总是先于任务结束
+    CTX.with(|rt_ctx| {
+        rt_ctx.export_tasks.borrow_mut().insert(seq_no, task);
+    });
+}
+
+/// 热重载时迁移模块状态的钩子
+#[derive(Clone, Copy)]
+pub struct StateHooks {
+    /// 导出当前状态，Host 重载模块前通过保留的导出函数 `abi::EXPORT_STATE` 调用
+    pub export: fn() -> Vec<u8>,
+    /// 导入旧版本模块导出的状态，在 `__bc_main` 中调用，此时尚未处理任何请求
+    pub import: fn(Vec<u8>),
+}
+
+/// 注册保留的状态导出函数，供 `bc_wasm_module!` 生成的入口使用
+#[doc(hidden)]
+pub fn register_state_export(exports: &mut RpcExports<WasmSendMessageAdapter>, hooks: StateHooks) {
+    let mut func = abi::FunctionIdent::new(abi::EXPORT_STATE);
+    func.set_hint(exports.hint().clone());
+    exports.add_exports(func.clone(), move |resp, _args| {
+        let ctx = RpcResponseCtx::new(resp.seq_no(), resp.serialize_ctx(), &());
+
+        // 只有 Host 可以请求导出状态，拒绝其他模块转发的请求
+        let msg = if abi::is_forwarded(resp.seq_no()) {
+            let error = RpcError::new(RpcErrorCode::PermissionDenied,
+                                      format!("{:?} is reserved for the host", func));
+            ctx.make_error(func.clone(), error)?
+        } else {
+            let state = resp.serialize_ctx().serialize(&(hooks.export)())?;
+            ctx.make_response(func.clone(), state)?
+        };
+        resp.data().send_message(&msg)
+    });
+}
+
+/// 导入 Host 提供的初始状态，供 `bc_wasm_module!` 生成的入口使用
+#[doc(hidden)]
+pub fn import_state(hooks: StateHooks) {
+    if let Some(state) = low_level::wasm::take_initial_state() {
+        (hooks.import)(state);
+    }
+}
+
 /// 产生模块入口
+///
+/// 可选的第三个参数为模块偏好的序列化格式（`SerializeFormat`），实际使用的格式在握手时与 Host 协商。
+/// 之后可以 `key = value` 的形式提供以下选项：
+///
+/// - `format = ...`：同第三个参数
+/// - `state = (export, import)`：热重载时迁移模块状态的 `StateHooks`
+/// - `version = "..."`：模块清单中的版本，默认为所在 crate 的版本
+/// - `imports = ["name" => "^1.0", ...]`：模块清单中所依赖的模块及其版本要求
+/// - `import_table = __bc_module_import`：`bc_import_module!` 生成的导入表，声明后 Host 拒绝模块调用
+///   未声明的函数
+///
+/// 模块清单中的导出函数由导出函数表生成，并在握手时发送至 Host。
+///
+/// ```ignore
+/// bc_wasm_module!("counter", __bc_module_export, state = (export_state, import_state));
+/// bc_wasm_module!("dispatch", __bc_module_export, imports = ["service" => "^1.0"],
+///                 import_table = __bc_module_import);
+/// ```
 #[macro_export]
 macro_rules! bc_wasm_module {
-    ($name:expr, $export_cb:ident) => {
+    (@main $name:expr, $export_cb:ident, $format:expr, $state_hooks:expr, $version:expr,
+     [$($dep:expr => $req:expr),*], $import_table:expr) => {
         #[no_mangle]
         #[cfg(target_arch = "wasm32")]
         pub extern "C" fn __bc_main() {
@@ -79,23 +194,39 @@ macro_rules! bc_wasm_module {
             use std::collections::hash_map::DefaultHasher;
             use bc_hostcall::rpc::RpcNode;
             use bc_hostcall::rpc::adapter::{WasmSendMessageAdapter, SendMessageAdapter};
+            use bc_hostcall::rpc::manifest::ModuleManifest;
             use bc_hostcall::serialize::SerializeCtx;
 
             // 初始化内部上下文
             let mut hasher = DefaultHasher::new();
             $name.hash(&mut hasher);
-            let mut rpc_ctx = RpcNode::new(SerializeCtx::new(),
+            let mut rpc_ctx = RpcNode::new(SerializeCtx::with_format($format),
                                       hasher.finish() as u32,
                                       WasmSendMessageAdapter::new());
+            // 生成模块清单
+            let mut exports = $export_cb();
+            let mut manifest = ModuleManifest::new($name, $version.parse().expect("invalid module version"));
+            manifest.exports = exports.names();
+            $(
+                manifest.add_import($dep, $req.parse().expect("invalid version requirement"));
+            )*
+            rpc_ctx.set_manifest(manifest);
+            // 声明导入表
+            let import_table: Option<bc_hostcall::rpc::RpcImports> = $import_table;
+            if let Some(import_table) = import_table {
+                rpc_ctx.set_imports(import_table);
+            }
             // 注册导出模块
-            let exports = $export_cb();
+            let state_hooks: Option<bc_hostcall::async_rt::rt::StateHooks> = $state_hooks;
+            if let Some(state_hooks) = state_hooks {
+                bc_hostcall::async_rt::rt::register_state_export(&mut exports, state_hooks);
+            }
             rpc_ctx.set_exports(exports);
-            // 设置回调
-            rpc_ctx.set_forward_cb(|_, _, _| {
-                panic!("模块未导出此函数！");
-            });
+            // 设置回调。模块内不进行转发，未导出的函数将回送错误结果
             rpc_ctx.set_result_cb(bc_hostcall::async_rt::rt::result_message_cb);
-            // 发送模块名称
+            rpc_ctx.set_reply_cb(|ctx, msg| ctx.data().send_message(&msg));
+            rpc_ctx.set_cancel_cb(bc_hostcall::async_rt::rt::cancel_message_cb);
+            // 发送模块名称、支持的序列化格式、模块清单及导入表
             let msg = rpc_ctx.make_peer_info($name.to_string());
             let adapter = WasmSendMessageAdapter::new();
             adapter.send_message(&msg).unwrap();
@@ -103,30 +234,123 @@ macro_rules! bc_wasm_module {
             bc_hostcall::async_rt::rt::CTX.with(|ctx| {
                 ctx.rpc_ctx.replace(Some(rpc_ctx));
             });
+            // 导入旧版本模块迁移来的状态
+            if let Some(state_hooks) = state_hooks {
+                bc_hostcall::async_rt::rt::import_state(state_hooks);
+            }
             // 把控制权交回 Host
         }
     };
+    // 依次解析 `key = value` 形式的选项
+    (@opts [$($args:tt)*]) => {
+        $crate::bc_wasm_module!(@main $($args)*);
+    };
+    (@opts [$name:expr, $export_cb:ident, $format:expr, $state_hooks:expr, $version:expr, $imports:tt, $import_table:expr]
+     format = $new_format:expr $(, $($rest:tt)*)?) => {
+        $crate::bc_wasm_module!(@opts [$name, $export_cb, $new_format, $state_hooks, $version, $imports, $import_table]
+                                $($($rest)*)?);
+    };
+    (@opts [$name:expr, $export_cb:ident, $format:expr, $state_hooks:expr, $version:expr, $imports:tt, $import_table:expr]
+     state = ($export_state:path, $import_state:path) $(, $($rest:tt)*)?) => {
+        $crate::bc_wasm_module!(@opts [$name, $export_cb, $format,
+                                       Some(bc_hostcall::async_rt::rt::StateHooks {
+                                           export: $export_state,
+                                           import: $import_state,
+                                       }),
+                                       $version, $imports, $import_table]
+                                $($($rest)*)?);
+    };
+    (@opts [$name:expr, $export_cb:ident, $format:expr, $state_hooks:expr, $version:expr, $imports:tt, $import_table:expr]
+     version = $new_version:expr $(, $($rest:tt)*)?) => {
+        $crate::bc_wasm_module!(@opts [$name, $export_cb, $format, $state_hooks, $new_version, $imports, $import_table]
+                                $($($rest)*)?);
+    };
+    (@opts [$name:expr, $export_cb:ident, $format:expr, $state_hooks:expr, $version:expr, $imports:tt, $import_table:expr]
+     import_table = $import_cb:path $(, $($rest:tt)*)?) => {
+        $crate::bc_wasm_module!(@opts [$name, $export_cb, $format, $state_hooks, $version, $imports, Some($import_cb())]
+                                $($($rest)*)?);
+    };
+    (@opts [$name:expr, $export_cb:ident, $format:expr, $state_hooks:expr, $version:expr, $imports:tt, $import_table:expr]
+     imports = [$($dep:expr => $req:expr),* $(,)?] $(, $($rest:tt)*)?) => {
+        $crate::bc_wasm_module!(@opts [$name, $export_cb, $format, $state_hooks, $version, [$($dep => $req),*],
+                                       $import_table]
+                                $($($rest)*)?);
+    };
+    ($name:expr, $export_cb:ident $(,)?) => {
+        $crate::bc_wasm_module!(@opts [$name, $export_cb, bc_hostcall::serialize::SerializeFormat::default(),
+                                       None, env!("CARGO_PKG_VERSION"), [], None]);
+    };
+    ($name:expr, $export_cb:ident, $key:ident = $($opts:tt)+) => {
+        $crate::bc_wasm_module!(@opts [$name, $export_cb, bc_hostcall::serialize::SerializeFormat::default(),
+                                       None, env!("CARGO_PKG_VERSION"), [], None]
+                                $key = $($opts)+);
+    };
+    ($name:expr, $export_cb:ident, $format:expr $(, $($opts:tt)*)?) => {
+        $crate::bc_wasm_module!(@opts [$name, $export_cb, $format, None, env!("CARGO_PKG_VERSION"), [], None]
+                                $($($opts)*)?);
+    };
 }
 
 #[no_mangle]
 #[cfg(target_arch = "wasm32")]
 pub extern "C" fn __bc_low_level_wasm_poll() {
     use crate::queue::QUEUE;
-    QUEUE.with(|queue| {
-        queue.run_all();
-    });
+    // 任务发出的消息在本次 poll 结束后一并发送至 Host
+    low_level::wasm::batch_messages_to_host(|| {
+        QUEUE.with(|queue| {
+            queue.run_all();
+        });
+    }).unwrap();
+}
+
+/// 获得当前模块的链接提示，供生成的导出函数包装使用
+#[doc(hidden)]
+pub fn module_hint() -> abi::LinkHint {
+    CTX.with(|rt_ctx| {
+        let rpc_ctx = rt_ctx.rpc_ctx.borrow();
+        rpc_ctx.as_ref()
+            .and_then(|rpc_ctx| rpc_ctx.hint().cloned())
+            .unwrap_or(abi::LinkHint::Host)
+    })
+}
+
+/// 获得当前用于发送报文的序列化上下文，供生成的导入函数包装使用
+#[doc(hidden)]
+pub fn serialize_ctx() -> SerializeCtx {
+    CTX.with(|rt_ctx| {
+        let rpc_ctx = rt_ctx.rpc_ctx.borrow();
+        rpc_ctx.as_ref()
+            .map(|rpc_ctx| rpc_ctx.serialize_ctx())
+            .unwrap_or_default()
+    })
 }
 
 /// 创建异步 API 请求
 pub fn request_api(func: abi::FunctionIdent, args: Vec<u8>) -> WasmAsyncRequestFuture {
+    request_api_with_deadline(func, args, None)
+}
+
+/// 创建带有超时时间的异步 API 请求
+pub fn request_api_timeout(func: abi::FunctionIdent, args: Vec<u8>, timeout: Duration) -> WasmAsyncRequestFuture {
+    request_api_with_deadline(func, args, Some(rpc::deadline_after(timeout)))
+}
+
+/// 创建带有截止时间的异步 API 请求
+///
+/// 截止时间随请求报文传递，转发请求的 Host 会在超时后回送超时的错误结果以唤醒等待者。
+pub fn request_api_with_deadline(func: abi::FunctionIdent,
+                                 args: Vec<u8>,
+                                 deadline: Option<RpcDeadline>,
+) -> WasmAsyncRequestFuture {
     CTX.with(|rt_ctx| {
         let req = rt_ctx.rpc_ctx.borrow();
         let req = req.as_ref().unwrap().request();
 
         // 序列化
-        let msg = req.make_request(func, args).unwrap();
+        let cancel_msg = req.make_cancel(func.clone()).unwrap();
+        let msg = req.make_request_with_deadline(func, args, deadline).unwrap();
 
-        WasmAsyncRequestFuture::new(req.seq_no(), msg)
+        WasmAsyncRequestFuture::new(req.seq_no(), msg, cancel_msg, deadline)
     })
 }
 
@@ -134,25 +358,71 @@ pub fn request_api(func: abi::FunctionIdent, args: Vec<u8>) -> WasmAsyncRequestF
 pub struct WasmAsyncRequestFuture {
     seq_no: RpcSeqNo,
     msg: Cell<Vec<u8>>,
+    /// 取消请求的报文，请求结束后置空
+    cancel_msg: Cell<Option<Vec<u8>>>,
+    deadline: Option<RpcDeadline>,
 }
 
 impl WasmAsyncRequestFuture {
-    pub fn new(seq_no: RpcSeqNo, msg: Vec<u8>) -> Self {
+    pub fn new(seq_no: RpcSeqNo, msg: Vec<u8>, cancel_msg: Vec<u8>, deadline: Option<RpcDeadline>) -> Self {
         WasmAsyncRequestFuture {
             seq_no,
             msg: Cell::new(msg),
+            cancel_msg: Cell::new(Some(cancel_msg)),
+            deadline,
+        }
+    }
+
+    /// 取消请求：移除其返回动作，仍在等待结果时以丢弃动作替换，并通知对端中止调用
+    fn cancel(&self, return_actions: &mut HashMap<RpcSeqNo, WasmReturnAction>) {
+        // 总是移除本请求的返回动作，已经到达但未被取走的结果随之丢弃
+        let action = return_actions.remove(&self.seq_no);
+        let cancel_msg = match self.cancel_msg.take() {
+            Some(cancel_msg) => cancel_msg,
+            None => return,
+        };
+
+        // 对端通常会回送取消的错误结果，此前到达的结果均被丢弃。超时仍未到达时不再等待
+        return_actions.retain(|_, action| !matches!(action, WasmReturnAction::Discard(until) if rpc::expired(*until)));
+
+        // 请求尚未发送或结果已经到达时，无需通知对端
+        if let Some(WasmReturnAction::Wake(_)) = action {
+            return_actions.insert(self.seq_no, WasmReturnAction::Discard(rpc::deadline_after(rpc::DISCARD_TIMEOUT)));
+            if let Err(e) = WasmSendMessageAdapter::new().send_message(&cancel_msg) {
+                eprintln!("[WasmAsyncRequestFuture]: send cancel error: {:?}", e);
+            }
         }
     }
 }
 
+impl Drop for WasmAsyncRequestFuture {
+    fn drop(&mut self) {
+        // 线程局部变量可能已经析构，此时无需处理
+        let _ = CTX.try_with(|rt_ctx| {
+            let mut return_actions = rt_ctx.return_actions.borrow_mut();
+            self.cancel(&mut return_actions);
+        });
+    }
+}
+
 impl Future for WasmAsyncRequestFuture {
     type Output = crate::Result<Vec<u8>>;
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         // 检查是否有结果
         CTX.with(|rt_ctx| {
             let mut return_actions = rt_ctx.return_actions.borrow_mut();
-            let action = return_actions.remove(&self.seq_no);
 
+            // 超过截止时间，取消请求，迟到的结果将被丢弃
+            if self.deadline.map_or(false, rpc::expired) {
+                if let Some(WasmReturnAction::Response(_)) = return_actions.get(&self.seq_no) {
+                    // 结果已经到达，正常返回
+                } else {
+                    self.cancel(&mut return_actions);
+                    return Poll::Ready(Err(crate::Error::Timeout(self.seq_no)));
+                }
+            }
+
+            let action = return_actions.remove(&self.seq_no);
             match action {
                 None => {
                     // 保存 Waker
@@ -167,9 +437,10 @@ impl Future for WasmAsyncRequestFuture {
                     req.data().send_message(&msg)?;
                     Poll::Pending
                 }
-                Some(WasmReturnAction::Response(msg)) => {
-                    // 获取结果
-                    Poll::Ready(Ok(msg))
+                Some(WasmReturnAction::Response(result)) => {
+                    // 获取结果，请求已经结束，无需再取消
+                    self.cancel_msg.take();
+                    Poll::Ready(result.map_err(|e| e.into()))
                 }
                 Some(action) => {
                     // 不支持的结果类型，放回
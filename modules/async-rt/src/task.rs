@@ -21,20 +21,40 @@ pub(crate) struct Task {
 
     // This is used to ensure that the Task will only be queued once
     is_queued: Cell<bool>,
+
+    // 任务已被中止。任务运行时被中止，则在本次 poll 结束后丢弃其中的 Future
+    aborted: Cell<bool>,
 }
 
 impl Task {
-    pub(crate) fn spawn(future: Pin<Box<dyn Future<Output=()> + 'static>>) {
+    pub(crate) fn spawn(future: Pin<Box<dyn Future<Output=()> + 'static>>) -> Rc<Self> {
         let this = Rc::new(Self {
             inner: RefCell::new(None),
             is_queued: Cell::new(true),
+            aborted: Cell::new(false),
         });
 
         let waker = unsafe { Waker::from_raw(Task::into_raw_waker(Rc::clone(&this))) };
 
         *this.inner.borrow_mut() = Some(Inner { future, waker });
 
-        crate::queue::QUEUE.with(|queue| queue.schedule_task(this));
+        crate::queue::QUEUE.with(|queue| queue.schedule_task(Rc::clone(&this)));
+
+        this
+    }
+
+    /// 中止任务，丢弃其中的 Future。之后的唤醒将被忽略。
+    ///
+    /// 任务正在运行时（例如 poll 中处理到取消消息），Future 在本次 poll 结束后才被丢弃。
+    pub(crate) fn abort(&self) {
+        self.aborted.set(true);
+
+        // 先取出再丢弃，以免 Future 在析构时唤醒自身而重复借用
+        let inner = match self.inner.try_borrow_mut() {
+            Ok(mut inner) => inner.take(),
+            Err(_) => return,
+        };
+        drop(inner);
     }
 
     fn wake_by_ref(this: &Rc<Self>) {
@@ -108,8 +128,12 @@ impl Task {
         // actually go away until all wakers referencing us go away, which may
         // take quite some time, so ensure that the heaviest of resources are
         // released early.
-        if let Poll::Ready(_) = poll {
-            *borrow = None;
+        //
+        // 在 poll 期间被中止的任务同样在此丢弃。
+        if poll.is_ready() || self.aborted.get() {
+            let inner = borrow.take();
+            drop(borrow);
+            drop(inner);
         }
     }
 }
@@ -0,0 +1,58 @@
+//! 批量消息的编解码，用于在一次函数调用中传递多条消息
+//!
+//! 批量消息由若干条 `消息长度 (u32) | 消息内容` 依次拼接而成，整数均为小端序。
+
+use crate::{Error, Result};
+
+/// 消息长度字段的长度
+const LEN_SIZE: usize = std::mem::size_of::<u32>();
+
+/// 把多条消息编码为一条批量消息
+pub fn encode<'a>(messages: impl IntoIterator<Item=&'a [u8]>) -> Vec<u8> {
+    let mut batch = Vec::new();
+    for msg in messages {
+        batch.extend_from_slice(&(msg.len() as u32).to_le_bytes());
+        batch.extend_from_slice(msg);
+    }
+    batch
+}
+
+/// 把批量消息解码为各条消息，消息直接借用批量消息中的数据
+pub fn decode(mut batch: &[u8]) -> Result<Vec<&[u8]>> {
+    let mut messages = Vec::new();
+    while !batch.is_empty() {
+        if batch.len() < LEN_SIZE {
+            return Err(Error::CorruptedBatch);
+        }
+        let (len, rest) = batch.split_at(LEN_SIZE);
+        let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+        if rest.len() < len {
+            return Err(Error::CorruptedBatch);
+        }
+        let (msg, rest) = rest.split_at(len);
+        messages.push(msg);
+        batch = rest;
+    }
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let messages: [&[u8]; 3] = [b"hello", b"", b"world"];
+        let batch = encode(messages);
+        assert_eq!(4 * 3 + 10, batch.len());
+        assert_eq!(messages.to_vec(), decode(&batch).unwrap());
+        assert!(decode(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_corrupted() {
+        let batch = encode([b"hello".as_slice()]);
+        assert!(matches!(decode(&batch[..2]), Err(Error::CorruptedBatch)));
+        assert!(matches!(decode(&batch[..7]), Err(Error::CorruptedBatch)));
+    }
+}
@@ -0,0 +1,99 @@
+//! 登记正在执行的 Host 函数的 Caller，用于处理回调中对 WASM 的嵌套调用
+//!
+//! WASM 调用 Host 函数时，Store 被该函数的 Caller 独占，嵌套调用 WASM 只能借用此 Caller。Host 函数
+//! 在调用回调期间通过 `CallerStack::enter` 登记其 Caller，回调返回前注销，因此登记的 Caller 总是
+//! 有效的。登记的 Caller 只能被登记它的线程借用，且同一时刻只能被借用一次。嵌套调用中 WASM 再次调用
+//! Host 函数时，新的 Caller 压入栈顶，借用总是使用最内层的 Caller，因此可以支持任意深度的嵌套。
+
+use std::collections::HashMap;
+use std::ptr::NonNull;
+use std::sync::Mutex;
+use std::thread::{self, ThreadId};
+
+use wasmtime::{AsContextMut, Caller, StoreContextMut};
+
+use crate::{Error, Result};
+
+/// 指向登记的 Caller，借用期间为 `None`
+struct Frame<T: 'static>(Option<NonNull<Caller<'static, T>>>);
+
+// 登记的 Caller 只会被登记它的线程访问
+unsafe impl<T: 'static> Send for Frame<T> {}
+
+/// 各线程中正在执行的 Host 函数的 Caller
+pub(crate) struct CallerStack<T: 'static> {
+    frames: Mutex<HashMap<ThreadId, Vec<Frame<T>>>>,
+}
+
+impl<T: 'static> CallerStack<T> {
+    pub fn new() -> Self {
+        CallerStack {
+            frames: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 在 `f` 执行期间登记 `caller`，使当前线程中对 WASM 的嵌套调用可以借用之
+    pub fn enter<R>(&self, caller: &mut Caller<'_, T>, f: impl FnOnce() -> R) -> R {
+        let caller = NonNull::from(caller).cast::<Caller<'static, T>>();
+        self.frames.lock().unwrap()
+            .entry(thread::current().id())
+            .or_default()
+            .push(Frame(Some(caller)));
+
+        // 即使 `f` 发生 panic 也注销 Caller
+        let _leave = Leave(self);
+        f()
+    }
+
+    /// 借用当前线程最内层的 Caller，当前线程没有登记 Caller 时返回 `None`
+    ///
+    /// 最内层的 Caller 已被借用时，说明对应的 Store 正被使用，返回 `Error::NoStore`。
+    pub fn borrow(&self) -> Result<Option<BorrowedCaller<'_, T>>> {
+        let mut frames = self.frames.lock().unwrap();
+        let frames = match frames.get_mut(&thread::current().id()) {
+            Some(frames) => frames,
+            None => return Ok(None),
+        };
+        let depth = frames.len() - 1;
+        let caller = frames[depth].0.take().ok_or(Error::NoStore)?;
+        Ok(Some(BorrowedCaller { stack: self, depth, caller }))
+    }
+}
+
+/// 注销当前线程最内层的 Caller
+struct Leave<'a, T: 'static>(&'a CallerStack<T>);
+
+impl<T: 'static> Drop for Leave<'_, T> {
+    fn drop(&mut self) {
+        let mut frames = self.0.frames.lock().unwrap();
+        let id = thread::current().id();
+        let thread_frames = frames.get_mut(&id).unwrap();
+        let frame = thread_frames.pop().unwrap();
+        debug_assert!(frame.0.is_some(), "caller is still borrowed");
+        if thread_frames.is_empty() {
+            frames.remove(&id);
+        }
+    }
+}
+
+/// 借用中的 Caller，析构时归还
+pub(crate) struct BorrowedCaller<'a, T: 'static> {
+    stack: &'a CallerStack<T>,
+    depth: usize,
+    caller: NonNull<Caller<'static, T>>,
+}
+
+impl<T: 'static> BorrowedCaller<'_, T> {
+    pub fn as_context_mut(&mut self) -> StoreContextMut<'_, T> {
+        // 登记 Caller 的 Host 函数在注销前不会返回，且借用期间不会有其他访问
+        unsafe { self.caller.as_mut() }.as_context_mut()
+    }
+}
+
+impl<T: 'static> Drop for BorrowedCaller<'_, T> {
+    fn drop(&mut self) {
+        let mut frames = self.stack.frames.lock().unwrap();
+        let frames = frames.get_mut(&thread::current().id()).unwrap();
+        frames[self.depth].0 = Some(self.caller);
+    }
+}
@@ -0,0 +1,87 @@
+//! 低层通信模块的错误类型
+
+use std::fmt;
+
+#[cfg(not(target_arch = "wasm32"))]
+use wasmtime::{Trap, TrapCode};
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::host::Resource;
+
+/// 低层通信中可能发生的错误
+#[derive(Debug)]
+pub enum Error {
+    /// WASM 执行过程中发生 Trap
+    #[cfg(not(target_arch = "wasm32"))]
+    Trap(Trap),
+    /// 与 wasmtime 实例链接失败，如注册函数、获取导出函数失败
+    #[cfg(not(target_arch = "wasm32"))]
+    Link(anyhow::Error),
+    /// WASM 模块缺少所需的导出
+    MissingExport(&'static str),
+    /// 当前没有可用于执行 WASM 的 Store
+    NoStore,
+    /// 对 WASM 线性内存的访问越界
+    OutOfBounds,
+    /// 环形缓冲区的内容不合法
+    CorruptedRing,
+    /// 批量消息的内容不合法
+    CorruptedBatch,
+    /// WASM 执行超出 CPU 预算，即燃料耗尽或超过 epoch 截止时间
+    CpuExhausted,
+    /// WASM 模块超出资源限制
+    #[cfg(not(target_arch = "wasm32"))]
+    ResourceLimit(Resource),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(not(target_arch = "wasm32"))]
+            Error::Trap(trap) => write!(f, "wasm trap: {}", trap),
+            #[cfg(not(target_arch = "wasm32"))]
+            Error::Link(e) => write!(f, "link error: {}", e),
+            Error::MissingExport(name) => write!(f, "No `{}` exported!", name),
+            Error::NoStore => write!(f, "No available store!"),
+            Error::OutOfBounds => write!(f, "out of bounds memory access"),
+            Error::CorruptedRing => write!(f, "corrupted ring buffer"),
+            Error::CorruptedBatch => write!(f, "corrupted message batch"),
+            Error::CpuExhausted => write!(f, "CPU budget exhausted"),
+            #[cfg(not(target_arch = "wasm32"))]
+            Error::ResourceLimit(resource) => write!(f, "resource limit exceeded: {}", resource),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            #[cfg(not(target_arch = "wasm32"))]
+            Error::Trap(trap) => Some(trap),
+            #[cfg(not(target_arch = "wasm32"))]
+            Error::Link(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl From<Trap> for Error {
+    fn from(trap: Trap) -> Self {
+        // 超过 epoch 截止时间时 wasmtime 以 `TrapCode::Interrupt` 中断执行；燃料耗尽的 Trap 不带
+        // TrapCode，由 `LowLevelCtx` 根据 Store 剩余的燃料判断
+        match trap.trap_code() {
+            Some(TrapCode::Interrupt) => Error::CpuExhausted,
+            _ => Error::Trap(trap),
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl From<anyhow::Error> for Error {
+    fn from(e: anyhow::Error) -> Self {
+        Error::Link(e)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -1,22 +1,314 @@
 //! 本模块用于提供在 Host 中进行信息传送的低层接口
 
 use std::cell::Cell;
+use std::fmt;
+use std::future::Future;
 use std::mem;
 use std::sync::{Arc, Mutex};
+use std::task::{self, Poll, RawWaker, RawWakerVTable, Waker};
 
-use wasmtime::{AsContextMut, Caller, Instance, Linker, Memory, Store, Trap, TypedFunc};
+use wasmtime::{AsContextMut, Caller, Instance, Linker, Memory, Module, ResourceLimiter, Store, StoreContextMut, Trap,
+               TypedFunc, WasmParams, WasmResults};
 
-use crate::Result;
+use crate::caller::CallerStack;
+use crate::ring::{self, RingBuffer, FLAG_DEFERRED};
+use crate::{batch, Error, Result};
 
 type OptionWrapper<T> = Mutex<Cell<Option<T>>>;
 
+/// Host 与 WASM 之间传递消息的方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Transport {
+    /// 每条消息通过一次函数调用传递，由 Host 在 WASM 内分配消息内存
+    #[default]
+    Call,
+    /// 消息经由 WASM 在 `__bc_main` 前分配的一对环形缓冲区传递，函数调用仅用于敲门
+    ///
+    /// `capacity` 为单个环形缓冲区数据区的容量，超过容量的消息仍通过函数调用传递。
+    /// WASM 模块不支持环形缓冲区时，退回到 `Call`。
+    Ring { capacity: usize },
+}
+
+impl Transport {
+    /// 使用默认容量的环形缓冲区
+    pub fn ring() -> Self {
+        Transport::Ring { capacity: ring::DEFAULT_CAPACITY }
+    }
+}
+
+/// WASM 执行的 CPU 预算，超出预算的调用以 `Error::CpuExhausted` 失败
+///
+/// 燃料预算需 Engine 开启 `Config::consume_fuel`；epoch 预算需开启 `Config::epoch_interruption`，
+/// 并由外部定期调用 `Engine::increment_epoch`。预算在每次顶层调用前重置，嵌套调用与外层调用共享预算。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CpuLimits {
+    /// 每次发送消息或调用 `__bc_main` 可消耗的燃料
+    pub fuel_per_call: Option<u64>,
+    /// 每次 `wasm_poll` 可消耗的燃料
+    pub fuel_per_poll: Option<u64>,
+    /// 每次发送消息或调用 `__bc_main` 可经过的 epoch 数
+    pub epoch_per_call: Option<u64>,
+    /// 每次 `wasm_poll` 可经过的 epoch 数
+    pub epoch_per_poll: Option<u64>,
+    /// 异步模式下每消耗此数量的燃料让出一次执行，燃料预算按注入次数计算
+    pub fuel_per_yield: Option<u64>,
+}
+
+/// WASM 模块的资源限制，由 `LowLevelCtx::resource_limiter` 创建的 `Limiter` 施加于 Store
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ResourceLimits {
+    /// 线性内存的最大页数，每页 64 KiB
+    pub max_memory_pages: Option<u64>,
+    /// 单个表的最大元素数
+    pub max_table_elements: Option<u32>,
+    /// Store 中的最大实例数
+    pub max_instances: Option<usize>,
+    /// Store 中的最大表数
+    pub max_tables: Option<usize>,
+}
+
+/// 超出限制的资源
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resource {
+    MemoryPages,
+    TableElements,
+    Instances,
+    Tables,
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Resource::MemoryPages => write!(f, "memory pages"),
+            Resource::TableElements => write!(f, "table elements"),
+            Resource::Instances => write!(f, "instances"),
+            Resource::Tables => write!(f, "tables"),
+        }
+    }
+}
+
+/// WASM 执行失败的描述，包括 Trap 的原因与 WASM 调用栈
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrapInfo {
+    /// Trap 的原因
+    pub reason: String,
+    /// WASM 调用栈，由内向外，每帧形如 `模块名!函数名`
+    pub backtrace: Vec<String>,
+}
+
+impl From<&Trap> for TrapInfo {
+    fn from(trap: &Trap) -> Self {
+        let backtrace = trap.trace().iter()
+            .map(|frame| {
+                let module = frame.module_name().unwrap_or("<unknown>");
+                match frame.func_name() {
+                    Some(func) => format!("{}!{}", module, func),
+                    None => format!("{}!<wasm function {}>", module, frame.func_index()),
+                }
+            })
+            .collect();
+        TrapInfo {
+            reason: trap.display_reason().to_string(),
+            backtrace,
+        }
+    }
+}
+
+impl From<&Error> for TrapInfo {
+    fn from(e: &Error) -> Self {
+        match e {
+            Error::Trap(trap) => trap.into(),
+            // 其他错误没有 WASM 调用栈
+            e => TrapInfo {
+                reason: e.to_string(),
+                backtrace: Vec::new(),
+            },
+        }
+    }
+}
+
+impl fmt::Display for TrapInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.reason)?;
+        if !self.backtrace.is_empty() {
+            write!(f, "\nwasm backtrace:")?;
+            for (i, frame) in self.backtrace.iter().enumerate() {
+                write!(f, "\n  {:>3}: {}", i, frame)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// WASM 线性内存的页大小
+const WASM_PAGE_SIZE: usize = 64 << 10;
+
+/// 未设置限制时的实例数、表数与内存数上限，与 wasmtime 的默认值相同
+const DEFAULT_COUNT_LIMIT: usize = 10000;
+
+/// 施加资源限制的 `ResourceLimiter`，拒绝的增长会记录在创建它的 `LowLevelCtx` 中
+///
+/// WASM 通常在内存分配失败后中止执行，记录的超限资源使得随后的 Trap 可以报告为 `Error::ResourceLimit`。
+pub struct Limiter {
+    limits: ResourceLimits,
+    violation: Arc<OptionWrapper<Resource>>,
+    /// 是否正在实例化，由创建它的 `LowLevelCtx` 设置
+    instantiating: Arc<Mutex<Cell<bool>>>,
+    /// 实例化时创建的表数
+    tables: usize,
+}
+
+impl Limiter {
+    fn check(&mut self, resource: Resource, allowed: bool) -> bool {
+        if !allowed {
+            self.violation.lock().unwrap().set(Some(resource));
+        }
+        allowed
+    }
+}
+
+impl ResourceLimiter for Limiter {
+    fn memory_growing(&mut self, _current: usize, desired: usize, _maximum: Option<usize>) -> bool {
+        let allowed = match self.limits.max_memory_pages {
+            Some(max) => (desired / WASM_PAGE_SIZE) as u64 <= max,
+            None => true,
+        };
+        self.check(Resource::MemoryPages, allowed)
+    }
+
+    fn table_growing(&mut self, current: u32, desired: u32, _maximum: Option<u32>) -> bool {
+        // wasmtime 在实例化时以当前大小 0 创建模块定义的表
+        if current == 0 && self.instantiating.lock().unwrap().get() {
+            self.tables += 1;
+            if self.limits.max_tables.map_or(false, |max| self.tables > max) {
+                return self.check(Resource::Tables, false);
+            }
+        }
+
+        let allowed = match self.limits.max_table_elements {
+            Some(max) => desired <= max,
+            None => true,
+        };
+        self.check(Resource::TableElements, allowed)
+    }
+
+    /// 实例数由 `LowLevelCtx::instantiate` 在实例化前检查，此处的限制只约束未经由它的实例化
+    fn instances(&self) -> usize {
+        self.limits.max_instances.unwrap_or(DEFAULT_COUNT_LIMIT)
+    }
+
+    /// 表数在 `table_growing` 中创建表时检查，以便记录超出限制的资源
+    fn tables(&self) -> usize {
+        DEFAULT_COUNT_LIMIT
+    }
+
+    fn memories(&self) -> usize {
+        DEFAULT_COUNT_LIMIT
+    }
+}
+
+#[derive(Clone, Copy)]
 struct InstanceCtx {
     canonical_abi_free: TypedFunc<(i32, i32, i32), ()>,
     canonical_abi_realloc: TypedFunc<(i32, i32, i32, i32), i32>,
     host_message_handler: TypedFunc<(i32, i32), ()>,
+    host_batch_handler: Option<TypedFunc<(i32, i32), ()>>,
     wasm_poll: Option<TypedFunc<(), ()>>,
     wasm_main: Option<TypedFunc<(), ()>>,
+    ring_init: Option<TypedFunc<i32, i32>>,
+    ring_doorbell: Option<TypedFunc<(), ()>>,
+    memory: Memory,
+}
+
+/// 对 WASM 的一次调用
+enum WasmCall<'a> {
+    SendMessages(Vec<&'a [u8]>),
+    Poll,
+    Main,
+}
+
+/// 异步调用期间从 `temp_store` 中取出的 Store，析构时放回
+struct TakenStore<'a, T> {
+    slot: &'a OptionWrapper<Store<T>>,
+    store: Option<Store<T>>,
+}
+
+impl<'a, T> TakenStore<'a, T> {
+    fn take(slot: &'a OptionWrapper<Store<T>>) -> Result<Self> {
+        let store = slot.lock().unwrap().take().ok_or(Error::NoStore)?;
+        Ok(TakenStore { slot, store: Some(store) })
+    }
+
+    fn as_context_mut(&mut self) -> StoreContextMut<'_, T> {
+        self.store.as_mut().unwrap().as_context_mut()
+    }
+}
+
+impl<T> Drop for TakenStore<'_, T> {
+    fn drop(&mut self) {
+        self.slot.lock().unwrap().replace(self.store.take());
+    }
+}
+
+/// 经由环形缓冲区发送期间的暂存区，析构时关闭，丢弃未能发送的消息
+struct RingOutbox<'a> {
+    slot: &'a OptionWrapper<Vec<Vec<u8>>>,
+}
+
+impl<'a> RingOutbox<'a> {
+    /// 开启暂存区，已在发送期间时返回 `None`
+    fn open(slot: &'a OptionWrapper<Vec<Vec<u8>>>) -> Option<Self> {
+        let outbox = slot.lock().unwrap();
+        let queued = outbox.take();
+        if queued.is_some() {
+            outbox.set(queued);
+            return None;
+        }
+        outbox.set(Some(Vec::new()));
+        Some(RingOutbox { slot })
+    }
+}
+
+impl Drop for RingOutbox<'_> {
+    fn drop(&mut self) {
+        self.slot.lock().unwrap().set(None);
+    }
+}
+
+/// WASM 线性内存中的一对环形缓冲区，依次为 Host 至 WASM、WASM 至 Host 的环形缓冲区
+#[derive(Clone, Copy)]
+struct Rings {
     memory: Memory,
+    base: usize,
+    len: usize,
+}
+
+impl Rings {
+    fn ring_at<'a, T: 'a>(&self, store: impl Into<StoreContextMut<'a, T>>, index: usize) -> Result<RingBuffer<'a>> {
+        let start = self.base + index * self.len;
+        let mem = self.memory.data_mut(store)
+            .get_mut(start..start + self.len)
+            .ok_or(Error::OutOfBounds)?;
+        RingBuffer::attach(mem)
+    }
+
+    fn host_to_wasm<'a, T: 'a>(&self, store: impl Into<StoreContextMut<'a, T>>) -> Result<RingBuffer<'a>> {
+        self.ring_at(store, 0)
+    }
+
+    fn wasm_to_host<'a, T: 'a>(&self, store: impl Into<StoreContextMut<'a, T>>) -> Result<RingBuffer<'a>> {
+        self.ring_at(store, 1)
+    }
+}
+
+/// 取出环形缓冲区中的全部消息
+fn take_messages(ring: &mut RingBuffer) -> Result<Vec<Vec<u8>>> {
+    let mut messages = Vec::new();
+    while let Some(msg) = ring.peek()? {
+        messages.push(msg.to_vec());
+        ring.pop()?;
+    }
+    Ok(messages)
 }
 
 pub struct LowLevelCtx<T>
@@ -24,10 +316,28 @@ pub struct LowLevelCtx<T>
 {
     instance_ctx: OptionWrapper<InstanceCtx>,
     message_cb: Option<Box<dyn Fn(&[u8]) -> () + Send + Sync + 'static>>,
-    /// 临时 caller，用来处理嵌套 call
-    oneshot_caller: OptionWrapper<Caller<'static, T>>,
+    /// 正在执行的 Host 函数的 Caller，用来处理嵌套 call
+    callers: CallerStack<T>,
     /// 临时 store
     temp_store: OptionWrapper<Store<T>>,
+    transport: Transport,
+    /// 是否使用 wasmtime 的异步支持调用 WASM
+    async_mode: bool,
+    limits: CpuLimits,
+    /// `Limiter` 拒绝的资源增长，每次顶层调用前清除
+    violation: Arc<OptionWrapper<Resource>>,
+    /// `resource_limiter` 施加的资源限制
+    resource_limits: Mutex<Cell<ResourceLimits>>,
+    /// 已实例化的实例数
+    instances: Mutex<Cell<usize>>,
+    /// 是否正在实例化，实例化期间 `Limiter` 统计创建的表数
+    instantiating: Arc<Mutex<Cell<bool>>>,
+    /// WASM 分配的环形缓冲区，未启用环形缓冲区时为 `None`
+    rings: OptionWrapper<Rings>,
+    /// 交给 WASM 在 `__bc_main` 中读取的初始状态，读取后置空
+    initial_state: OptionWrapper<Vec<u8>>,
+    /// 经由环形缓冲区发送期间嵌套发送的消息，由外层发送在其消息之后写入。不在发送期间时为 `None`
+    ring_outbox: OptionWrapper<Vec<Vec<u8>>>,
 }
 
 impl<T> LowLevelCtx<T>
@@ -37,8 +347,18 @@ impl<T> LowLevelCtx<T>
         Self {
             instance_ctx: Mutex::new(Cell::new(None)),
             message_cb: None,
-            oneshot_caller: Mutex::new(Cell::new(None)),
+            callers: CallerStack::new(),
             temp_store: Mutex::new(Cell::new(None)),
+            transport: Transport::default(),
+            async_mode: false,
+            limits: CpuLimits::default(),
+            violation: Arc::new(Mutex::new(Cell::new(None))),
+            resource_limits: Mutex::new(Cell::new(ResourceLimits::default())),
+            instances: Mutex::new(Cell::new(0)),
+            instantiating: Arc::new(Mutex::new(Cell::new(false))),
+            rings: Mutex::new(Cell::new(None)),
+            initial_state: Mutex::new(Cell::new(None)),
+            ring_outbox: Mutex::new(Cell::new(None)),
         }
     }
 
@@ -46,39 +366,67 @@ impl<T> LowLevelCtx<T>
     pub fn add_to_linker(self: Arc<Self>, linker: &mut Linker<T>) -> Result<()> {
         // 将内部的信息处理函数 __bc_low_level receive_message_from_wasm 注册到 Module 中
         // 用于对从 WASM 模块发送过来的消息进行处理
-        let cb = move |mut caller: Caller<'_, T>, msg: i32, msg_len: i32| {
-
-            // 从 WASM 的线性内存中获得消息引用
-            let memory = &get_memory(&mut caller, "memory")
-                .unwrap(); // FIXME: unwrap
-            let (mem, _) = memory.data_and_store_mut(&mut caller);
-            let mem_base = mem.as_ptr();
-            let msg = unsafe {
-                std::slice::from_raw_parts(mem_base.offset(msg as isize),
-                                           msg_len as usize)
-            };
+        // WASM 提供的数据无效时以 Trap 结束执行，模块随之被毒化，不影响 Host
+        let ctx = self.clone();
+        let cb = move |mut caller: Caller<'_, T>, msg: i32, msg_len: i32| -> std::result::Result<(), Trap> {
+            // 从 WASM 的线性内存中复制消息。回调中的嵌套调用可能修改甚至移动线性内存，因此不能直接借用
+            let msg = read_memory(&mut caller, msg, msg_len).map_err(host_trap)?;
 
-            // 保存 Caller
-            unsafe {
-                let temp_caller = self.oneshot_caller.lock().unwrap();
-                let caller: Caller<'static, T> = std::mem::transmute(caller);
-                temp_caller.replace(Some(caller));
-            }
+            ctx.dispatch(&mut caller, [msg.as_slice()]);
+            Ok(())
+        };
+        linker.func_wrap("__bc_low_level", "receive_message_from_wasm", cb)?;
 
-            // 调用回调
-            (self.message_cb.as_ref().unwrap())(msg);
+        // 注册 __bc_low_level receive_batch_from_wasm，用于处理 WASM 通过一次调用发送的批量消息
+        let ctx = self.clone();
+        let cb = move |mut caller: Caller<'_, T>, batch: i32, batch_len: i32| -> std::result::Result<(), Trap> {
+            let batch = read_memory(&mut caller, batch, batch_len).map_err(host_trap)?;
+            let messages = batch::decode(&batch).map_err(host_trap)?;
 
-            // 丢弃 Caller，无论是否已经被消耗
-            {
-                let temp_caller = self.oneshot_caller.lock().unwrap();
-                temp_caller.replace(None);
-            }
+            ctx.dispatch(&mut caller, messages);
+            Ok(())
         };
-        linker.func_wrap("__bc_low_level", "receive_message_from_wasm", cb)?;
+        linker.func_wrap("__bc_low_level", "receive_batch_from_wasm", cb)?;
+
+        // 注册 __bc_low_level initial_state_len 与 read_initial_state，供 WASM 读取初始状态
+        let ctx = self.clone();
+        let cb = move || -> i32 {
+            let initial_state = ctx.initial_state.lock().unwrap();
+            let state = initial_state.take();
+            let len = state.as_ref().map_or(-1, |state| state.len() as i32);
+            initial_state.set(state);
+            len
+        };
+        linker.func_wrap("__bc_low_level", "initial_state_len", cb)?;
+
+        let ctx = self.clone();
+        let cb = move |mut caller: Caller<'_, T>, ptr: i32| -> std::result::Result<(), Trap> {
+            let state = ctx.initial_state.lock().unwrap().take().unwrap_or_default();
+            let memory = get_memory(&mut caller, "memory").map_err(host_trap)?;
+            store_many(memory.data_mut(&mut caller), ptr, &state).map_err(host_trap)
+        };
+        linker.func_wrap("__bc_low_level", "read_initial_state", cb)?;
+
+        // 注册敲门函数 __bc_low_level ring_doorbell，用于处理 WASM 经由环形缓冲区发送的消息
+        let cb = move |mut caller: Caller<'_, T>| -> std::result::Result<(), Trap> {
+            let messages = self.take_ring_messages(&mut caller).map_err(host_trap)?;
+            self.dispatch(&mut caller, messages.iter().map(Vec::as_slice));
+            Ok(())
+        };
+        linker.func_wrap("__bc_low_level", "ring_doorbell", cb)?;
 
         Ok(())
     }
 
+    /// 以所给的消息依次调用回调。回调执行期间登记 Caller，以处理回调中的嵌套 call
+    fn dispatch<'a>(&self, caller: &mut Caller<'_, T>, messages: impl IntoIterator<Item=&'a [u8]>) {
+        self.callers.enter(caller, || {
+            for msg in messages {
+                (self.message_cb.as_ref().unwrap())(msg);
+            }
+        });
+    }
+
     /// 将 LowLevelCtx 与 wasmtime 实例绑定
     pub fn attach(&self, mut store: impl AsContextMut, instance: &Instance) -> Result<()> {
         // 准备发送消息所必要的函数。可以通过 `instance.get_typed_func` 获取到 WASM 内的处理函数
@@ -91,18 +439,28 @@ impl<T> LowLevelCtx<T>
             store.as_context_mut(), "canonical_abi_free")?;
         let host_message_handler = instance.get_typed_func(
             store.as_context_mut(), "__bc_low_level_host_message_handler")?;
+        let host_batch_handler = instance.get_typed_func(
+            store.as_context_mut(), "__bc_low_level_host_batch_handler").ok();
         let wasm_poll = instance.get_typed_func(
             store.as_context_mut(), "__bc_low_level_wasm_poll").ok();
         let wasm_main = instance.get_typed_func(
             store.as_context_mut(), "__bc_main").ok();
+        let ring_init = instance.get_typed_func(
+            store.as_context_mut(), "__bc_low_level_ring_init").ok();
+        let ring_doorbell = instance.get_typed_func(
+            store.as_context_mut(), "__bc_low_level_ring_doorbell").ok();
 
         *wasm_funcs = Some(InstanceCtx {
             canonical_abi_realloc,
             canonical_abi_free,
             host_message_handler,
+            host_batch_handler,
             wasm_poll,
             wasm_main,
-            memory: instance.get_memory(store.as_context_mut(), "memory").unwrap(), // FIXME
+            ring_init,
+            ring_doorbell,
+            memory: instance.get_memory(store.as_context_mut(), "memory")
+                .ok_or(Error::MissingExport("memory"))?,
         });
 
         Ok(())
@@ -120,6 +478,12 @@ impl<T> LowLevelCtx<T>
         temp_store.take()
     }
 
+    /// 设置 WASM 在 `__bc_main` 中通过 `low_level::wasm::take_initial_state` 读取的初始状态
+    pub fn set_initial_state(&self, state: Vec<u8>) {
+        let initial_state = self.initial_state.lock().unwrap();
+        initial_state.replace(Some(state));
+    }
+
     /// 设置接受 WASM 模块消息的回调函数。与 `low_level::wasm::send_message_to_host` 函数
     /// 相对应，共同完成消息的发送与接收。
     ///
@@ -142,6 +506,115 @@ impl<T> LowLevelCtx<T>
         self.message_cb = Some(Box::new(cb));
     }
 
+    /// 设置与 WASM 模块传递消息的方式，需在调用 `wasm_main` 之前设置
+    pub fn set_transport(&mut self, transport: Transport) {
+        self.transport = transport;
+    }
+
+    /// 设置是否以异步模式调用 WASM，需与 Store 所属 Engine 的 `Config::async_support` 一致
+    ///
+    /// 异步模式下，`*_async` 系列函数在 WASM 让出执行时（如配置了 `Store::out_of_fuel_async_yield`）
+    /// 返回 `Poll::Pending`，使 WASM 的执行不会阻塞异步运行时。同步函数仍然可用，但会阻塞当前线程
+    /// 直至 WASM 执行完毕。
+    pub fn set_async_mode(&mut self, async_mode: bool) {
+        self.async_mode = async_mode;
+    }
+
+    pub fn async_mode(&self) -> bool {
+        self.async_mode
+    }
+
+    /// 设置 WASM 执行的 CPU 预算
+    pub fn set_cpu_limits(&mut self, limits: CpuLimits) {
+        self.limits = limits;
+    }
+
+    pub fn cpu_limits(&self) -> CpuLimits {
+        self.limits
+    }
+
+    /// 创建施加 `limits` 的 `ResourceLimiter`，需通过 `Store::limiter` 设置于 Store
+    ///
+    /// 设置后，因超出限制而中止的调用以 `Error::ResourceLimit` 失败。
+    pub fn resource_limiter(&self, limits: ResourceLimits) -> Limiter {
+        self.resource_limits.lock().unwrap().set(limits);
+        Limiter {
+            limits,
+            violation: self.violation.clone(),
+            instantiating: self.instantiating.clone(),
+            tables: 0,
+        }
+    }
+
+    /// 实例化模块，失败时的错误见 `instantiate_error`
+    ///
+    /// 异步模式下阻塞当前线程直至实例化完成，不能在异步运行时的线程上调用，此时应使用 `instantiate_async`。
+    pub fn instantiate(&self,
+                       linker: &Linker<T>,
+                       mut store: impl AsContextMut<Data=T>,
+                       module: &Module,
+    ) -> Result<Instance> {
+        self.begin_instantiate()?;
+        let instance = if self.async_mode {
+            block_on(linker.instantiate_async(&mut store, module))
+        } else {
+            linker.instantiate(&mut store, module)
+        };
+        self.instantiating.lock().unwrap().set(false);
+        instance.map_err(|e| self.instantiate_error(e))
+    }
+
+    /// `instantiate` 的异步版本，异步模式下 WASM 让出执行时让出当前任务
+    pub async fn instantiate_async(&self,
+                                   linker: &Linker<T>,
+                                   mut store: impl AsContextMut<Data=T>,
+                                   module: &Module,
+    ) -> Result<Instance> {
+        self.begin_instantiate()?;
+        let instance = if self.async_mode {
+            linker.instantiate_async(&mut store, module).await
+        } else {
+            linker.instantiate(&mut store, module)
+        };
+        self.instantiating.lock().unwrap().set(false);
+        instance.map_err(|e| self.instantiate_error(e))
+    }
+
+    /// 实例化前检查实例数，之后 `Limiter` 统计实例化时创建的表数
+    fn begin_instantiate(&self) -> Result<()> {
+        let instances = self.instances.lock().unwrap();
+        let max_instances = self.resource_limits.lock().unwrap().get().max_instances;
+        if max_instances.map_or(false, |max| instances.get() >= max) {
+            return Err(Error::ResourceLimit(Resource::Instances));
+        }
+        instances.set(instances.get() + 1);
+        self.violation.lock().unwrap().set(None);
+        self.instantiating.lock().unwrap().set(true);
+        Ok(())
+    }
+
+    /// 转换实例化失败的错误，实例化中资源增长被拒绝（包括表数超出限制）时为 `Error::ResourceLimit`
+    pub fn instantiate_error(&self, e: anyhow::Error) -> Error {
+        match self.violation.lock().unwrap().take() {
+            Some(resource) => Error::ResourceLimit(resource),
+            None => e.into(),
+        }
+    }
+
+    /// 是否已经启用环形缓冲区传递消息
+    pub fn ring_enabled(&self) -> bool {
+        self.rings().is_some()
+    }
+
+    /// 已绑定实例的导出。复制后释放锁，使调用 WASM 期间的嵌套调用可以再次获取
+    fn instance_ctx(&self) -> InstanceCtx {
+        self.instance_ctx.lock().unwrap().get().unwrap()
+    }
+
+    fn rings(&self) -> Option<Rings> {
+        self.rings.lock().unwrap().get()
+    }
+
     /// 将消息发送至 WASM 模块
     ///
     /// ## 使用示例
@@ -155,133 +628,337 @@ impl<T> LowLevelCtx<T>
     /// ```
     ///
     pub fn send_message_to_wasm(&self, msg: &[u8]) -> Result<()> {
-        // 如果有 oneshot_caller，说明是嵌套调用，则直接使用
-        let oneshot_caller = self.oneshot_caller.lock().unwrap();
-        let caller = oneshot_caller.replace(None);
-        drop(oneshot_caller);
-        if let Some(caller) = caller {
-            return self.send_message_to_wasm_with_store(caller, msg);
-        }
+        self.call_wasm(WasmCall::SendMessages(vec![msg]))
+    }
 
-        // 没有的话看看是否持有 Store
-        let temp_store = self.temp_store.lock().unwrap();
-        let mut store = temp_store.replace(None).ok_or("No available store!")?;
+    /// 将多条消息发送至 WASM 模块，与逐条调用 `send_message_to_wasm` 等价，但只调用一次 WASM
+    ///
+    /// 启用环形缓冲区时，消息全部写入环形缓冲区后只敲门一次；否则编码为一条批量消息发送。
+    /// WASM 模块不支持批量消息时，退回到逐条发送。
+    pub fn send_messages_to_wasm<'a>(&self, messages: impl IntoIterator<Item=&'a [u8]>) -> Result<()> {
+        self.call_wasm(WasmCall::SendMessages(messages.into_iter().collect()))
+    }
+
+    pub fn wasm_poll(&self) -> Result<()> {
+        self.call_wasm(WasmCall::Poll)
+    }
 
-        let result = self.send_message_to_wasm_with_store(store.as_context_mut(), msg);
+    /// 调用 WASM 的主函数进行初始化
+    ///
+    /// 与其他同步调用相同，异步模式下阻塞当前线程直至调用完成，不能在异步运行时的线程上调用，此时应使用
+    /// `wasm_main_async`。
+    pub fn wasm_main(&self) -> Result<()> {
+        self.call_wasm(WasmCall::Main)
+    }
 
-        // 放回 store
-        temp_store.replace(Some(store));
+    /// `send_message_to_wasm` 的异步版本，需启用异步模式
+    pub async fn send_message_to_wasm_async(&self, msg: &[u8]) -> Result<()> {
+        self.call_wasm_async(WasmCall::SendMessages(vec![msg])).await
+    }
 
-        return result;
+    /// `send_messages_to_wasm` 的异步版本，需启用异步模式
+    pub async fn send_messages_to_wasm_async<'a>(&self, messages: impl IntoIterator<Item=&'a [u8]>) -> Result<()> {
+        self.call_wasm_async(WasmCall::SendMessages(messages.into_iter().collect())).await
     }
 
-    fn send_message_to_wasm_with_store(&self, mut store: impl AsContextMut, msg: &[u8]) -> Result<()> {
-        let mut instance_ctx = self.instance_ctx.lock().unwrap();
-        let instance_ctx = instance_ctx.get_mut().as_ref().unwrap();
-        let func_canonical_abi_free = &instance_ctx.canonical_abi_free;
-        let func_canonical_abi_realloc = &instance_ctx.canonical_abi_realloc;
-        let func_host_message_handler = &instance_ctx.host_message_handler;
+    /// `wasm_poll` 的异步版本，需启用异步模式
+    pub async fn wasm_poll_async(&self) -> Result<()> {
+        self.call_wasm_async(WasmCall::Poll).await
+    }
 
-        // 在 WASM 内分配消息内存
-        let msg_len = msg.len() as i32;
-        let msg_ptr = func_canonical_abi_realloc
-            .call(&mut store, (0, 0, 1, msg_len))?;
+    /// `wasm_main` 的异步版本，需启用异步模式
+    pub async fn wasm_main_async(&self) -> Result<()> {
+        self.call_wasm_async(WasmCall::Main).await
+    }
 
-        // 将消息复制到 WASM 内存中
-        let memory = &instance_ctx.memory;
-        store_many(memory.data_mut(&mut store), msg_ptr, msg)?;
+    /// 获取可用的 Store 同步调用 WASM，并在调用结束后处理 WASM 经由环形缓冲区发送的消息
+    fn call_wasm(&self, call: WasmCall) -> Result<()> {
+        // 如果当前线程正在执行 Host 函数，说明是嵌套调用，则借用其 Caller
+        let messages = if let Some(mut caller) = self.callers.borrow()? {
+            block_on(self.call_deferred(caller.as_context_mut(), call))?
+        } else {
+            // 没有的话看看是否持有 Store
+            let temp_store = self.temp_store.lock().unwrap();
+            let mut store = temp_store.replace(None).ok_or(Error::NoStore)?;
 
-        // 发送消息
-        func_host_message_handler.call(&mut store, (msg_ptr, msg_len))?;
+            let result = self.apply_limits(store.as_context_mut(), &call)
+                .and_then(|()| block_on(self.call_deferred(store.as_context_mut(), call)));
 
-        // 释放消息内存
-        func_canonical_abi_free.call(&mut store, (msg_ptr, msg_len, 1))?;
+            // 放回 store
+            temp_store.replace(Some(store));
+            result?
+        };
 
+        self.dispatch_deferred(messages);
         Ok(())
     }
 
-    pub fn wasm_poll(&self) -> Result<()> {
-        // 如果有 oneshot_caller，说明是嵌套调用，则直接使用
-        let oneshot_caller = self.oneshot_caller.lock().unwrap();
-        let caller = oneshot_caller.replace(None);
-        drop(oneshot_caller);
-        if let Some(caller) = caller {
-            return self.wasm_poll_with_store(caller);
-        }
+    /// 使用移入的 Store 异步调用 WASM。调用期间 Store 被取出，其他非嵌套的调用将得到 `Error::NoStore`
+    async fn call_wasm_async(&self, call: WasmCall<'_>) -> Result<()> {
+        let mut store = TakenStore::take(&self.temp_store)?;
+        self.apply_limits(store.as_context_mut(), &call)?;
+        let messages = self.call_deferred(store.as_context_mut(), call).await;
+        drop(store);
 
-        // 没有的话看看是否持有 Store
-        let temp_store = self.temp_store.lock().unwrap();
-        let mut store = temp_store.replace(None).ok_or("No available store!")?;
+        self.dispatch_deferred(messages?);
+        Ok(())
+    }
 
-        let result = self.wasm_poll_with_store(store.as_context_mut());
+    /// 在顶层调用前按 CPU 预算重置 Store 的燃料与 epoch 截止时间，并清除之前的资源超限记录
+    fn apply_limits(&self, mut store: StoreContextMut<'_, T>, call: &WasmCall) -> Result<()> {
+        self.violation.lock().unwrap().set(None);
 
-        // 放回 store
-        temp_store.replace(Some(store));
+        let (fuel, epoch) = match call {
+            WasmCall::Poll => (self.limits.fuel_per_poll, self.limits.epoch_per_poll),
+            _ => (self.limits.fuel_per_call, self.limits.epoch_per_call),
+        };
+
+        if let Some(ticks) = epoch {
+            store.set_epoch_deadline(ticks);
+        }
 
-        return result;
+        match (self.async_mode, self.limits.fuel_per_yield) {
+            // 异步模式下分次注入燃料，每次注入前让出执行
+            (true, Some(per_yield)) => {
+                let (initial, injections) = match fuel {
+                    Some(fuel) => {
+                        let initial = fuel.min(per_yield);
+                        (initial, (fuel - initial + per_yield - 1) / per_yield)
+                    }
+                    None => (per_yield, u64::MAX),
+                };
+                set_fuel(&mut store, initial)?;
+                store.out_of_fuel_async_yield(injections, per_yield);
+            }
+            _ => {
+                if let Some(fuel) = fuel {
+                    set_fuel(&mut store, fuel)?;
+                }
+            }
+        }
+        Ok(())
     }
 
-    fn wasm_poll_with_store(&self, mut store: impl AsContextMut) -> Result<()> {
-        let mut instance_ctx = self.instance_ctx.lock().unwrap();
-        let instance_ctx = instance_ctx.get_mut().as_ref().unwrap();
-        let func_wasm_poll = &instance_ctx.wasm_poll
-            .ok_or("No `wasm_poll` function exported!")?;
+    /// 处理调用期间 WASM 经由环形缓冲区发送的消息
+    fn dispatch_deferred(&self, messages: Vec<Vec<u8>>) {
+        for msg in messages {
+            (self.message_cb.as_ref().unwrap())(&msg);
+        }
+    }
 
-        // 发送消息
-        func_wasm_poll.call(&mut store, ())?;
+    /// 调用 WASM，调用期间 WASM 写入环形缓冲区的消息无需敲门，而是在调用结束后一并取出
+    async fn call_deferred(&self, mut store: StoreContextMut<'_, T>, call: WasmCall<'_>) -> Result<Vec<Vec<u8>>> {
+        let rings = match self.rings() {
+            Some(rings) => rings,
+            None => {
+                self.call(&mut store, call).await?;
+                // 调用中可能启用了环形缓冲区
+                return self.take_ring_messages(&mut store);
+            }
+        };
 
-        Ok(())
+        // 嵌套调用时保留外层调用设置的标志
+        let flags = rings.wasm_to_host(&mut store)?.flags();
+        rings.wasm_to_host(&mut store)?.set_flags(flags | FLAG_DEFERRED);
+        let result = self.call(&mut store, call).await;
+        let mut ring = rings.wasm_to_host(&mut store)?;
+        ring.set_flags(flags);
+
+        result?;
+        take_messages(&mut ring)
     }
 
-    pub fn wasm_main(&self) -> Result<()> {
-        // 如果有 oneshot_caller，说明是嵌套调用，则直接使用
-        let oneshot_caller = self.oneshot_caller.lock().unwrap();
-        let caller = oneshot_caller.replace(None);
-        drop(oneshot_caller);
-        if let Some(caller) = caller {
-            return self.wasm_main_with_store(caller);
+    async fn call(&self, store: &mut StoreContextMut<'_, T>, call: WasmCall<'_>) -> Result<()> {
+        let instance_ctx = self.instance_ctx();
+        match call {
+            WasmCall::SendMessages(messages) => {
+                self.send_messages_with_store(store, &instance_ctx, messages).await
+            }
+            WasmCall::Poll => {
+                let func_wasm_poll = instance_ctx.wasm_poll
+                    .ok_or(Error::MissingExport("__bc_low_level_wasm_poll"))?;
+                self.call_func(store, func_wasm_poll, ()).await
+            }
+            WasmCall::Main => {
+                let func_wasm_main = instance_ctx.wasm_main
+                    .ok_or(Error::MissingExport("__bc_main"))?;
+
+                // 由 WASM 分配环形缓冲区，之后双方的消息均经由环形缓冲区传递
+                if let (Transport::Ring { capacity }, Some(ring_init)) = (self.transport, instance_ctx.ring_init) {
+                    let base = self.call_func(store, ring_init, capacity as i32).await? as u32;
+                    let rings = Rings {
+                        memory: instance_ctx.memory,
+                        base: base as usize,
+                        len: ring::ring_len(capacity),
+                    };
+                    self.rings.lock().unwrap().set(Some(rings));
+                }
+
+                self.call_func(store, func_wasm_main, ()).await
+            }
         }
+    }
 
-        // 没有的话看看是否持有 Store
-        let temp_store = self.temp_store.lock().unwrap();
-        let mut store = temp_store.replace(None).ok_or("No available store!")?;
+    async fn send_messages_with_store(&self,
+                                      store: &mut StoreContextMut<'_, T>,
+                                      instance_ctx: &InstanceCtx,
+                                      messages: Vec<&[u8]>,
+    ) -> Result<()> {
+        let handler = instance_ctx.host_message_handler;
 
-        let result = self.wasm_main_with_store(store.as_context_mut());
+        if let (Some(rings), Some(doorbell)) = (self.rings(), instance_ctx.ring_doorbell) {
+            // 嵌套在另一次发送之中时，WASM 可能正在处理缓冲区中的消息，此时敲门不会再次处理，
+            // 直接发送又会先于缓冲区中的消息到达，因此暂存消息，由外层发送在其消息之后写入
+            let _outbox = match RingOutbox::open(&self.ring_outbox) {
+                Some(outbox) => outbox,
+                None => {
+                    let outbox = self.ring_outbox.lock().unwrap();
+                    let mut queued = outbox.take().unwrap_or_default();
+                    queued.extend(messages.iter().map(|msg| msg.to_vec()));
+                    outbox.set(Some(queued));
+                    return Ok(());
+                }
+            };
 
-        // 放回 store
-        temp_store.replace(Some(store));
+            let mut pending = self.push_ring_messages(store, instance_ctx, rings, doorbell, messages).await?;
+            loop {
+                if pending {
+                    self.call_func(store, doorbell, ()).await?;
+                }
+                let queued = self.ring_outbox.lock().unwrap().replace(Some(Vec::new())).unwrap_or_default();
+                if queued.is_empty() {
+                    return Ok(());
+                }
+                let queued = queued.iter().map(Vec::as_slice).collect();
+                pending = self.push_ring_messages(store, instance_ctx, rings, doorbell, queued).await?;
+            }
+        }
 
-        return result;
+        match instance_ctx.host_batch_handler {
+            Some(batch_handler) if messages.len() > 1 => {
+                let batch = batch::encode(messages);
+                self.call_handler(store, instance_ctx, batch_handler, &batch).await?;
+            }
+            _ => {
+                // 单条消息或 WASM 模块不支持批量消息时，逐条发送
+                for msg in messages {
+                    self.call_handler(store, instance_ctx, handler, msg).await?;
+                }
+            }
+        }
+
+        Ok(())
     }
 
-    fn wasm_main_with_store(&self, mut store: impl AsContextMut) -> Result<()> {
-        let mut instance_ctx = self.instance_ctx.lock().unwrap();
-        let instance_ctx = instance_ctx.get_mut().as_ref().unwrap();
-        let func_wasm_main = &instance_ctx.wasm_main
-            .ok_or("No `wasm_main` function exported!")?;
+    /// 把消息写入 Host 至 WASM 的环形缓冲区，返回是否仍需敲门
+    ///
+    /// 若空间不足，则敲门使 WASM 取走缓冲区中的消息后重试；若仍无法写入，则说明消息过长，
+    /// 通过函数调用发送。
+    async fn push_ring_messages(&self,
+                                store: &mut StoreContextMut<'_, T>,
+                                instance_ctx: &InstanceCtx,
+                                rings: Rings,
+                                doorbell: TypedFunc<(), ()>,
+                                messages: Vec<&[u8]>,
+    ) -> Result<bool> {
+        let mut pending = false;
+        for msg in messages {
+            if !rings.host_to_wasm(&mut *store)?.push(msg) {
+                self.call_func(store, doorbell, ()).await?;
+                if !rings.host_to_wasm(&mut *store)?.push(msg) {
+                    self.call_handler(store, instance_ctx, instance_ctx.host_message_handler, msg).await?;
+                    pending = false;
+                    continue;
+                }
+            }
+            pending = true;
+        }
+        Ok(pending)
+    }
+
+    /// 把数据复制到 WASM 内分配的内存中，并以之调用 WASM 的处理函数
+    async fn call_handler(&self,
+                          store: &mut StoreContextMut<'_, T>,
+                          instance_ctx: &InstanceCtx,
+                          handler: TypedFunc<(i32, i32), ()>,
+                          data: &[u8],
+    ) -> Result<()> {
+        // 在 WASM 内分配消息内存
+        let data_len = data.len() as i32;
+        let data_ptr = self.call_func(store, instance_ctx.canonical_abi_realloc, (0, 0, 1, data_len)).await?;
+
+        // 将消息复制到 WASM 内存中
+        store_many(instance_ctx.memory.data_mut(&mut *store), data_ptr, data)?;
 
         // 发送消息
-        func_wasm_main.call(&mut store, ())?;
+        self.call_func(store, handler, (data_ptr, data_len)).await?;
+
+        // 释放消息内存
+        self.call_func(store, instance_ctx.canonical_abi_free, (data_ptr, data_len, 1)).await?;
+
         Ok(())
     }
+
+    /// 调用 WASM 函数。异步模式下，WASM 让出执行时返回 `Poll::Pending`
+    async fn call_func<P, R>(&self, store: &mut StoreContextMut<'_, T>, func: TypedFunc<P, R>, params: P) -> Result<R>
+        where P: WasmParams, R: WasmResults,
+    {
+        let result = if self.async_mode {
+            func.call_async(&mut *store, params).await
+        } else {
+            func.call(&mut *store, params)
+        };
+        result.map_err(|trap| self.trap_error(store, trap))
+    }
+
+    /// WASM 在资源增长被拒绝后发生的 Trap 报告为超出资源限制，燃料耗尽后发生的 Trap 报告为超出 CPU 预算
+    fn trap_error(&self, store: &mut StoreContextMut<'_, T>, trap: Trap) -> Error {
+        if let Some(resource) = self.violation.lock().unwrap().take() {
+            return Error::ResourceLimit(resource);
+        }
+        // 未开启燃料计量时 `consume_fuel` 返回错误
+        if let Ok(0) = store.consume_fuel(0) {
+            return Error::CpuExhausted;
+        }
+        trap.into()
+    }
+
+    /// 取出 WASM 经由环形缓冲区发送的全部消息
+    fn take_ring_messages(&self, mut store: impl AsContextMut) -> Result<Vec<Vec<u8>>> {
+        match self.rings() {
+            Some(rings) => take_messages(&mut rings.wasm_to_host(&mut store)?),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+/// 将 Host 函数中的错误转换为 Trap，使 WASM 停止执行
+fn host_trap(e: Error) -> Trap {
+    match e {
+        Error::Trap(trap) => trap,
+        e => Trap::new(e.to_string()),
+    }
 }
 
 /// 本函数是开源项目 bytecodealliance/wit-bindgen 的一部分，遵照 Apache License 协议引入
-fn get_memory<T>(caller: &mut Caller<'_, T>, mem: &str) -> Result<Memory> {
+fn get_memory<T>(caller: &mut Caller<'_, T>, mem: &'static str) -> Result<Memory> {
     let mem = caller
         .get_export(mem)
-        .ok_or_else(|| {
-            let msg = format!("`{}` export not available", mem);
-            Trap::new(msg)
-        })?
-        .into_memory()
-        .ok_or_else(|| {
-            let msg = format!("`{}` export not a memory", mem);
-            Trap::new(msg)
-        })?;
+        .and_then(|export| export.into_memory())
+        .ok_or(Error::MissingExport(mem))?;
     Ok(mem)
 }
 
+/// 复制 WASM 线性内存中的一段数据
+fn read_memory<T>(caller: &mut Caller<'_, T>, ptr: i32, len: i32) -> Result<Vec<u8>> {
+    let memory = get_memory(caller, "memory")?;
+    let start = ptr as u32 as usize;
+    let data = memory.data(&*caller)
+        .get(start..start + len as u32 as usize)
+        .ok_or(Error::OutOfBounds)?;
+    Ok(data.to_vec())
+}
+
 /// 本函数是开源项目 bytecodealliance/wit-bindgen 的一部分，遵照 Apache License 协议引入
 fn store_many(this: &mut [u8], offset: i32, val: &[u8]) -> Result<()> {
     let mem = this
@@ -290,13 +967,60 @@ fn store_many(this: &mut [u8], offset: i32, val: &[u8]) -> Result<()> {
             let len = mem::size_of::<u8>().checked_mul(val.len())?;
             m.get_mut(..len)
         })
-        .ok_or_else(|| Trap::new("out of bounds write"))?;
+        .ok_or(Error::OutOfBounds)?;
     mem.copy_from_slice(val);
     Ok(())
 }
 
+/// 把 Store 剩余的燃料设为 `fuel`
+fn set_fuel(mut store: impl AsContextMut, fuel: u64) -> Result<()> {
+    let mut store = store.as_context_mut();
+    let remaining = store.consume_fuel(0)?;
+    if remaining < fuel {
+        store.add_fuel(fuel - remaining)?;
+    } else {
+        store.consume_fuel(remaining - fuel)?;
+    }
+    Ok(())
+}
+
+/// 在当前线程上执行 Future 直至完成
+///
+/// 同步模式下对 WASM 的调用不会让出执行，Future 总是在第一次轮询时完成；异步模式下，WASM 让出执行
+/// 后立即继续轮询。
+///
+/// 轮询时不等待唤醒，而是让出线程后立即重试，因此只用于同步调用 WASM。Future 未完成前一直占用当前线程，
+/// 不能在异步运行时的线程上调用，否则会阻塞该线程上的其他任务。
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    let waker = noop_waker();
+    let mut cx = task::Context::from_waker(&waker);
+    let mut future = Box::pin(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        std::thread::yield_now();
+    }
+}
+
+fn noop_waker() -> Waker {
+    fn clone(_: *const ()) -> RawWaker {
+        RawWaker::new(std::ptr::null(), &VTABLE)
+    }
+    fn noop(_: *const ()) {}
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+
+    unsafe { Waker::from_raw(RawWaker::new(std::ptr::null(), &VTABLE)) }
+}
+
 #[cfg(test)]
 mod tests {
+    use std::sync::Weak;
+    use std::time::Duration;
+
+    use wasmtime::{Config, Engine};
+    use wasmtime_wasi::WasiCtx;
+
     use crate::tests::*;
 
     use super::*;
@@ -355,4 +1079,498 @@ mod tests {
             .unwrap();
         check.call(&mut store, ()).unwrap()
     }
+
+    #[test]
+    fn test_ring_echo() {
+        let Context { mut store, module, mut linker } = guest_prepare();
+
+        // 创建 Ctx，使用较小的环形缓冲区以覆盖回绕与过长消息
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let mut ctx = LowLevelCtx::new();
+        ctx.set_transport(Transport::Ring { capacity: 64 });
+        let received_cb = received.clone();
+        ctx.set_message_callback(move |msg| {
+            received_cb.lock().unwrap().push(msg.to_vec());
+        });
+        let ctx = Arc::new(ctx);
+
+        // Linker
+        ctx.clone().add_to_linker(&mut linker).unwrap();
+
+        // 实例化
+        let instance = linker.instantiate(&mut store, &module).unwrap();
+        ctx.attach(&mut store, &instance).unwrap();
+        let set_echo = instance
+            .get_typed_func::<u32, (), _>(&mut store, "set_echo")
+            .unwrap();
+        set_echo.call(&mut store, 1).unwrap();
+
+        // 启用环形缓冲区
+        ctx.move_store(store);
+        ctx.wasm_main().unwrap();
+        assert!(ctx.ring_enabled());
+
+        // 发送消息，WASM 原样发回
+        let messages: Vec<Vec<u8>> = (0..20)
+            .map(|i| format!("message {}", i).into_bytes())
+            .chain([vec![0x42; 100]])
+            .collect();
+        for msg in &messages {
+            ctx.send_message_to_wasm(msg).unwrap();
+        }
+        assert_eq!(messages, *received.lock().unwrap());
+    }
+
+    #[test]
+    fn test_ring_nested_order() {
+        let Context { mut store, module, mut linker } = guest_prepare();
+
+        // 创建 Ctx。WASM 至 Host 的缓冲区写满时 WASM 在处理消息的过程中敲门，
+        // 回调中嵌套发送的回复须在此前发送的消息之后到达
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let this = Arc::new(Mutex::new(Weak::<LowLevelCtx<WasiCtx>>::new()));
+        let mut ctx = LowLevelCtx::new();
+        ctx.set_transport(Transport::Ring { capacity: 64 });
+        let sent_cb = sent.clone();
+        let received_cb = received.clone();
+        let this_cb = this.clone();
+        ctx.set_message_callback(move |msg| {
+            received_cb.lock().unwrap().push(msg.to_vec());
+            if msg == b"message 0" {
+                let reply = [b"reply ".as_slice(), msg, &[b'x'; 24]].concat();
+                sent_cb.lock().unwrap().push(reply.clone());
+                let ctx = this_cb.lock().unwrap().upgrade().unwrap();
+                ctx.send_message_to_wasm(&reply).unwrap();
+            }
+        });
+        let ctx = Arc::new(ctx);
+        *this.lock().unwrap() = Arc::downgrade(&ctx);
+
+        // Linker
+        ctx.clone().add_to_linker(&mut linker).unwrap();
+
+        // 实例化并启用环形缓冲区
+        let instance = linker.instantiate(&mut store, &module).unwrap();
+        ctx.attach(&mut store, &instance).unwrap();
+        let set_echo = instance
+            .get_typed_func::<u32, (), _>(&mut store, "set_echo")
+            .unwrap();
+        set_echo.call(&mut store, 1).unwrap();
+        ctx.move_store(store);
+        ctx.wasm_main().unwrap();
+
+        // WASM 按 Host 发送的顺序回显全部消息
+        let messages: Vec<Vec<u8>> = (0..10)
+            .map(|i| format!("message {}", i).into_bytes())
+            .collect();
+        sent.lock().unwrap().extend(messages.iter().cloned());
+        ctx.send_messages_to_wasm(messages.iter().map(Vec::as_slice)).unwrap();
+        assert_eq!(*sent.lock().unwrap(), *received.lock().unwrap());
+        assert_eq!(11, received.lock().unwrap().len());
+    }
+
+    #[test]
+    fn test_ring_receive_message_from_wasm() {
+        let Context { mut store, module, mut linker } = guest_prepare();
+
+        // 创建 Ctx
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let mut ctx = LowLevelCtx::new();
+        ctx.set_transport(Transport::ring());
+        let received_cb = received.clone();
+        ctx.set_message_callback(move |msg| {
+            received_cb.lock().unwrap().push(msg.to_vec());
+        });
+        let ctx = Arc::new(ctx);
+
+        // Linker
+        ctx.clone().add_to_linker(&mut linker).unwrap();
+
+        // 实例化并启用环形缓冲区
+        let instance = linker.instantiate(&mut store, &module).unwrap();
+        ctx.attach(&mut store, &instance).unwrap();
+        ctx.move_store(store);
+        ctx.wasm_main().unwrap();
+        let mut store = ctx.take_store().unwrap();
+
+        // 直接调用 WASM 时，WASM 写入环形缓冲区后敲门
+        let check = instance
+            .get_typed_func::<(), (), _>(&mut store, "test_send_message")
+            .unwrap();
+        check.call(&mut store, ()).unwrap();
+        assert_eq!(vec!["hello, host!".as_bytes().to_vec()], *received.lock().unwrap());
+    }
+
+    #[test]
+    fn test_send_messages_to_wasm() {
+        let Context { mut store, module, mut linker } = guest_prepare();
+
+        // 创建 Ctx
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let mut ctx = LowLevelCtx::new();
+        let received_cb = received.clone();
+        ctx.set_message_callback(move |msg| {
+            received_cb.lock().unwrap().push(msg.to_vec());
+        });
+        let ctx = Arc::new(ctx);
+
+        // Linker
+        ctx.clone().add_to_linker(&mut linker).unwrap();
+
+        // 实例化，WASM 将收到的消息原样发回
+        let instance = linker.instantiate(&mut store, &module).unwrap();
+        ctx.attach(&mut store, &instance).unwrap();
+        let set_echo = instance
+            .get_typed_func::<u32, (), _>(&mut store, "set_echo")
+            .unwrap();
+        set_echo.call(&mut store, 1).unwrap();
+
+        // 批量发送消息
+        let messages: [&[u8]; 3] = [b"hello", b"", b"wasm"];
+        ctx.move_store(store);
+        ctx.send_messages_to_wasm(messages).unwrap();
+        assert_eq!(messages.map(<[u8]>::to_vec).to_vec(), *received.lock().unwrap());
+    }
+
+    #[test]
+    fn test_receive_messages_from_wasm() {
+        let Context { mut store, module, mut linker } = guest_prepare();
+
+        // 创建 Ctx
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let mut ctx = LowLevelCtx::new();
+        let received_cb = received.clone();
+        ctx.set_message_callback(move |msg| {
+            received_cb.lock().unwrap().push(msg.to_vec());
+        });
+        let ctx = Arc::new(ctx);
+
+        // Linker
+        ctx.clone().add_to_linker(&mut linker).unwrap();
+
+        // 实例化
+        let instance = linker.instantiate(&mut store, &module).unwrap();
+        ctx.attach(&mut store, &instance).unwrap();
+
+        // 触发批量消息发送
+        let check = instance
+            .get_typed_func::<(), (), _>(&mut store, "test_send_messages")
+            .unwrap();
+        check.call(&mut store, ()).unwrap();
+        let expected: Vec<Vec<u8>> = vec![b"hello".to_vec(), vec![], b"host".to_vec()];
+        assert_eq!(expected, *received.lock().unwrap());
+    }
+
+    /// 创建开启回显的 WASM 实例。Host 收到单字节消息 `n` 后，若 `n > 0`，则在回调中嵌套发送 `n - 1`，
+    /// 从而形成 guest→host→guest→host 的嵌套调用链
+    fn chain_prepare(transport: Transport, async_mode: bool) -> (Arc<LowLevelCtx<WasiCtx>>, Store<WasiCtx>, Instance, Arc<Mutex<Vec<u8>>>) {
+        let Context { mut store, module, mut linker } = if async_mode {
+            guest_prepare_async(1000)
+        } else {
+            guest_prepare()
+        };
+
+        // 创建 Ctx，回调中通过弱引用嵌套调用
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let this = Arc::new(Mutex::new(Weak::<LowLevelCtx<WasiCtx>>::new()));
+        let mut ctx = LowLevelCtx::new();
+        ctx.set_transport(transport);
+        ctx.set_async_mode(async_mode);
+        let received_cb = received.clone();
+        let this_cb = this.clone();
+        ctx.set_message_callback(move |msg| {
+            received_cb.lock().unwrap().push(msg[0]);
+            if msg[0] > 0 {
+                let ctx = this_cb.lock().unwrap().upgrade().unwrap();
+                ctx.send_message_to_wasm(&[msg[0] - 1]).unwrap();
+            }
+        });
+        let ctx = Arc::new(ctx);
+        *this.lock().unwrap() = Arc::downgrade(&ctx);
+
+        // Linker
+        ctx.clone().add_to_linker(&mut linker).unwrap();
+
+        // 实例化
+        let instance = if async_mode {
+            block_on(linker.instantiate_async(&mut store, &module)).unwrap()
+        } else {
+            linker.instantiate(&mut store, &module).unwrap()
+        };
+        ctx.attach(&mut store, &instance).unwrap();
+        let set_echo = instance
+            .get_typed_func::<u32, (), _>(&mut store, "set_echo")
+            .unwrap();
+        if async_mode {
+            block_on(set_echo.call_async(&mut store, 1)).unwrap();
+        } else {
+            set_echo.call(&mut store, 1).unwrap();
+        }
+
+        (ctx, store, instance, received)
+    }
+
+    #[test]
+    fn test_nested_chain_from_wasm() {
+        let (_ctx, mut store, instance, received) = chain_prepare(Transport::Call, false);
+
+        // 直接调用 WASM，嵌套调用只能借用 Host 函数的 Caller
+        let start = instance
+            .get_typed_func::<u32, (), _>(&mut store, "test_send_chain")
+            .unwrap();
+        start.call(&mut store, 16).unwrap();
+        assert_eq!((0..=16).rev().collect::<Vec<u8>>(), *received.lock().unwrap());
+    }
+
+    #[test]
+    fn test_nested_chain_from_host() {
+        for transport in [Transport::Call, Transport::Ring { capacity: 64 }] {
+            let (ctx, store, _instance, received) = chain_prepare(transport, false);
+
+            // 通过移入的 Store 发起调用链
+            ctx.move_store(store);
+            ctx.wasm_main().unwrap();
+            ctx.send_message_to_wasm(&[16]).unwrap();
+            assert_eq!((0..=16).rev().collect::<Vec<u8>>(), *received.lock().unwrap());
+
+            // 调用链结束后 Store 被放回，Caller 均已注销
+            assert!(ctx.take_store().is_some());
+            assert!(matches!(ctx.send_message_to_wasm(&[0]), Err(Error::NoStore)));
+        }
+    }
+
+    #[test]
+    fn test_async_send_message_to_wasm() {
+        let Context { mut store, module, mut linker } = guest_prepare_async(100);
+
+        // 创建 Ctx
+        let mut ctx = LowLevelCtx::new();
+        ctx.set_async_mode(true);
+        let ctx = Arc::new(ctx);
+
+        // Linker
+        ctx.clone().add_to_linker(&mut linker).unwrap();
+
+        // 实例化
+        let instance = block_on(linker.instantiate_async(&mut store, &module)).unwrap();
+        ctx.attach(&mut store, &instance).unwrap();
+
+        // 发送消息，WASM 执行期间让出执行
+        ctx.move_store(store);
+        let waker = noop_waker();
+        let mut cx = task::Context::from_waker(&waker);
+        let mut future = Box::pin(ctx.send_message_to_wasm_async("hello, wasm!".as_bytes()));
+        let mut yields = 0;
+        let result = loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(result) => break result,
+                Poll::Pending => yields += 1,
+            }
+        };
+        result.unwrap();
+        assert!(yields > 0);
+
+        // 让出期间 Store 被取出，完成后放回
+        drop(future);
+        let mut store = ctx.take_store().unwrap();
+
+        // 检查结果
+        let check = instance
+            .get_typed_func::<(), u32, _>(&mut store, "get_receive_check")
+            .unwrap();
+        assert_eq!(1, block_on(check.call_async(&mut store, ())).unwrap());
+    }
+
+    #[test]
+    fn test_async_nested_chain() {
+        for transport in [Transport::Call, Transport::Ring { capacity: 64 }] {
+            let (ctx, store, _instance, received) = chain_prepare(transport, true);
+
+            // 回调中的嵌套调用使用同步接口，在 WASM 的执行中阻塞完成
+            ctx.move_store(store);
+            block_on(ctx.wasm_main_async()).unwrap();
+            block_on(ctx.send_message_to_wasm_async(&[16])).unwrap();
+            assert_eq!((0..=16).rev().collect::<Vec<u8>>(), *received.lock().unwrap());
+        }
+    }
+
+    /// 创建带有 CPU 预算的 WASM 实例
+    fn limited_prepare(config: &Config, limits: CpuLimits) -> (Arc<LowLevelCtx<WasiCtx>>, Engine) {
+        let engine = Engine::new(config).unwrap();
+        let Context { mut store, module, mut linker } = guest_prepare_with(engine.clone());
+
+        // 实例化期间不限制
+        if let Some(fuel) = limits.fuel_per_call {
+            store.add_fuel(fuel).unwrap();
+        }
+        store.set_epoch_deadline(1 << 32);
+
+        // 创建 Ctx
+        let mut ctx = LowLevelCtx::new();
+        ctx.set_cpu_limits(limits);
+        let ctx = Arc::new(ctx);
+
+        // Linker
+        ctx.clone().add_to_linker(&mut linker).unwrap();
+
+        // 实例化
+        let instance = linker.instantiate(&mut store, &module).unwrap();
+        ctx.attach(&mut store, &instance).unwrap();
+        ctx.move_store(store);
+
+        (ctx, engine)
+    }
+
+    #[test]
+    fn test_fuel_limit() {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        let limits = CpuLimits {
+            fuel_per_call: Some(1_000_000),
+            ..Default::default()
+        };
+        let (ctx, _engine) = limited_prepare(&config, limits);
+
+        // 预算在每次调用前重置，正常的调用不受影响
+        for _ in 0..3 {
+            ctx.send_message_to_wasm("hello, wasm!".as_bytes()).unwrap();
+        }
+        assert!(matches!(ctx.send_message_to_wasm(b"spin"), Err(Error::CpuExhausted)));
+    }
+
+    #[test]
+    fn test_epoch_limit() {
+        let mut config = Config::new();
+        config.epoch_interruption(true);
+        let limits = CpuLimits {
+            epoch_per_call: Some(2),
+            ..Default::default()
+        };
+        let (ctx, engine) = limited_prepare(&config, limits);
+
+        // 定期推进 epoch
+        std::thread::spawn(move || loop {
+            std::thread::sleep(Duration::from_millis(10));
+            engine.increment_epoch();
+        });
+
+        ctx.send_message_to_wasm("hello, wasm!".as_bytes()).unwrap();
+        assert!(matches!(ctx.send_message_to_wasm(b"spin"), Err(Error::CpuExhausted)));
+    }
+
+    #[test]
+    fn test_async_fuel_limit() {
+        let mut config = Config::new();
+        config.async_support(true).consume_fuel(true);
+        let Context { mut store, module, mut linker } = guest_prepare_with(Engine::new(&config).unwrap());
+        store.out_of_fuel_async_yield(u64::MAX, 1000);
+
+        // 创建 Ctx
+        let mut ctx = LowLevelCtx::new();
+        ctx.set_async_mode(true);
+        ctx.set_cpu_limits(CpuLimits {
+            fuel_per_call: Some(1_000_000),
+            fuel_per_yield: Some(1000),
+            ..Default::default()
+        });
+        let ctx = Arc::new(ctx);
+
+        // Linker
+        ctx.clone().add_to_linker(&mut linker).unwrap();
+
+        // 实例化
+        let instance = block_on(linker.instantiate_async(&mut store, &module)).unwrap();
+        ctx.attach(&mut store, &instance).unwrap();
+        ctx.move_store(store);
+
+        // 超出预算前多次让出执行
+        block_on(ctx.send_message_to_wasm_async("hello, wasm!".as_bytes())).unwrap();
+        let result = block_on(ctx.send_message_to_wasm_async(b"spin"));
+        assert!(matches!(result, Err(Error::CpuExhausted)));
+    }
+
+    /// 以任意参数直接调用 Host 函数的模块
+    const HOSTILE_WAT: &str = r#"
+        (module
+            (import "__bc_low_level" "receive_message_from_wasm" (func $receive (param i32 i32)))
+            (import "__bc_low_level" "receive_batch_from_wasm" (func $receive_batch (param i32 i32)))
+            (import "__bc_low_level" "read_initial_state" (func $read_initial_state (param i32)))
+            (memory (export "memory") 1)
+            (func (export "bad_message") (call $receive (i32.const 65530) (i32.const 64)))
+            (func (export "bad_batch") (call $receive_batch (i32.const 0) (i32.const 3)))
+            (func (export "bad_initial_state") (call $read_initial_state (i32.const 65530))))
+    "#;
+
+    /// 实例化 `HOSTILE_WAT`，模块未导出低层所需的函数，因此不调用 `attach`
+    fn hostile_prepare(ctx: Arc<LowLevelCtx<()>>) -> (Store<()>, Instance) {
+        let engine = Engine::default();
+        let module = wasmtime::Module::new(&engine, HOSTILE_WAT).unwrap();
+        let mut linker = Linker::new(&engine);
+        ctx.add_to_linker(&mut linker).unwrap();
+        let mut store = Store::new(&engine, ());
+        let instance = linker.instantiate(&mut store, &module).unwrap();
+        (store, instance)
+    }
+
+    #[test]
+    fn test_bad_message_traps() {
+        let mut ctx = LowLevelCtx::new();
+        ctx.set_message_callback(|_| panic!("不应收到消息"));
+        let (mut store, instance) = hostile_prepare(Arc::new(ctx));
+
+        // 越界的消息使 WASM 发生 Trap，而不是使 Host panic
+        let func = instance.get_typed_func::<(), (), _>(&mut store, "bad_message").unwrap();
+        assert!(func.call(&mut store, ()).is_err());
+    }
+
+    #[test]
+    fn test_bad_batch_traps() {
+        let mut ctx = LowLevelCtx::new();
+        ctx.set_message_callback(|_| panic!("不应收到消息"));
+        let (mut store, instance) = hostile_prepare(Arc::new(ctx));
+
+        // 不完整的批量消息使 WASM 发生 Trap
+        let func = instance.get_typed_func::<(), (), _>(&mut store, "bad_batch").unwrap();
+        assert!(func.call(&mut store, ()).is_err());
+    }
+
+    #[test]
+    fn test_bad_initial_state_traps() {
+        let ctx = LowLevelCtx::new();
+        ctx.set_initial_state(vec![0; 64]);
+        let (mut store, instance) = hostile_prepare(Arc::new(ctx));
+
+        // 初始状态写入越界时 WASM 发生 Trap
+        let func = instance.get_typed_func::<(), (), _>(&mut store, "bad_initial_state").unwrap();
+        assert!(func.call(&mut store, ()).is_err());
+    }
+
+    #[test]
+    fn test_trap_info() {
+        let Context { mut store, module, mut linker } = guest_prepare();
+
+        // 创建 Ctx
+        let ctx = Arc::new(LowLevelCtx::new());
+
+        // Linker
+        ctx.clone().add_to_linker(&mut linker).unwrap();
+
+        // 实例化
+        let instance = linker.instantiate(&mut store, &module).unwrap();
+        ctx.attach(&mut store, &instance).unwrap();
+        ctx.move_store(store);
+
+        // Trap 带有 WASM 调用栈
+        let e = ctx.send_message_to_wasm(b"trap").unwrap_err();
+        assert!(matches!(e, Error::Trap(_)));
+        let trap = TrapInfo::from(&e);
+        assert!(!trap.backtrace.is_empty());
+        assert!(trap.to_string().contains("wasm backtrace:"));
+
+        // 其他错误没有调用栈
+        let trap = TrapInfo::from(&Error::NoStore);
+        assert!(trap.backtrace.is_empty());
+    }
 }
+
@@ -2,14 +2,15 @@
 
 #[cfg(not(target_arch = "wasm32"))]
 pub mod host;
+#[cfg(not(target_arch = "wasm32"))]
+mod caller;
 
 pub mod wasm;
+pub mod ring;
+pub mod batch;
+mod error;
 
-// FIXME: 此处的错误类型仅仅是最简单，可用于容纳任何错误的类型。而实际上好的错误类型
-//        应该囊括更加细节的错误信息。此处仅为适应短时间的开发需求而临时设计。
-pub type Error = Box<dyn std::error::Error + Send + Sync>;
-
-pub type Result<T> = std::result::Result<T, Error>;
+pub use error::*;
 
 #[cfg(test)]
 #[cfg(not(target_arch = "wasm32"))]
@@ -25,7 +26,20 @@ mod tests {
     }
 
     pub fn guest_prepare() -> Context<WasiCtx> {
-        let engine = Engine::default();
+        guest_prepare_with(Engine::default())
+    }
+
+    /// 启用异步支持，WASM 每消耗 `fuel_per_yield` 单位燃料让出一次执行
+    pub fn guest_prepare_async(fuel_per_yield: u64) -> Context<WasiCtx> {
+        let mut config = Config::new();
+        config.async_support(true).consume_fuel(true);
+        let mut context = guest_prepare_with(Engine::new(&config).unwrap());
+        context.store.out_of_fuel_async_yield(u64::MAX, fuel_per_yield);
+        context
+    }
+
+    /// 使用所给的 Engine 创建测试用的 WASM guest 模块
+    pub fn guest_prepare_with(engine: Engine) -> Context<WasiCtx> {
         let mut linker = Linker::new(&engine);
 
         // 链接 WASI 函数
@@ -51,6 +65,10 @@ mod tests {
         let Context { mut store, module, mut linker } = guest_prepare();
         linker.func_wrap("__bc_low_level", "receive_message_from_wasm",
                          |_: Caller<'_, _>, _: i32, _: i32| {}).unwrap();
+        linker.func_wrap("__bc_low_level", "receive_batch_from_wasm",
+                         |_: Caller<'_, _>, _: i32, _: i32| {}).unwrap();
+        linker.func_wrap("__bc_low_level", "ring_doorbell",
+                         |_: Caller<'_, _>| {}).unwrap();
         let instance = linker.instantiate(&mut store, &module).unwrap();
 
         let alloc_signal_buffer =
@@ -0,0 +1,260 @@
+//! 位于 WASM 线性内存中的环形缓冲区，用于在 Host 与 WASM 之间批量传递消息
+//!
+//! 环形缓冲区由 WASM 分配，双方直接读写其中的数据，函数调用仅用于通知对方处理消息（敲门）。
+//! 由于 WASM 总是在 Host 的调用中运行，双方不会同时访问缓冲区，因此无需原子操作。
+//!
+//! 单个环形缓冲区的布局为：
+//!
+//! ```text
+//! | 读位置 (u32) | 写位置 (u32) | 数据区容量 (u32) | 标志 (u32) | 数据区 |
+//! ```
+//!
+//! 数据区中的每条消息以 `消息长度 (u32) | 消息内容` 的形式存放，且总是连续的。若数据区末尾
+//! 的剩余空间不足以存放一条消息，则写入填充标记，并从数据区开头继续写入。整数均为小端序。
+
+use crate::{Error, Result};
+
+/// 环形缓冲区头部的长度
+pub const HEADER_LEN: usize = 16;
+
+/// 默认的数据区容量
+pub const DEFAULT_CAPACITY: usize = 64 << 10;
+
+/// 消息长度字段的长度
+const LEN_SIZE: usize = std::mem::size_of::<u32>();
+
+/// 填充标记，表示数据区剩余的部分不含消息
+const PADDING: u32 = u32::MAX;
+
+const HEAD: usize = 0;
+const TAIL: usize = 4;
+const CAPACITY: usize = 8;
+const FLAGS: usize = 12;
+
+/// 标志：读取方会在当前调用结束后处理消息，写入方无需敲门
+pub const FLAG_DEFERRED: u32 = 1;
+
+/// 数据区容量为 `capacity` 时，环形缓冲区所占的内存长度
+pub fn ring_len(capacity: usize) -> usize {
+    HEADER_LEN + capacity
+}
+
+/// 对一段内存中的环形缓冲区的访问
+///
+/// 所借用的内存包含头部与数据区，其内容可能由对方写入，因此读取时总是检查其合法性。
+pub struct RingBuffer<'a> {
+    mem: &'a mut [u8],
+}
+
+impl<'a> RingBuffer<'a> {
+    /// 在所给的内存中初始化一个空的环形缓冲区，数据区占据头部之后的全部内存
+    pub fn init(mem: &'a mut [u8]) -> Result<Self> {
+        let capacity = mem.len().checked_sub(HEADER_LEN)
+            .filter(|capacity| *capacity > LEN_SIZE)
+            .ok_or(Error::CorruptedRing)?;
+        let mut ring = RingBuffer { mem };
+        ring.write_u32(HEAD, 0);
+        ring.write_u32(TAIL, 0);
+        ring.write_u32(CAPACITY, capacity as u32);
+        ring.write_u32(FLAGS, 0);
+        Ok(ring)
+    }
+
+    /// 访问所给内存中已经初始化的环形缓冲区
+    pub fn attach(mem: &'a mut [u8]) -> Result<Self> {
+        let ring = RingBuffer { mem };
+        let capacity = ring.capacity();
+        if ring.mem.len() != ring_len(capacity) || ring.head() >= capacity || ring.tail() >= capacity {
+            return Err(Error::CorruptedRing);
+        }
+        Ok(ring)
+    }
+
+    fn read_u32(&self, offset: usize) -> u32 {
+        u32::from_le_bytes(self.mem[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn write_u32(&mut self, offset: usize, value: u32) {
+        self.mem[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn head(&self) -> usize {
+        self.read_u32(HEAD) as usize
+    }
+
+    fn tail(&self) -> usize {
+        self.read_u32(TAIL) as usize
+    }
+
+    /// 数据区容量
+    pub fn capacity(&self) -> usize {
+        self.read_u32(CAPACITY) as usize
+    }
+
+    pub fn flags(&self) -> u32 {
+        self.read_u32(FLAGS)
+    }
+
+    pub fn set_flags(&mut self, flags: u32) {
+        self.write_u32(FLAGS, flags);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head() == self.tail()
+    }
+
+    /// 空的环形缓冲区所能容纳的最长消息
+    pub fn max_message_len(&self) -> usize {
+        // 保留一个字节以区分缓冲区满与空
+        self.capacity() - 1 - LEN_SIZE
+    }
+
+    /// 把一条消息写入环形缓冲区，剩余空间不足时返回 `false`
+    pub fn push(&mut self, msg: &[u8]) -> bool {
+        let (head, tail, capacity) = (self.head(), self.tail(), self.capacity());
+        let frame_len = LEN_SIZE + msg.len();
+
+        let start = if tail >= head {
+            // 读位置为 0 时，写满数据区末尾会使写位置回到 0，因此需要保留一个字节
+            let end_space = capacity - tail - (head == 0) as usize;
+            if frame_len <= end_space {
+                tail
+            } else if frame_len < head {
+                // 末尾空间不足，写入填充标记后从数据区开头写入
+                if capacity - tail >= LEN_SIZE {
+                    self.write_u32(HEADER_LEN + tail, PADDING);
+                }
+                0
+            } else {
+                return false;
+            }
+        } else if frame_len < head - tail {
+            tail
+        } else {
+            return false;
+        };
+
+        let offset = HEADER_LEN + start;
+        self.write_u32(offset, msg.len() as u32);
+        self.mem[offset + LEN_SIZE..offset + frame_len].copy_from_slice(msg);
+        self.write_u32(TAIL, ((start + frame_len) % capacity) as u32);
+        true
+    }
+
+    /// 第一条消息在所借用内存中的范围，缓冲区为空时返回 `None`
+    fn front(&self) -> Result<Option<(usize, usize)>> {
+        let (mut head, tail, capacity) = (self.head(), self.tail(), self.capacity());
+        if head == tail {
+            return Ok(None);
+        }
+
+        // 跳过数据区末尾的填充
+        if capacity - head < LEN_SIZE || self.read_u32(HEADER_LEN + head) == PADDING {
+            if tail > head {
+                return Err(Error::CorruptedRing);
+            }
+            head = 0;
+        }
+
+        let start = HEADER_LEN + head + LEN_SIZE;
+        let len = self.read_u32(HEADER_LEN + head) as usize;
+        // 消息不能越过写位置或数据区末尾
+        let end = head + LEN_SIZE + len;
+        let limit = if tail > head { tail } else { capacity };
+        if end > limit {
+            return Err(Error::CorruptedRing);
+        }
+        Ok(Some((start, len)))
+    }
+
+    /// 第一条消息的内容，缓冲区为空时返回 `None`
+    pub fn peek(&self) -> Result<Option<&[u8]>> {
+        Ok(self.front()?.map(|(start, len)| &self.mem[start..start + len]))
+    }
+
+    /// 移除第一条消息
+    pub fn pop(&mut self) -> Result<()> {
+        if let Some((start, len)) = self.front()? {
+            let capacity = self.capacity();
+            let head = (start - HEADER_LEN + len) % capacity;
+            if head == self.tail() {
+                // 缓冲区已空，回到数据区开头，使之后的消息可以使用连续的全部空间
+                self.write_u32(HEAD, 0);
+                self.write_u32(TAIL, 0);
+            } else {
+                self.write_u32(HEAD, head as u32);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pop_vec(ring: &mut RingBuffer) -> Option<Vec<u8>> {
+        let msg = ring.peek().unwrap().map(|msg| msg.to_vec());
+        ring.pop().unwrap();
+        msg
+    }
+
+    #[test]
+    fn test_push_pop() {
+        let mut mem = vec![0u8; ring_len(64)];
+        let mut ring = RingBuffer::init(&mut mem).unwrap();
+        assert!(ring.is_empty());
+        assert_eq!(59, ring.max_message_len());
+
+        assert!(ring.push(b"hello"));
+        assert!(ring.push(b""));
+        assert!(ring.push(b"world"));
+        assert_eq!(Some(b"hello".to_vec()), pop_vec(&mut ring));
+        assert_eq!(Some(vec![]), pop_vec(&mut ring));
+        assert_eq!(Some(b"world".to_vec()), pop_vec(&mut ring));
+        assert_eq!(None, pop_vec(&mut ring));
+
+        // 可以重新访问已经初始化的缓冲区
+        assert!(ring.push(b"again"));
+        let mut ring = RingBuffer::attach(&mut mem).unwrap();
+        assert_eq!(Some(b"again".to_vec()), pop_vec(&mut ring));
+    }
+
+    #[test]
+    fn test_full_and_wrap() {
+        let mut mem = vec![0u8; ring_len(32)];
+        let mut ring = RingBuffer::init(&mut mem).unwrap();
+
+        // 最长的消息恰好可以写入空的缓冲区
+        assert!(!ring.push(&[0; 28]));
+        assert!(ring.push(&[1; 27]));
+        assert!(!ring.push(b""));
+        assert_eq!(Some(vec![1; 27]), pop_vec(&mut ring));
+
+        // 使读写位置移动到数据区中部，之后的消息需要回绕到开头
+        assert!(ring.push(&[2; 10]));
+        assert!(ring.push(&[3; 10]));
+        assert_eq!(Some(vec![2; 10]), pop_vec(&mut ring));
+        assert!(ring.push(&[4; 8]));
+        assert!(!ring.push(&[5; 8]));
+        assert_eq!(Some(vec![3; 10]), pop_vec(&mut ring));
+        assert_eq!(Some(vec![4; 8]), pop_vec(&mut ring));
+        assert!(ring.is_empty());
+    }
+
+    #[test]
+    fn test_corrupted() {
+        let mut mem = vec![0u8; 4];
+        assert!(matches!(RingBuffer::init(&mut mem), Err(Error::CorruptedRing)));
+
+        let mut mem = vec![0u8; ring_len(32)];
+        RingBuffer::init(&mut mem).unwrap().push(b"hello");
+        // 消息长度越过写位置
+        mem[HEADER_LEN] = 30;
+        let ring = RingBuffer::attach(&mut mem).unwrap();
+        assert!(matches!(ring.peek(), Err(Error::CorruptedRing)));
+        // 读位置越界
+        mem[HEAD] = 32;
+        assert!(matches!(RingBuffer::attach(&mut mem), Err(Error::CorruptedRing)));
+    }
+}
@@ -2,12 +2,141 @@
 
 #[cfg(target_arch = "wasm32")]
 use std::alloc::{self, Layout};
+use std::cell::{Cell, RefCell};
 
+use crate::batch;
+use crate::ring::{self, RingBuffer};
+#[cfg(target_arch = "wasm32")]
+use crate::ring::FLAG_DEFERRED;
 use crate::Result;
 
+/// Host 至 WASM 的环形缓冲区的序号
+const HOST_TO_WASM: usize = 0;
+/// WASM 至 Host 的环形缓冲区的序号
+#[cfg(target_arch = "wasm32")]
+const WASM_TO_HOST: usize = 1;
+
+thread_local! {
+    /// 环形缓冲区所在内存块的地址及单个环形缓冲区的长度，未启用环形缓冲区时为 `None`
+    static RINGS: Cell<Option<(usize, usize)>> = const { Cell::new(None) };
+    /// 是否正在处理 Host 经由环形缓冲区发送的消息
+    static DRAINING: Cell<bool> = const { Cell::new(false) };
+    /// 批量发送期间暂存的发往 Host 的消息，不在批量发送期间时为 `None`
+    static OUTBOX: RefCell<Option<Vec<Vec<u8>>>> = const { RefCell::new(None) };
+}
+
+/// 访问指定序号的环形缓冲区。返回值借用的内存可能被 Host 修改，因此不能跨越对 Host 的调用持有
+fn ring_at(index: usize) -> Option<RingBuffer<'static>> {
+    let (base, len) = RINGS.with(|rings| rings.get())?;
+    let mem = unsafe {
+        std::slice::from_raw_parts_mut((base + index * len) as *mut u8, len)
+    };
+    RingBuffer::attach(mem).ok()
+}
+
+/// 分配一对数据区容量为 `capacity` 的环形缓冲区，之后的消息均经由环形缓冲区传递
+///
+/// 返回内存块的地址。内存块中依次为 Host 至 WASM、WASM 至 Host 的环形缓冲区。
+#[doc(hidden)]
+pub fn init_rings(capacity: usize) -> Result<usize> {
+    let len = ring::ring_len(capacity);
+    let mem: &'static mut [u8] = Vec::leak(vec![0u8; len * 2]);
+    let (host_to_wasm, wasm_to_host) = mem.split_at_mut(len);
+    RingBuffer::init(host_to_wasm)?;
+    RingBuffer::init(wasm_to_host)?;
+
+    let base = host_to_wasm.as_ptr() as usize;
+    RINGS.with(|rings| rings.set(Some((base, len))));
+    Ok(base)
+}
+
+/// 把消息写入 WASM 至 Host 的环形缓冲区，剩余空间不足时返回 `false`
+#[cfg(target_arch = "wasm32")]
+fn push_ring(msg: &[u8]) -> bool {
+    match ring_at(WASM_TO_HOST) {
+        Some(mut ring) => ring.push(msg),
+        None => false,
+    }
+}
+
+/// 经由环形缓冲区把消息发送至 Host，未启用环形缓冲区或消息过长时返回 `false`
+///
+/// 写入后需调用 `notify_host` 通知 Host 处理消息，连续写入多条消息时只需通知一次。
+#[cfg(target_arch = "wasm32")]
+fn push_to_host(msg: &[u8], doorbell: impl Fn()) -> bool {
+    if ring_at(WASM_TO_HOST).is_none() {
+        return false;
+    }
+    if !push_ring(msg) {
+        // 空间不足，通知 Host 取走缓冲区中的消息后重试。若仍无法写入，则说明消息过长
+        doorbell();
+        if !push_ring(msg) {
+            return false;
+        }
+    }
+    true
+}
+
+/// 通知 Host 处理环形缓冲区中的消息。若 Host 会在当前调用结束后处理消息，则无需调用 `doorbell`
+#[cfg(target_arch = "wasm32")]
+fn notify_host(doorbell: impl Fn()) {
+    match ring_at(WASM_TO_HOST) {
+        Some(ring) if ring.flags() & FLAG_DEFERRED != 0 => {}
+        _ => doorbell(),
+    }
+}
+
+/// 处理 Host 经由环形缓冲区发送的全部消息，供 `set_message_callback!` 生成的敲门函数使用
+///
+/// 消息直接借用环形缓冲区中的数据，在回调返回后才从缓冲区中移除。回调中 Host 再次敲门时，
+/// 新的消息由外层的循环处理；缓冲区已满时，Host 暂存消息，待本次处理结束后再写入，
+/// 而不是通过函数调用直接发送，以免先于缓冲区中的消息到达。
+#[doc(hidden)]
+pub fn drain_host_messages(cb: impl Fn(&[u8])) {
+    if DRAINING.with(|draining| draining.replace(true)) {
+        return;
+    }
+
+    while let Some(ring) = ring_at(HOST_TO_WASM) {
+        let (msg, msg_len) = match ring.peek() {
+            Ok(Some(msg)) => (msg.as_ptr(), msg.len()),
+            _ => break,
+        };
+        let msg = unsafe {
+            std::slice::from_raw_parts(msg, msg_len)
+        };
+        cb(msg);
+
+        if let Some(mut ring) = ring_at(HOST_TO_WASM) {
+            ring.pop().unwrap();
+        }
+    }
+
+    DRAINING.with(|draining| draining.set(false));
+}
+
+/// 处理 Host 通过一次调用发送的批量消息，供 `set_message_callback!` 生成的处理函数使用
+#[doc(hidden)]
+pub fn dispatch_batch(batch: &[u8], cb: impl Fn(&[u8])) -> Result<()> {
+    for msg in batch::decode(batch)? {
+        cb(msg);
+    }
+    Ok(())
+}
+
+/// 由 Host 调用，分配环形缓冲区。参见 `init_rings`
+#[doc(hidden)]
+#[cfg(target_arch = "wasm32")]
+#[no_mangle]
+pub extern "C" fn __bc_low_level_ring_init(capacity: usize) -> usize {
+    init_rings(capacity).unwrap()
+}
+
 /// 将一段缓冲区由 WASM 发送至 Host。与 `low_level::host::LowLevelCtx::set_message_callback`
 /// 函数设置的回调函数相对应，共同完成消息的发送与接收。
 ///
+/// Host 启用环形缓冲区时，消息经由环形缓冲区发送，过长的消息仍通过函数调用直接发送。
+///
 /// ## 使用示例
 ///
 /// ```rust
@@ -18,24 +147,129 @@ use crate::Result;
 ///
 #[allow(unused_variables)]
 pub fn send_message_to_host(msg: &[u8]) -> Result<()> {
+    // 批量发送期间暂存消息
+    let buffered = OUTBOX.with(|outbox| {
+        outbox.borrow_mut().as_mut()
+            .map(|outbox| outbox.push(msg.to_vec()))
+            .is_some()
+    });
+    if buffered {
+        return Ok(());
+    }
+
     #[cfg(target_arch = "wasm32")]
     {
-        #[link(wasm_import_module = "__bc_low_level")]
-        extern "C" {
-            #[link_name = "receive_message_from_wasm"]
-            fn host_message_handler(msg: *const u8, msg_len: usize);
+        if push_to_host(msg, || unsafe { host::ring_doorbell() }) {
+            notify_host(|| unsafe { host::ring_doorbell() });
+            return Ok(());
         }
 
         unsafe {
-            host_message_handler(msg.as_ptr(), msg.len());
+            host::receive_message_from_wasm(msg.as_ptr(), msg.len());
         }
     }
     Ok(())
 }
 
+/// 将多条消息由 WASM 发送至 Host，与逐条调用 `send_message_to_host` 等价，但只调用一次 Host
+///
+/// Host 启用环形缓冲区时，消息全部写入环形缓冲区后只通知 Host 一次。
+#[allow(unused_variables)]
+pub fn send_messages_to_host<'a>(messages: impl IntoIterator<Item=&'a [u8]>) -> Result<()> {
+    #[cfg(target_arch = "wasm32")]
+    {
+        if ring_at(WASM_TO_HOST).is_some() {
+            let mut pending = false;
+            for msg in messages {
+                if push_to_host(msg, || unsafe { host::ring_doorbell() }) {
+                    pending = true;
+                } else {
+                    // 消息过长，此时缓冲区中的消息均已被 Host 取走，直接发送不会打乱顺序
+                    unsafe {
+                        host::receive_message_from_wasm(msg.as_ptr(), msg.len());
+                    }
+                    pending = false;
+                }
+            }
+            if pending {
+                notify_host(|| unsafe { host::ring_doorbell() });
+            }
+            return Ok(());
+        }
+
+        let messages: Vec<_> = messages.into_iter().collect();
+        match messages.as_slice() {
+            [] => {}
+            [msg] => unsafe {
+                host::receive_message_from_wasm(msg.as_ptr(), msg.len());
+            },
+            _ => {
+                let batch = batch::encode(messages);
+                unsafe {
+                    host::receive_batch_from_wasm(batch.as_ptr(), batch.len());
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 在 `f` 执行期间暂存发往 Host 的消息，并在 `f` 返回后通过 `send_messages_to_host` 一并发送
+///
+/// 嵌套使用时，消息在最外层返回后发送。
+pub fn batch_messages_to_host<R>(f: impl FnOnce() -> R) -> Result<R> {
+    let nested = OUTBOX.with(|outbox| {
+        let mut outbox = outbox.borrow_mut();
+        let nested = outbox.is_some();
+        outbox.get_or_insert_with(Vec::new);
+        nested
+    });
+    let ret = f();
+    if !nested {
+        let messages = OUTBOX.with(|outbox| outbox.take()).unwrap_or_default();
+        send_messages_to_host(messages.iter().map(Vec::as_slice))?;
+    }
+    Ok(ret)
+}
+
+/// 读取 Host 通过 `low_level::host::LowLevelCtx::set_initial_state` 设置的初始状态
+///
+/// 初始状态只能读取一次，未设置或已被读取时返回 `None`。
+pub fn take_initial_state() -> Option<Vec<u8>> {
+    #[cfg(target_arch = "wasm32")]
+    {
+        let len = unsafe { host::initial_state_len() };
+        if len < 0 {
+            return None;
+        }
+        let mut state = vec![0u8; len as usize];
+        unsafe {
+            host::read_initial_state(state.as_mut_ptr());
+        }
+        Some(state)
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    None
+}
+
+/// Host 提供的消息处理函数
+#[cfg(target_arch = "wasm32")]
+mod host {
+    #[link(wasm_import_module = "__bc_low_level")]
+    extern "C" {
+        pub fn receive_message_from_wasm(msg: *const u8, msg_len: usize);
+        pub fn receive_batch_from_wasm(batch: *const u8, batch_len: usize);
+        pub fn ring_doorbell();
+        pub fn initial_state_len() -> i32;
+        pub fn read_initial_state(state: *mut u8);
+    }
+}
+
 /// 设置接受 Host 模块消息的回调函数。与 `low_level::host::LowLevelCtx::send_message_to_wasm`
 /// 函数相对应，共同完成消息的发送与接收。
 ///
+/// 同时生成供 Host 调用的批量消息处理函数，以及用于处理经由环形缓冲区发送的消息的敲门函数。
+///
 /// ## 使用示例
 ///
 /// ```rust
@@ -59,6 +293,23 @@ macro_rules! set_message_callback {
             };
             $cb(msg);
         }
+
+        #[cfg(target_arch = "wasm32")]
+        #[no_mangle]
+        pub extern "C" fn __bc_low_level_host_batch_handler(batch: *const u8, batch_len: usize) {
+            let batch = unsafe {
+                std::slice::from_raw_parts(batch, batch_len)
+            };
+            if let Err(e) = $crate::wasm::dispatch_batch(batch, $cb) {
+                eprintln!("[LowLevel]: dispatch batch error: {}, discard!", e);
+            }
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        #[no_mangle]
+        pub extern "C" fn __bc_low_level_ring_doorbell() {
+            $crate::wasm::drain_host_messages($cb);
+        }
     }
 }
 
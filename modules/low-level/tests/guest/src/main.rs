@@ -27,10 +27,46 @@ pub extern "C" fn test_send_message() {
     wasm::send_message_to_host("hello, host!".as_bytes()).unwrap();
 }
 
+/// 通过一次调用发送多条消息至 host，用于对 `low_level::wasm::send_messages_to_host` 进行测试
+#[no_mangle]
+pub extern "C" fn test_send_messages() {
+    let messages: [&[u8]; 3] = [b"hello", b"", b"host"];
+    wasm::send_messages_to_host(messages).unwrap();
+}
+
+/// 发送单字节消息 `depth` 至 host，用于对 guest→host→guest→host 嵌套调用链进行测试
+#[no_mangle]
+pub extern "C" fn test_send_chain(depth: u32) {
+    wasm::send_message_to_host(&[depth as u8]).unwrap();
+}
+
 static mut RECV_CHECK: u32 = 0;
+static mut ECHO: u32 = 0;
+
+/// 设置是否把接收到的 host 消息原样发回，用于对环形缓冲区的测试与性能测试
+#[no_mangle]
+pub extern "C" fn set_echo(echo: u32) {
+    unsafe {
+        ECHO = echo;
+    }
+}
 
 /// 设置接收 host 消息的回调函数，用于对 `low_level::wasm::set_message_callback!` 进行测试
 fn receive_message_from_host(msg: &[u8]) {
+    // 收到 `trap` 时中止执行，用于对 Trap 处理的测试
+    if msg == b"trap" {
+        panic!("trap requested by host");
+    }
+    // 收到 `spin` 时陷入死循环，用于对 CPU 预算的测试
+    if msg == b"spin" {
+        loop {
+            std::hint::spin_loop();
+        }
+    }
+    if unsafe { ECHO } != 0 {
+        wasm::send_message_to_host(msg).unwrap();
+        return;
+    }
     println!("接收到 Host 消息：{:?}", msg);
 
     let expected = "hello, wasm!".as_bytes();
@@ -46,4 +82,8 @@ pub extern "C" fn get_receive_check() -> u32 {
     unsafe { RECV_CHECK }
 }
 
+/// 供 host 调用的入口，用于启用环形缓冲区
+#[no_mangle]
+pub extern "C" fn __bc_main() {}
+
 fn main() {}
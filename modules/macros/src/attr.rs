@@ -0,0 +1,73 @@
+//! 过程宏属性参数的解析
+
+use syn::{AttributeArgs, Lit, Meta, NestedMeta};
+
+/// `#[bc_export(...)]`、`#[bc_import(...)]` 的属性参数
+///
+/// - `host`：所修饰的函数位于 Host 端
+/// - `module = "name"`：链接目标为指定名称的 Bc Hostcall Module
+/// - `version = "^1.0"`：导入时对 `module` 的版本要求
+#[derive(Default)]
+pub struct BcAttr {
+    pub host: bool,
+    pub module: Option<String>,
+    pub version: Option<String>,
+}
+
+impl BcAttr {
+    pub fn parse(args: AttributeArgs) -> syn::Result<Self> {
+        let mut attr = BcAttr::default();
+
+        for arg in args {
+            match arg {
+                NestedMeta::Meta(Meta::Path(path)) if path.is_ident("host") => {
+                    attr.host = true;
+                }
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("module") => {
+                    match nv.lit {
+                        Lit::Str(name) => attr.module = Some(name.value()),
+                        lit => return Err(syn::Error::new_spanned(lit, "`module` 应该是字符串")),
+                    }
+                }
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("version") => {
+                    match nv.lit {
+                        Lit::Str(req) => attr.version = Some(req.value()),
+                        lit => return Err(syn::Error::new_spanned(lit, "`version` 应该是字符串")),
+                    }
+                }
+                arg => return Err(syn::Error::new_spanned(arg, "未知的属性参数")),
+            }
+        }
+
+        Ok(attr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use quote::quote;
+
+    use crate::test_util::*;
+
+    #[test]
+    fn test_parse() {
+        let attr = bc_attr(quote!()).unwrap();
+        assert!(!attr.host && attr.module.is_none() && attr.version.is_none());
+
+        let attr = bc_attr(quote!(host)).unwrap();
+        assert!(attr.host);
+
+        let attr = bc_attr(quote!(module = "service", version = "^1.0")).unwrap();
+        assert_eq!(Some("service".to_string()), attr.module);
+        assert_eq!(Some("^1.0".to_string()), attr.version);
+    }
+
+    #[test]
+    fn test_parse_error() {
+        let err = |tokens| bc_attr(tokens).err().unwrap().to_string();
+        assert_eq!("未知的属性参数", err(quote!(guest)));
+        assert_eq!("未知的属性参数", err(quote!(host = true)));
+        assert_eq!("`module` 应该是字符串", err(quote!(module = 1)));
+        assert_eq!("`version` 应该是字符串", err(quote!(module = "service", version = 1)));
+    }
+}
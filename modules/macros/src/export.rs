@@ -0,0 +1,339 @@
+//! `#[bc_export]` 与 `bc_export_module!` 的代码生成
+
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::{Expr, FnArg, ItemFn, Path, ReturnType, Token};
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+
+use crate::attr::BcAttr;
+use crate::ty::{is_byte_slice, is_str_ref};
+
+/// 为导出函数生成 `__bc_wrapper_*` 包装函数，原函数保持不变
+pub fn expand_export(attr: BcAttr, func: ItemFn) -> syn::Result<TokenStream> {
+    let name = &func.sig.ident;
+    let name_str = name.to_string();
+    let vis = &func.vis;
+    let wrapper = format_ident!("__bc_wrapper_{}", name);
+
+    if attr.version.is_some() {
+        return Err(syn::Error::new_spanned(&func.sig, "导出函数的版本由模块清单决定，无需指定 `version`"));
+    }
+
+    // 参数
+    let mut arg_names = Vec::new();
+    let mut arg_decodes = Vec::new();
+    let mut arg_values = Vec::new();
+    for (i, input) in func.sig.inputs.iter().enumerate() {
+        match input {
+            FnArg::Typed(pat) => {
+                let arg_name = format_ident!("__bc_arg{}", i);
+                // `&[u8]`、`&str` 尽可能借用报文中的数据，无法借用时使用副本，以引用传给函数。
+                // 其余类型的借用与否由类型自身决定
+                if is_byte_slice(&pat.ty) {
+                    arg_decodes.push(quote!(__bc_args.get::<bc_hostcall::serialize::Bytes>(#i)));
+                    arg_values.push(quote!(&#arg_name));
+                } else if is_str_ref(&pat.ty) {
+                    arg_decodes.push(quote!(__bc_args.get::<bc_hostcall::serialize::Str>(#i)));
+                    arg_values.push(quote!(&#arg_name));
+                } else {
+                    arg_decodes.push(quote!(__bc_args.get(#i)));
+                    arg_values.push(quote!(#arg_name));
+                }
+                arg_names.push(arg_name);
+            }
+            FnArg::Receiver(recv) => {
+                return Err(syn::Error::new_spanned(recv, "`#[bc_export]` 不能用于方法"));
+            }
+        }
+    }
+
+    // 返回值
+    let ret_ty = match &func.sig.output {
+        ReturnType::Default => quote!(()),
+        ReturnType::Type(_, ty) => quote!(#ty),
+    };
+
+    // 函数调用
+    let call = if func.sig.asyncness.is_some() {
+        quote!(#name(#(#arg_values),*).await)
+    } else {
+        quote!(#name(#(#arg_values),*))
+    };
+
+    // 函数标识符的链接提示
+    let hint = if attr.host {
+        quote!(bc_hostcall::rpc::abi::LinkHint::Host)
+    } else if let Some(module) = &attr.module {
+        quote!(bc_hostcall::rpc::abi::LinkHint::BcModule(#module.to_string()))
+    } else {
+        quote!(bc_hostcall::async_rt::rt::module_hint())
+    };
+
+    // 参数解析，失败时以 `InvalidArgs` 错误结果回送
+    let invalid_args = quote! {
+        |e| bc_hostcall::rpc::RpcError::new(bc_hostcall::rpc::RpcErrorCode::InvalidArgs, e.to_string())
+    };
+    let parse_args = quote! {
+        let __bc_args = bc_hostcall::serialize::Args::from_bytes(&__bc_ser_ctx, &__bc_args)
+            .map_err(#invalid_args)?;
+        #(
+            let #arg_names = #arg_decodes.map_err(#invalid_args)?;
+        )*
+    };
+
+    // 调用函数并序列化返回值
+    let call_serialized = quote! {
+        let __bc_result: #ret_ty = #call;
+        let __bc_serialized = __bc_ser_ctx.serialize(&__bc_result)
+            .map_err(|e| bc_hostcall::rpc::RpcError::new(
+                bc_hostcall::rpc::RpcErrorCode::CallFailed, e.to_string()));
+    };
+    // 生成返回报文，失败时向 RPC 层返回错误
+    let make_msg = quote! {
+        let __bc_msg = bc_hostcall::rpc::RpcResponseCtx::new(__bc_seq_no, &__bc_ser_ctx, &())
+            .make_result(__bc_func, __bc_serialized)?;
+    };
+    let make_response = quote! {
+        #parse_args
+        #call_serialized
+        #make_msg
+    };
+    // 异步函数在任务中持有参数数据的副本，参数借用该副本。参数解析失败时以错误结果回送
+    let call_async = quote! {
+        let __bc_args = __bc_args.to_vec();
+        let __bc_call = async move {
+            #parse_args
+            #call_serialized
+            __bc_serialized
+        };
+    };
+
+    let body = match (attr.host, func.sig.asyncness.is_some()) {
+        // Host 端的异步函数遵守调用请求的截止时间，超时后回送超时的错误结果
+        (true, true) => quote! {
+            let __bc_ctx = __bc_resp.data().clone();
+            let __bc_deadline = __bc_resp.deadline();
+            #call_async
+            __bc_ctx.spawn_export(__bc_seq_no, async move {
+                let __bc_serialized = match __bc_deadline {
+                    Some(deadline) => {
+                        let remaining = bc_hostcall::rpc::remaining(deadline).unwrap_or_default();
+                        tokio::time::timeout(remaining, __bc_call).await
+                            .unwrap_or_else(|_| Err(bc_hostcall::rpc::RpcError::new(
+                                bc_hostcall::rpc::RpcErrorCode::DeadlineExceeded, "deadline exceeded")))
+                    }
+                    None => __bc_call.await,
+                };
+                #make_msg
+                Ok::<_, bc_hostcall::rpc::Error>(__bc_msg)
+            });
+        },
+        (true, false) => quote! {
+            #make_response
+            __bc_resp.data().push_rx(__bc_msg);
+        },
+        (false, true) => quote! {
+            #call_async
+            bc_hostcall::async_rt::rt::spawn_export(__bc_seq_no, async move {
+                let __bc_serialized = __bc_call.await;
+                #make_msg
+                Ok::<_, bc_hostcall::rpc::Error>(__bc_msg)
+            });
+        },
+        (false, false) => quote! {
+            #make_response
+            bc_hostcall::rpc::adapter::SendMessageAdapter::send_message(
+                __bc_resp.data(), &__bc_msg)?;
+        },
+    };
+
+    let resp_data = if attr.host {
+        quote!(std::sync::Arc<bc_hostcall::async_api::ctx::AsyncCtx>)
+    } else {
+        quote!(bc_hostcall::rpc::adapter::WasmSendMessageAdapter)
+    };
+
+    Ok(quote! {
+        #func
+
+        #[doc(hidden)]
+        #[allow(non_snake_case, unused_variables)]
+        #vis fn #wrapper(
+            __bc_resp: &bc_hostcall::rpc::RpcResponseCtx<#resp_data>,
+            __bc_args: &[u8],
+        ) -> bc_hostcall::rpc::Result<()> {
+            // 函数标识符
+            let mut __bc_func = bc_hostcall::rpc::abi::FunctionIdent::new(#name_str);
+            __bc_func.set_hint(#hint);
+            // 解析参数，调用函数并回送结果，沿用调用请求的序列化格式
+            let __bc_seq_no = __bc_resp.seq_no();
+            let __bc_ser_ctx = *__bc_resp.serialize_ctx();
+            #body
+            Ok(())
+        }
+    })
+}
+
+/// `bc_export_module!` 的参数
+///
+/// 第一个参数为 `host` 或模块名称表达式，其余为导出函数的路径。
+pub struct ExportModule {
+    host: bool,
+    name: Option<Expr>,
+    funcs: Vec<Path>,
+}
+
+impl Parse for ExportModule {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let first: Expr = input.parse()?;
+        let host = matches!(&first, Expr::Path(p) if p.path.is_ident("host"));
+        let name = if host { None } else { Some(first) };
+
+        let mut funcs = Vec::new();
+        if !input.is_empty() {
+            input.parse::<Token![,]>()?;
+            let rest = Punctuated::<Path, Token![,]>::parse_terminated(input)?;
+            funcs.extend(rest);
+        }
+
+        Ok(ExportModule { host, name, funcs })
+    }
+}
+
+/// 生成模块的导出函数表 `__bc_module_export`
+pub fn expand_export_module(module: ExportModule) -> syn::Result<TokenStream> {
+    let (exports_data, hint) = if module.host {
+        (
+            quote!(std::sync::Arc<bc_hostcall::async_api::ctx::AsyncCtx>),
+            quote!(bc_hostcall::rpc::abi::LinkHint::Host),
+        )
+    } else {
+        let name = module.name.as_ref().unwrap();
+        (
+            quote!(bc_hostcall::rpc::adapter::WasmSendMessageAdapter),
+            quote!(bc_hostcall::rpc::abi::LinkHint::BcModule((#name).to_string())),
+        )
+    };
+
+    let mut names = Vec::new();
+    let mut wrappers = Vec::new();
+    for func in &module.funcs {
+        let last = func.segments.last()
+            .ok_or_else(|| syn::Error::new_spanned(func, "函数路径不能为空"))?;
+        names.push(last.ident.to_string());
+
+        let mut wrapper = func.clone();
+        let last = wrapper.segments.last_mut().unwrap();
+        last.ident = format_ident!("__bc_wrapper_{}", last.ident);
+        wrappers.push(wrapper);
+    }
+
+    Ok(quote! {
+        pub fn __bc_module_export() -> bc_hostcall::rpc::RpcExports<#exports_data> {
+            let mut exports = bc_hostcall::rpc::RpcExports::new(#hint);
+            // 添加导出函数的回调
+            #(
+                exports.add_exports(bc_hostcall::rpc::abi::FunctionIdent::new(#names), #wrappers);
+            )*
+            exports
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use quote::quote;
+    use syn::parse_quote;
+
+    use crate::test_util::*;
+
+    use super::*;
+
+    #[test]
+    fn test_export_guest() {
+        let func: ItemFn = parse_quote! {
+            pub fn checksum(name: &str, data: &[u8]) -> u32 { 0 }
+        };
+        let generated = items(expand_export(bc_attr(quote!()).unwrap(), func).unwrap());
+
+        // 原函数保持不变
+        assert_eq!(2, generated.len());
+        assert_eq!(2, find_fn(&generated, "checksum").sig.inputs.len());
+
+        // WASM 端的包装同步回送结果，`&[u8]` 以字节串解析
+        let wrapper = find_fn(&generated, "__bc_wrapper_checksum");
+        let body = compact(&wrapper.block);
+        assert!(compact(&wrapper.sig).contains("RpcResponseCtx<bc_hostcall::rpc::adapter::WasmSendMessageAdapter>"));
+        assert!(body.contains("FunctionIdent::new(\"checksum\")"));
+        assert!(body.contains("bc_hostcall::async_rt::rt::module_hint()"));
+        assert!(body.contains("__bc_args.get::<bc_hostcall::serialize::Str>(0usize)"));
+        assert!(body.contains("__bc_args.get::<bc_hostcall::serialize::Bytes>(1usize)"));
+        assert!(body.contains("checksum(&__bc_arg0,&__bc_arg1)"));
+        assert!(body.contains("send_message"));
+        assert!(!body.contains("spawn_export"));
+        // 生成报文失败时返回错误，而非在用户代码中 panic
+        assert!(body.contains(".make_result(__bc_func,__bc_serialized)?;"));
+        assert!(!body.contains("unwrap()"));
+    }
+
+    #[test]
+    fn test_export_async() {
+        // WASM 端的异步函数在任务中运行
+        let func: ItemFn = parse_quote! {
+            async fn app(param: String) -> String { param }
+        };
+        let generated = items(expand_export(bc_attr(quote!(module = "dispatch")).unwrap(), func).unwrap());
+        let body = compact(&find_fn(&generated, "__bc_wrapper_app").block);
+        assert!(body.contains("LinkHint::BcModule(\"dispatch\".to_string())"));
+        assert!(body.contains("bc_hostcall::async_rt::rt::spawn_export"));
+        assert!(body.contains("app(__bc_arg0).await"));
+        assert!(body.contains("Ok::<_,bc_hostcall::rpc::Error>(__bc_msg)"));
+        assert!(!body.contains("unwrap()"));
+
+        // Host 端的异步函数遵守截止时间
+        let func: ItemFn = parse_quote! {
+            async fn http_get(url: String) -> String { url }
+        };
+        let generated = items(expand_export(bc_attr(quote!(host)).unwrap(), func).unwrap());
+        let wrapper = find_fn(&generated, "__bc_wrapper_http_get");
+        let body = compact(&wrapper.block);
+        assert!(compact(&wrapper.sig).contains("std::sync::Arc<bc_hostcall::async_api::ctx::AsyncCtx>"));
+        assert!(body.contains("LinkHint::Host"));
+        assert!(body.contains("__bc_ctx.spawn_export"));
+        assert!(body.contains("DeadlineExceeded"));
+    }
+
+    #[test]
+    fn test_export_error() {
+        let func: ItemFn = parse_quote! {
+            fn method(&self) {}
+        };
+        let err = expand_export(bc_attr(quote!()).unwrap(), func).unwrap_err();
+        assert_eq!("`#[bc_export]` 不能用于方法", err.to_string());
+
+        let func: ItemFn = parse_quote! {
+            fn app() {}
+        };
+        let err = expand_export(bc_attr(quote!(module = "dispatch", version = "^1.0")).unwrap(), func).unwrap_err();
+        assert_eq!("导出函数的版本由模块清单决定，无需指定 `version`", err.to_string());
+    }
+
+    #[test]
+    fn test_export_module() {
+        let module: ExportModule = parse_quote!("dispatch", app, exports::checksum);
+        let generated = items(expand_export_module(module).unwrap());
+        let table = find_fn(&generated, "__bc_module_export");
+        let body = compact(&table.block);
+        assert!(compact(&table.sig.output).contains("RpcExports<bc_hostcall::rpc::adapter::WasmSendMessageAdapter>"));
+        assert!(body.contains("LinkHint::BcModule((\"dispatch\").to_string())"));
+        assert!(body.contains("FunctionIdent::new(\"app\"),__bc_wrapper_app"));
+        assert!(body.contains("FunctionIdent::new(\"checksum\"),exports::__bc_wrapper_checksum"));
+
+        let module: ExportModule = parse_quote!(host, http_get);
+        let generated = items(expand_export_module(module).unwrap());
+        let table = find_fn(&generated, "__bc_module_export");
+        assert!(compact(&table.sig.output).contains("RpcExports<std::sync::Arc<bc_hostcall::async_api::ctx::AsyncCtx>>"));
+        assert!(compact(&table.block).contains("LinkHint::Host"));
+    }
+}
@@ -0,0 +1,251 @@
+//! `#[bc_import]` 与 `bc_import_module!` 的代码生成
+
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::{FnArg, ForeignItemFn, GenericArgument, Pat, Path, PathArguments, ReturnType, Token, Type};
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+
+use crate::attr::BcAttr;
+use crate::ty::is_byte_slice;
+
+/// 为导入函数的声明生成调用桩，WASM 端还会生成供 `bc_import_module!` 使用的 `__bc_import_*` 函数
+pub fn expand_import(attr: BcAttr, func: ForeignItemFn) -> syn::Result<TokenStream> {
+    let attrs = &func.attrs;
+    let vis = &func.vis;
+    let name = &func.sig.ident;
+    let name_str = name.to_string();
+
+    if attr.host && attr.module.is_some() {
+        return Err(syn::Error::new_spanned(
+            &func.sig, "Host 端导入的链接目标由传入的模块决定，无需指定 `module`"));
+    }
+    if attr.version.is_some() && attr.module.is_none() {
+        return Err(syn::Error::new_spanned(&func.sig, "`version` 需要与 `module` 一同指定"));
+    }
+
+    // 参数
+    let mut params = Vec::new();
+    let mut arg_values = Vec::new();
+    for input in func.sig.inputs.iter() {
+        match input {
+            FnArg::Typed(pat) => match pat.pat.as_ref() {
+                Pat::Ident(ident) => {
+                    // `&[u8]` 以字节串的形式序列化，与导出函数的解析方式保持一致
+                    let name = &ident.ident;
+                    arg_values.push(if is_byte_slice(&pat.ty) {
+                        quote!(&bc_hostcall::serialize::Bytes::from(#name))
+                    } else {
+                        quote!(&#name)
+                    });
+                    params.push(input.clone());
+                }
+                other => {
+                    return Err(syn::Error::new_spanned(other, "`#[bc_import]` 的参数必须为标识符"));
+                }
+            },
+            FnArg::Receiver(recv) => {
+                return Err(syn::Error::new_spanned(recv, "`#[bc_import]` 不能用于方法"));
+            }
+        }
+    }
+
+    // 返回值：若声明为 `Result<T>` 则沿用，否则包装为 `Result<T>`
+    let (ret_ty, value_ty) = match &func.sig.output {
+        ReturnType::Default => (None, quote!(())),
+        ReturnType::Type(_, ty) => match result_inner(ty) {
+            Some(inner) => (Some(quote!(#ty)), quote!(#inner)),
+            None => (None, quote!(#ty)),
+        },
+    };
+    let ret_ty = ret_ty.unwrap_or_else(|| if attr.host {
+        quote!(bc_hostcall::module_api::Result<#value_ty>)
+    } else {
+        quote!(bc_hostcall::async_rt::Result<#value_ty>)
+    });
+
+    let (ctx_param, func_ident, ident_fn, ser_ctx, request) = if attr.host {
+        (
+            quote!(__bc_ctx: &bc_hostcall::module_api::module::WasmModule,),
+            quote! {{
+                let mut __bc_func = bc_hostcall::rpc::abi::FunctionIdent::new(#name_str);
+                __bc_func.set_hint(__bc_ctx.get_hint());
+                __bc_func
+            }},
+            quote!(),
+            quote!(__bc_ctx.serialize_ctx()),
+            quote!(__bc_ctx.async_ctx().request_api(__bc_func, __bc_args).await?),
+        )
+    } else {
+        // 带有版本要求时，由 Host 解析为满足要求的最高版本
+        let hint = match (&attr.module, &attr.version) {
+            (Some(module), Some(version)) => quote!(bc_hostcall::rpc::abi::LinkHint::BcModuleVersion(
+                #module.to_string(), #version.parse().expect("invalid version requirement"))),
+            (Some(module), None) => quote!(bc_hostcall::rpc::abi::LinkHint::BcModule(#module.to_string())),
+            (None, _) => quote!(bc_hostcall::rpc::abi::LinkHint::Host),
+        };
+        let ident_name = format_ident!("__bc_import_{}", name);
+        (
+            quote!(),
+            quote!(#ident_name()),
+            quote! {
+                /// 导入函数的标识符，供 `bc_import_module!` 生成导入表
+                #[doc(hidden)]
+                #vis fn #ident_name() -> bc_hostcall::rpc::abi::FunctionIdent {
+                    let mut func = bc_hostcall::rpc::abi::FunctionIdent::new(#name_str);
+                    func.set_hint(#hint);
+                    func
+                }
+            },
+            quote!(bc_hostcall::async_rt::rt::serialize_ctx()),
+            quote!(bc_hostcall::async_rt::rt::request_api(__bc_func, __bc_args).await?),
+        )
+    };
+
+    Ok(quote! {
+        #ident_fn
+
+        #(#attrs)*
+        #vis async fn #name(#ctx_param #(#params),*) -> #ret_ty {
+            // 使用与对端协商确定的序列化格式
+            let __bc_ser_ctx = #ser_ctx;
+            // 函数标识符
+            let __bc_func = #func_ident;
+            // 参数拼接
+            let __bc_args = bc_hostcall::serialize::ArgsBuilder::new(&__bc_ser_ctx)
+                #( .push(#arg_values)? )*
+                .build()?;
+            // 调用函数
+            let __bc_ret = #request;
+            // 解析返回值
+            let __bc_result = __bc_ser_ctx.deserialize::<#value_ty>(&__bc_ret)?;
+            Ok(__bc_result)
+        }
+    })
+}
+
+pub struct ImportModule {
+    funcs: Vec<Path>,
+}
+
+impl Parse for ImportModule {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let funcs = Punctuated::<Path, Token![,]>::parse_terminated(input)?;
+        Ok(ImportModule { funcs: funcs.into_iter().collect() })
+    }
+}
+
+/// 生成模块的导入表 `__bc_module_import`
+pub fn expand_import_module(module: ImportModule) -> syn::Result<TokenStream> {
+    let mut idents = Vec::new();
+    for func in &module.funcs {
+        let mut ident = func.clone();
+        let last = ident.segments.last_mut()
+            .ok_or_else(|| syn::Error::new_spanned(func, "函数路径不能为空"))?;
+        last.ident = format_ident!("__bc_import_{}", last.ident);
+        idents.push(ident);
+    }
+
+    Ok(quote! {
+        pub fn __bc_module_import() -> bc_hostcall::rpc::RpcImports {
+            let mut imports = bc_hostcall::rpc::RpcImports::new();
+            // 添加导入函数的标识符
+            #(
+                imports.add_imports(#idents());
+            )*
+            imports
+        }
+    })
+}
+
+/// 若类型形如 `Result<T>` 或 `Result<T, E>`，则返回 `T`
+fn result_inner(ty: &Type) -> Option<&Type> {
+    let path = match ty {
+        Type::Path(path) => &path.path,
+        _ => return None,
+    };
+    let last = path.segments.last()?;
+    if last.ident != "Result" {
+        return None;
+    }
+    match &last.arguments {
+        PathArguments::AngleBracketed(args) => match args.args.first()? {
+            GenericArgument::Type(inner) => Some(inner),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use quote::quote;
+    use syn::parse_quote;
+
+    use crate::test_util::*;
+
+    use super::*;
+
+    #[test]
+    fn test_import_guest() {
+        let func: ForeignItemFn = parse_quote! {
+            pub fn do_service(name: String, data: &[u8]) -> Result<String>;
+        };
+        let attr = bc_attr(quote!(module = "service", version = "^1.0")).unwrap();
+        let generated = items(expand_import(attr, func).unwrap());
+
+        // 导入表使用的函数标识符带有版本要求
+        let ident = compact(&find_fn(&generated, "__bc_import_do_service").block);
+        assert!(ident.contains("FunctionIdent::new(\"do_service\")"));
+        assert!(ident.contains("LinkHint::BcModuleVersion(\"service\".to_string(),\"^1.0\".parse()"));
+
+        // 调用桩是异步的，沿用声明的 `Result<T>`，`&[u8]` 以字节串序列化
+        let stub = find_fn(&generated, "do_service");
+        assert!(stub.sig.asyncness.is_some());
+        assert_eq!(2, stub.sig.inputs.len());
+        assert_eq!("->Result<String>", compact(&stub.sig.output));
+        let body = compact(&stub.block);
+        assert!(body.contains(".push(&name)?.push(&bc_hostcall::serialize::Bytes::from(data))?"));
+        assert!(body.contains("bc_hostcall::async_rt::rt::request_api(__bc_func,__bc_args)"));
+        assert!(body.contains("deserialize::<String>"));
+    }
+
+    #[test]
+    fn test_import_host() {
+        let func: ForeignItemFn = parse_quote! {
+            fn app(param: String) -> String;
+        };
+        let generated = items(expand_import(bc_attr(quote!(host)).unwrap(), func).unwrap());
+
+        // Host 端不生成导入表使用的函数，第一个参数为目标模块，返回值被包装为 `Result<T>`
+        assert_eq!(1, generated.len());
+        let stub = find_fn(&generated, "app");
+        assert_eq!(2, stub.sig.inputs.len());
+        assert!(compact(&stub.sig.inputs[0]).contains("bc_hostcall::module_api::module::WasmModule"));
+        assert_eq!("->bc_hostcall::module_api::Result<String>", compact(&stub.sig.output));
+        assert!(compact(&stub.block).contains("__bc_ctx.async_ctx().request_api(__bc_func,__bc_args)"));
+    }
+
+    #[test]
+    fn test_import_error() {
+        let err = |attr, func: ForeignItemFn| expand_import(bc_attr(attr).unwrap(), func).unwrap_err().to_string();
+
+        assert_eq!("Host 端导入的链接目标由传入的模块决定，无需指定 `module`",
+                   err(quote!(host, module = "service"), parse_quote!(fn app();)));
+        assert_eq!("`version` 需要与 `module` 一同指定",
+                   err(quote!(version = "^1.0"), parse_quote!(fn app();)));
+        assert_eq!("`#[bc_import]` 的参数必须为标识符",
+                   err(quote!(), parse_quote!(fn app((a, b): (u32, u32));)));
+        assert_eq!("`#[bc_import]` 不能用于方法",
+                   err(quote!(), parse_quote!(fn app(&self);)));
+    }
+
+    #[test]
+    fn test_import_module() {
+        let module: ImportModule = parse_quote!(do_service, imports::http_get);
+        let generated = items(expand_import_module(module).unwrap());
+        let body = compact(&find_fn(&generated, "__bc_module_import").block);
+        assert!(body.contains("imports.add_imports(__bc_import_do_service())"));
+        assert!(body.contains("imports.add_imports(imports::__bc_import_http_get())"));
+    }
+}
@@ -0,0 +1,164 @@
+//! 用于生成导出函数包装、导入函数调用桩及模块导出表的过程宏
+
+use proc_macro::TokenStream;
+use syn::{AttributeArgs, ForeignItemFn, ItemFn, parse_macro_input};
+
+use crate::attr::BcAttr;
+use crate::export::ExportModule;
+use crate::import::ImportModule;
+
+mod attr;
+mod export;
+mod import;
+mod ty;
+
+/// 将函数导出，生成供 `RpcExports` 注册的 `__bc_wrapper_*` 包装函数
+///
+/// 函数可以是同步或异步的，参数与返回值需要可以被序列化。默认生成 WASM 端的包装，
+/// 使用 `#[bc_export(host)]` 生成 Host 端的包装。生成的包装函数需要通过
+/// `bc_export_module!` 注册到导出函数表。
+///
+/// 参数可以是 `&str`、`&[u8]` 等借用类型，此时直接借用收到的报文中的数据，在调用期间有效。
+/// 格式无法借用时（如 JSON 中的字节串及含有转义字符的字符串）则使用数据的副本。其他含有借用的
+/// 类型仍由类型自身的 `Deserialize` 决定，在 JSON 下可能失败。异步函数的参数数据会被复制到其异步任务中。
+///
+/// ## 使用示例
+///
+/// ```ignore
+/// #[bc_export]
+/// async fn app(param: String) -> String {
+///     // ...
+/// }
+///
+/// #[bc_export]
+/// fn checksum(name: &str, data: &[u8]) -> u32 {
+///     // ...
+/// }
+///
+/// #[bc_export(host)]
+/// async fn http_get(url: String) -> String {
+///     // ...
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn bc_export(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(attr as AttributeArgs);
+    let func = parse_macro_input!(item as ItemFn);
+
+    BcAttr::parse(args)
+        .and_then(|attr| export::expand_export(attr, func))
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+/// 根据函数声明生成调用其他模块或 Host 导出函数的异步函数
+///
+/// 生成的函数总是异步的，并返回 `Result<T>`。若声明的返回值已是 `Result<T>` 则沿用该类型。
+///
+/// - `#[bc_import]`：WASM 端调用 Host 导出的函数
+/// - `#[bc_import(module = "name")]`：WASM 端调用指定模块导出的函数
+/// - `#[bc_import(module = "name", version = "^1.0")]`：同上，调用满足版本要求的最高版本
+/// - `#[bc_import(host)]`：Host 端调用模块导出的函数，生成的函数第一个参数为目标模块
+///   `&WasmModule`
+///
+/// WASM 端导入的函数需要通过 `bc_import_module!` 声明到导入表。
+///
+/// ## 使用示例
+///
+/// ```ignore
+/// #[bc_import(module = "service")]
+/// fn do_service() -> Result<String>;
+///
+/// #[bc_import(host)]
+/// fn app(param: String) -> Result<String>;
+///
+/// // Host 端调用
+/// let result = app(&module, "param".to_string()).await?;
+/// ```
+#[proc_macro_attribute]
+pub fn bc_import(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(attr as AttributeArgs);
+    let func = parse_macro_input!(item as ForeignItemFn);
+
+    BcAttr::parse(args)
+        .and_then(|attr| import::expand_import(attr, func))
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+/// 生成模块的导出函数表 `__bc_module_export`，其中的函数需要由 `#[bc_export]` 修饰
+///
+/// 第一个参数为模块名称，或者 `host` 表示生成 Host 端的导出函数表。
+///
+/// ## 使用示例
+///
+/// ```ignore
+/// bc_export_module!("dispatch", app);
+/// bc_wasm_module!("dispatch", __bc_module_export);
+///
+/// // Host 端
+/// bc_export_module!(host, http_get);
+/// ```
+#[proc_macro]
+pub fn bc_export_module(input: TokenStream) -> TokenStream {
+    let module = parse_macro_input!(input as ExportModule);
+
+    export::expand_export_module(module)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+/// 生成模块的导入表 `__bc_module_import`，其中的函数需要由 `#[bc_import]` 修饰
+///
+/// 导入表通过 `bc_wasm_module!` 的 `import_table` 选项在握手时声明，Host 拒绝模块调用未声明的函数。
+///
+/// ## 使用示例
+///
+/// ```ignore
+/// bc_import_module!(do_service, http_get);
+/// bc_wasm_module!("dispatch", __bc_module_export, import_table = __bc_module_import);
+/// ```
+#[proc_macro]
+pub fn bc_import_module(input: TokenStream) -> TokenStream {
+    let module = parse_macro_input!(input as ImportModule);
+
+    import::expand_import_module(module)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+#[cfg(test)]
+pub(crate) mod test_util {
+    use proc_macro2::TokenStream;
+    use syn::{Item, ItemFn, NestedMeta, Token};
+    use syn::parse::Parser;
+    use syn::punctuated::Punctuated;
+
+    use crate::attr::BcAttr;
+
+    /// 以属性参数 `tokens` 解析 `BcAttr`
+    pub fn bc_attr(tokens: TokenStream) -> syn::Result<BcAttr> {
+        let args = Punctuated::<NestedMeta, Token![,]>::parse_terminated.parse2(tokens)?;
+        BcAttr::parse(args.into_iter().collect())
+    }
+
+    /// 把生成的代码解析为条目，同时检查其是合法的 Rust 代码
+    pub fn items(tokens: TokenStream) -> Vec<Item> {
+        syn::parse2::<syn::File>(tokens).unwrap().items
+    }
+
+    /// 找到名称为 `name` 的函数
+    pub fn find_fn<'a>(items: &'a [Item], name: &str) -> &'a ItemFn {
+        items.iter()
+            .find_map(|item| match item {
+                Item::Fn(func) if func.sig.ident == name => Some(func),
+                _ => None,
+            })
+            .unwrap_or_else(|| panic!("{} not generated", name))
+    }
+
+    /// 代码的文本形式，去掉空白以便比较
+    pub fn compact(tokens: impl quote::ToTokens) -> String {
+        tokens.to_token_stream().to_string().replace(' ', "")
+    }
+}
@@ -0,0 +1,32 @@
+//! 参数类型的辨别
+
+use syn::{Type, TypeReference};
+
+/// 类型是否形如 `&[u8]`
+///
+/// 此类参数以 `serialize::Bytes` 的形式序列化与解析，使导出函数可以直接借用报文中的数据。
+pub fn is_byte_slice(ty: &Type) -> bool {
+    match ty {
+        Type::Reference(TypeReference { mutability: None, elem, .. }) => match elem.as_ref() {
+            Type::Slice(slice) => matches!(slice.elem.as_ref(), Type::Path(path) if path.path.is_ident("u8")),
+            _ => false,
+        },
+        Type::Group(group) => is_byte_slice(&group.elem),
+        Type::Paren(paren) => is_byte_slice(&paren.elem),
+        _ => false,
+    }
+}
+
+/// 类型是否形如 `&str`
+///
+/// 此类参数以 `serialize::Str` 的形式解析，格式无法借用时（如 JSON 中含有转义字符的字符串）退化为副本。
+pub fn is_str_ref(ty: &Type) -> bool {
+    match ty {
+        Type::Reference(TypeReference { mutability: None, elem, .. }) => {
+            matches!(elem.as_ref(), Type::Path(path) if path.path.is_ident("str"))
+        }
+        Type::Group(group) => is_str_ref(&group.elem),
+        Type::Paren(paren) => is_str_ref(&paren.elem),
+        _ => false,
+    }
+}
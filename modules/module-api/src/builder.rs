@@ -0,0 +1,433 @@
+//! 可配置 Engine、WASI 与 Store 数据的模块构建器
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::Context;
+use wasmtime::{Config, Engine, Linker};
+use wasmtime_wasi::{ambient_authority, Dir, WasiCtx, WasiCtxBuilder, WasiFile};
+
+use async_api::ctx::AsyncCtx;
+use low_level::host::{CpuLimits, ResourceLimits, Transport};
+use rpc::{abi, ImportPolicy, RpcExports};
+use rpc::capability::Capabilities;
+use serialize::SerializeFormat;
+
+use crate::module::{ModuleData, WasmModule};
+use crate::Result;
+
+type LinkerFn<T> = Box<dyn FnOnce(&mut Linker<ModuleData<T>>) -> anyhow::Result<()>>;
+
+/// 初始化模块时宿主一侧的环境
+pub(crate) struct HostEnv<T> {
+    /// 共享的 Engine，为 `None` 时以 `config` 创建
+    pub(crate) engine: Option<Engine>,
+    pub(crate) config: Config,
+    pub(crate) wasi: WasiCtx,
+    pub(crate) data: T,
+    pub(crate) linker_fns: Vec<LinkerFn<T>>,
+    pub(crate) nonce: u32,
+}
+
+impl Default for HostEnv<()> {
+    fn default() -> Self {
+        HostEnv {
+            engine: None,
+            config: Config::new(),
+            wasi: WasiCtxBuilder::new().inherit_stdio().build(),
+            data: (),
+            linker_fns: Vec::new(),
+            nonce: 0,
+        }
+    }
+}
+
+/// 模块的 WASI 配置
+struct WasiOptions {
+    args: Vec<String>,
+    envs: Vec<(String, String)>,
+    preopened_dirs: Vec<(PathBuf, String)>,
+    inherit_stdio: bool,
+    stdin: Option<Box<dyn WasiFile>>,
+    stdout: Option<Box<dyn WasiFile>>,
+    stderr: Option<Box<dyn WasiFile>>,
+}
+
+impl WasiOptions {
+    fn build(self) -> anyhow::Result<WasiCtx> {
+        let mut builder = WasiCtxBuilder::new();
+        if self.inherit_stdio {
+            builder = builder.inherit_stdio();
+        }
+        if let Some(stdin) = self.stdin {
+            builder = builder.stdin(stdin);
+        }
+        if let Some(stdout) = self.stdout {
+            builder = builder.stdout(stdout);
+        }
+        if let Some(stderr) = self.stderr {
+            builder = builder.stderr(stderr);
+        }
+        builder = builder.args(&self.args)?.envs(&self.envs)?;
+        for (host_path, guest_path) in self.preopened_dirs {
+            let dir = Dir::open_ambient_dir(&host_path, ambient_authority())
+                .with_context(|| format!("failed to open {}", host_path.display()))?;
+            builder = builder.preopened_dir(dir, guest_path)?;
+        }
+        Ok(builder.build())
+    }
+}
+
+/// 以自定义的 Engine、WASI 上下文与 Store 数据构建 `WasmModule`
+///
+/// `T` 为与 WASI 上下文一同存放在 Store 中的宿主数据，可在 `link` 链接的函数中通过
+/// `ModuleData::data` 访问。
+pub struct WasmModuleBuilder<T = ()> {
+    module: WasmModule,
+    engine: Option<Engine>,
+    config: Config,
+    wasi: WasiOptions,
+    data: T,
+    linker_fns: Vec<LinkerFn<T>>,
+    nonce: u32,
+}
+
+impl WasmModuleBuilder<()> {
+    pub fn new() -> Self {
+        Self::with_data(())
+    }
+}
+
+impl<T> WasmModuleBuilder<T>
+    where T: Send + Sync + 'static,
+{
+    /// 以 `data` 作为 Store 中的宿主数据
+    pub fn with_data(data: T) -> Self {
+        WasmModuleBuilder {
+            module: WasmModule::new(),
+            engine: None,
+            config: Config::new(),
+            wasi: WasiOptions {
+                args: Vec::new(),
+                envs: Vec::new(),
+                preopened_dirs: Vec::new(),
+                inherit_stdio: true,
+                stdin: None,
+                stdout: None,
+                stderr: None,
+            },
+            data,
+            linker_fns: Vec::new(),
+            nonce: 0,
+        }
+    }
+
+    /// 使用共享的 Engine
+    ///
+    /// Engine 的 `async_support`、`consume_fuel` 与 `epoch_interruption` 需与模块的异步模式及
+    /// CPU 预算一致。使用 epoch 预算时，宿主需每隔 `EPOCH_TICK` 自行推进 Engine 的 epoch。
+    pub fn engine(mut self, engine: Engine) -> Self {
+        self.engine = Some(engine);
+        self
+    }
+
+    /// 以 `config` 为基础创建 Engine，其中与异步模式及 CPU 预算相关的选项会被覆盖
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// 模块的命令行参数，包括程序名
+    pub fn args<I, S>(mut self, args: I) -> Self
+        where I: IntoIterator<Item=S>,
+              S: Into<String>,
+    {
+        self.wasi.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// 设置模块的环境变量
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.wasi.envs.push((key.into(), value.into()));
+        self
+    }
+
+    /// 将宿主目录 `host_path` 以 `guest_path` 开放给模块
+    pub fn preopened_dir(mut self, host_path: impl AsRef<Path>, guest_path: impl Into<String>) -> Self {
+        self.wasi.preopened_dirs.push((host_path.as_ref().to_path_buf(), guest_path.into()));
+        self
+    }
+
+    /// 是否继承宿主的标准输入输出，默认继承。单独设置的 stdin、stdout、stderr 优先
+    pub fn inherit_stdio(mut self, inherit: bool) -> Self {
+        self.wasi.inherit_stdio = inherit;
+        self
+    }
+
+    pub fn stdin(mut self, file: Box<dyn WasiFile>) -> Self {
+        self.wasi.stdin = Some(file);
+        self
+    }
+
+    pub fn stdout(mut self, file: Box<dyn WasiFile>) -> Self {
+        self.wasi.stdout = Some(file);
+        self
+    }
+
+    pub fn stderr(mut self, file: Box<dyn WasiFile>) -> Self {
+        self.wasi.stderr = Some(file);
+        self
+    }
+
+    /// 向 Linker 中添加额外的函数，在 WASI 与低层函数之后链接
+    pub fn link<F>(mut self, f: F) -> Self
+        where F: FnOnce(&mut Linker<ModuleData<T>>) -> anyhow::Result<()> + 'static,
+    {
+        self.linker_fns.push(Box::new(f));
+        self
+    }
+
+    /// RPC 节点的唯一标识，用于区分各节点发出的请求
+    ///
+    /// # Panics
+    ///
+    /// `nonce` 为 Host 转发请求时使用的 `abi::FORWARD_NONCE` 时 panic。
+    pub fn nonce(mut self, nonce: u32) -> Self {
+        assert_ne!(abi::FORWARD_NONCE, nonce, "nonce {:#x} is reserved for forwarded requests", nonce);
+        self.nonce = nonce;
+        self
+    }
+
+    /// 见 `WasmModule::set_serialize_format`
+    pub fn serialize_format(mut self, format: SerializeFormat) -> Self {
+        self.module.set_serialize_format(format);
+        self
+    }
+
+    /// 见 `WasmModule::set_transport`
+    pub fn transport(mut self, transport: Transport) -> Self {
+        self.module.set_transport(transport);
+        self
+    }
+
+    /// 见 `WasmModule::set_async_mode`
+    pub fn async_mode(mut self, async_mode: bool) -> Self {
+        self.module.set_async_mode(async_mode);
+        self
+    }
+
+    /// 见 `WasmModule::set_cpu_limits`
+    pub fn cpu_limits(mut self, limits: CpuLimits) -> Self {
+        self.module.set_cpu_limits(limits);
+        self
+    }
+
+    /// 见 `WasmModule::set_resource_limits`
+    pub fn resource_limits(mut self, limits: ResourceLimits) -> Self {
+        self.module.set_resource_limits(limits);
+        self
+    }
+
+    /// 见 `WasmModule::set_initial_state`
+    pub fn initial_state(mut self, state: Vec<u8>) -> Self {
+        self.module.set_initial_state(state);
+        self
+    }
+
+    /// 见 `WasmModule::set_import_policy`
+    pub fn import_policy(mut self, policy: ImportPolicy) -> Self {
+        self.module.set_import_policy(policy);
+        self
+    }
+
+    /// 见 `WasmModule::set_capabilities`
+    pub fn capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.module.set_capabilities(capabilities);
+        self
+    }
+
+    /// 加载模块文件并进行初始化
+    pub fn build(self, filename: &str, host_exports: RpcExports<Arc<AsyncCtx>>) -> Result<WasmModule> {
+        self.build_with(|engine| wasmtime::Module::from_file(engine, filename), host_exports)
+    }
+
+    /// 从内存中的 WASM 字节码加载模块并进行初始化
+    pub fn build_from_bytes(self, bytes: &[u8], host_exports: RpcExports<Arc<AsyncCtx>>) -> Result<WasmModule> {
+        self.build_with(|engine| wasmtime::Module::new(engine, bytes), host_exports)
+    }
+
+    /// 以已编译的模块进行初始化，模块所属的 Engine 作为共享 Engine 使用
+    pub fn build_module(mut self,
+                        module: &wasmtime::Module,
+                        host_exports: RpcExports<Arc<AsyncCtx>>,
+    ) -> Result<WasmModule> {
+        self.engine = Some(module.engine().clone());
+        self.build_with(|_| Ok(module.clone()), host_exports)
+    }
+
+    /// 加载 wasmtime 预编译的 `.cwasm` 模块并进行初始化
+    ///
+    /// # Safety
+    ///
+    /// 见 `WasmModule::init_precompiled`。
+    pub unsafe fn build_precompiled(self,
+                                    filename: &str,
+                                    host_exports: RpcExports<Arc<AsyncCtx>>,
+    ) -> Result<WasmModule> {
+        self.build_with(|engine| wasmtime::Module::deserialize_file(engine, filename), host_exports)
+    }
+
+    fn build_with<F>(self, load: F, host_exports: RpcExports<Arc<AsyncCtx>>) -> Result<WasmModule>
+        where F: FnOnce(&Engine) -> anyhow::Result<wasmtime::Module>,
+    {
+        let env = HostEnv {
+            engine: self.engine,
+            config: self.config,
+            wasi: self.wasi.build()?,
+            data: self.data,
+            linker_fns: self.linker_fns,
+            nonce: self.nonce,
+        };
+        let mut module = self.module;
+        module.init_with(load, host_exports, env)?;
+        Ok(module)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Mutex;
+
+    use wasmtime::Caller;
+
+    use rpc::RpcNode;
+    use serialize::SerializeCtx;
+
+    use crate::test_util::*;
+
+    use super::*;
+
+    /// 测试模块在 `__bc_main` 中观察到的内容
+    #[derive(Default)]
+    struct Observed {
+        count: AtomicU32,
+        reports: Mutex<Vec<(i32, Vec<u8>)>>,
+    }
+
+    /// 在 `__bc_main` 中读取命令行参数、环境变量及预打开目录名并经 `bc_test.report` 交给 Host，
+    /// 调用一次 `bc_test.count`，最后以 `peer_info` 完成握手
+    fn guest_wat(peer_info: &[u8]) -> String {
+        let data: String = peer_info.iter().map(|b| format!("\\{:02x}", b)).collect();
+        format!(r#"
+(module
+  (import "wasi_snapshot_preview1" "args_sizes_get" (func $args_sizes_get (param i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "args_get" (func $args_get (param i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "environ_sizes_get" (func $environ_sizes_get (param i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "environ_get" (func $environ_get (param i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_prestat_get" (func $fd_prestat_get (param i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_prestat_dir_name" (func $fd_prestat_dir_name (param i32 i32 i32) (result i32)))
+  (import "__bc_low_level" "receive_message_from_wasm" (func $send (param i32 i32)))
+  (import "bc_test" "count" (func $count (result i32)))
+  (import "bc_test" "report" (func $report (param i32 i32 i32)))
+
+  (memory (export "memory") 1)
+  (global $heap (mut i32) (i32.const 8192))
+  (data (i32.const 4096) "{data}")
+
+  (func $check (param i32)
+    (if (local.get 0) (then unreachable)))
+
+  (func (export "__bc_main")
+    ;; 命令行参数
+    (call $check (call $args_sizes_get (i32.const 0) (i32.const 4)))
+    (call $check (call $args_get (i32.const 64) (i32.const 128)))
+    (call $report (i32.const 0) (i32.const 128) (i32.load (i32.const 4)))
+    ;; 环境变量
+    (call $check (call $environ_sizes_get (i32.const 8) (i32.const 12)))
+    (call $check (call $environ_get (i32.const 384) (i32.const 448)))
+    (call $report (i32.const 1) (i32.const 448) (i32.load (i32.const 12)))
+    ;; 标准输入输出之后的第一个描述符即预打开目录
+    (call $check (call $fd_prestat_get (i32.const 3) (i32.const 16)))
+    (call $check (call $fd_prestat_dir_name (i32.const 3) (i32.const 704) (i32.load (i32.const 20))))
+    (call $report (i32.const 2) (i32.const 704) (i32.load (i32.const 20)))
+
+    (drop (call $count))
+    (call $send (i32.const 4096) (i32.const {len})))
+
+  (func (export "canonical_abi_realloc") (param i32 i32 i32 i32) (result i32)
+    (local $ptr i32)
+    (local.set $ptr (global.get $heap))
+    (global.set $heap (i32.and (i32.add (i32.add (local.get $ptr) (local.get 3)) (i32.const 7)) (i32.const -8)))
+    (local.get $ptr))
+  (func (export "canonical_abi_free") (param i32 i32 i32))
+  (func (export "__bc_low_level_host_message_handler") (param i32 i32)))
+"#, data = data, len = peer_info.len())
+    }
+
+    #[tokio::test]
+    async fn test_builder() {
+        let peer_info = RpcNode::new(SerializeCtx::new(), 0, ()).make_peer_info("builder-wasm".to_string());
+        let observed = Arc::new(Observed::default());
+        let module = WasmModuleBuilder::with_data(observed.clone())
+            .args(["builder-wasm", "--verbose"])
+            .env("BC_TEST", "1")
+            .preopened_dir(std::env::temp_dir(), "/data")
+            .inherit_stdio(false)
+            .nonce(1)
+            .link(|linker| {
+                linker.func_wrap("bc_test", "count", |caller: Caller<'_, ModuleData<Arc<Observed>>>| {
+                    caller.data().data().count.fetch_add(1, Ordering::SeqCst)
+                })?;
+                linker.func_wrap("bc_test", "report", |mut caller: Caller<'_, ModuleData<Arc<Observed>>>,
+                                                       kind: i32, ptr: i32, len: i32| {
+                    let memory = caller.get_export("memory").and_then(|e| e.into_memory()).unwrap();
+                    let mut buf = vec![0; len as usize];
+                    memory.read(&caller, ptr as usize, &mut buf).unwrap();
+                    caller.data().data().reports.lock().unwrap().push((kind, buf));
+                })?;
+                Ok(())
+            })
+            .build_from_bytes(guest_wat(&peer_info).as_bytes(), init_exports())
+            .unwrap();
+
+        assert_eq!("builder-wasm", module.get_name());
+        assert_eq!(1, observed.count.load(Ordering::SeqCst));
+        assert_eq!(*observed.reports.lock().unwrap(), vec![
+            (0, b"builder-wasm\0--verbose\0".to_vec()),
+            (1, b"BC_TEST=1\0".to_vec()),
+            (2, b"/data".to_vec()),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_shared_engine() {
+        let engine = Engine::default();
+        let bytes = std::fs::read(WASM).unwrap();
+
+        let mod_a = WasmModuleBuilder::new()
+            .engine(engine.clone())
+            .build_from_bytes(&bytes, init_exports())
+            .unwrap();
+        let mod_b = WasmModuleBuilder::new()
+            .engine(engine)
+            .build_from_bytes(&bytes, init_exports())
+            .unwrap();
+        mod_a.start().await;
+        mod_b.start().await;
+
+        let ret = wasm_export_to_host(&mod_a, "mod a".to_string()).await.unwrap();
+        assert_eq!(ret, "Hello mod a, I'm a wasm module!".to_string());
+        let ret = wasm_export_to_host(&mod_b, "mod b".to_string()).await.unwrap();
+        assert_eq!(ret, "Hello mod b, I'm a wasm module!".to_string());
+        mod_a.kill();
+        mod_b.kill();
+    }
+
+    #[test]
+    #[should_panic(expected = "reserved for forwarded requests")]
+    fn test_forward_nonce() {
+        // Host 转发请求使用的 nonce 不能分配给模块
+        let _ = WasmModuleBuilder::new().nonce(abi::FORWARD_NONCE);
+    }
+}
@@ -0,0 +1,380 @@
+//! 共享 Engine 的模块编译缓存
+
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use sha2::{Digest, Sha256};
+use wasmtime::{Engine, Module};
+
+use crate::{Error, Result};
+
+/// 内存中默认最多缓存的模块数
+pub const DEFAULT_CAPACITY: usize = 64;
+
+/// 不含任何内容的 WASM 模块，其编译结果用于计算 Engine 的指纹
+const EMPTY_MODULE: &[u8] = b"\0asm\x01\0\0\0";
+
+/// 缓存的键，即 Engine 指纹与模块内容的 SHA-256
+type Key = [u8; 32];
+
+/// 以模块内容的哈希为键缓存编译结果，同一模块多次加载、实例化时无需重新编译
+///
+/// 缓存的模块均属于同一个 Engine，需通过 `WasmModule::init_module` 或
+/// `WasmModuleBuilder::build_module` 实例化。内存中最多缓存 `DEFAULT_CAPACITY` 个模块，
+/// 超出时淘汰最久未使用的模块，见 `set_capacity`。
+pub struct ModuleCache {
+    engine: Engine,
+    /// Engine 配置及 wasmtime 版本的指纹，仅磁盘缓存使用
+    fingerprint: Option<Key>,
+    /// 磁盘缓存目录，为 `None` 时仅缓存在内存中
+    dir: Option<PathBuf>,
+    capacity: usize,
+    /// 编译结果及其最近一次使用的序号
+    modules: Mutex<Cell<HashMap<Key, (Module, u64)>>>,
+    uses: Mutex<Cell<u64>>,
+}
+
+impl ModuleCache {
+    /// 创建仅缓存在内存中的编译缓存
+    ///
+    /// `engine` 的 `async_support`、`consume_fuel` 与 `epoch_interruption` 需与使用这些模块的
+    /// `WasmModule` 的设置一致。
+    pub fn new(engine: Engine) -> Self {
+        ModuleCache {
+            engine,
+            fingerprint: None,
+            dir: None,
+            capacity: DEFAULT_CAPACITY,
+            modules: Mutex::new(Cell::new(HashMap::new())),
+            uses: Mutex::new(Cell::new(0)),
+        }
+    }
+
+    /// 创建同时缓存在目录 `dir` 中的编译缓存
+    ///
+    /// 缓存目录中的文件会被直接作为机器码加载，因此目录以 0700 权限创建，文件以 0600 权限写入。
+    /// 目录不属于当前用户、或可被其他用户访问时，以 `Error::InsecureCache` 失败；其中的文件不满足
+    /// 要求时不加载，重新编译并覆盖。编译结果以 Engine 配置、wasmtime 版本及模块内容共同的 SHA-256
+    /// 命名，配置或版本变化后不会加载不兼容的编译结果。
+    pub fn with_dir(engine: Engine, dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        create_private_dir(&dir)?;
+
+        // 空模块的编译结果包含 wasmtime 版本及影响编译结果的 Engine 配置
+        let fingerprint = Sha256::digest(&engine.precompile_module(EMPTY_MODULE)?).into();
+        Ok(ModuleCache {
+            fingerprint: Some(fingerprint),
+            dir: Some(dir),
+            ..Self::new(engine)
+        })
+    }
+
+    /// 创建缓存在当前用户缓存目录 `default_dir()` 中的编译缓存
+    pub fn with_user_dir(engine: Engine) -> Result<Self> {
+        Self::with_dir(engine, default_dir())
+    }
+
+    /// 设置内存中最多缓存的模块数，为 0 时不在内存中缓存
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+    }
+
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    /// 加载模块文件，内容未变化时复用此前的编译结果
+    pub fn load_file(&self, filename: impl AsRef<Path>) -> Result<Module> {
+        let bytes = std::fs::read(filename).map_err(anyhow::Error::from)?;
+        self.load(&bytes)
+    }
+
+    /// 加载 WASM 字节码，内容未变化时复用此前的编译结果
+    pub fn load(&self, bytes: &[u8]) -> Result<Module> {
+        let key = self.key(bytes);
+        let uses = {
+            let uses = self.uses.lock().unwrap();
+            uses.set(uses.get() + 1);
+            uses.get()
+        };
+        {
+            let mut modules = self.modules.lock().unwrap();
+            if let Some((module, last_used)) = modules.get_mut().get_mut(&key) {
+                *last_used = uses;
+                return Ok(module.clone());
+            }
+        }
+
+        let module = self.load_uncached(&key, bytes)?;
+        self.insert(key, module.clone(), uses);
+        Ok(module)
+    }
+
+    fn key(&self, bytes: &[u8]) -> Key {
+        let mut hasher = Sha256::new();
+        hasher.update(self.fingerprint.unwrap_or_default());
+        hasher.update(bytes);
+        hasher.finalize().into()
+    }
+
+    /// 加入内存缓存，超出容量时淘汰最久未使用的模块
+    fn insert(&self, key: Key, module: Module, uses: u64) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut modules = self.modules.lock().unwrap();
+        let modules = modules.get_mut();
+        while modules.len() >= self.capacity {
+            let oldest = modules.iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(key, _)| *key)
+                .unwrap();
+            modules.remove(&oldest);
+        }
+        modules.insert(key, (module, uses));
+    }
+
+    fn load_uncached(&self, key: &Key, bytes: &[u8]) -> Result<Module> {
+        let path = match &self.dir {
+            Some(dir) => dir.join(format!("{}.cwasm", hex(key))),
+            None => return Ok(Module::new(&self.engine, bytes)?),
+        };
+
+        match read_private(&path) {
+            Ok(Some(serialized)) => {
+                // SAFETY: 缓存文件属于当前用户且其他用户不可写，文件名包含 Engine 指纹与模块内容的哈希
+                // 加载失败时重新编译并覆盖
+                if let Ok(module) = unsafe { Module::deserialize(&self.engine, &serialized) } {
+                    return Ok(module);
+                }
+            }
+            Ok(None) => {}
+            // 不安全的缓存文件视为未命中，重新编译并覆盖
+            Err(Error::InsecureCache(file)) => {
+                eprintln!("[ModuleCache]: ignoring insecure cache file {}", file.display());
+            }
+            Err(e) => return Err(e),
+        }
+
+        let module = Module::new(&self.engine, bytes)?;
+        if let Err(e) = store(&path, &module) {
+            eprintln!("[ModuleCache]: failed to write {}: {}", path.display(), e);
+        }
+        Ok(module)
+    }
+}
+
+/// 当前用户的缓存目录
+///
+/// 依次使用 `$XDG_CACHE_HOME/bc-hostcall`、`$HOME/.cache/bc-hostcall`，都未设置时使用临时目录下
+/// 以用户区分的目录。
+pub fn default_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os("XDG_CACHE_HOME").filter(|dir| !dir.is_empty()) {
+        return PathBuf::from(dir).join("bc-hostcall");
+    }
+    if let Some(home) = std::env::var_os("HOME").filter(|home| !home.is_empty()) {
+        return PathBuf::from(home).join(".cache").join("bc-hostcall");
+    }
+    std::env::temp_dir().join(format!("bc-hostcall-cache-{}", current_user()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(unix)]
+fn current_user() -> u32 {
+    // SAFETY: `geteuid` 总是成功
+    unsafe { libc::geteuid() }
+}
+
+#[cfg(not(unix))]
+fn current_user() -> String {
+    std::env::var("USERNAME").unwrap_or_default()
+}
+
+/// 创建仅当前用户可以访问的目录，目录已存在时检查其所有者及权限
+#[cfg(unix)]
+fn create_private_dir(dir: &Path) -> Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+
+    std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+        .map_err(anyhow::Error::from)?;
+    let meta = std::fs::symlink_metadata(dir).map_err(anyhow::Error::from)?;
+    if !meta.is_dir() || meta.uid() != current_user() || meta.mode() & 0o077 != 0 {
+        return Err(Error::InsecureCache(dir.to_path_buf()));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn create_private_dir(dir: &Path) -> Result<()> {
+    std::fs::create_dir_all(dir).map_err(anyhow::Error::from)?;
+    Ok(())
+}
+
+/// 读取缓存文件，文件不存在时返回 `None`
+///
+/// 所有者及权限在打开的文件上检查，避免检查后文件被替换。
+fn read_private(path: &Path) -> Result<Option<Vec<u8>>> {
+    let mut file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(anyhow::Error::from(e).into()),
+    };
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        let meta = file.metadata().map_err(anyhow::Error::from)?;
+        if !meta.is_file() || meta.uid() != current_user() || meta.mode() & 0o022 != 0 {
+            return Err(Error::InsecureCache(path.to_path_buf()));
+        }
+    }
+
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes).map_err(anyhow::Error::from)?;
+    Ok(Some(bytes))
+}
+
+/// 将编译结果写入磁盘缓存
+fn store(path: &Path, module: &Module) -> anyhow::Result<()> {
+    // 先写入临时文件再重命名，避免其他进程读到不完整的文件
+    let temp = path.with_extension(format!("{}.tmp", std::process::id()));
+    let _ = std::fs::remove_file(&temp);
+    create_private_file(&temp)?.write_all(&module.serialize()?)?;
+    std::fs::rename(&temp, path)?;
+    Ok(())
+}
+
+/// 创建仅当前用户可以读写的新文件，权限不受 umask 影响
+#[cfg(unix)]
+fn create_private_file(path: &Path) -> std::io::Result<std::fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+}
+
+#[cfg(not(unix))]
+fn create_private_file(path: &Path) -> std::io::Result<std::fs::File> {
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::module::WasmModule;
+    use crate::test_util::*;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_disk_cache() {
+        let dir = std::env::temp_dir().join(format!("bc-hostcall-cache-{}", std::process::id()));
+        let bytes = std::fs::read(WASM).unwrap();
+
+        // 首次加载时编译并写入磁盘
+        let cache = ModuleCache::with_dir(Engine::default(), &dir).unwrap();
+        cache.load(&bytes).unwrap();
+        let cached = dir.join(format!("{}.cwasm", hex(&cache.key(&bytes))));
+        assert!(cached.exists());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(0o700, std::fs::metadata(&dir).unwrap().permissions().mode() & 0o777);
+        }
+
+        // 新的缓存从磁盘加载
+        let cache = ModuleCache::with_dir(Engine::default(), &dir).unwrap();
+        let compiled = cache.load_file(WASM).unwrap();
+
+        // 同一编译结果可多次实例化
+        for name in ["cache a", "cache b"] {
+            let mut module = WasmModule::new();
+            module.init_module(&compiled, init_exports()).unwrap();
+            module.start().await;
+            let ret = wasm_export_to_host(&module, name.to_string()).await.unwrap();
+            assert_eq!(ret, format!("Hello {}, I'm a wasm module!", name));
+            module.kill();
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_insecure_dir() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("bc-hostcall-insecure-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o777)).unwrap();
+
+        // 其他用户可写的目录被拒绝
+        let ret = ModuleCache::with_dir(Engine::default(), &dir);
+        assert!(matches!(ret, Err(Error::InsecureCache(_))));
+
+        // 缓存文件可被其他用户写入时不加载，重新编译并覆盖
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o700)).unwrap();
+        let cache = ModuleCache::with_dir(Engine::default(), &dir).unwrap();
+        let planted = dir.join(format!("{}.cwasm", hex(&cache.key(EMPTY_MODULE))));
+        std::fs::write(&planted, b"planted").unwrap();
+        std::fs::set_permissions(&planted, std::fs::Permissions::from_mode(0o666)).unwrap();
+        cache.load(EMPTY_MODULE).unwrap();
+        assert_ne!(b"planted".to_vec(), std::fs::read(&planted).unwrap());
+        assert_eq!(0o600, std::fs::metadata(&planted).unwrap().permissions().mode() & 0o777);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_umask() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("bc-hostcall-umask-{}", std::process::id()));
+
+        // 组用户可写的 umask 下写入的缓存文件仍然可以加载
+        // SAFETY: `umask` 总是成功
+        let umask = unsafe { libc::umask(0o002) };
+        let cache = ModuleCache::with_dir(Engine::default(), &dir).unwrap();
+        let ret = cache.load(EMPTY_MODULE);
+        unsafe { libc::umask(umask) };
+        ret.unwrap();
+
+        let cached = dir.join(format!("{}.cwasm", hex(&cache.key(EMPTY_MODULE))));
+        assert_eq!(0o600, std::fs::metadata(&cached).unwrap().permissions().mode() & 0o777);
+        let cache = ModuleCache::with_dir(Engine::default(), &dir).unwrap();
+        assert!(matches!(read_private(&cached), Ok(Some(_))));
+        cache.load(EMPTY_MODULE).unwrap();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_capacity() {
+        let mut cache = ModuleCache::new(Engine::default());
+        cache.set_capacity(1);
+
+        // 超出容量时淘汰最久未使用的模块
+        let bytes = std::fs::read(WASM).unwrap();
+        cache.load(&bytes).unwrap();
+        cache.load(EMPTY_MODULE).unwrap();
+        let mut modules = cache.modules.lock().unwrap();
+        assert_eq!(1, modules.get_mut().len());
+        assert!(modules.get_mut().contains_key(&cache.key(EMPTY_MODULE)));
+    }
+}
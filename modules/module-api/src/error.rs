@@ -0,0 +1,109 @@
+//! 模块接口的错误类型
+
+use std::fmt;
+use std::path::PathBuf;
+
+use low_level::host::Resource;
+use rpc::abi;
+use rpc::manifest::ModuleDependency;
+
+/// 加载、管理模块中可能发生的错误
+#[derive(Debug)]
+pub enum Error {
+    /// 加载、链接或实例化 WASM 模块失败
+    Link(anyhow::Error),
+    /// 低层传输失败，包括 WASM 执行中发生 Trap
+    Transport(low_level::Error),
+    /// 异步调用失败
+    Async(async_api::Error),
+    /// 参数或返回值的序列化失败
+    Serialize(serialize::Error),
+    /// 模块在初始化时没有发送模块信息
+    MissingPeerInfo,
+    /// 模块超出资源限制
+    ResourceLimit(Resource),
+    /// 模块正在重启，调用被快速失败
+    Restarting(abi::LinkHint),
+    /// 模块重启次数超出策略限制，已不再运行
+    ModuleDown(abi::LinkHint),
+    /// 重载得到的模块与被替换的模块名称不同，依次为被替换与新模块的链接提示
+    HintMismatch(abi::LinkHint, abi::LinkHint),
+    /// 模块导入的模块尚未注册或版本不满足要求
+    UnsatisfiedImports(abi::LinkHint, Vec<ModuleDependency>),
+    /// 编译缓存的目录或文件不属于当前用户，或可被其他用户写入
+    InsecureCache(PathBuf),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Link(e) => write!(f, "link error: {}", e),
+            Error::Transport(e) => write!(f, "transport error: {}", e),
+            Error::Async(e) => write!(f, "{}", e),
+            Error::Serialize(e) => write!(f, "{}", e),
+            Error::MissingPeerInfo => write!(f, "Peer Info not presents!"),
+            Error::ResourceLimit(resource) => write!(f, "resource limit exceeded: {}", resource),
+            Error::Restarting(hint) => write!(f, "module {:?} is restarting", hint),
+            Error::ModuleDown(hint) => write!(f, "module {:?} is down after too many restarts", hint),
+            Error::HintMismatch(expected, actual) => write!(f, "expect module {:?}, got {:?}", expected, actual),
+            Error::UnsatisfiedImports(hint, deps) => {
+                let deps: Vec<_> = deps.iter().map(|dep| format!("{} {}", dep.name, dep.req)).collect();
+                write!(f, "module {:?} has unsatisfied imports: {}", hint, deps.join(", "))
+            }
+            Error::InsecureCache(path) => write!(f, "cache path {} is not private to the current user", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Link(e) => Some(e.as_ref()),
+            Error::Transport(e) => Some(e),
+            Error::Async(e) => Some(e),
+            Error::Serialize(e) => Some(e),
+            Error::MissingPeerInfo
+            | Error::ResourceLimit(_)
+            | Error::Restarting(_)
+            | Error::ModuleDown(_)
+            | Error::HintMismatch(_, _)
+            | Error::UnsatisfiedImports(_, _)
+            | Error::InsecureCache(_) => None,
+        }
+    }
+}
+
+impl From<anyhow::Error> for Error {
+    fn from(e: anyhow::Error) -> Self {
+        Error::Link(e)
+    }
+}
+
+impl From<low_level::Error> for Error {
+    fn from(e: low_level::Error) -> Self {
+        match e {
+            low_level::Error::ResourceLimit(resource) => Error::ResourceLimit(resource),
+            e => Error::Transport(e),
+        }
+    }
+}
+
+impl From<async_api::Error> for Error {
+    fn from(e: async_api::Error) -> Self {
+        Error::Async(e)
+    }
+}
+
+impl From<rpc::Error> for Error {
+    fn from(e: rpc::Error) -> Self {
+        Error::Async(e.into())
+    }
+}
+
+impl From<serialize::Error> for Error {
+    fn from(e: serialize::Error) -> Self {
+        Error::Serialize(e)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
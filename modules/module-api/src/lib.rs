@@ -1,10 +1,43 @@
 #![cfg(not(target_arch = "wasm32"))]
 
+pub use error::*;
+// 接口中使用了 wasmtime 的类型（如 `Engine`、`Module`）
+pub use wasmtime;
+
+pub mod builder;
+pub mod cache;
 pub mod module;
 pub mod manager;
+pub mod pool;
+pub mod supervisor;
+mod error;
+
+#[cfg(test)]
+pub(crate) mod test_util {
+    use std::sync::Arc;
+
+    use async_api::ctx::AsyncCtx;
+    use rpc::{abi, RpcExports};
+    use serialize::ArgsBuilder;
+
+    use crate::module::WasmModule;
+    use crate::Result;
+
+    /// 测试使用的 WASM 模块，导出 `wasm_export_to_host`
+    pub const WASM: &str = "../async-api/tests/integrate-wasm/integrate-wasm.wasm";
 
-// FIXME: 此处的错误类型仅仅是最简单，可用于容纳任何错误的类型。而实际上好的错误类型
-//        应该囊括更加细节的错误信息。此处仅为适应短时间的开发需求而临时设计。
-pub type Error = Box<dyn std::error::Error + Send + Sync>;
+    pub fn init_exports() -> RpcExports<Arc<AsyncCtx>> {
+        RpcExports::new(abi::LinkHint::BcModule("integrate-wasm".to_string()))
+    }
 
-pub type Result<T> = std::result::Result<T, Error>;
+    pub async fn wasm_export_to_host(ctx: &WasmModule, param: String) -> Result<String> {
+        let ser_ctx = ctx.serialize_ctx();
+        let mut func = abi::FunctionIdent::new("wasm_export_to_host");
+        func.set_hint(ctx.get_hint());
+        let args = ArgsBuilder::new(&ser_ctx)
+            .push(&param).unwrap()
+            .build().unwrap();
+        let ret = ctx.async_ctx().request_api(func, args).await?;
+        Ok(ser_ctx.deserialize::<String>(&ret)?)
+    }
+}
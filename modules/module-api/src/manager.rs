@@ -1,43 +1,612 @@
 use std::cell::Cell;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use rpc::abi;
+use rpc::manifest::{ModuleDependency, ModuleManifest, Version, VersionReq};
 
 use crate::module::WasmModule;
+use crate::pool::ModulePool;
+use crate::{Error, Result};
 
+/// 链接提示对应的单个模块或模块池
+#[derive(Clone)]
+enum Entry {
+    Module(Arc<WasmModule>),
+    Pool(Arc<ModulePool>),
+}
+
+impl Entry {
+    fn healthy(&self) -> bool {
+        match self {
+            Entry::Module(module) => module.healthy(),
+            Entry::Pool(pool) => pool.healthy(),
+        }
+    }
+
+    async fn shutdown(self, timeout: Duration) -> bool {
+        match self {
+            Entry::Module(module) => module.shutdown(timeout).await,
+            Entry::Pool(pool) => pool.shutdown(timeout).await,
+        }
+    }
+
+    fn manifest(&self) -> Option<&ModuleManifest> {
+        match self {
+            Entry::Module(module) => module.manifest(),
+            Entry::Pool(pool) => pool.manifest(),
+        }
+    }
+
+    fn version(&self) -> Option<Version> {
+        self.manifest().map(|manifest| manifest.version.clone())
+    }
+
+    /// 取出单个模块；模块池会被结束
+    fn into_module(self) -> Option<Arc<WasmModule>> {
+        match self {
+            Entry::Module(module) => Some(module),
+            Entry::Pool(pool) => {
+                pool.kill();
+                None
+            }
+        }
+    }
+}
+
+/// 同一模块的各个版本。没有清单的模块版本未知，视为最低的版本
+type Versions = BTreeMap<Option<Version>, Entry>;
+
+/// 满足链接提示版本要求的最高版本
+fn best_match<'a>(versions: &'a Versions, link_hint: &abi::LinkHint) -> Option<(&'a Option<Version>, &'a Entry)> {
+    versions.iter()
+        .rev()
+        .find(|(version, _)| link_hint.matches_version(version.as_ref()))
+}
+
+/// 精确指定版本的链接提示，版本未知时不附带版本
+fn versioned_hint(link_hint: &abi::LinkHint, version: Option<&Version>) -> abi::LinkHint {
+    match (link_hint, version) {
+        (abi::LinkHint::BcModule(name), Some(version)) => abi::LinkHint::exact(name.clone(), version),
+        (link_hint, _) => link_hint.clone(),
+    }
+}
+
+/// `manifest` 中尚未被已注册模块满足的依赖
+fn unsatisfied(modules: &HashMap<abi::LinkHint, Versions>, manifest: Option<&ModuleManifest>) -> Vec<ModuleDependency> {
+    let manifest = match manifest {
+        Some(manifest) => manifest,
+        None => return Vec::new(),
+    };
+
+    manifest.imports.iter()
+        .filter(|dep| {
+            let hint = abi::LinkHint::BcModuleVersion(dep.name.clone(), dep.req.clone());
+            let versions = modules.get(&hint.unversioned());
+            versions.and_then(|versions| best_match(versions, &hint)).is_none()
+        })
+        .cloned()
+        .collect()
+}
+
+/// 管理已加载的模块
+///
+/// 模块以名称及清单中的版本注册，同一模块的多个版本可以同时存在。解析带有版本要求的链接提示时
+/// 返回满足要求的最高版本，不带版本要求时返回最高版本。
 pub struct ModuleManager {
-    modules: Mutex<Cell<HashMap<abi::LinkHint, Arc<WasmModule>>>>,
+    /// 以不带版本的链接提示为键
+    modules: Mutex<Cell<HashMap<abi::LinkHint, Versions>>>,
+    /// 依赖尚未满足、等待注册的模块
+    deferred: Mutex<Vec<(abi::LinkHint, Arc<WasmModule>)>>,
 }
 
 impl ModuleManager {
     pub fn new() -> Self {
         ModuleManager {
-            modules: Mutex::new(Cell::new(HashMap::new()))
+            modules: Mutex::new(Cell::new(HashMap::new())),
+            deferred: Mutex::new(Vec::new()),
         }
     }
 
+    /// 解析链接提示对应的模块，模块池返回其中负载最低的实例
     pub fn resolve(&self, link_hint: &abi::LinkHint) -> Option<Arc<WasmModule>> {
+        let entry = {
+            let mut modules = self.modules.lock().unwrap();
+            let versions = modules.get_mut().get(&link_hint.unversioned())?;
+            best_match(versions, link_hint).map(|(_, entry)| entry.clone())
+        };
+
+        // 选择实例时可能启动新的实例，因此不持有锁
+        match entry? {
+            Entry::Module(module) => Some(module),
+            Entry::Pool(pool) => pool.pick(),
+        }
+    }
+
+    /// 注册模块，返回被替换的同一版本的模块。被替换的模块池会被结束
+    ///
+    /// 模块清单中导入的模块均需已注册且版本满足要求，否则返回 `Error::UnsatisfiedImports`，
+    /// 此时可使用 `defer` 等待依赖满足后再注册。
+    pub fn register(&self, link_hint: abi::LinkHint, module: Arc<WasmModule>) -> Result<Option<Arc<WasmModule>>> {
+        let old = self.insert_checked(link_hint, Entry::Module(module))?;
+        self.promote_deferred();
+        Ok(old.and_then(Entry::into_module))
+    }
+
+    /// 不检查依赖地注册模块，返回被替换的同一版本的模块。用于重启已注册过的模块，或注册相互依赖的模块
+    pub fn register_unchecked(&self, link_hint: abi::LinkHint, module: Arc<WasmModule>) -> Option<Arc<WasmModule>> {
+        let old = {
+            let mut modules = self.modules.lock().unwrap();
+            let entry = Entry::Module(module);
+            modules.get_mut().entry(link_hint.unversioned()).or_default()
+                .insert(entry.version(), entry)
+        };
+        self.promote_deferred();
+
+        old?.into_module()
+    }
+
+    /// 注册模块池，返回被替换的同一版本的模块。被替换的模块池会被结束
+    ///
+    /// 与 `register` 相同，模块清单中导入的模块需已满足。
+    pub fn register_pool(&self, link_hint: abi::LinkHint, pool: Arc<ModulePool>) -> Result<Option<Arc<WasmModule>>> {
+        let old = self.insert_checked(link_hint, Entry::Pool(pool))?;
+        self.promote_deferred();
+        Ok(old.and_then(Entry::into_module))
+    }
+
+    /// 注册模块，依赖尚未满足时暂缓注册，返回模块是否已被注册
+    ///
+    /// 暂缓的模块在此后注册的模块满足其依赖时自动注册，被替换的同一版本的模块会被结束。
+    pub fn defer(&self, link_hint: abi::LinkHint, module: Arc<WasmModule>) -> bool {
+        match self.insert_checked(link_hint.clone(), Entry::Module(module.clone())) {
+            Ok(old) => {
+                self.promote_deferred();
+                if let Some(old) = old.and_then(Entry::into_module) {
+                    old.kill();
+                }
+                true
+            }
+            Err(_) => {
+                let mut deferred = self.deferred.lock().unwrap();
+                // 同一模块的同一版本只保留最新的一个
+                let version = module.manifest().map(|manifest| &manifest.version);
+                deferred.retain(|(hint, pending)| {
+                    let replaced = hint.unversioned() == link_hint.unversioned()
+                        && pending.manifest().map(|manifest| &manifest.version) == version;
+                    if replaced {
+                        pending.kill();
+                    }
+                    !replaced
+                });
+                deferred.push((link_hint, module));
+                false
+            }
+        }
+    }
+
+    /// 暂缓注册的模块
+    pub fn list_deferred(&self) -> Vec<abi::LinkHint> {
+        let deferred = self.deferred.lock().unwrap();
+
+        deferred.iter()
+            .map(|(hint, module)| versioned_hint(hint, module.manifest().map(|manifest| &manifest.version)))
+            .collect()
+    }
+
+    /// 模块清单中尚未被已注册模块满足的依赖
+    pub fn unsatisfied_imports(&self, module: &WasmModule) -> Vec<ModuleDependency> {
         let mut modules = self.modules.lock().unwrap();
 
-        modules.get_mut().get(link_hint).cloned()
+        unsatisfied(modules.get_mut(), module.manifest())
     }
 
-    pub fn register(&self, link_hint: abi::LinkHint, module: Arc<WasmModule>) -> Option<Arc<WasmModule>> {
+    /// 依赖满足时以模块的版本注册，返回被替换的同一版本的模块或模块池
+    fn insert_checked(&self, link_hint: abi::LinkHint, entry: Entry) -> Result<Option<Entry>> {
         let mut modules = self.modules.lock().unwrap();
+        let modules = modules.get_mut();
 
-        modules.get_mut().insert(link_hint, module)
+        let unsatisfied = unsatisfied(modules, entry.manifest());
+        if !unsatisfied.is_empty() {
+            return Err(Error::UnsatisfiedImports(link_hint, unsatisfied));
+        }
+        Ok(modules.entry(link_hint.unversioned()).or_default().insert(entry.version(), entry))
     }
 
-    pub fn unregister(&self, link_hint: &abi::LinkHint) -> Option<Arc<WasmModule>> {
+    /// 注册依赖已经满足的暂缓模块。注册的模块可能满足其他暂缓模块的依赖，因此重复直至没有模块可以注册
+    fn promote_deferred(&self) {
+        loop {
+            let pending = std::mem::take(&mut *self.deferred.lock().unwrap());
+            if pending.is_empty() {
+                return;
+            }
+
+            let mut promoted = false;
+            let mut remaining = Vec::new();
+            for (hint, module) in pending {
+                match self.insert_checked(hint.clone(), Entry::Module(module.clone())) {
+                    Ok(old) => {
+                        promoted = true;
+                        if let Some(old) = old.and_then(Entry::into_module) {
+                            old.kill();
+                        }
+                    }
+                    Err(_) => remaining.push((hint, module)),
+                }
+            }
+
+            // 保留期间新加入的暂缓模块
+            self.deferred.lock().unwrap().extend(remaining);
+            if !promoted {
+                return;
+            }
+        }
+    }
+
+    /// 取出链接提示的版本要求所匹配的全部版本
+    fn take_matching(&self, link_hint: &abi::LinkHint) -> Vec<Entry> {
         let mut modules = self.modules.lock().unwrap();
+        let modules = modules.get_mut();
+
+        let key = link_hint.unversioned();
+        let versions = match modules.get_mut(&key) {
+            Some(versions) => versions,
+            None => return Vec::new(),
+        };
+        let matching: Vec<_> = versions.keys()
+            .filter(|version| link_hint.matches_version(version.as_ref()))
+            .cloned()
+            .collect();
+        let taken = matching.iter()
+            .filter_map(|version| versions.remove(version))
+            .collect();
+        if versions.is_empty() {
+            modules.remove(&key);
+        }
+        taken
+    }
 
-        modules.get_mut().remove(link_hint)
+    /// 注销链接提示的版本要求所匹配的全部版本，返回被注销的模块。注销的模块池会被结束
+    pub fn unregister(&self, link_hint: &abi::LinkHint) -> Vec<Arc<WasmModule>> {
+        self.take_matching(link_hint).into_iter()
+            .filter_map(Entry::into_module)
+            .collect()
     }
 
+    /// 注册模块，并卸载被替换的同一版本的模块或模块池，返回是否替换了已有的模块
+    ///
+    /// 其他版本不受影响。此后对该版本的请求均由新模块处理；被替换的模块最多等待 `timeout` 让已接受
+    /// 的请求返回结果，见 `WasmModule::shutdown`。与 `register` 相同，模块清单中导入的模块需已满足。
+    pub async fn replace(&self, link_hint: abi::LinkHint, module: Arc<WasmModule>, timeout: Duration) -> Result<bool> {
+        let old = self.insert_checked(link_hint, Entry::Module(module))?;
+        self.promote_deferred();
+
+        match old {
+            Some(old) => {
+                old.shutdown(timeout).await;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// 热重载模块并迁移其状态，返回新的模块
+    ///
+    /// 被替换的是链接提示解析到的版本。旧模块先停止接受新的请求，最多等待 `timeout` 让已接受的请求
+    /// 返回结果，再在 `timeout` 内导出状态；之后以 `init` 创建新模块，由其在 `__bc_main` 中导入状态。
+    /// 新模块启动并注册成功后才卸载旧模块；导出状态、新模块初始化、启动或注册失败时，新模块被结束，
+    /// 旧模块恢复接受请求并继续运行。
+    ///
+    /// 新模块的版本不同时，旧版本与新版本原有的模块都会被卸载。链接提示对应模块池或尚无模块时，
+    /// `init` 收到 `None`。
+    pub async fn reload<F>(self: &Arc<Self>,
+                           link_hint: abi::LinkHint,
+                           init: F,
+                           timeout: Duration,
+    ) -> Result<Arc<WasmModule>>
+        where F: FnOnce(Option<Vec<u8>>) -> Result<WasmModule>,
+    {
+        let old = {
+            let mut modules = self.modules.lock().unwrap();
+            modules.get_mut().get(&link_hint.unversioned())
+                .and_then(|versions| best_match(versions, &link_hint))
+                .map(|(version, entry)| (version.clone(), entry.clone()))
+        };
+
+        // 旧模块排空后再导出状态，以免遗漏已接受的请求对状态的修改
+        let old_module = match &old {
+            Some((_, Entry::Module(module))) => Some(module.clone()),
+            _ => None,
+        };
+        if let Some(old_module) = &old_module {
+            old_module.drain(timeout).await;
+        }
+
+        let (module, displaced) = match self.start_reloaded(&link_hint, old_module.as_deref(), init, timeout).await {
+            Ok(started) => started,
+            Err(e) => {
+                if let Some(old_module) = &old_module {
+                    old_module.resume();
+                }
+                return Err(e);
+            }
+        };
+
+        // 新模块的版本不同时，同样移除被替换的版本
+        let mut replaced: Vec<_> = displaced.into_iter().collect();
+        if let Some((version, _)) = old {
+            if version.as_ref() != module.version() {
+                let mut modules = self.modules.lock().unwrap();
+                if let Some(versions) = modules.get_mut().get_mut(&link_hint.unversioned()) {
+                    replaced.extend(versions.remove(&version));
+                }
+            }
+        }
+        self.promote_deferred();
+
+        for entry in replaced {
+            entry.shutdown(timeout).await;
+        }
+        Ok(module)
+    }
+
+    /// 以旧模块导出的状态创建、启动并注册新模块，返回新模块及被其替换的同一版本的模块或模块池
+    ///
+    /// 失败时结束新模块，旧模块不受影响。
+    async fn start_reloaded<F>(self: &Arc<Self>,
+                               link_hint: &abi::LinkHint,
+                               old: Option<&WasmModule>,
+                               init: F,
+                               timeout: Duration,
+    ) -> Result<(Arc<WasmModule>, Option<Entry>)>
+        where F: FnOnce(Option<Vec<u8>>) -> Result<WasmModule>,
+    {
+        let state = match old {
+            Some(old) => old.export_state(timeout).await?,
+            None => None,
+        };
+
+        // 新模块在注册前不会被解析到，失败时直接结束
+        let module = init(state)?;
+        if module.get_hint() != link_hint.unversioned() {
+            module.kill();
+            return Err(Error::HintMismatch(link_hint.clone(), module.get_hint()));
+        }
+        let unsatisfied = self.unsatisfied_imports(&module);
+        if !unsatisfied.is_empty() {
+            module.kill();
+            return Err(Error::UnsatisfiedImports(link_hint.clone(), unsatisfied));
+        }
+        module.start().await;
+        if let Some(poison) = module.poisoned() {
+            module.kill();
+            return Err(Error::Async(poison.into()));
+        }
+
+        let module = Arc::new(module);
+        module.clone().attach_to_manager(self.clone());
+        match self.insert_checked(link_hint.clone(), Entry::Module(module.clone())) {
+            Ok(displaced) => Ok((module, displaced)),
+            Err(e) => {
+                module.kill();
+                Err(e)
+            }
+        }
+    }
+
+    /// 注销并卸载链接提示的版本要求所匹配的全部版本，返回模块是否存在
+    ///
+    /// 模块最多等待 `timeout` 让已接受的请求返回结果，见 `WasmModule::shutdown`。
+    pub async fn remove(&self, link_hint: &abi::LinkHint, timeout: Duration) -> bool {
+        let taken = self.take_matching(link_hint);
+        let found = !taken.is_empty();
+
+        for entry in taken {
+            entry.shutdown(timeout).await;
+        }
+        found
+    }
+
+    /// 已注册的模块，带有版本的模块以精确指定版本的链接提示表示
     pub fn list_modules(&self) -> Vec<abi::LinkHint> {
         let mut modules = self.modules.lock().unwrap();
 
-        modules.get_mut().keys().cloned().collect()
+        modules.get_mut().iter()
+            .flat_map(|(hint, versions)| {
+                versions.keys().map(move |version| versioned_hint(hint, version.as_ref()))
+            })
+            .collect()
+    }
+
+    /// 链接提示解析到的模块是否仍在正常运行，模块不存在时返回 `None`
+    ///
+    /// 模块池中仍有正常运行的实例时视为健康。
+    pub fn is_healthy(&self, link_hint: &abi::LinkHint) -> Option<bool> {
+        let mut modules = self.modules.lock().unwrap();
+
+        let versions = modules.get_mut().get(&link_hint.unversioned())?;
+        best_match(versions, link_hint).map(|(_, entry)| entry.healthy())
+    }
+
+    /// 被毒化而停止运行的模块
+    pub fn list_unhealthy(&self) -> Vec<abi::LinkHint> {
+        let mut modules = self.modules.lock().unwrap();
+
+        modules.get_mut().iter()
+            .flat_map(|(hint, versions)| {
+                versions.iter()
+                    .filter(|(_, entry)| !entry.healthy())
+                    .map(move |(version, _)| versioned_hint(hint, version.as_ref()))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::*;
+
+    use super::*;
+
+    fn load_module() -> Result<WasmModule> {
+        let mut module = WasmModule::new();
+        module.init(WASM, init_exports())?;
+        Ok(module)
+    }
+
+    async fn register_module(manager: &Arc<ModuleManager>) -> Arc<WasmModule> {
+        let module = load_module().unwrap();
+        module.start().await;
+        let module = Arc::new(module);
+        module.clone().attach_to_manager(manager.clone());
+        manager.register(module.get_hint(), module.clone()).unwrap();
+        module
+    }
+
+    /// 带有清单的模块，`imports` 为依赖的模块名称及版本要求
+    fn manifest_module(name: &str, version: &str, imports: &[(&str, &str)]) -> Arc<WasmModule> {
+        let mut manifest = ModuleManifest::new(name, version.parse().unwrap());
+        for (name, req) in imports {
+            manifest.add_import(*name, req.parse().unwrap());
+        }
+        Arc::new(WasmModule::with_manifest(manifest))
+    }
+
+    fn hint(name: &str) -> abi::LinkHint {
+        abi::LinkHint::BcModule(name.to_string())
+    }
+
+    #[test]
+    fn test_register_unsatisfied() {
+        let manager = ModuleManager::new();
+        let dispatch = manifest_module("dispatch", "0.1.0", &[("service", "^1.1")]);
+
+        // 依赖的模块尚未注册
+        match manager.register(hint("dispatch"), dispatch.clone()) {
+            Err(Error::UnsatisfiedImports(_, deps)) => {
+                assert_eq!(1, deps.len());
+                assert_eq!("service", deps[0].name);
+            }
+            _ => panic!("expect UnsatisfiedImports"),
+        }
+        assert!(manager.resolve(&hint("dispatch")).is_none());
+
+        // 依赖的模块版本不满足要求
+        manager.register(hint("service"), manifest_module("service", "1.0.0", &[])).unwrap();
+        assert!(matches!(manager.register(hint("dispatch"), dispatch.clone()),
+                         Err(Error::UnsatisfiedImports(_, _))));
+
+        manager.register(hint("service"), manifest_module("service", "1.2.0", &[])).unwrap();
+        assert!(manager.unsatisfied_imports(&dispatch).is_empty());
+        manager.register(hint("dispatch"), dispatch.clone()).unwrap();
+        assert!(Arc::ptr_eq(&dispatch, &manager.resolve(&hint("dispatch")).unwrap()));
+    }
+
+    #[test]
+    fn test_defer() {
+        let manager = ModuleManager::new();
+        let storage = manifest_module("storage", "1.0.0", &[]);
+        let service = manifest_module("service", "1.0.0", &[("storage", "^1")]);
+        let dispatch = manifest_module("dispatch", "0.1.0", &[("service", "^1")]);
+
+        // 依赖尚未满足的模块暂缓注册
+        assert!(!manager.defer(hint("dispatch"), dispatch.clone()));
+        assert!(!manager.defer(hint("service"), service.clone()));
+        assert!(manager.resolve(&hint("dispatch")).is_none());
+        let version = Version::new(0, 1, 0);
+        assert!(manager.list_deferred().contains(&abi::LinkHint::exact("dispatch", &version)));
+        assert_eq!(2, manager.list_deferred().len());
+
+        // 注册的模块满足依赖后，依次注册暂缓的模块
+        assert!(manager.defer(hint("storage"), storage));
+        assert!(manager.list_deferred().is_empty());
+        assert!(Arc::ptr_eq(&service, &manager.resolve(&hint("service")).unwrap()));
+        assert!(Arc::ptr_eq(&dispatch, &manager.resolve(&hint("dispatch")).unwrap()));
+    }
+
+    #[test]
+    fn test_versions() {
+        let manager = ModuleManager::new();
+        let v1_0 = manifest_module("service", "1.0.0", &[]);
+        let v1_2 = manifest_module("service", "1.2.0", &[]);
+        let v2_0 = manifest_module("service", "2.0.0", &[]);
+        for module in [&v1_2, &v2_0, &v1_0] {
+            assert!(manager.register(hint("service"), module.clone()).unwrap().is_none());
+        }
+
+        // 同一模块的多个版本同时存在
+        let mut modules = manager.list_modules();
+        modules.sort_by_key(|hint| format!("{:?}", hint));
+        let expected: Vec<_> = ["1.0.0", "1.2.0", "2.0.0"].iter()
+            .map(|version| abi::LinkHint::exact("service", &version.parse().unwrap()))
+            .collect();
+        assert_eq!(expected, modules);
+
+        // 不带版本要求时解析到最高版本，带有版本要求时解析到满足要求的最高版本
+        let resolve = |hint: abi::LinkHint| manager.resolve(&hint);
+        let versioned = |req: &str| abi::LinkHint::BcModuleVersion("service".to_string(), req.parse().unwrap());
+        assert!(Arc::ptr_eq(&v2_0, &resolve(hint("service")).unwrap()));
+        assert!(Arc::ptr_eq(&v1_2, &resolve(versioned("^1")).unwrap()));
+        assert!(Arc::ptr_eq(&v1_0, &resolve(versioned("<1.1")).unwrap()));
+        assert!(resolve(versioned("^3")).is_none());
+
+        // 精确指定版本
+        assert!(Arc::ptr_eq(&v1_0, &resolve(abi::LinkHint::exact("service", &Version::new(1, 0, 0))).unwrap()));
+        assert!(resolve(abi::LinkHint::exact("service", &Version::new(1, 1, 0))).is_none());
+
+        // 注册同一版本时替换该版本，其他版本不受影响
+        let new_v1_2 = manifest_module("service", "1.2.0", &[]);
+        let old = manager.register(hint("service"), new_v1_2.clone()).unwrap().unwrap();
+        assert!(Arc::ptr_eq(&v1_2, &old));
+        assert!(Arc::ptr_eq(&new_v1_2, &resolve(versioned("^1")).unwrap()));
+        assert_eq!(3, manager.list_modules().len());
+
+        // 注销满足版本要求的全部版本
+        assert_eq!(2, manager.unregister(&versioned("^1")).len());
+        assert!(resolve(versioned("^1")).is_none());
+        assert!(Arc::ptr_eq(&v2_0, &resolve(hint("service")).unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_reload() {
+        let manager = Arc::new(ModuleManager::new());
+        let old = register_module(&manager).await;
+        let hint = old.get_hint();
+
+        // 旧模块在导出状态后、新模块创建时已停止接受请求。测试模块没有状态钩子，因此没有状态
+        let draining = old.clone();
+        let module = manager.reload(hint.clone(), |state| {
+            assert!(draining.async_ctx().draining());
+            assert!(state.is_none());
+            load_module()
+        }, Duration::from_secs(1)).await.unwrap();
+
+        // 请求由新模块处理，旧模块已被卸载
+        assert!(Arc::ptr_eq(&module, &manager.resolve(&hint).unwrap()));
+        let ret = wasm_export_to_host(&module, "reloaded".to_string()).await.unwrap();
+        assert_eq!(ret, "Hello reloaded, I'm a wasm module!".to_string());
+        assert!(!old.async_ctx().alive());
+        module.kill();
+    }
+
+    #[tokio::test]
+    async fn test_reload_rollback() {
+        let manager = Arc::new(ModuleManager::new());
+        let old = register_module(&manager).await;
+        let hint = old.get_hint();
+
+        // 新模块初始化失败
+        let ret = manager.reload(hint.clone(), |_| {
+            Err(Error::MissingPeerInfo)
+        }, Duration::from_secs(1)).await;
+        assert!(matches!(ret, Err(Error::MissingPeerInfo)));
+
+        // 旧模块恢复接受请求并继续运行
+        assert!(Arc::ptr_eq(&old, &manager.resolve(&hint).unwrap()));
+        assert!(!old.async_ctx().draining());
+        let ret = wasm_export_to_host(&old, "rollback".to_string()).await.unwrap();
+        assert_eq!(ret, "Hello rollback, I'm a wasm module!".to_string());
+        old.kill();
     }
 }
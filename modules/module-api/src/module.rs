@@ -1,98 +1,473 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 
 use wasmtime::{Engine, Linker, Store};
-use wasmtime_wasi::{WasiCtx, WasiCtxBuilder};
+use wasmtime_wasi::WasiCtx;
 
-use async_api::ctx::AsyncCtx;
-use low_level::host::LowLevelCtx;
-use rpc::{abi, RpcExports, RpcNode};
-use serialize::SerializeCtx;
+use async_api::ctx::{AsyncCtx, LifecycleEvent, Poison};
+use low_level::host::{CpuLimits, Limiter, LowLevelCtx, ResourceLimits, Transport};
+use rpc::{abi, ImportPolicy, RpcDeadline, RpcErrorCode, RpcExports, RpcImports, RpcNode};
+use rpc::capability::Capabilities;
+use rpc::manifest::{ModuleManifest, Version};
+use serialize::{ArgsBuilder, SerializeCtx, SerializeFormat};
 
+use crate::builder::HostEnv;
 use crate::manager::ModuleManager;
-use crate::Result;
+use crate::{Error, Result};
+
+/// 异步模式下，WASM 每消耗此数量的燃料后让出执行
+const FUEL_PER_YIELD: u64 = 10_000;
+
+/// 推进 epoch 的间隔，即 `CpuLimits` 中一个 epoch 的时长
+pub const EPOCH_TICK: Duration = Duration::from_millis(10);
+
+/// 实例化期间的 epoch 截止时间
+const INIT_EPOCH_DEADLINE: u64 = 1 << 32;
+
+/// 模块 Store 中的数据，`T` 为宿主通过 `WasmModuleBuilder::data` 提供的自定义数据
+pub struct ModuleData<T = ()> {
+    wasi: WasiCtx,
+    limiter: Limiter,
+    data: T,
+}
+
+impl<T> ModuleData<T> {
+    pub fn wasi(&mut self) -> &mut WasiCtx {
+        &mut self.wasi
+    }
+
+    pub fn data(&self) -> &T {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut T {
+        &mut self.data
+    }
+}
+
+/// 擦除 Store 数据类型后的低层上下文，使 `WasmModule` 不必带有类型参数
+trait Runtime: Send + Sync {
+    fn start(self: Arc<Self>, async_ctx: Arc<AsyncCtx>) -> Pin<Box<dyn Future<Output=()> + Send>>;
+
+    /// 回收 Store 及其中的实例。仍有调用正在执行时，Store 在调用结束后随模块释放
+    fn teardown(&self);
+}
+
+impl<T> Runtime for LowLevelCtx<ModuleData<T>>
+    where T: Send + Sync + 'static,
+{
+    fn start(self: Arc<Self>, async_ctx: Arc<AsyncCtx>) -> Pin<Box<dyn Future<Output=()> + Send>> {
+        Box::pin(async_ctx.start(self))
+    }
+
+    fn teardown(&self) {
+        drop(self.take_store());
+    }
+}
+
+/// 实例化前准备好的 Engine、Store 等，见 `WasmModule::prepare_init`
+struct PendingInit<T>
+    where T: Send + Sync + 'static,
+{
+    engine: Engine,
+    linker: Linker<ModuleData<T>>,
+    store: Store<ModuleData<T>>,
+    module: wasmtime::Module,
+    ll_ctx: Arc<LowLevelCtx<ModuleData<T>>>,
+    /// 是否由本模块定期推进 epoch
+    tick_epoch: bool,
+}
 
 /// WASM 模块在本运行时中的封装
 pub struct WasmModule {
     name: Option<String>,
+    /// 模块在握手时发送的清单
+    manifest: Option<ModuleManifest>,
     async_ctx: Arc<AsyncCtx>,
-    ll_ctx: Option<Arc<LowLevelCtx<WasiCtx>>>,
+    ll_ctx: Option<Arc<dyn Runtime>>,
+    format: SerializeFormat,
+    transport: Transport,
+    async_mode: bool,
+    limits: CpuLimits,
+    resource_limits: ResourceLimits,
+    /// 交给模块在 `__bc_main` 中导入的状态
+    initial_state: Option<Vec<u8>>,
+    /// 授予模块的能力
+    capabilities: Option<Capabilities>,
+    /// 对模块导入表的要求
+    import_policy: ImportPolicy,
+    /// 通知推进 epoch 的线程结束，模块被释放时设置
+    ticker_stop: Arc<AtomicBool>,
 }
 
 impl WasmModule {
     pub fn new() -> Self {
         WasmModule {
             name: None,
+            manifest: None,
             async_ctx: Arc::new(AsyncCtx::new()),
             ll_ctx: None,
+            format: SerializeFormat::default(),
+            transport: Transport::default(),
+            async_mode: false,
+            limits: CpuLimits::default(),
+            resource_limits: ResourceLimits::default(),
+            initial_state: None,
+            capabilities: None,
+            import_policy: ImportPolicy::default(),
+            ticker_stop: Arc::new(AtomicBool::new(false)),
         }
     }
 
+    /// 设置 Host 端偏好的序列化格式，需在 `init` 之前调用
+    ///
+    /// 实际使用的格式在握手时从双方都支持的格式中选取，优先使用此处设置的格式。
+    pub fn set_serialize_format(&mut self, format: SerializeFormat) {
+        self.format = format;
+    }
+
+    /// 设置与模块传递消息的方式，需在 `init` 之前调用
+    ///
+    /// 模块不支持环形缓冲区时，仍通过函数调用传递消息。
+    pub fn set_transport(&mut self, transport: Transport) {
+        self.transport = transport;
+    }
+
+    /// 设置是否以异步模式执行模块，需在 `init` 之前调用
+    ///
+    /// 异步模式下，模块的执行会定期让出，长时间运行的模块不会阻塞异步运行时中的其他任务。
+    pub fn set_async_mode(&mut self, async_mode: bool) {
+        self.async_mode = async_mode;
+    }
+
+    /// 设置模块执行的 CPU 预算，需在 `init` 之前调用
+    ///
+    /// epoch 预算以 `EPOCH_TICK` 为单位。模块超出预算后停止运行，等待中与之后的请求均以
+    /// `async_api::Error::CpuExhausted` 失败，模块被标记为不健康。
+    pub fn set_cpu_limits(&mut self, limits: CpuLimits) {
+        self.limits = limits;
+    }
+
+    /// 设置模块的内存、表与实例数限制，需在 `init` 之前调用
+    ///
+    /// 模块在初始化时超出限制则 `init` 以 `Error::ResourceLimit` 失败；运行中超出限制则停止运行，
+    /// 等待中与之后的请求均以 `async_api::Error::ResourceLimit` 失败。
+    pub fn set_resource_limits(&mut self, limits: ResourceLimits) {
+        self.resource_limits = limits;
+    }
+
+    /// 设置模块在 `__bc_main` 中导入的初始状态，需在 `init` 之前调用
+    ///
+    /// 状态通常来自旧版本模块的 `export_state`，见 `ModuleManager::reload`。模块没有提供状态钩子时忽略。
+    pub fn set_initial_state(&mut self, state: Vec<u8>) {
+        self.initial_state = Some(state);
+    }
+
+    /// 设置对模块导入表的要求，需在 `init` 之前调用
+    ///
+    /// 默认为 `ImportPolicy::Optional`，即未声明导入表的模块不受限制。使用 `ImportPolicy::Required`
+    /// 拒绝未声明导入表的模块的所有调用，或以 `ImportPolicy::Fixed` 由 Host 提供导入表。
+    pub fn set_import_policy(&mut self, policy: ImportPolicy) {
+        self.import_policy = policy;
+    }
+
+    /// 设置授予模块的能力，需在 `init` 之前调用
+    ///
+    /// 模块调用 Host 导出的函数或其他模块导出的函数时均需被授权，未被授权的调用以
+    /// `RpcErrorCode::PermissionDenied` 失败。未设置时不作限制。
+    pub fn set_capabilities(&mut self, capabilities: Capabilities) {
+        self.capabilities = Some(capabilities);
+    }
+
+    /// 设置等待发送至模块、等待处理的消息数上限，超出时新的请求以 `async_api::Error::QueueFull` 失败
+    pub fn set_max_pending_messages(&self, max: Option<usize>) {
+        self.async_ctx.set_max_pending_messages(max);
+    }
+
+    /// 模块是否仍在正常运行，被毒化的模块不再健康
+    pub fn healthy(&self) -> bool {
+        self.async_ctx.poisoned().is_none()
+    }
+
+    /// 模块当前的负载，即等待模块返回结果的请求数与等待发送至模块的消息数之和
+    pub fn load(&self) -> usize {
+        self.async_ctx.load()
+    }
+
+    /// 模块被毒化的原因，即 Trap、超出 CPU 预算或资源限制
+    pub fn poisoned(&self) -> Option<Poison> {
+        self.async_ctx.poisoned()
+    }
+
+    /// 设置模块生命周期事件的回调，宿主可以在模块被毒化后重启或卸载模块
+    pub fn set_lifecycle_cb<CB>(&self, cb: CB)
+        where CB: Fn(LifecycleEvent) + Send + Sync + 'static,
+    {
+        self.async_ctx.set_lifecycle_cb(cb);
+    }
+
+    /// 当前与模块通信所使用的序列化上下文
+    pub fn serialize_ctx(&self) -> SerializeCtx {
+        self.async_ctx.serialize_ctx()
+    }
+
     // 加载模块并进行初始化
-    // FIXME: 其实是一个很差劲的封装，大部分操作都是硬编码的，几乎没有可拓展性。但也没有时间去做到
-    //        更好了，done is better than nothing.
+    // 需要自定义 Engine、WASI 或 Store 数据时使用 `WasmModuleBuilder`
+    // 异步模式下阻塞当前线程直至初始化完成，在异步运行时中应使用 `init_async`
     pub fn init(&mut self,
                 filename: &str,
                 host_exports: RpcExports<Arc<AsyncCtx>>,
     ) -> Result<()> {
-        let engine = Engine::default();
-        let mut linker = Linker::new(&engine);
+        self.init_with(|engine| wasmtime::Module::from_file(engine, filename),
+                       host_exports, HostEnv::default())
+    }
 
-        // 链接 WASI 函数
-        wasmtime_wasi::add_to_linker(&mut linker, |s| s)?;
+    /// 从内存中的 WASM 字节码加载模块并进行初始化
+    pub fn init_from_bytes(&mut self,
+                           bytes: &[u8],
+                           host_exports: RpcExports<Arc<AsyncCtx>>,
+    ) -> Result<()> {
+        self.init_with(|engine| wasmtime::Module::new(engine, bytes),
+                       host_exports, HostEnv::default())
+    }
+
+    /// `init` 的异步版本，异步模式下实例化与调用主函数时不阻塞当前线程
+    pub async fn init_async(&mut self,
+                            filename: &str,
+                            host_exports: RpcExports<Arc<AsyncCtx>>,
+    ) -> Result<()> {
+        self.init_with_async(|engine| wasmtime::Module::from_file(engine, filename),
+                             host_exports, HostEnv::default()).await
+    }
+
+    /// `init_from_bytes` 的异步版本，异步模式下实例化与调用主函数时不阻塞当前线程
+    pub async fn init_from_bytes_async(&mut self,
+                                       bytes: &[u8],
+                                       host_exports: RpcExports<Arc<AsyncCtx>>,
+    ) -> Result<()> {
+        self.init_with_async(|engine| wasmtime::Module::new(engine, bytes),
+                             host_exports, HostEnv::default()).await
+    }
+
+    /// 以已编译的模块进行初始化，模块所属的 Engine 作为共享 Engine 使用
+    ///
+    /// 配合 `ModuleCache` 使用时，同一模块多次实例化无需重新编译。
+    pub fn init_module(&mut self,
+                       module: &wasmtime::Module,
+                       host_exports: RpcExports<Arc<AsyncCtx>>,
+    ) -> Result<()> {
+        let env = HostEnv {
+            engine: Some(module.engine().clone()),
+            ..HostEnv::default()
+        };
+        self.init_with(|_| Ok(module.clone()), host_exports, env)
+    }
+
+    /// 加载 wasmtime 预编译的 `.cwasm` 模块并进行初始化
+    ///
+    /// 预编译时的配置需与本模块的异步模式及 CPU 预算一致。
+    ///
+    /// # Safety
+    ///
+    /// 文件中的机器码会被直接执行，因此只能加载可信的预编译产物，见
+    /// `wasmtime::Module::deserialize_file`。
+    pub unsafe fn init_precompiled(&mut self,
+                                   filename: &str,
+                                   host_exports: RpcExports<Arc<AsyncCtx>>,
+    ) -> Result<()> {
+        self.init_with(|engine| wasmtime::Module::deserialize_file(engine, filename),
+                       host_exports, HostEnv::default())
+    }
+
+    pub(crate) fn init_with<F, T>(&mut self,
+                                  load: F,
+                                  host_exports: RpcExports<Arc<AsyncCtx>>,
+                                  env: HostEnv<T>,
+    ) -> Result<()>
+        where F: FnOnce(&Engine) -> anyhow::Result<wasmtime::Module>,
+              T: Send + Sync + 'static,
+    {
+        let PendingInit { engine, linker, mut store, module, ll_ctx, tick_epoch } =
+            self.prepare_init(load, host_exports, env)?;
+
+        // 实例化 WASM
+        let instance = ll_ctx.instantiate(&linker, &mut store, &module)?;
+        ll_ctx.attach(&mut store, &instance)?;
+        // XX 我们在这里直接移入 Store，因为几乎没有什么情况是需要在外侧操作 Store 的。
+        //    就算如果有，`ll_ctx` 也允许我们移出。
+        ll_ctx.move_store(store);
+
+        // 调用主函数进行初始化
+        ll_ctx.wasm_main()?;
+
+        self.finish_init(ll_ctx, engine, tick_epoch)
+    }
 
-        // 创建 WASI 上下文
-        let wasi = WasiCtxBuilder::new()
-            .inherit_stdio()
-            .build();
-        let mut store = Store::new(&engine, wasi);
+    /// `init_with` 的异步版本，实例化与调用主函数期间 WASM 让出执行时让出当前任务
+    pub(crate) async fn init_with_async<F, T>(&mut self,
+                                              load: F,
+                                              host_exports: RpcExports<Arc<AsyncCtx>>,
+                                              env: HostEnv<T>,
+    ) -> Result<()>
+        where F: FnOnce(&Engine) -> anyhow::Result<wasmtime::Module>,
+              T: Send + Sync + 'static,
+    {
+        let PendingInit { engine, linker, mut store, module, ll_ctx, tick_epoch } =
+            self.prepare_init(load, host_exports, env)?;
 
-        // 创建 Module 并进行实例化
-        let module = wasmtime::Module::from_file(store.engine(), filename)?;
+        // 实例化 WASM
+        let instance = ll_ctx.instantiate_async(&linker, &mut store, &module).await?;
+        ll_ctx.attach(&mut store, &instance)?;
+        ll_ctx.move_store(store);
+
+        // 调用主函数进行初始化
+        ll_ctx.wasm_main_async().await?;
+
+        self.finish_init(ll_ctx, engine, tick_epoch)
+    }
+
+    /// 创建 Engine、Store 与低层上下文并绑定 RpcNode，准备实例化
+    fn prepare_init<F, T>(&mut self,
+                          load: F,
+                          host_exports: RpcExports<Arc<AsyncCtx>>,
+                          env: HostEnv<T>,
+    ) -> Result<PendingInit<T>>
+        where F: FnOnce(&Engine) -> anyhow::Result<wasmtime::Module>,
+              T: Send + Sync + 'static,
+    {
+        let mut limits = self.limits;
+        if self.async_mode {
+            limits.fuel_per_yield.get_or_insert(FUEL_PER_YIELD);
+        }
+        let consume_fuel = self.async_mode || limits.fuel_per_call.is_some() || limits.fuel_per_poll.is_some();
+        let epoch_interruption = limits.epoch_per_call.is_some() || limits.epoch_per_poll.is_some();
+
+        // 共享的 Engine 由外部负责配置与推进 epoch
+        let shared_engine = env.engine.is_some();
+        let engine = match env.engine {
+            Some(engine) => engine,
+            None => {
+                let mut config = env.config;
+                config.async_support(self.async_mode)
+                    .consume_fuel(consume_fuel)
+                    .epoch_interruption(epoch_interruption);
+                Engine::new(&config)?
+            }
+        };
+        let mut linker = Linker::new(&engine);
+
+        // 链接 WASI 函数
+        wasmtime_wasi::add_to_linker(&mut linker, |s: &mut ModuleData<T>| &mut s.wasi)?;
 
         // 初始化 Lowlevel
         let async_ctx = self.async_ctx.clone();
         let mut ll_ctx = LowLevelCtx::new();
+        ll_ctx.set_transport(self.transport);
+        ll_ctx.set_async_mode(self.async_mode);
+        ll_ctx.set_cpu_limits(limits);
         async_ctx.clone().bind_low_level(&mut ll_ctx);
+        if let Some(state) = self.initial_state.take() {
+            ll_ctx.set_initial_state(state);
+        }
         let ll_ctx = Arc::new(ll_ctx);
         ll_ctx.clone().add_to_linker(&mut linker)?;
 
+        // 链接宿主提供的函数
+        for link in env.linker_fns {
+            link(&mut linker)?;
+        }
+
+        // 创建 Store
+        let data = ModuleData {
+            wasi: env.wasi,
+            limiter: ll_ctx.resource_limiter(self.resource_limits),
+            data: env.data,
+        };
+        let mut store = Store::new(&engine, data);
+        store.limiter(|s| &mut s.limiter);
+
+        // 实例化期间不限制 CPU，此后每次调用前由 `ll_ctx` 按预算重置
+        if self.async_mode {
+            store.out_of_fuel_async_yield(u64::MAX, FUEL_PER_YIELD);
+        } else if consume_fuel {
+            store.add_fuel(u64::MAX)?;
+        } else if shared_engine {
+            // 共享的 Engine 可能开启了燃料计量，未开启时忽略错误
+            let _ = store.add_fuel(u64::MAX);
+        }
+        if epoch_interruption || shared_engine {
+            store.set_epoch_deadline(INIT_EPOCH_DEADLINE);
+        }
+
+        // 创建 Module
+        let module = load(store.engine())?;
+
         // 创建 RpcNode
         let mut rpc_node = RpcNode::new(
-            SerializeCtx::new(),
-            0,
+            SerializeCtx::with_format(self.format),
+            env.nonce,
             async_ctx.clone(),
         );
 
         // 增加 Host 端的导入导出需求
         rpc_node.set_exports(host_exports);
+        rpc_node.set_import_policy(self.import_policy.clone());
+        if let Some(capabilities) = self.capabilities.clone() {
+            rpc_node.set_capabilities(capabilities);
+        }
 
         // 绑定 RpcNode
         async_ctx.bind_rpc(rpc_node);
 
-        // 实例化 WASM
-        let instance = linker.instantiate(&mut store, &module)?;
-        ll_ctx.attach(&mut store, &instance)?;
-        // XX 我们在这里直接移入 Store，因为几乎没有什么情况是需要在外侧操作 Store 的。
-        //    就算如果有，`ll_ctx` 也允许我们移出。
-        ll_ctx.move_store(store);
+        Ok(PendingInit {
+            engine,
+            linker,
+            store,
+            module,
+            ll_ctx,
+            tick_epoch: epoch_interruption && !shared_engine,
+        })
+    }
 
-        // 调用主函数进行初始化
-        // XX 这里可以修改为异步，但是实际上这个函数只是做内部数据结构的初始化，不会有什么
-        //    很长的 Block 或者甚至 Polling。所以实际上没有必要异步。
-        ll_ctx.wasm_main()?;
+    /// 主函数返回后记录握手得到的模块信息，并按需定期推进 epoch
+    fn finish_init<T>(&mut self,
+                      ll_ctx: Arc<LowLevelCtx<ModuleData<T>>>,
+                      engine: Engine,
+                      tick_epoch: bool,
+    ) -> Result<()>
+        where T: Send + Sync + 'static,
+    {
+        let async_ctx = self.async_ctx.clone();
 
         // 获得模块名称（对端模块）
         {
             let mut rpc_ctx = async_ctx.rpc_ctx.lock().unwrap();
             let rpc_ctx = rpc_ctx.get_mut().as_ref().unwrap();
             let peer_name = rpc_ctx.get_peer_name()
-                .ok_or("Peer Info not presents!")?;
+                .ok_or(Error::MissingPeerInfo)?;
             self.name = Some(peer_name);
+            self.manifest = rpc_ctx.get_peer_manifest();
             async_ctx.set_peer_hint(self.get_hint());
         }
 
         self.ll_ctx = Some(ll_ctx);
 
+        // 定期推进 epoch，直至模块结束或被释放。线程不持有异步上下文，以免延长其生命期
+        if tick_epoch {
+            let async_ctx = Arc::downgrade(&self.async_ctx);
+            let stop = self.ticker_stop.clone();
+            thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) && async_ctx.upgrade().map_or(false, |ctx| ctx.alive()) {
+                    thread::sleep(EPOCH_TICK);
+                    engine.increment_epoch();
+                }
+            });
+        }
+
         Ok(())
     }
 
@@ -100,30 +475,87 @@ impl WasmModule {
     ///
     /// 返回的异步任务会在启动完毕后结束。
     pub async fn start(&self) {
-        self.async_ctx.clone().start(self.ll_ctx.as_ref().unwrap().clone()).await
+        self.ll_ctx.as_ref().unwrap().clone().start(self.async_ctx.clone()).await
     }
 
     /// 异步请求 API 并返回其结果。相关参数序列化应该在包装函数中进行。
     pub async fn request_api(self: Arc<Self>, func: abi::FunctionIdent, args: Vec<u8>) -> Result<Vec<u8>> {
-        self.async_ctx.clone().request_api(func, args).await
+        Ok(self.async_ctx.clone().request_api(func, args).await?)
+    }
+
+    /// 异步请求 API，超过 `timeout` 仍未返回时返回超时错误
+    pub async fn request_api_timeout(self: Arc<Self>,
+                                     func: abi::FunctionIdent,
+                                     args: Vec<u8>,
+                                     timeout: Duration,
+    ) -> Result<Vec<u8>> {
+        Ok(self.async_ctx.clone().request_api_timeout(func, args, timeout).await?)
+    }
+
+    /// 异步请求 API，超过截止时间仍未返回时返回超时错误
+    pub async fn request_api_with_deadline(self: Arc<Self>,
+                                           func: abi::FunctionIdent,
+                                           args: Vec<u8>,
+                                           deadline: Option<RpcDeadline>,
+    ) -> Result<Vec<u8>> {
+        Ok(self.async_ctx.clone().request_api_with_deadline(func, args, deadline).await?)
+    }
+
+    /// 请求模块通过保留的导出函数 `abi::EXPORT_STATE` 导出其状态，模块没有提供状态钩子时返回 `None`
+    ///
+    /// 模块排空期间同样可以导出状态，超过 `timeout` 仍未返回时返回超时错误。
+    pub async fn export_state(&self, timeout: Duration) -> Result<Option<Vec<u8>>> {
+        let ser_ctx = self.serialize_ctx();
+        let mut func = abi::FunctionIdent::new(abi::EXPORT_STATE);
+        func.set_hint(self.get_hint());
+        let args = ArgsBuilder::new(&ser_ctx).build()?;
+
+        let request = self.async_ctx.clone().request_api_timeout(func, args, timeout).forced();
+        match request.await {
+            Ok(ret) => Ok(Some(ser_ctx.deserialize::<Vec<u8>>(&ret)?)),
+            Err(async_api::Error::Rpc(rpc::Error::Rpc(e))) if e.code == RpcErrorCode::NoSuchFunction => Ok(None),
+            Err(e) => Err(e.into()),
+        }
     }
 
     /// 结束模块异步任务。异步任务将在完成相关收尾工作之后在下一次 poll 结束。
+    ///
+    /// 等待中的请求不会再有结果，需要等待请求完成时使用 `shutdown`。
     pub fn kill(&self) {
-        // 是否需要提供一个 async 的方式允许等待异步任务完成？
         self.async_ctx.kill();
     }
 
+    /// 停止接受新的请求，最多等待 `timeout` 让已接受的请求返回结果，见 `AsyncCtx::drain`
+    pub async fn drain(&self, timeout: Duration) -> bool {
+        self.async_ctx.drain(timeout).await
+    }
+
+    /// 恢复接受新的请求，用于撤销 `drain`
+    pub fn resume(&self) {
+        self.async_ctx.resume();
+    }
+
+    /// 卸载模块：停止接受新的请求，最多等待 `timeout` 让已接受的请求返回结果，之后结束模块并回收 Store
+    ///
+    /// 返回已接受的请求是否都在超时前返回。超时仍未返回的请求以 `async_api::Error::ShuttingDown` 失败。
+    pub async fn shutdown(&self, timeout: Duration) -> bool {
+        let drained = self.async_ctx.shutdown(timeout).await;
+        if let Some(ll_ctx) = self.ll_ctx.as_ref() {
+            ll_ctx.teardown();
+        }
+        drained
+    }
+
     pub fn attach_to_manager(self: Arc<Self>, manager: Arc<ModuleManager>) {
         // 注册模块解析回调
         let my_hint = self.get_hint();
         self.async_ctx.set_resolve_cb(move |hint| {
             let result = manager.resolve(&hint)
-                .ok_or(format!("Failed to resolve module with hint: {:?}", hint))?;
+                .ok_or(async_api::Error::ModuleNotFound(hint.clone()))?;
 
             // 检查是否是本身，避免循环调用
             if result.get_hint() == my_hint {
-                return Err(format!("Circular import: {:?}", hint).into());
+                return Err(async_api::Error::CircularImport(hint));
             }
 
             Ok(result.async_ctx.clone())
@@ -138,6 +570,43 @@ impl WasmModule {
         abi::LinkHint::BcModule(self.get_name().to_string())
     }
 
+    /// 模块清单，模块没有发送清单时返回 `None`
+    ///
+    /// 清单随握手发送，握手只进行一次，此后模块不能替换其清单，注册时对依赖的检查因此持续有效。
+    pub fn manifest(&self) -> Option<&ModuleManifest> {
+        self.manifest.as_ref()
+    }
+
+    /// 限制模块调用的导入表，没有导入表时返回 `None`，见 `set_import_policy`
+    ///
+    /// 导入表在 `init` 后即可获得，Host 可以在 `start` 之前审查模块可能调用的函数。握手只进行一次，
+    /// 此后模块不能替换其导入表。
+    pub fn imports(&self) -> Option<RpcImports> {
+        self.async_ctx.peer_imports()
+    }
+
+    /// 模块清单中的版本
+    pub fn version(&self) -> Option<&Version> {
+        self.manifest.as_ref().map(|manifest| &manifest.version)
+    }
+
+    /// 精确指定模块版本的链接提示，模块没有发送清单时与 `get_hint` 相同
+    pub fn versioned_hint(&self) -> abi::LinkHint {
+        match self.version() {
+            Some(version) => abi::LinkHint::exact(self.get_name().to_string(), version),
+            None => self.get_hint(),
+        }
+    }
+
+    /// 带有清单、未初始化的模块，用于测试模块管理
+    #[cfg(test)]
+    pub(crate) fn with_manifest(manifest: ModuleManifest) -> Self {
+        let mut module = Self::new();
+        module.name = Some(manifest.name.clone());
+        module.manifest = Some(manifest);
+        module
+    }
+
     /// 仅供生成的函数使用，无需直接调用
     #[doc(hidden)]
     pub fn async_ctx(&self) -> Arc<AsyncCtx> {
@@ -145,45 +614,31 @@ impl WasmModule {
     }
 }
 
+impl Drop for WasmModule {
+    fn drop(&mut self) {
+        self.ticker_stop.store(true, Ordering::Relaxed);
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use low_level::host::Resource;
     use rpc::abi;
-    use serialize::ArgsBuilder;
 
-    use super::*;
+    use crate::test_util::*;
 
-    fn init_exports() -> RpcExports<Arc<AsyncCtx>> {
-        RpcExports::new(abi::LinkHint::BcModule("integrate-wasm".to_string()))
-    }
-
-    async fn wasm_export_to_host(ctx: &WasmModule, param: String) -> Result<String> {
-        let ser_ctx = SerializeCtx::new();
-        // 函数标识符
-        let mut func = abi::FunctionIdent::new("wasm_export_to_host");
-        func.set_hint(ctx.get_hint());
-        // 参数拼接
-        let args = ArgsBuilder::new(&ser_ctx)
-            .push(&param).unwrap()
-            .build().unwrap();
-        // 调用函数
-        let ret = ctx.async_ctx().request_api(func, args).await?;
-        // 解析返回值
-        let result = ser_ctx.deserialize::<String>(&ret)?;
-        Ok(result)
-    }
+    use super::*;
 
     #[tokio::test]
     #[allow(unused_must_use)]
     async fn test_multiple_module() {
-        let wasm = "../async-api/tests/integrate-wasm/integrate-wasm.wasm";
-
         // 加载两遍同一个模块
         let mut mod_a = WasmModule::new();
-        mod_a.init(wasm, init_exports()).unwrap();
+        mod_a.init(WASM, init_exports()).unwrap();
         println!("mod_a 名称：{}", mod_a.get_name());
 
         let mut mod_b = WasmModule::new();
-        mod_b.init(wasm, init_exports()).unwrap();
+        mod_b.init(WASM, init_exports()).unwrap();
         println!("mod_b 名称：{}", mod_b.get_name());
 
         // 启动
@@ -220,4 +675,129 @@ mod tests {
 
         tokio::join!(task_a, task_b);
     }
+
+    #[tokio::test]
+    async fn test_async_mode() {
+        // 以异步模式加载模块
+        let mut module = WasmModule::new();
+        module.set_async_mode(true);
+        module.init_async(WASM, init_exports()).await.unwrap();
+        module.start().await;
+
+        let ret = wasm_export_to_host(&module, "async host".to_string()).await.unwrap();
+        assert_eq!(ret, "Hello async host, I'm a wasm module!".to_string());
+        module.kill();
+    }
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let mut module = WasmModule::new();
+        module.init(WASM, init_exports()).unwrap();
+        module.start().await;
+        let module = Arc::new(module);
+
+        // 卸载前发出的请求正常返回
+        let cmodule = module.clone();
+        let request = tokio::spawn(async move {
+            wasm_export_to_host(&cmodule, "draining".to_string()).await
+        });
+        tokio::task::yield_now().await;
+        assert!(module.shutdown(Duration::from_secs(1)).await);
+        assert_eq!(request.await.unwrap().unwrap(), "Hello draining, I'm a wasm module!".to_string());
+
+        // 卸载后的请求直接失败
+        let ret = wasm_export_to_host(&module, "host".to_string()).await;
+        assert!(matches!(ret, Err(Error::Async(async_api::Error::ShuttingDown))));
+    }
+
+    /// 测试模块经由 Host 转发调用其他模块时受其能力限制
+    #[tokio::test]
+    async fn test_capabilities() {
+        let manager = Arc::new(ModuleManager::new());
+        let dest = Arc::new(WasmModule::with_manifest(ModuleManifest::new("dest", Version::new(1, 0, 0))));
+        manager.register(dest.get_hint(), dest.clone()).unwrap();
+
+        // 模块发出调用 dest 的请求，返回目标模块负载的增量及模块收到的错误码。转发的请求在目标模块的
+        // 队列中等待发送，同时等待返回结果，因此每个请求计为 2
+        let forward = |capabilities: Option<Capabilities>| {
+            let mut module = WasmModule::new();
+            if let Some(capabilities) = capabilities {
+                module.set_capabilities(capabilities);
+            }
+            module.init(WASM, init_exports()).unwrap();
+            let module = Arc::new(module);
+            module.clone().attach_to_manager(manager.clone());
+
+            // 模块尚未启动，报文停留在队列中
+            let ser_ctx = module.serialize_ctx();
+            let mut func = abi::FunctionIdent::new("dest_func");
+            func.set_hint(dest.get_hint());
+            let req = rpc::RpcRequestCtx::new(1, &ser_ctx, &()).make_request(func, vec![]).unwrap();
+            let async_ctx = module.async_ctx();
+            let load = dest.load();
+            async_ctx.handle_message(&req).unwrap();
+            let resp = async_ctx.rx_queue.lock().unwrap().get_mut().pop_front();
+            let code = resp.and_then(|resp| match rpc::RpcMessage::decode(&resp).unwrap().1.message() {
+                rpc::Message::Error(error) => Some(error.code),
+                _ => None,
+            });
+            module.kill();
+            (dest.load() - load, code)
+        };
+
+        // 未设置能力时请求被转发到目标模块
+        assert_eq!((2, None), forward(None));
+
+        // 未被授权调用目标模块时，请求不被转发并回送权限错误
+        let mut capabilities = Capabilities::allow_all();
+        capabilities.deny_module(dest.get_hint());
+        assert_eq!((0, Some(RpcErrorCode::PermissionDenied)), forward(Some(capabilities)));
+    }
+
+    #[test]
+    fn test_epoch_ticker_stop() {
+        let mut module = WasmModule::new();
+        module.set_cpu_limits(CpuLimits {
+            epoch_per_call: Some(100),
+            ..Default::default()
+        });
+        module.init(WASM, init_exports()).unwrap();
+        let stop = module.ticker_stop.clone();
+        assert_eq!(2, Arc::strong_count(&stop));
+
+        // 未经 `kill` 或 `shutdown` 直接释放模块，推进 epoch 的线程随之结束
+        drop(module);
+        std::thread::sleep(EPOCH_TICK * 5);
+        assert_eq!(1, Arc::strong_count(&stop));
+    }
+
+    #[test]
+    fn test_resource_limits() {
+        // 内存限制过小，初始化失败
+        let mut module = WasmModule::new();
+        module.set_resource_limits(ResourceLimits {
+            max_memory_pages: Some(1),
+            ..Default::default()
+        });
+        let ret = module.init(WASM, init_exports());
+        assert!(matches!(ret, Err(Error::ResourceLimit(Resource::MemoryPages))));
+
+        // 实例数限制
+        let mut module = WasmModule::new();
+        module.set_resource_limits(ResourceLimits {
+            max_instances: Some(0),
+            ..Default::default()
+        });
+        let ret = module.init(WASM, init_exports());
+        assert!(matches!(ret, Err(Error::ResourceLimit(Resource::Instances))));
+
+        // 表数限制，模块定义了间接调用使用的函数表
+        let mut module = WasmModule::new();
+        module.set_resource_limits(ResourceLimits {
+            max_tables: Some(0),
+            ..Default::default()
+        });
+        let ret = module.init(WASM, init_exports());
+        assert!(matches!(ret, Err(Error::ResourceLimit(Resource::Tables))));
+    }
 }
@@ -0,0 +1,320 @@
+//! 由同一模块的多个实例组成的模块池
+//!
+//! 模块池以一个 `LinkHint` 注册到 `ModuleManager` 中，解析时返回负载最低的实例，使对同一模块的
+//! 并发调用可以由多个实例同时处理。
+
+use std::cell::Cell;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rpc::abi;
+use rpc::manifest::ModuleManifest;
+
+use crate::manager::ModuleManager;
+use crate::module::WasmModule;
+use crate::Result;
+
+type InstanceFactory = dyn Fn() -> Result<WasmModule> + Send + Sync;
+
+/// 模块池的实例数与扩缩容策略
+#[derive(Debug, Clone)]
+pub struct PoolPolicy {
+    /// 最少实例数，创建模块池时即启动这些实例
+    pub min_instances: usize,
+    /// 最多实例数，与 `min_instances` 相等时模块池大小固定
+    pub max_instances: usize,
+    /// 负载最低的实例的负载达到此值时增加实例
+    pub scale_up_load: usize,
+    /// 多于 `min_instances` 的实例空闲超过此时间后被结束
+    pub idle_timeout: Duration,
+}
+
+impl PoolPolicy {
+    /// 固定大小的模块池
+    pub fn fixed(instances: usize) -> Self {
+        PoolPolicy {
+            min_instances: instances,
+            max_instances: instances,
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for PoolPolicy {
+    fn default() -> Self {
+        PoolPolicy {
+            min_instances: 1,
+            max_instances: 4,
+            scale_up_load: 4,
+            idle_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+struct Instance {
+    module: Arc<WasmModule>,
+    /// 最近一次被选中处理请求的时间
+    last_used: Instant,
+}
+
+/// 同一模块的多个实例
+pub struct ModulePool {
+    hint: abi::LinkHint,
+    /// 首个实例的模块清单
+    manifest: Option<ModuleManifest>,
+    policy: PoolPolicy,
+    factory: Box<InstanceFactory>,
+    manager: Arc<ModuleManager>,
+    instances: Mutex<Vec<Instance>>,
+    /// 是否正在启动新的实例
+    scaling: Mutex<Cell<bool>>,
+    alive: Mutex<Cell<bool>>,
+}
+
+impl ModulePool {
+    /// 创建模块池并以模块的 `LinkHint` 注册到管理器中，模块导入的模块不满足时返回
+    /// `Error::UnsatisfiedImports`
+    ///
+    /// `factory` 创建已经初始化、尚未启动的模块，在启动及增加实例时被调用，可配合 `ModuleCache`
+    /// 避免重复编译。
+    pub async fn start<F>(manager: Arc<ModuleManager>, factory: F, policy: PoolPolicy) -> Result<Arc<Self>>
+        where F: Fn() -> Result<WasmModule> + Send + Sync + 'static,
+    {
+        let first = factory()?;
+        let pool = Arc::new(ModulePool {
+            hint: first.get_hint(),
+            manifest: first.manifest().cloned(),
+            policy,
+            factory: Box::new(factory),
+            manager: manager.clone(),
+            instances: Mutex::new(Vec::new()),
+            scaling: Mutex::new(Cell::new(false)),
+            alive: Mutex::new(Cell::new(true)),
+        });
+
+        pool.add_instance(first).await;
+        for _ in 1..pool.policy.min_instances {
+            let module = (pool.factory)()?;
+            pool.add_instance(module).await;
+        }
+
+        if pool.policy.max_instances > pool.policy.min_instances {
+            tokio::spawn(pool.clone().scale_down());
+        }
+        if let Err(e) = manager.register_pool(pool.hint.clone(), pool.clone()) {
+            pool.kill();
+            return Err(e);
+        }
+        Ok(pool)
+    }
+
+    pub fn get_hint(&self) -> &abi::LinkHint {
+        &self.hint
+    }
+
+    /// 模块清单，模块没有发送清单时返回 `None`
+    pub fn manifest(&self) -> Option<&ModuleManifest> {
+        self.manifest.as_ref()
+    }
+
+    /// 当前的实例数
+    pub fn instance_count(&self) -> usize {
+        self.instances.lock().unwrap().len()
+    }
+
+    /// 是否仍有正常运行的实例
+    pub fn healthy(&self) -> bool {
+        self.instances.lock().unwrap().iter().any(|instance| instance.module.healthy())
+    }
+
+    /// 选择负载最低的实例，被毒化的实例会被移出模块池
+    ///
+    /// 负载达到 `scale_up_load` 或实例数少于 `min_instances` 时，在后台启动新的实例。
+    pub fn pick(self: &Arc<Self>) -> Option<Arc<WasmModule>> {
+        let (picked, count) = {
+            let mut instances = self.instances.lock().unwrap();
+            instances.retain(|instance| instance.module.healthy());
+
+            let picked = instances.iter_mut()
+                .map(|instance| (instance.module.load(), instance))
+                .min_by_key(|(load, _)| *load)
+                .map(|(load, instance)| {
+                    instance.last_used = Instant::now();
+                    (load, instance.module.clone())
+                });
+            (picked, instances.len())
+        };
+
+        let busy = matches!(&picked, Some((load, _)) if *load >= self.policy.scale_up_load);
+        if count < self.policy.min_instances || (busy && count < self.policy.max_instances) {
+            self.scale_up();
+        }
+        picked.map(|(_, module)| module)
+    }
+
+    /// 结束所有实例
+    pub fn kill(&self) {
+        self.alive.lock().unwrap().set(false);
+        let instances = std::mem::take(&mut *self.instances.lock().unwrap());
+        for instance in instances {
+            instance.module.kill();
+        }
+    }
+
+    /// 卸载所有实例，见 `WasmModule::shutdown`
+    pub async fn shutdown(&self, timeout: Duration) -> bool {
+        self.alive.lock().unwrap().set(false);
+        let instances = std::mem::take(&mut *self.instances.lock().unwrap());
+
+        // 同时卸载各个实例
+        let tasks: Vec<_> = instances.into_iter()
+            .map(|instance| tokio::spawn(async move { instance.module.shutdown(timeout).await }))
+            .collect();
+        let mut drained = true;
+        for task in tasks {
+            drained &= task.await.unwrap_or(false);
+        }
+        drained
+    }
+
+    fn alive(&self) -> bool {
+        self.alive.lock().unwrap().get()
+    }
+
+    /// 启动实例并加入模块池
+    async fn add_instance(&self, module: WasmModule) {
+        module.start().await;
+        let module = Arc::new(module);
+        module.clone().attach_to_manager(self.manager.clone());
+
+        if !self.alive() {
+            module.kill();
+            return;
+        }
+        self.instances.lock().unwrap().push(Instance {
+            module,
+            last_used: Instant::now(),
+        });
+    }
+
+    /// 在后台启动一个新的实例，同一时间只启动一个
+    fn scale_up(self: &Arc<Self>) {
+        {
+            let scaling = self.scaling.lock().unwrap();
+            if scaling.get() || !self.alive() {
+                return;
+            }
+            scaling.set(true);
+        }
+
+        let pool = self.clone();
+        tokio::spawn(async move {
+            match (pool.factory)() {
+                Ok(module) => pool.add_instance(module).await,
+                Err(e) => eprintln!("[ModulePool]: failed to start instance of {:?}: {}", pool.hint, e),
+            }
+            pool.scaling.lock().unwrap().set(false);
+        });
+    }
+
+    /// 定期结束空闲的实例，直至模块池被结束
+    async fn scale_down(self: Arc<Self>) {
+        while self.alive() {
+            tokio::time::sleep(self.policy.idle_timeout).await;
+
+            let idle: Vec<_> = {
+                let mut instances = self.instances.lock().unwrap();
+                let mut removable = instances.len().saturating_sub(self.policy.min_instances);
+                let mut idle = vec![];
+                instances.retain(|instance| {
+                    let expired = removable > 0
+                        && instance.last_used.elapsed() >= self.policy.idle_timeout
+                        && instance.module.load() == 0;
+                    if expired {
+                        removable -= 1;
+                        idle.push(instance.module.clone());
+                    }
+                    !expired
+                });
+                idle
+            };
+            for module in idle {
+                module.kill();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_api::ctx::Poison;
+
+    use crate::cache::ModuleCache;
+    use crate::test_util::*;
+
+    use super::*;
+
+    async fn start_pool(policy: PoolPolicy) -> (Arc<ModuleManager>, Arc<ModulePool>) {
+        let manager = Arc::new(ModuleManager::new());
+        let cache = ModuleCache::new(wasmtime::Engine::default());
+        let compiled = cache.load_file(WASM).unwrap();
+        let pool = ModulePool::start(manager.clone(), move || {
+            let mut module = WasmModule::new();
+            module.init_module(&compiled, init_exports())?;
+            Ok(module)
+        }, policy).await.unwrap();
+        (manager, pool)
+    }
+
+    #[tokio::test]
+    async fn test_fixed_pool() {
+        let (manager, pool) = start_pool(PoolPolicy::fixed(3)).await;
+        assert_eq!(3, pool.instance_count());
+
+        // 并发调用分散到各个实例
+        let tasks: Vec<_> = (0..30).map(|i| {
+            let manager = manager.clone();
+            let hint = pool.get_hint().clone();
+            tokio::spawn(async move {
+                let module = manager.resolve(&hint).unwrap();
+                let ret = wasm_export_to_host(&module, format!("host {}", i)).await.unwrap();
+                assert_eq!(ret, format!("Hello host {}, I'm a wasm module!", i));
+            })
+        }).collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        // 被毒化的实例被移出模块池，并补充新的实例
+        pool.pick().unwrap().async_ctx().poison(Poison::Cpu);
+        assert!(pool.pick().unwrap().healthy());
+        assert_eq!(2, pool.instance_count());
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert_eq!(3, pool.instance_count());
+
+        pool.kill();
+        assert_eq!(0, pool.instance_count());
+    }
+
+    #[tokio::test]
+    async fn test_autoscale() {
+        let (_, pool) = start_pool(PoolPolicy {
+            min_instances: 1,
+            max_instances: 2,
+            scale_up_load: 0,
+            idle_timeout: Duration::from_millis(200),
+        }).await;
+        assert_eq!(1, pool.instance_count());
+
+        // 负载达到阈值时扩容
+        pool.pick().unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(2, pool.instance_count());
+
+        // 空闲后缩容至最少实例数
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        assert_eq!(1, pool.instance_count());
+
+        pool.kill();
+    }
+}
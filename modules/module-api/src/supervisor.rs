@@ -0,0 +1,417 @@
+//! 模块的监督与自动重启
+//!
+//! 被监督的模块在 Trap 或超出限制而被毒化后，会按重启策略从原始字节码重新实例化，并以相同的
+//! `LinkHint` 重新注册到 `ModuleManager` 中。
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::{mpsc, Notify};
+
+use async_api::ctx::{AsyncCtx, LifecycleEvent};
+use rpc::{abi, RpcExports};
+
+use crate::manager::ModuleManager;
+use crate::module::WasmModule;
+use crate::{Error, Result};
+
+/// 重启期间到达的调用的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestartMode {
+    /// 等待重启完成后调用新的模块
+    Queue,
+    /// 立即以 `Error::Restarting` 失败
+    FailFast,
+}
+
+/// 模块的重启策略
+#[derive(Debug, Clone)]
+pub struct RestartPolicy {
+    /// 时间窗口内允许的最大重启次数，超出后模块不再重启并被卸载
+    pub max_restarts: u32,
+    /// 统计重启次数的时间窗口
+    pub window: Duration,
+    /// 首次重启前的等待时间，窗口内每多重启一次等待时间加倍
+    pub initial_backoff: Duration,
+    /// 重启前等待时间的上限
+    pub max_backoff: Duration,
+    /// 重启期间到达的调用的处理方式
+    pub mode: RestartMode,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        RestartPolicy {
+            max_restarts: 5,
+            window: Duration::from_secs(60),
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            mode: RestartMode::Queue,
+        }
+    }
+}
+
+impl RestartPolicy {
+    /// 窗口内第 `restarts` 次重启前的等待时间
+    fn backoff(&self, restarts: u32) -> Duration {
+        let factor = 2u32.saturating_pow(restarts.saturating_sub(1));
+        self.initial_backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+}
+
+type ModuleBuilder = dyn Fn() -> WasmModule + Send + Sync;
+type ExportsBuilder = dyn Fn() -> RpcExports<Arc<AsyncCtx>> + Send + Sync;
+
+enum State {
+    Running(Arc<WasmModule>),
+    Restarting,
+    Down,
+}
+
+enum Signal {
+    Poisoned,
+    Shutdown,
+}
+
+/// 被监督的模块
+pub struct SupervisedModule {
+    hint: abi::LinkHint,
+    bytes: Vec<u8>,
+    build: Box<ModuleBuilder>,
+    exports: Box<ExportsBuilder>,
+    policy: RestartPolicy,
+    manager: Arc<ModuleManager>,
+    state: Mutex<State>,
+    restarts: Mutex<u32>,
+    notify: Notify,
+    signal: mpsc::UnboundedSender<Signal>,
+}
+
+impl SupervisedModule {
+    pub fn get_hint(&self) -> &abi::LinkHint {
+        &self.hint
+    }
+
+    /// 模块自开始监督以来的重启次数
+    pub fn restarts(&self) -> u32 {
+        *self.restarts.lock().unwrap()
+    }
+
+    /// 获得当前运行的模块
+    ///
+    /// 模块正在重启时，按重启策略等待重启完成或以 `Error::Restarting` 失败；模块已停止运行时返回
+    /// `Error::ModuleDown`。
+    pub async fn module(&self) -> Result<Arc<WasmModule>> {
+        loop {
+            // 需在检查状态前创建，以免错过检查后、等待前的通知
+            let notified = self.notify.notified();
+            {
+                let state = self.state.lock().unwrap();
+                match &*state {
+                    // 已毒化但尚未开始重启的模块同样视为正在重启
+                    State::Running(module) if module.healthy() => return Ok(module.clone()),
+                    State::Down => return Err(Error::ModuleDown(self.hint.clone())),
+                    _ if self.policy.mode == RestartMode::FailFast => {
+                        return Err(Error::Restarting(self.hint.clone()));
+                    }
+                    _ => {}
+                }
+            }
+            notified.await;
+        }
+    }
+
+    /// 异步请求当前运行的模块的 API
+    pub async fn request_api(&self, func: abi::FunctionIdent, args: Vec<u8>) -> Result<Vec<u8>> {
+        self.module().await?.request_api(func, args).await
+    }
+
+    /// 停止监督，结束并卸载模块
+    pub fn shutdown(&self) {
+        let _ = self.signal.send(Signal::Shutdown);
+    }
+
+    /// 加载、启动模块并注册到管理器中
+    async fn spawn_module(self: &Arc<Self>) -> Result<Arc<WasmModule>> {
+        let mut module = (self.build)();
+        module.init_from_bytes_async(&self.bytes, (self.exports)()).await?;
+        let module = start_module(module).await?;
+
+        let signal = self.signal.clone();
+        module.set_lifecycle_cb(move |event| {
+            if let LifecycleEvent::Poisoned(_) = event {
+                let _ = signal.send(Signal::Poisoned);
+            }
+        });
+        // 首次加载时已检查过依赖
+        self.manager.register_unchecked(self.hint.clone(), module.clone());
+        module.clone().attach_to_manager(self.manager.clone());
+        Ok(module)
+    }
+
+    fn set_state(&self, state: State) {
+        *self.state.lock().unwrap() = state;
+        self.notify.notify_waiters();
+    }
+
+    /// 处理被监督模块的信号，直至模块停止运行
+    async fn supervise(self: Arc<Self>, mut signals: mpsc::UnboundedReceiver<Signal>) {
+        let mut history = VecDeque::new();
+        let mut pending = false;
+
+        loop {
+            if !pending {
+                match signals.recv().await {
+                    Some(Signal::Poisoned) => {}
+                    Some(Signal::Shutdown) | None => break,
+                }
+            }
+            pending = false;
+
+            // 统计窗口内的重启次数
+            let now = Instant::now();
+            while history.front().map_or(false, |t| now.duration_since(*t) > self.policy.window) {
+                history.pop_front();
+            }
+            if history.len() as u32 >= self.policy.max_restarts {
+                eprintln!("[Supervisor]: module {:?} restarted too many times, giving up", self.hint);
+                break;
+            }
+            history.push_back(now);
+
+            // 重启期间从管理器中注销，其他模块的调用不再被转发到已毒化的模块
+            self.set_state(State::Restarting);
+            self.manager.unregister(&self.hint);
+
+            // 等待期间仍响应停止监督的信号
+            let backoff = tokio::time::sleep(self.policy.backoff(history.len() as u32));
+            tokio::pin!(backoff);
+            let shutdown = loop {
+                tokio::select! {
+                    _ = &mut backoff => break false,
+                    signal = signals.recv() => match signal {
+                        Some(Signal::Poisoned) => {}
+                        Some(Signal::Shutdown) | None => break true,
+                    },
+                }
+            };
+            if shutdown {
+                break;
+            }
+
+            match self.spawn_module().await {
+                Ok(module) => {
+                    *self.restarts.lock().unwrap() += 1;
+                    self.set_state(State::Running(module));
+                }
+                Err(e) => {
+                    // 重新实例化失败同样计入重启次数
+                    eprintln!("[Supervisor]: failed to restart module {:?}: {}", self.hint, e);
+                    pending = true;
+                }
+            }
+        }
+
+        if let State::Running(module) = &*self.state.lock().unwrap() {
+            module.kill();
+        }
+        self.manager.unregister(&self.hint);
+        self.set_state(State::Down);
+    }
+}
+
+/// 启动模块
+///
+/// 生命周期回调在启动后才设置，启动期间被毒化的模块不会再通知监督者，因此结束模块并视为启动失败。
+async fn start_module(module: WasmModule) -> Result<Arc<WasmModule>> {
+    module.start().await;
+    if let Some(poison) = module.poisoned() {
+        module.kill();
+        return Err(Error::Async(poison.into()));
+    }
+    Ok(Arc::new(module))
+}
+
+/// 监督模块并在其被毒化后自动重启
+pub struct Supervisor {
+    manager: Arc<ModuleManager>,
+}
+
+impl Supervisor {
+    pub fn new(manager: Arc<ModuleManager>) -> Self {
+        Supervisor { manager }
+    }
+
+    /// 加载并启动模块，此后按 `policy` 监督模块
+    ///
+    /// `build` 创建尚未初始化的模块，可在其中设置序列化格式、限制等选项；`exports` 创建 Host
+    /// 端的导出函数。二者在每次重启时都会被调用。模块导入的模块不满足时返回
+    /// `Error::UnsatisfiedImports`。
+    pub async fn supervise<B, E>(&self,
+                                 bytes: Vec<u8>,
+                                 build: B,
+                                 exports: E,
+                                 policy: RestartPolicy,
+    ) -> Result<Arc<SupervisedModule>>
+        where B: Fn() -> WasmModule + Send + Sync + 'static,
+              E: Fn() -> RpcExports<Arc<AsyncCtx>> + Send + Sync + 'static,
+    {
+        // 首次加载失败时直接返回错误，不进行重启
+        let mut module = build();
+        module.init_from_bytes_async(&bytes, exports()).await?;
+        let module = start_module(module).await?;
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let supervised = Arc::new(SupervisedModule {
+            hint: module.versioned_hint(),
+            bytes,
+            build: Box::new(build),
+            exports: Box::new(exports),
+            policy,
+            manager: self.manager.clone(),
+            state: Mutex::new(State::Running(module.clone())),
+            restarts: Mutex::new(0),
+            notify: Notify::new(),
+            signal: tx.clone(),
+        });
+
+        module.set_lifecycle_cb(move |event| {
+            if let LifecycleEvent::Poisoned(_) = event {
+                let _ = tx.send(Signal::Poisoned);
+            }
+        });
+        if let Err(e) = self.manager.register(supervised.hint.clone(), module.clone()) {
+            module.kill();
+            return Err(e);
+        }
+        module.attach_to_manager(self.manager.clone());
+
+        tokio::spawn(supervised.clone().supervise(rx));
+        Ok(supervised)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_api::ctx::Poison;
+    use serialize::ArgsBuilder;
+
+    use crate::test_util::{init_exports, WASM};
+
+    use super::*;
+
+    /// 经由被监督的模块调用，重启期间按重启策略等待或失败
+    async fn wasm_export_to_host(module: &SupervisedModule, param: String) -> Result<String> {
+        let ser_ctx = module.module().await?.serialize_ctx();
+        let mut func = abi::FunctionIdent::new("wasm_export_to_host");
+        func.set_hint(module.get_hint().clone());
+        let args = ArgsBuilder::new(&ser_ctx)
+            .push(&param).unwrap()
+            .build().unwrap();
+        let ret = module.request_api(func, args).await?;
+        Ok(ser_ctx.deserialize::<String>(&ret)?)
+    }
+
+    async fn supervise(policy: RestartPolicy) -> (Arc<ModuleManager>, Arc<SupervisedModule>) {
+        let manager = Arc::new(ModuleManager::new());
+        let supervisor = Supervisor::new(manager.clone());
+        let bytes = std::fs::read(WASM).unwrap();
+        let module = supervisor.supervise(bytes, WasmModule::new, init_exports, policy)
+            .await.unwrap();
+        (manager, module)
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = RestartPolicy {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(500),
+            ..Default::default()
+        };
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(400));
+        assert_eq!(policy.backoff(4), Duration::from_millis(500));
+        assert_eq!(policy.backoff(100), Duration::from_millis(500));
+    }
+
+    #[tokio::test]
+    async fn test_restart() {
+        let (manager, supervised) = supervise(RestartPolicy {
+            initial_backoff: Duration::from_millis(10),
+            ..Default::default()
+        }).await;
+
+        let old = supervised.module().await.unwrap();
+        old.async_ctx().poison(Poison::Cpu);
+
+        // 重启期间的调用等待重启完成
+        let ret = wasm_export_to_host(&supervised, "restarted".to_string()).await.unwrap();
+        assert_eq!(ret, "Hello restarted, I'm a wasm module!".to_string());
+
+        let new = supervised.module().await.unwrap();
+        assert!(!Arc::ptr_eq(&old, &new));
+        assert_eq!(supervised.restarts(), 1);
+        assert!(Arc::ptr_eq(&manager.resolve(supervised.get_hint()).unwrap(), &new));
+
+        supervised.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_restart_fail_fast() {
+        let (manager, supervised) = supervise(RestartPolicy {
+            initial_backoff: Duration::from_millis(200),
+            mode: RestartMode::FailFast,
+            ..Default::default()
+        }).await;
+
+        supervised.module().await.unwrap().async_ctx().poison(Poison::Cpu);
+        let ret = wasm_export_to_host(&supervised, "host".to_string()).await;
+        assert!(matches!(ret, Err(Error::Restarting(_))));
+
+        // 重启期间模块从管理器中注销，其他模块无法解析到已毒化的模块
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(manager.resolve(supervised.get_hint()).is_none());
+
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let ret = wasm_export_to_host(&supervised, "host".to_string()).await.unwrap();
+        assert_eq!(ret, "Hello host, I'm a wasm module!".to_string());
+        assert!(manager.resolve(supervised.get_hint()).is_some());
+
+        supervised.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_restart_limit() {
+        let (manager, supervised) = supervise(RestartPolicy {
+            max_restarts: 1,
+            initial_backoff: Duration::from_millis(10),
+            ..Default::default()
+        }).await;
+
+        supervised.module().await.unwrap().async_ctx().poison(Poison::Cpu);
+        supervised.module().await.unwrap().async_ctx().poison(Poison::Cpu);
+
+        let ret = supervised.module().await;
+        assert!(matches!(ret, Err(Error::ModuleDown(_))));
+        assert!(manager.resolve(supervised.get_hint()).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_during_backoff() {
+        let (manager, supervised) = supervise(RestartPolicy {
+            initial_backoff: Duration::from_secs(10),
+            ..Default::default()
+        }).await;
+
+        // 等待重启期间停止监督，无需等待重启前的等待时间结束
+        supervised.module().await.unwrap().async_ctx().poison(Poison::Cpu);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        supervised.shutdown();
+
+        let ret = tokio::time::timeout(Duration::from_secs(1), supervised.module()).await.unwrap();
+        assert!(matches!(ret, Err(Error::ModuleDown(_))));
+        assert!(manager.resolve(supervised.get_hint()).is_none());
+    }
+}
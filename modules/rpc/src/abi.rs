@@ -1,7 +1,19 @@
 //! 函数调用相关的 ABI 定义，用于定位函数符号、检验调用数据等
 
+use semver::{Comparator, Op, Version, VersionReq};
 use serde::{Deserialize, Serialize};
 
+/// 模块导出自身状态的保留函数名，热重载时由 Host 调用以迁移状态，无参数，返回 `Vec<u8>`
+pub const EXPORT_STATE: &str = "__bc_export_state";
+
+/// Host 为转发的模块间请求分配序列号时使用的 nonce，节点不应使用该 nonce
+pub const FORWARD_NONCE: u32 = u32::MAX;
+
+/// 序列号是否由 Host 为转发的请求分配，即请求来自其他模块而不是 Host
+pub fn is_forwarded(seq_no: crate::RpcSeqNo) -> bool {
+    (seq_no >> 32) as u32 == FORWARD_NONCE
+}
+
 /// 链接函数时提供给链接器的提示
 #[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum LinkHint {
@@ -9,10 +21,52 @@ pub enum LinkHint {
     Host,
     /// 链接目标为指定名称的 Bc Hostcall Module 实现的函数
     BcModule(String),
+    /// 链接目标为指定名称、版本满足要求的 Bc Hostcall Module 实现的函数，存在多个版本时使用最高的版本
+    BcModuleVersion(String, VersionReq),
     /// 链接目标为指定名称的 WASM Module 实现的函数
     NativeModule(String),
 }
 
+impl LinkHint {
+    /// 精确指定模块版本的链接提示
+    pub fn exact(name: impl Into<String>, version: &Version) -> Self {
+        // 版本的构建元数据不参与比较，直接构造比较器而不经过解析
+        let comparator = Comparator {
+            op: Op::Exact,
+            major: version.major,
+            minor: Some(version.minor),
+            patch: Some(version.patch),
+            pre: version.pre.clone(),
+        };
+        LinkHint::BcModuleVersion(name.into(), VersionReq { comparators: vec![comparator] })
+    }
+
+    /// 去除版本要求的链接提示，用于比较链接目标是否为同一模块
+    pub fn unversioned(&self) -> LinkHint {
+        match self {
+            LinkHint::BcModuleVersion(name, _) => LinkHint::BcModule(name.clone()),
+            hint => hint.clone(),
+        }
+    }
+
+    /// 版本要求，没有要求时返回 `None`
+    pub fn version_req(&self) -> Option<&VersionReq> {
+        match self {
+            LinkHint::BcModuleVersion(_, req) => Some(req),
+            _ => None,
+        }
+    }
+
+    /// 版本为 `version` 的模块是否满足本链接提示的版本要求，版本未知的模块只满足没有要求或要求任意版本的提示
+    pub fn matches_version(&self, version: Option<&Version>) -> bool {
+        match (self.version_req(), version) {
+            (None, _) => true,
+            (Some(req), Some(version)) => req.matches(version),
+            (Some(req), None) => *req == VersionReq::STAR,
+        }
+    }
+}
+
 /// 函数标识符，用于提供链接器以确定调用的目标函数
 #[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct FunctionIdent {
@@ -33,3 +87,28 @@ impl FunctionIdent {
         self.hint = hint;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_version() {
+        let v1 = Version::new(1, 2, 0);
+        let v2 = Version::new(2, 0, 0);
+
+        let hint = LinkHint::BcModuleVersion("service".to_string(), "^1.1".parse().unwrap());
+        assert!(hint.matches_version(Some(&v1)));
+        assert!(!hint.matches_version(Some(&v2)));
+        assert!(!hint.matches_version(None));
+        assert_eq!(hint.unversioned(), LinkHint::BcModule("service".to_string()));
+
+        let exact = LinkHint::exact("service", &v2);
+        assert!(exact.matches_version(Some(&v2)));
+        assert!(!exact.matches_version(Some(&v1)));
+
+        let any = LinkHint::BcModule("service".to_string());
+        assert!(any.matches_version(None));
+        assert!(any.matches_version(Some(&v1)));
+    }
+}
@@ -26,7 +26,7 @@ mod wasm {
 
     impl SendMessageAdapter for WasmSendMessageAdapter {
         fn send_message(&self, message: &[u8]) -> Result<()> {
-            send_message_to_host(message)
+            Ok(send_message_to_host(message)?)
         }
     }
 }
@@ -57,7 +57,7 @@ mod host {
         where T: Send + Sync + 'static,
     {
         fn send_message(&self, message: &[u8]) -> Result<()> {
-            self.ctx.send_message_to_wasm(message)
+            Ok(self.ctx.send_message_to_wasm(message)?)
         }
     }
 }
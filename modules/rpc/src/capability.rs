@@ -0,0 +1,157 @@
+//! 能力授权，限制模块可以调用的 Host 导出函数及其他模块导出的函数
+//!
+//! 规则按函数、模块、默认规则的顺序匹配，先匹配到的规则生效。模块以不带版本的链接提示表示，
+//! Host 导出的函数对应 `abi::LinkHint::Host`。
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use serialize::{Args, SerializeCtx};
+
+use crate::abi;
+
+/// 参数约束，返回 `false` 时拒绝调用
+pub type ArgsConstraint = dyn Fn(&Args) -> bool + Send + Sync + 'static;
+
+/// 单个函数的授权规则
+#[derive(Clone)]
+enum Rule {
+    Allow,
+    Deny,
+    /// 参数满足约束时允许调用
+    AllowIf(Arc<ArgsConstraint>),
+}
+
+/// 模块的能力授权
+#[derive(Clone)]
+pub struct Capabilities {
+    /// 没有匹配的规则时是否允许调用
+    default_allow: bool,
+    modules: HashMap<abi::LinkHint, bool>,
+    functions: HashMap<(abi::LinkHint, String), Rule>,
+}
+
+impl Capabilities {
+    /// 默认允许所有调用，通过 `deny_*` 禁止部分调用
+    pub fn allow_all() -> Self {
+        Capabilities {
+            default_allow: true,
+            modules: HashMap::new(),
+            functions: HashMap::new(),
+        }
+    }
+
+    /// 默认禁止所有调用，通过 `allow_*` 授予部分调用
+    pub fn deny_all() -> Self {
+        Capabilities {
+            default_allow: false,
+            ..Self::allow_all()
+        }
+    }
+
+    /// 允许调用链接目标 `hint` 的所有函数
+    pub fn allow_module(&mut self, hint: abi::LinkHint) {
+        self.modules.insert(hint.unversioned(), true);
+    }
+
+    /// 禁止调用链接目标 `hint` 的所有函数
+    pub fn deny_module(&mut self, hint: abi::LinkHint) {
+        self.modules.insert(hint.unversioned(), false);
+    }
+
+    /// 允许调用函数 `func`
+    pub fn allow_function(&mut self, func: &abi::FunctionIdent) {
+        self.functions.insert(Self::key(func), Rule::Allow);
+    }
+
+    /// 禁止调用函数 `func`
+    pub fn deny_function(&mut self, func: &abi::FunctionIdent) {
+        self.functions.insert(Self::key(func), Rule::Deny);
+    }
+
+    /// 参数满足 `constraint` 时允许调用函数 `func`，参数无法解析时拒绝调用
+    pub fn allow_function_if<CB>(&mut self, func: &abi::FunctionIdent, constraint: CB)
+        where CB: Fn(&Args) -> bool + Send + Sync + 'static,
+    {
+        self.functions.insert(Self::key(func), Rule::AllowIf(Arc::new(constraint)));
+    }
+
+    /// 是否允许以参数 `args` 调用函数 `func`
+    pub fn permits(&self, serialize_ctx: &SerializeCtx, func: &abi::FunctionIdent, args: &[u8]) -> bool {
+        match self.functions.get(&Self::key(func)) {
+            Some(Rule::Allow) => true,
+            Some(Rule::Deny) => false,
+            Some(Rule::AllowIf(constraint)) => {
+                Args::from_bytes(serialize_ctx, args).map_or(false, |args| constraint(&args))
+            }
+            None => self.modules.get(&func.hint.unversioned())
+                .copied()
+                .unwrap_or(self.default_allow),
+        }
+    }
+
+    fn key(func: &abi::FunctionIdent) -> (abi::LinkHint, String) {
+        (func.hint.unversioned(), func.name.clone())
+    }
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self::allow_all()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serialize::ArgsBuilder;
+
+    use super::*;
+
+    fn ident(name: &str, hint: abi::LinkHint) -> abi::FunctionIdent {
+        let mut func = abi::FunctionIdent::new(name);
+        func.set_hint(hint);
+        func
+    }
+
+    #[test]
+    fn test_permits() {
+        let ctx = SerializeCtx::new();
+        let args = ArgsBuilder::new(&ctx).build().unwrap();
+        let service = abi::LinkHint::BcModule("service".to_string());
+
+        // 只允许调用 service 模块，以及 Host 的 log 函数
+        let mut caps = Capabilities::deny_all();
+        caps.allow_module(service.clone());
+        caps.deny_function(&ident("secret", service.clone()));
+        caps.allow_function(&ident("log", abi::LinkHint::Host));
+
+        assert!(caps.permits(&ctx, &ident("app", service.clone()), &args));
+        assert!(!caps.permits(&ctx, &ident("secret", service), &args));
+        assert!(caps.permits(&ctx, &ident("log", abi::LinkHint::Host), &args));
+        assert!(!caps.permits(&ctx, &ident("http_get", abi::LinkHint::Host), &args));
+
+        // 带有版本要求的链接提示与不带版本的规则匹配
+        let versioned = abi::LinkHint::BcModuleVersion("service".to_string(), "^1.0".parse().unwrap());
+        assert!(caps.permits(&ctx, &ident("app", versioned), &args));
+    }
+
+    #[test]
+    fn test_args_constraint() {
+        let ctx = SerializeCtx::new();
+        let http_get = ident("http_get", abi::LinkHint::Host);
+
+        // 只允许访问指定域名
+        let mut caps = Capabilities::allow_all();
+        caps.allow_function_if(&http_get, |args| {
+            args.get::<String>(0).map_or(false, |url| url.starts_with("https://example.com/"))
+        });
+
+        let allowed = ArgsBuilder::new(&ctx).push(&"https://example.com/a".to_string()).unwrap()
+            .build().unwrap();
+        let denied = ArgsBuilder::new(&ctx).push(&"https://evil.com/".to_string()).unwrap()
+            .build().unwrap();
+        assert!(caps.permits(&ctx, &http_get, &allowed));
+        assert!(!caps.permits(&ctx, &http_get, &denied));
+        assert!(!caps.permits(&ctx, &http_get, b"malformed"));
+    }
+}
@@ -1,36 +1,182 @@
 //! RPC 调用中的临时上下文管理
+use std::fmt;
+
 use serde::{Deserialize, Serialize};
 
-use serialize::SerializeCtx;
+use serialize::{Args, SerializeCtx, SerializeFormat};
 
-use crate::{abi, Result, RpcSeqNo};
+use crate::{abi, Error, Result, RpcDeadline, RpcImports, RpcSeqNo};
+use crate::manifest::ModuleManifest;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Message {
     Request,
     Response,
-    PeerInfo(String),
+    PeerInfo(PeerInfo),
+    /// 对 `PeerInfo` 的应答，告知双方协商确定的序列化格式
+    Format(SerializeFormat),
+    /// 调用失败时代替 `Response` 返回的错误结果
+    Error(RpcError),
+    /// 取消尚未返回的调用请求
+    Cancel,
+}
+
+/// 节点在握手时向对端通告的信息
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PeerInfo {
+    /// 节点名称
+    pub name: String,
+    /// 节点支持的序列化格式，按偏好从高到低排列
+    pub formats: Vec<SerializeFormat>,
+    /// 模块清单，Host 不发送清单
+    pub manifest: Option<ModuleManifest>,
+    /// 节点声明的导入表，未声明时对端不限制其调用请求
+    pub imports: Option<RpcImports>,
+}
+
+/// 调用错误的错误码
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RpcErrorCode {
+    /// 目标未导出所调用的函数
+    NoSuchFunction,
+    /// 调用参数解析失败
+    InvalidArgs,
+    /// 导出函数执行失败
+    CallFailed,
+    /// 转发调用请求失败
+    ForwardFailed,
+    /// 超过调用请求的截止时间
+    DeadlineExceeded,
+    /// 调用请求已被调用方取消
+    Cancelled,
+    /// 目标模块超出 CPU 预算而停止运行
+    ResourceExhausted,
+    /// 目标模块超出资源限制而停止运行
+    LimitExceeded,
+    /// 目标模块执行时发生 Trap 而停止运行
+    Trapped,
+    /// 目标模块正在卸载，不再接受新的请求
+    Unavailable,
+    /// 调用的函数未在调用方的导入表中声明
+    UndeclaredImport,
+    /// 调用方未被授予调用该函数的能力
+    PermissionDenied,
+    /// 待处理的消息数达到上限，请求被拒绝
+    QueueFull,
+}
+
+/// 在 RPC 节点间传递的调用错误
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RpcError {
+    pub code: RpcErrorCode,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn new(code: RpcErrorCode, message: impl Into<String>) -> Self {
+        RpcError {
+            code,
+            message: message.into(),
+        }
+    }
 }
 
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+/// 调用结果，成功时为序列化后的返回值
+pub type RpcCallResult = std::result::Result<Vec<u8>, RpcError>;
+
+/// 报文头长度字段的长度
+const HEADER_LEN_SIZE: usize = std::mem::size_of::<u32>();
+
+/// 报文头的起始位置
+const HEADER_OFFSET: usize = 1 + HEADER_LEN_SIZE;
+
 // 请求消息 便于序列化
+//
+// 报文的布局为：
+//
+// | 格式标记 (u8) | 报文头长度 (u32) | 报文头 | 数据 |
+//
+// 其中报文头为除数据外的其余字段，以格式标记所标明的格式序列化，因此接收方无需预先知道对端使用的格式。
+// 数据（调用参数、返回值）已经序列化过，因此原样附加在报文末尾，解码时直接借用而不再复制。
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct RpcMessage<'a> {
     seq_no: RpcSeqNo,
     func: abi::FunctionIdent,
     message: Message,
-    #[serde(with = "serde_bytes")]
+    /// 调用请求的截止时间，转发时随报文一并传递
+    deadline: Option<RpcDeadline>,
+    #[serde(skip)]
     data: &'a [u8],
 }
 
 impl<'a> RpcMessage<'a> {
     pub fn new(seq_no: RpcSeqNo, func: abi::FunctionIdent, message: Message, data: &'a [u8]) -> Self {
-        RpcMessage { seq_no, func, message, data }
+        RpcMessage { seq_no, func, message, deadline: None, data }
+    }
+
+    /// 以指定的序列化格式编码报文
+    pub fn encode(&self, serialize_ctx: &SerializeCtx) -> Result<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(HEADER_OFFSET + 64 + self.data.len());
+        bytes.push(serialize_ctx.format().tag());
+        bytes.extend([0; HEADER_LEN_SIZE]);
+
+        // 序列化报文头并回填其长度
+        serialize_ctx.serialize_into(&mut bytes, self)?;
+        let header_len = (bytes.len() - HEADER_OFFSET) as u32;
+        bytes[1..HEADER_OFFSET].copy_from_slice(&header_len.to_le_bytes());
+
+        bytes.extend_from_slice(self.data);
+        Ok(bytes)
+    }
+
+    /// 把以 `from` 格式序列化的报文转码为 `to` 的格式后编码，用于在使用不同格式的节点之间转发
+    ///
+    /// 调用参数与返回值随报文头一同转码。数据无法转码时仍以 `from` 的格式编码，
+    /// 接收方按报文的格式标记解析。
+    pub fn transcode(&self, from: &SerializeCtx, to: &SerializeCtx) -> Result<Vec<u8>> {
+        let data = match self.message {
+            Message::Request => Args::from_bytes(from, self.data).and_then(|args| args.transcode(to)),
+            Message::Response => from.transcode(to, self.data),
+            _ => Ok(self.data.to_vec()),
+        };
+        match data {
+            Ok(data) => RpcMessage { data: &data, ..self.clone() }.encode(to),
+            Err(serialize::Error::Untranscodable(_, _)) => self.encode(from),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// 解码报文，同时返回报文所使用格式的序列化上下文
+    pub fn decode(bytes: &'a [u8]) -> Result<(SerializeCtx, Self)> {
+        let tag = *bytes.first().ok_or(Error::MalformedMessage)?;
+        let serialize_ctx = SerializeCtx::with_format(SerializeFormat::from_tag(tag)?);
+
+        let header_len = bytes.get(1..HEADER_OFFSET).ok_or(Error::MalformedMessage)?;
+        let header_end = HEADER_OFFSET + u32::from_le_bytes(header_len.try_into().unwrap()) as usize;
+        let header = bytes.get(HEADER_OFFSET..header_end).ok_or(Error::MalformedMessage)?;
+
+        let mut msg: RpcMessage = serialize_ctx.deserialize(header)?;
+        msg.data = &bytes[header_end..];
+        Ok((serialize_ctx, msg))
     }
 
     pub fn seq_no(&self) -> RpcSeqNo {
         self.seq_no
     }
 
+    /// 替换报文的序列号，用于转发时重新分配序列号
+    pub fn set_seq_no(&mut self, seq_no: RpcSeqNo) {
+        self.seq_no = seq_no;
+    }
+
     pub fn func(&self) -> &abi::FunctionIdent {
         &self.func
     }
@@ -39,7 +185,12 @@ impl<'a> RpcMessage<'a> {
         &self.message
     }
 
-    pub fn data(&self) -> &[u8] {
+    pub fn deadline(&self) -> Option<RpcDeadline> {
+        self.deadline
+    }
+
+    /// 报文头之后的数据（调用参数、返回值），直接借用原始报文
+    pub fn data(&self) -> &'a [u8] {
         self.data
     }
 }
@@ -47,36 +198,63 @@ impl<'a> RpcMessage<'a> {
 /// RPC 函数调用请求的临时上下文，用于在相关函数回调中提供调用请求所需的 API
 pub struct RpcRequestCtx<'a, T> {
     seq_no: RpcSeqNo,
-    serialize_ctx: &'a SerializeCtx,
+    serialize_ctx: SerializeCtx,
     data: &'a T,
 }
 
 impl<'a, T> RpcRequestCtx<'a, T> {
-    pub fn new(seq_no: RpcSeqNo, serialize_ctx: &'a SerializeCtx, data: &'a T) -> Self {
+    pub fn new(seq_no: RpcSeqNo, serialize_ctx: &SerializeCtx, data: &'a T) -> Self {
         RpcRequestCtx {
             seq_no,
-            serialize_ctx,
+            serialize_ctx: *serialize_ctx,
             data,
         }
     }
 
     pub fn make_request(&self, func: abi::FunctionIdent, args: Vec<u8>) -> Result<Vec<u8>> {
+        self.make_request_with_deadline(func, args, None)
+    }
+
+    /// 生成带有截止时间的调用请求报文
+    pub fn make_request_with_deadline(&self,
+                                      func: abi::FunctionIdent,
+                                      args: Vec<u8>,
+                                      deadline: Option<RpcDeadline>,
+    ) -> Result<Vec<u8>> {
         // 拼接报文
         let msg = RpcMessage {
             seq_no: self.seq_no,
             func,
             message: Message::Request,
+            deadline,
             data: &args,
         };
 
         // 序列化
-        let msg_bytes = self.serialize_ctx.serialize(&msg)?;
+        let msg_bytes = msg.encode(&self.serialize_ctx)?;
+
+        Ok(msg_bytes)
+    }
+
+    /// 生成取消调用请求的报文
+    pub fn make_cancel(&self, func: abi::FunctionIdent) -> Result<Vec<u8>> {
+        // 拼接报文
+        let msg = RpcMessage {
+            seq_no: self.seq_no,
+            func,
+            message: Message::Cancel,
+            deadline: None,
+            data: &[],
+        };
+
+        // 序列化
+        let msg_bytes = msg.encode(&self.serialize_ctx)?;
 
         Ok(msg_bytes)
     }
 
     pub fn serialize_ctx(&self) -> &SerializeCtx {
-        self.serialize_ctx
+        &self.serialize_ctx
     }
 
     pub fn seq_no(&self) -> RpcSeqNo {
@@ -91,36 +269,75 @@ impl<'a, T> RpcRequestCtx<'a, T> {
 /// RPC 函数调用回应（真正进行函数调用的时刻）的临时上下文，用于在相关函数回调中提供返回调用结果所需的 API
 pub struct RpcResponseCtx<'a, T> {
     seq_no: RpcSeqNo,
-    serialize_ctx: &'a SerializeCtx,
+    serialize_ctx: SerializeCtx,
     data: &'a T,
+    deadline: Option<RpcDeadline>,
 }
 
 impl<'a, T> RpcResponseCtx<'a, T> {
-    pub fn new(seq_no: RpcSeqNo, serialize_ctx: &'a SerializeCtx, data: &'a T) -> Self {
+    pub fn new(seq_no: RpcSeqNo, serialize_ctx: &SerializeCtx, data: &'a T) -> Self {
         RpcResponseCtx {
             seq_no,
-            serialize_ctx,
+            serialize_ctx: *serialize_ctx,
             data,
+            deadline: None,
         }
     }
 
+    /// 设置调用请求的截止时间
+    pub fn with_deadline(mut self, deadline: Option<RpcDeadline>) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    /// 调用请求的截止时间
+    pub fn deadline(&self) -> Option<RpcDeadline> {
+        self.deadline
+    }
+
     pub fn make_response(&self, func: abi::FunctionIdent, result: Vec<u8>) -> Result<Vec<u8>> {
         // 拼接报文
         let msg = RpcMessage {
             seq_no: self.seq_no,
             func,
             message: Message::Response,
+            deadline: None,
             data: &result,
         };
 
         // 序列化
-        let msg_bytes = self.serialize_ctx.serialize(&msg)?;
+        let msg_bytes = msg.encode(&self.serialize_ctx)?;
+
+        Ok(msg_bytes)
+    }
+
+    /// 生成调用失败的返回报文
+    pub fn make_error(&self, func: abi::FunctionIdent, error: RpcError) -> Result<Vec<u8>> {
+        // 拼接报文
+        let msg = RpcMessage {
+            seq_no: self.seq_no,
+            func,
+            message: Message::Error(error),
+            deadline: None,
+            data: &[],
+        };
+
+        // 序列化
+        let msg_bytes = msg.encode(&self.serialize_ctx)?;
 
         Ok(msg_bytes)
     }
 
+    /// 根据调用结果生成返回报文
+    pub fn make_result(&self, func: abi::FunctionIdent, result: RpcCallResult) -> Result<Vec<u8>> {
+        match result {
+            Ok(result) => self.make_response(func, result),
+            Err(error) => self.make_error(func, error),
+        }
+    }
+
     pub fn serialize_ctx(&self) -> &SerializeCtx {
-        self.serialize_ctx
+        &self.serialize_ctx
     }
 
     pub fn seq_no(&self) -> RpcSeqNo {
@@ -135,21 +352,34 @@ impl<'a, T> RpcResponseCtx<'a, T> {
 /// RPC 函数调用结果返回值、转发的临时上下文，用于在相关函数回调中提供解析、处理调用结果所需的 API
 pub struct RpcEndCtx<'a, T> {
     seq_no: RpcSeqNo,
-    serialize_ctx: &'a SerializeCtx,
+    serialize_ctx: SerializeCtx,
     data: &'a T,
+    deadline: Option<RpcDeadline>,
 }
 
 impl<'a, T> RpcEndCtx<'a, T> {
-    pub fn new(seq_no: RpcSeqNo, serialize_ctx: &'a SerializeCtx, data: &'a T) -> Self {
+    pub fn new(seq_no: RpcSeqNo, serialize_ctx: &SerializeCtx, data: &'a T) -> Self {
         RpcEndCtx {
             seq_no,
-            serialize_ctx,
+            serialize_ctx: *serialize_ctx,
             data,
+            deadline: None,
         }
     }
 
+    /// 设置调用请求的截止时间
+    pub fn with_deadline(mut self, deadline: Option<RpcDeadline>) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    /// 调用请求的截止时间
+    pub fn deadline(&self) -> Option<RpcDeadline> {
+        self.deadline
+    }
+
     pub fn serialize_ctx(&self) -> &SerializeCtx {
-        self.serialize_ctx
+        &self.serialize_ctx
     }
 
     pub fn seq_no(&self) -> RpcSeqNo {
@@ -160,3 +390,53 @@ impl<'a, T> RpcEndCtx<'a, T> {
         self.data
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_encode_decode() {
+        let data = vec![7u8; 1024];
+        let mut msg = RpcMessage::new(1, abi::FunctionIdent::new("test"), Message::Request, &data);
+        msg.deadline = Some(100);
+
+        for format in SerializeFormat::supported() {
+            let bytes = msg.encode(&SerializeCtx::with_format(format)).unwrap();
+            let (serialize_ctx, actual) = RpcMessage::decode(&bytes).unwrap();
+            assert_eq!(format, serialize_ctx.format());
+            assert_eq!(1, actual.seq_no());
+            assert_eq!("test", actual.func().name);
+            assert_eq!(Some(100), actual.deadline());
+            assert_eq!(&data[..], actual.data());
+        }
+    }
+
+    #[test]
+    fn test_message_transcode() {
+        let msgpack = SerializeCtx::new();
+        let args = serialize::ArgsBuilder::new(&msgpack).push(&"hello".to_string()).unwrap()
+            .build().unwrap();
+        let msg = RpcMessage::new(1, abi::FunctionIdent::new("test"), Message::Request, &args);
+
+        for format in SerializeFormat::supported() {
+            let to = SerializeCtx::with_format(format);
+            let bytes = msg.transcode(&msgpack, &to).unwrap();
+            let (serialize_ctx, actual) = RpcMessage::decode(&bytes).unwrap();
+            assert_eq!(1, actual.seq_no());
+            // 无法转码时保持原格式，接收方仍可按格式标记解析参数
+            let expected = if format.is_self_describing() { format } else { SerializeFormat::MessagePack };
+            assert_eq!(expected, serialize_ctx.format());
+            let args = Args::from_bytes(&serialize_ctx, actual.data()).unwrap();
+            assert_eq!("hello", args.get::<String>(0).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_malformed_message() {
+        assert!(matches!(RpcMessage::decode(&[]), Err(Error::MalformedMessage)));
+        assert!(matches!(RpcMessage::decode(&[0, 1]), Err(Error::MalformedMessage)));
+        assert!(matches!(RpcMessage::decode(&[0, 8, 0, 0, 0, 0]), Err(Error::MalformedMessage)));
+        assert!(matches!(RpcMessage::decode(&[0xff, 0, 0, 0, 0]), Err(Error::Serialize(_))));
+    }
+}
@@ -0,0 +1,56 @@
+//! 调用请求的截止时间
+//!
+//! 截止时间以 UNIX 时间戳（毫秒）表示。Host 与 WASM 模块（WASI）共享同一系统时钟，
+//! 因此截止时间可以随报文在模块间转发，各节点据此计算剩余的时间预算。
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// 调用请求的截止时间，为 UNIX 时间戳（毫秒）
+pub type RpcDeadline = u64;
+
+/// 被取消的请求等待对端回送结果的最长时间，超时后不再等待迟到的结果
+pub const DISCARD_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// 当前时间的 UNIX 时间戳（毫秒）
+pub fn now_millis() -> RpcDeadline {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as RpcDeadline)
+        .unwrap_or(0)
+}
+
+/// 计算从现在起经过 `timeout` 后的截止时间
+pub fn deadline_after(timeout: Duration) -> RpcDeadline {
+    now_millis().saturating_add(timeout.as_millis() as RpcDeadline)
+}
+
+/// 距离截止时间的剩余时间，已超时则返回 `None`
+pub fn remaining(deadline: RpcDeadline) -> Option<Duration> {
+    let now = now_millis();
+    if now < deadline {
+        Some(Duration::from_millis(deadline - now))
+    } else {
+        None
+    }
+}
+
+/// 是否已经超过截止时间
+pub fn expired(deadline: RpcDeadline) -> bool {
+    remaining(deadline).is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deadline() {
+        let deadline = deadline_after(Duration::from_secs(60));
+        assert!(!expired(deadline));
+        assert!(remaining(deadline).unwrap() <= Duration::from_secs(60));
+
+        let deadline = now_millis() - 1;
+        assert!(expired(deadline));
+        assert_eq!(None, remaining(deadline));
+    }
+}
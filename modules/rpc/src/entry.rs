@@ -2,6 +2,8 @@
 
 use std::collections::{HashMap, HashSet};
 
+use serde::{Deserialize, Serialize};
+
 use crate::{abi, Result, RpcResponseCtx};
 
 /// 导出函数的回调。第一个参数为发送返回结果的 RPC 上下文，第二个参数为反序列化前的参数。
@@ -34,8 +36,10 @@ impl<T> RpcExports<T> {
     }
 
     /// 根据链接提示在当前导出表中查找回调函数
+    ///
+    /// 链接提示中的版本要求已在解析模块时检查，此处忽略。
     pub fn get_callback(&self, func: &abi::FunctionIdent) -> Option<&RpcExportCallback<T>> {
-        if func.hint == self.hint {
+        if func.hint.unversioned() == self.hint {
             // 目标为当前模块，尝试返回对应导出函数
             self.exports_map.get(&func.name).map(|cb| &**cb)
         } else {
@@ -46,11 +50,19 @@ impl<T> RpcExports<T> {
     pub fn hint(&self) -> &abi::LinkHint {
         &self.hint
     }
-}
 
-/// 导出函数的回调。第一个参数为发送返回结果的 RPC 上下文，第二个参数为反序列化前的参数。
+    /// 导出函数的名称，按名称排序
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<_> = self.exports_map.keys().cloned().collect();
+        names.sort();
+        names
+    }
+}
 
 /// 导入函数表
+///
+/// 模块在握手时向 Host 声明其可能调用的函数，Host 拒绝未声明的调用请求，见 `ImportPolicy`。
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
 pub struct RpcImports {
     imports_set: HashSet<abi::FunctionIdent>,
 }
@@ -67,8 +79,27 @@ impl RpcImports {
         self.imports_set.insert(func);
     }
 
-    /// 根据链接提示在当前导入表中查找回调函数
+    /// 检查函数是否已在导入表中声明，函数名称与链接提示均需一致
     pub fn check(&self, func: &abi::FunctionIdent) -> bool {
         self.imports_set.contains(func)
     }
+
+    /// 导入表中的函数，按链接提示及名称排序，供 Host 审查
+    pub fn functions(&self) -> Vec<abi::FunctionIdent> {
+        let mut funcs: Vec<_> = self.imports_set.iter().cloned().collect();
+        funcs.sort_by_cached_key(|func| (format!("{:?}", func.hint), func.name.clone()));
+        funcs
+    }
+}
+
+/// 节点对对端导入表的要求
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ImportPolicy {
+    /// 对端声明导入表时按其限制，未声明时不作限制
+    #[default]
+    Optional,
+    /// 对端必须声明导入表，未声明时拒绝其所有调用请求
+    Required,
+    /// 使用本节点提供的导入表，忽略对端声明的导入表
+    Fixed(RpcImports),
 }
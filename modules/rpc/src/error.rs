@@ -0,0 +1,81 @@
+//! RPC 模块的错误类型
+
+use std::fmt;
+
+use crate::RpcError;
+
+/// RPC 通信中可能发生的错误
+#[derive(Debug)]
+pub enum Error {
+    /// 低层传输失败
+    Transport(low_level::Error),
+    /// 报文序列化或反序列化失败
+    Serialize(serialize::Error),
+    /// 调用失败，可能来自对端回送的错误结果
+    Rpc(RpcError),
+    /// 报文结构不完整
+    MalformedMessage,
+    /// RPC 节点缺少处理报文所需的回调
+    Unconfigured(&'static str),
+    /// 对端重复发送对端信息，握手只进行一次
+    DuplicatePeerInfo,
+    /// 由上层注册的回调返回的错误
+    Callback(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl Error {
+    /// 将上层的错误包装为回调错误
+    pub fn callback<E>(e: E) -> Self
+        where E: std::error::Error + Send + Sync + 'static,
+    {
+        Error::Callback(Box::new(e))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Transport(e) => write!(f, "transport error: {}", e),
+            Error::Serialize(e) => write!(f, "{}", e),
+            Error::Rpc(e) => write!(f, "rpc error: {}", e),
+            Error::MalformedMessage => write!(f, "malformed message"),
+            Error::Unconfigured(name) => write!(f, "no {}", name),
+            Error::DuplicatePeerInfo => write!(f, "duplicate peer info"),
+            Error::Callback(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Transport(e) => Some(e),
+            Error::Serialize(e) => Some(e),
+            Error::Rpc(e) => Some(e),
+            Error::MalformedMessage => None,
+            Error::Unconfigured(_) => None,
+            Error::DuplicatePeerInfo => None,
+            Error::Callback(e) => Some(e.as_ref()),
+        }
+    }
+}
+
+impl From<low_level::Error> for Error {
+    fn from(e: low_level::Error) -> Self {
+        Error::Transport(e)
+    }
+}
+
+impl From<serialize::Error> for Error {
+    fn from(e: serialize::Error) -> Self {
+        Error::Serialize(e)
+    }
+}
+
+impl From<RpcError> for Error {
+    fn from(e: RpcError) -> Self {
+        Error::Rpc(e)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,64 @@
+//! 模块清单，描述模块的版本、导出函数及所依赖的模块，在握手时随对端信息发送
+
+use serde::{Deserialize, Serialize};
+
+pub use semver::{Version, VersionReq};
+
+/// 模块清单
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ModuleManifest {
+    /// 模块名称，与 `abi::LinkHint::BcModule` 中的名称一致
+    pub name: String,
+    pub version: Version,
+    /// 导出函数的名称
+    pub exports: Vec<String>,
+    /// 导入的模块及其版本要求
+    pub imports: Vec<ModuleDependency>,
+}
+
+/// 对其他模块的依赖
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ModuleDependency {
+    pub name: String,
+    pub req: VersionReq,
+}
+
+impl ModuleManifest {
+    pub fn new(name: impl Into<String>, version: Version) -> Self {
+        ModuleManifest {
+            name: name.into(),
+            version,
+            exports: Vec::new(),
+            imports: Vec::new(),
+        }
+    }
+
+    /// 添加对模块 `name` 的依赖
+    pub fn add_import(&mut self, name: impl Into<String>, req: VersionReq) {
+        self.imports.push(ModuleDependency { name: name.into(), req });
+    }
+
+    /// 本模块是否满足依赖 `dep`
+    pub fn satisfies(&self, dep: &ModuleDependency) -> bool {
+        self.name == dep.name && dep.req.matches(&self.version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_satisfies() {
+        let service = ModuleManifest::new("service", Version::new(1, 2, 0));
+
+        let mut dispatch = ModuleManifest::new("dispatch", Version::new(0, 1, 0));
+        dispatch.add_import("service", "^1.1".parse().unwrap());
+        dispatch.add_import("storage", VersionReq::STAR);
+        assert!(service.satisfies(&dispatch.imports[0]));
+        assert!(!service.satisfies(&dispatch.imports[1]));
+
+        let dep = ModuleDependency { name: "service".to_string(), req: "^2".parse().unwrap() };
+        assert!(!service.satisfies(&dep));
+    }
+}
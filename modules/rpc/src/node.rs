@@ -3,9 +3,12 @@
 use std::cell::Cell;
 use std::sync::Mutex;
 
-use serialize::SerializeCtx;
+use serialize::{SerializeCtx, SerializeFormat};
 
-use crate::{abi, Message, Result, RpcEndCtx, RpcExports, RpcMessage, RpcRequestCtx, RpcResponseCtx};
+use crate::{abi, expired, Error, Message, PeerInfo, Result, RpcCallResult, RpcDeadline, RpcEndCtx, RpcError, RpcErrorCode, RpcExportCallback, RpcExports,
+            ImportPolicy, RpcImports, RpcMessage, RpcRequestCtx, RpcResponseCtx};
+use crate::capability::Capabilities;
+use crate::manifest::ModuleManifest;
 
 pub type RpcSeqNo = u64;
 
@@ -13,19 +16,43 @@ pub type RpcForwardCallback<T> =
 dyn Fn(&RpcEndCtx<T>, abi::FunctionIdent, &[u8]) -> Result<()> + Sync + Send + 'static;
 
 pub type RpcResultCallback<T> =
+dyn Fn(&RpcEndCtx<T>, RpcCallResult) -> Result<()> + Sync + Send + 'static;
+
+/// 向对端回送报文的回调，用于发送节点自身产生的报文（如错误结果）
+pub type RpcReplyCallback<T> =
 dyn Fn(&RpcEndCtx<T>, Vec<u8>) -> Result<()> + Sync + Send + 'static;
 
+/// 处理取消消息的回调，负责中止本地的调用或将取消消息继续转发
+pub type RpcCancelCallback<T> =
+dyn Fn(&RpcEndCtx<T>, abi::FunctionIdent, &[u8]) -> Result<()> + Sync + Send + 'static;
+
 pub struct RpcNode<T>
     where T: Send + Sync + 'static,
 {
-    serialize_ctx: SerializeCtx,
+    /// 当前用于发送报文的序列化上下文，握手后切换为双方协商确定的格式
+    serialize_ctx: Mutex<Cell<SerializeCtx>>,
+    /// 本节点支持的序列化格式，按偏好从高到低排列
+    formats: Vec<SerializeFormat>,
     exports: Option<RpcExports<T>>,
     nonce: u32,
     request_num: Mutex<Cell<u32>>,
     forward_cb: Option<Box<RpcForwardCallback<T>>>,
     result_cb: Option<Box<RpcResultCallback<T>>>,
+    reply_cb: Option<Box<RpcReplyCallback<T>>>,
+    cancel_cb: Option<Box<RpcCancelCallback<T>>>,
     data: T,
+    /// 握手时向对端发送的模块清单
+    manifest: Option<ModuleManifest>,
+    /// 握手时向对端声明的导入表
+    imports: Option<RpcImports>,
     peer_name: Mutex<Cell<Option<String>>>,
+    peer_manifest: Mutex<Cell<Option<ModuleManifest>>>,
+    /// 对端声明的导入表，对端的调用请求需在其中声明
+    peer_imports: Mutex<Cell<Option<RpcImports>>>,
+    /// 对对端导入表的要求
+    import_policy: ImportPolicy,
+    /// 授予对端的能力，限制对端可以调用的函数
+    capabilities: Option<Capabilities>,
 }
 
 impl<T> RpcNode<T>
@@ -33,17 +60,31 @@ impl<T> RpcNode<T>
 {
     /// 创建一个新的 RPC 节点
     ///
-    /// `nonce` 为该节点的唯一标识，用于标识该节点的调用请求和调用结果
+    /// `nonce` 为该节点的唯一标识，用于标识该节点的调用请求和调用结果。
+    /// `serialize_ctx` 的格式为该节点偏好的序列化格式，在握手时与对端协商。
     pub fn new(serialize_ctx: SerializeCtx, nonce: u32, data: T) -> Self {
+        let preferred = serialize_ctx.format();
+        let mut formats = vec![preferred];
+        formats.extend(SerializeFormat::supported().into_iter().filter(|format| *format != preferred));
+
         RpcNode {
-            serialize_ctx,
+            serialize_ctx: Mutex::new(Cell::new(serialize_ctx)),
+            formats,
             exports: None,
             nonce,
             request_num: Mutex::new(Cell::new(0)),
             forward_cb: None,
             result_cb: None,
+            reply_cb: None,
+            cancel_cb: None,
             data,
+            manifest: None,
+            imports: None,
             peer_name: Mutex::new(Cell::new(None)),
+            peer_manifest: Mutex::new(Cell::new(None)),
+            peer_imports: Mutex::new(Cell::new(None)),
+            import_policy: ImportPolicy::default(),
+            capabilities: None,
         }
     }
 
@@ -51,6 +92,26 @@ impl<T> RpcNode<T>
         self.exports = Some(exports);
     }
 
+    /// 设置握手时向对端发送的模块清单
+    pub fn set_manifest(&mut self, manifest: ModuleManifest) {
+        self.manifest = Some(manifest);
+    }
+
+    /// 设置握手时向对端声明的导入表，声明后本节点只能调用其中的函数
+    pub fn set_imports(&mut self, imports: RpcImports) {
+        self.imports = Some(imports);
+    }
+
+    /// 设置对对端导入表的要求，默认为 `ImportPolicy::Optional`
+    pub fn set_import_policy(&mut self, policy: ImportPolicy) {
+        self.import_policy = policy;
+    }
+
+    /// 设置授予对端的能力，对端调用本节点导出的函数或经由本节点转发的函数时均需被授权
+    pub fn set_capabilities(&mut self, capabilities: Capabilities) {
+        self.capabilities = Some(capabilities);
+    }
+
     pub fn set_forward_cb<CB>(&mut self, forward_cb: CB)
         where
             CB: Fn(&RpcEndCtx<T>, abi::FunctionIdent, &[u8]) -> Result<()> + Sync + Send + 'static,
@@ -60,11 +121,25 @@ impl<T> RpcNode<T>
 
     pub fn set_result_cb<CB>(&mut self, result_cb: CB)
         where
-            CB: Fn(&RpcEndCtx<T>, Vec<u8>) -> Result<()> + Sync + Send + 'static,
+            CB: Fn(&RpcEndCtx<T>, RpcCallResult) -> Result<()> + Sync + Send + 'static,
     {
         self.result_cb = Some(Box::new(result_cb));
     }
 
+    pub fn set_reply_cb<CB>(&mut self, reply_cb: CB)
+        where
+            CB: Fn(&RpcEndCtx<T>, Vec<u8>) -> Result<()> + Sync + Send + 'static,
+    {
+        self.reply_cb = Some(Box::new(reply_cb));
+    }
+
+    pub fn set_cancel_cb<CB>(&mut self, cancel_cb: CB)
+        where
+            CB: Fn(&RpcEndCtx<T>, abi::FunctionIdent, &[u8]) -> Result<()> + Sync + Send + 'static,
+    {
+        self.cancel_cb = Some(Box::new(cancel_cb));
+    }
+
     pub fn request(&self) -> RpcRequestCtx<T> {
         // 基于 `nonce` 生成一个唯一的 RPC 调用请求序列号 `seq_no`
         let request_num = self.request_num.lock().unwrap();
@@ -75,37 +150,122 @@ impl<T> RpcNode<T>
         drop(request_num);
 
         // 创建调用请求上下文
-        RpcRequestCtx::new(seq_no, &SerializeCtx, &self.data)
+        RpcRequestCtx::new(seq_no, &self.serialize_ctx(), &self.data)
     }
 
     pub fn reponse(&self, seq_no: RpcSeqNo) -> RpcResponseCtx<T> {
-        RpcResponseCtx::new(seq_no, &SerializeCtx, &self.data)
+        RpcResponseCtx::new(seq_no, &self.serialize_ctx(), &self.data)
     }
 
-    fn handle_request(&self, seq_no: RpcSeqNo, func: &abi::FunctionIdent, args: &[u8]) -> Result<()> {
-        let exports = self.exports.as_ref().ok_or(format!("no exports"))?;
-        let cb = exports.get_callback(func).ok_or(format!("no callback for {:?}", func))?;
+    /// 当前用于发送报文的序列化上下文
+    pub fn serialize_ctx(&self) -> SerializeCtx {
+        self.serialize_ctx.lock().unwrap().get()
+    }
 
-        // 创建返回上下文
-        let ctx = self.reponse(seq_no);
+    fn set_serialize_ctx(&self, serialize_ctx: SerializeCtx) {
+        self.serialize_ctx.lock().unwrap().set(serialize_ctx);
+    }
 
-        // 调用回调
-        cb(&ctx, args).unwrap();
+    /// 在导出表中查找调用请求对应的回调
+    fn find_callback(&self, func: &abi::FunctionIdent) -> Option<&RpcExportCallback<T>> {
+        self.exports.as_ref()?.get_callback(func)
+    }
 
-        Ok(())
+    /// 向对端回送调用失败的结果
+    fn reply_error(&self,
+                   serialize_ctx: &SerializeCtx,
+                   seq_no: RpcSeqNo,
+                   func: abi::FunctionIdent,
+                   error: RpcError,
+    ) -> Result<()> {
+        let reply_cb = self.reply_cb.as_ref()
+            .ok_or(Error::Unconfigured("reply_cb"))?;
+        let msg = RpcResponseCtx::new(seq_no, serialize_ctx, &self.data).make_error(func, error)?;
+        let ctx = RpcEndCtx::new(seq_no, serialize_ctx, &self.data);
+        reply_cb(&ctx, msg)
+    }
+
+    fn handle_request(&self,
+                      serialize_ctx: &SerializeCtx,
+                      seq_no: RpcSeqNo,
+                      func: abi::FunctionIdent,
+                      deadline: Option<RpcDeadline>,
+                      args: &[u8],
+                      raw_msg: &[u8],
+    ) -> Result<()> {
+        // 已经超过截止时间的请求不再处理
+        if matches!(deadline, Some(deadline) if expired(deadline)) {
+            let error = RpcError::new(RpcErrorCode::DeadlineExceeded,
+                                      format!("deadline exceeded before calling {:?}", func));
+            return self.reply_error(serialize_ctx, seq_no, func, error);
+        }
+
+        // 对端声明了导入表时，拒绝调用未声明的函数
+        if !self.peer_may_call(&func) {
+            let error = RpcError::new(RpcErrorCode::UndeclaredImport,
+                                      format!("{:?} is not declared in the caller's imports", func));
+            return self.reply_error(serialize_ctx, seq_no, func, error);
+        }
+
+        // 对端未被授权时拒绝调用
+        if !self.peer_permitted(serialize_ctx, &func, args) {
+            let error = RpcError::new(RpcErrorCode::PermissionDenied,
+                                      format!("permission denied to call {:?}", func));
+            return self.reply_error(serialize_ctx, seq_no, func, error);
+        }
+
+        // 调用本节点导出的函数
+        if let Some(cb) = self.find_callback(&func) {
+            // 创建返回上下文，以调用请求所使用的格式回送结果
+            let ctx = RpcResponseCtx::new(seq_no, serialize_ctx, &self.data)
+                .with_deadline(deadline);
+
+            // 调用回调。回调自身产生的 `RpcError`（如参数解析失败）原样返回，其余视为执行失败
+            return match cb(&ctx, args) {
+                Ok(_) => Ok(()),
+                Err(Error::Rpc(error)) => self.reply_error(serialize_ctx, seq_no, func, error),
+                Err(e) => {
+                    let error = RpcError::new(RpcErrorCode::CallFailed, e.to_string());
+                    self.reply_error(serialize_ctx, seq_no, func, error)
+                }
+            };
+        }
+
+        // 如果没有对应的回调，则调用 `forward_cb` 把这个报文转发给其他模块
+        let error = match self.forward_cb.as_ref() {
+            Some(forward_cb) => {
+                let ctx = RpcEndCtx::new(seq_no, serialize_ctx, &self.data)
+                    .with_deadline(deadline);
+                match forward_cb(&ctx, func.clone(), raw_msg) {
+                    Ok(_) => return Ok(()),
+                    Err(e) => RpcError::new(RpcErrorCode::ForwardFailed, e.to_string()),
+                }
+            }
+            None => RpcError::new(RpcErrorCode::NoSuchFunction,
+                                  format!("no callback for {:?}", func)),
+        };
+        self.reply_error(serialize_ctx, seq_no, func, error)
     }
 
     pub fn handle_message(&self, raw_msg: &[u8]) -> Result<()> {
-        // 对于收到的报文，首先要将其解码（可以反序列化为 `RpcMessage` 之类的）。
+        // 对于收到的报文，首先要根据其格式标记将其解码（可以反序列化为 `RpcMessage` 之类的）。
         // 因为这一次解码主要是用来判断如何处理报文的，所以不用反序列化详细的数据
-        // （比如调用参数、返回值）。只需要获得报文的类型（调用请求、返回结果）和
-        // `abi::FunctionIdent`（调用请求）、是否发生错误（返回结果）即可。
+        // （比如调用参数、返回值）。只需要获得报文的类型（调用请求、返回结果、错误结果）
+        // 和 `abi::FunctionIdent`（调用请求）即可。
+        // - 如果报文是调用请求且已经超过截止时间，则直接回送超时的错误结果。
+        // - 如果报文是调用请求且对端声明了导入表，则拒绝调用未声明的函数。
+        // - 如果报文是调用请求且对端未被授权调用该函数，则回送权限错误。
         // - 如果报文是调用请求，则需要调用 `exports` 中的对应的回调。如果没有
         //   对应的回调，则调用 `forward_cb` 把这个报文转发给其他模块。如果没
-        //   有定义 `forward_cb` 则失败（发送一个返回结果发生错误报文）。
-        // - 如果报文是返回结果，并且没有发生错误，则需要调用回调。
-        // - 如果报文是返回结果，并且发生错误，则返回错误。
-        let msg: RpcMessage = self.serialize_ctx.deserialize(raw_msg)?;
+        //   有定义 `forward_cb`、转发失败或者回调失败，则通过 `reply_cb` 回送
+        //   一个错误结果报文。
+        // - 如果报文是返回结果或错误结果，则调用 `result_cb`。
+        // - 如果报文是取消消息，则调用 `cancel_cb`。没有定义 `cancel_cb` 时忽略。
+        // - 如果报文是对端信息，则记录对端名称、清单及导入表并协商序列化格式。对端信息只接受一次，
+        //   重复的对端信息返回错误，通过 `reply_cb` 回送协商结果。
+        // - 如果报文是协商结果，则切换到协商确定的序列化格式。
+        // 对于调用请求，以请求所使用的格式回送结果。
+        let (serialize_ctx, msg) = RpcMessage::decode(raw_msg)?;
         let seq_no = msg.seq_no();
         let func = msg.func().clone();
         let data = msg.data();
@@ -114,47 +274,145 @@ impl<T> RpcNode<T>
         match message {
             Message::Request => {
                 // 调用请求
-                let result = self.handle_request(seq_no, &func, data);
-
-                match result {
-                    Ok(_) => Ok(()),
-                    Err(_) => {
-                        // 如果没有对应的回调，则调用 `forward_cb` 把这个报文转发给其他模块
-                        let forward_cb =
-                            self.forward_cb.as_ref().ok_or(format!("no forward_cb"))?;
-                        let ctx = RpcEndCtx::new(seq_no, &self.serialize_ctx, &self.data);
-                        forward_cb(&ctx, func.clone(), raw_msg)
-                    }
-                }
+                self.handle_request(&serialize_ctx, seq_no, func, msg.deadline(), data, raw_msg)
             }
             Message::Response => {
                 // 返回结果
-                let result_cb =
-                    self.result_cb.as_ref().ok_or(format!("no result_cb"))?;
-                let ctx = RpcEndCtx::new(seq_no, &self.serialize_ctx, &self.data);
-                result_cb(&ctx, data.to_vec())
+                self.handle_result(&serialize_ctx, seq_no, Ok(data.to_vec()))
+            }
+            Message::Error(error) => {
+                // 错误结果
+                self.handle_result(&serialize_ctx, seq_no, Err(error.clone()))
+            }
+            Message::Cancel => {
+                // 取消消息
+                self.handle_cancel(&serialize_ctx, seq_no, func, raw_msg)
+            }
+            Message::PeerInfo(info) => {
+                // 设置对端名称、清单及导入表。握手后三者不再改变，Host 审查过的导入表不会被替换
+                let mut peer_name = self.peer_name.lock().unwrap();
+                if peer_name.get_mut().is_some() {
+                    return Err(Error::DuplicatePeerInfo);
+                }
+                peer_name.set(Some(info.name.clone()));
+                self.peer_manifest.lock().unwrap().set(info.manifest.clone());
+                self.peer_imports.lock().unwrap().set(info.imports.clone());
+                drop(peer_name);
+
+                // 协商序列化格式
+                self.handle_peer_info(seq_no, info)
             }
-            Message::PeerInfo(name) => {
-                // 设置对端名称
-                let peer_name = self.peer_name.lock().unwrap();
-                peer_name.set(Some(name.clone()));
+            Message::Format(format) => {
+                // 切换到协商确定的序列化格式
+                if !format.is_supported() {
+                    return Err(serialize::Error::Unsupported(*format).into());
+                }
+                self.set_serialize_ctx(SerializeCtx::with_format(*format));
                 Ok(())
             }
         }
     }
 
+    /// 在双方都支持的格式中选取本节点最偏好的一个，没有共同支持的格式时使用 MessagePack
+    fn handle_peer_info(&self, seq_no: RpcSeqNo, info: &PeerInfo) -> Result<()> {
+        let format = self.formats.iter()
+            .find(|format| info.formats.contains(format))
+            .copied()
+            .unwrap_or_default();
+        self.set_serialize_ctx(SerializeCtx::with_format(format));
+
+        // 告知对端协商结果。协商结果总是以 MessagePack 编码
+        if let Some(reply_cb) = self.reply_cb.as_ref() {
+            let serialize_ctx = SerializeCtx::new();
+            let msg = RpcMessage::new(seq_no, abi::FunctionIdent::new(""), Message::Format(format), &[])
+                .encode(&serialize_ctx)?;
+            let ctx = RpcEndCtx::new(seq_no, &serialize_ctx, &self.data);
+            reply_cb(&ctx, msg)?;
+        }
+        Ok(())
+    }
+
+    fn handle_result(&self, serialize_ctx: &SerializeCtx, seq_no: RpcSeqNo, result: RpcCallResult) -> Result<()> {
+        let result_cb =
+            self.result_cb.as_ref().ok_or(Error::Unconfigured("result_cb"))?;
+        let ctx = RpcEndCtx::new(seq_no, serialize_ctx, &self.data);
+        result_cb(&ctx, result)
+    }
+
+    fn handle_cancel(&self,
+                     serialize_ctx: &SerializeCtx,
+                     seq_no: RpcSeqNo,
+                     func: abi::FunctionIdent,
+                     raw_msg: &[u8],
+    ) -> Result<()> {
+        match self.cancel_cb.as_ref() {
+            Some(cancel_cb) => {
+                let ctx = RpcEndCtx::new(seq_no, serialize_ctx, &self.data);
+                cancel_cb(&ctx, func, raw_msg)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// 本节点导出函数表的链接提示
+    pub fn hint(&self) -> Option<&abi::LinkHint> {
+        self.exports.as_ref().map(|exports| exports.hint())
+    }
+
     pub fn get_peer_name(&self) -> Option<String> {
         let mut peer_name = self.peer_name.lock().unwrap();
         peer_name.get_mut().clone()
     }
 
+    /// 对端在握手时发送的模块清单
+    pub fn get_peer_manifest(&self) -> Option<ModuleManifest> {
+        let mut peer_manifest = self.peer_manifest.lock().unwrap();
+        peer_manifest.get_mut().clone()
+    }
+
+    /// 限制对端调用的导入表，没有导入表时返回 `None`
+    ///
+    /// `ImportPolicy::Fixed` 时为本节点提供的导入表，否则为对端在握手时声明的导入表。
+    pub fn get_peer_imports(&self) -> Option<RpcImports> {
+        if let ImportPolicy::Fixed(imports) = &self.import_policy {
+            return Some(imports.clone());
+        }
+        let mut peer_imports = self.peer_imports.lock().unwrap();
+        peer_imports.get_mut().clone()
+    }
+
+    /// 对端是否可以调用函数 `func`，没有导入表时由 `ImportPolicy` 决定
+    fn peer_may_call(&self, func: &abi::FunctionIdent) -> bool {
+        let mut peer_imports = self.peer_imports.lock().unwrap();
+        match (&self.import_policy, peer_imports.get_mut().as_ref()) {
+            (ImportPolicy::Fixed(imports), _) | (_, Some(imports)) => imports.check(func),
+            (ImportPolicy::Required, None) => false,
+            (ImportPolicy::Optional, None) => true,
+        }
+    }
+
+    /// 对端是否被授权以参数 `args` 调用函数 `func`，没有设置能力时不作限制
+    fn peer_permitted(&self, serialize_ctx: &SerializeCtx, func: &abi::FunctionIdent, args: &[u8]) -> bool {
+        self.capabilities.as_ref()
+            .map_or(true, |capabilities| capabilities.permits(serialize_ctx, func, args))
+    }
+
+    /// 生成向对端通告本节点信息的报文
+    ///
+    /// 报文中附带本节点支持的序列化格式、模块清单及导入表。由于此时尚未协商格式，报文总是以 MessagePack 编码。
     pub fn make_peer_info(&self, name: String) -> Vec<u8> {
         // 拼接报文
         let func = abi::FunctionIdent::new("");
-        let msg = RpcMessage::new(u64::MAX, func, Message::PeerInfo(name), &[]);
+        let info = PeerInfo {
+            name,
+            formats: self.formats.clone(),
+            manifest: self.manifest.clone(),
+            imports: self.imports.clone(),
+        };
+        let msg = RpcMessage::new(u64::MAX, func, Message::PeerInfo(info), &[]);
 
         // 序列化
-        self.serialize_ctx.serialize(&msg).unwrap()
+        msg.encode(&SerializeCtx::new()).unwrap()
     }
 }
 
@@ -214,4 +472,332 @@ mod tests {
         // 检验结果
         assert_eq!(1, count.lock().unwrap().get());
     }
+
+    #[test]
+    fn test_call_no_such_function() {
+        // 调用方
+        let mut caller = RpcNode::new(SerializeCtx::new(), 0, ());
+        let result: Arc<Mutex<Cell<Option<RpcCallResult>>>> = Arc::new(Mutex::new(Cell::new(None)));
+        let inner_result = result.clone();
+        caller.set_result_cb(move |_, res| {
+            inner_result.lock().unwrap().set(Some(res));
+            Ok(())
+        });
+
+        // 被调用方，未导出 test 函数
+        let mut callee = RpcNode::new(SerializeCtx::new(), 1, ());
+        callee.set_exports(RpcExports::new(Host));
+        let reply: Arc<Mutex<Cell<Option<Vec<u8>>>>> = Arc::new(Mutex::new(Cell::new(None)));
+        let inner_reply = reply.clone();
+        callee.set_reply_cb(move |_, msg| {
+            inner_reply.lock().unwrap().set(Some(msg));
+            Ok(())
+        });
+
+        // 发送调用请求，被调用方应当回送错误结果
+        let msg = caller.request().make_request(abi::FunctionIdent::new("test"), vec![]).unwrap();
+        callee.handle_message(&msg).unwrap();
+        let reply = reply.lock().unwrap().take().unwrap();
+        caller.handle_message(&reply).unwrap();
+
+        // 检验结果
+        let error = result.lock().unwrap().take().unwrap().unwrap_err();
+        assert_eq!(RpcErrorCode::NoSuchFunction, error.code);
+    }
+
+    #[test]
+    fn test_cancel_request() {
+        // 调用方
+        let caller = RpcNode::new(SerializeCtx::new(), 0, ());
+
+        // 被调用方
+        let mut callee = RpcNode::new(SerializeCtx::new(), 1, ());
+        let cancelled: Arc<Mutex<Cell<Option<RpcSeqNo>>>> = Arc::new(Mutex::new(Cell::new(None)));
+        let inner_cancelled = cancelled.clone();
+        callee.set_cancel_cb(move |ctx, func, _| {
+            assert_eq!("test", func.name);
+            inner_cancelled.lock().unwrap().set(Some(ctx.seq_no()));
+            Ok(())
+        });
+
+        // 发送取消消息
+        let req = caller.request();
+        let msg = req.make_cancel(abi::FunctionIdent::new("test")).unwrap();
+        callee.handle_message(&msg).unwrap();
+
+        // 检验结果
+        assert_eq!(Some(req.seq_no()), cancelled.lock().unwrap().get());
+    }
+
+    #[test]
+    fn test_call_deadline_exceeded() {
+        // 调用方
+        let mut caller = RpcNode::new(SerializeCtx::new(), 0, ());
+        let result: Arc<Mutex<Cell<Option<RpcCallResult>>>> = Arc::new(Mutex::new(Cell::new(None)));
+        let inner_result = result.clone();
+        caller.set_result_cb(move |_, res| {
+            inner_result.lock().unwrap().set(Some(res));
+            Ok(())
+        });
+
+        // 被调用方，导出了 test 函数
+        let mut callee = RpcNode::new(SerializeCtx::new(), 1, ());
+        let func = abi::FunctionIdent::new("test");
+        let mut exports = RpcExports::new(func.hint.clone());
+        let called: Arc<Mutex<Cell<bool>>> = Arc::new(Mutex::new(Cell::new(false)));
+        let inner_called = called.clone();
+        exports.add_exports(func.clone(), Box::new(move |_: &'_ RpcResponseCtx<'_, _>, _: &'_ [u8]| {
+            inner_called.lock().unwrap().set(true);
+            Ok(())
+        }));
+        callee.set_exports(exports);
+        let reply: Arc<Mutex<Cell<Option<Vec<u8>>>>> = Arc::new(Mutex::new(Cell::new(None)));
+        let inner_reply = reply.clone();
+        callee.set_reply_cb(move |_, msg| {
+            inner_reply.lock().unwrap().set(Some(msg));
+            Ok(())
+        });
+
+        // 发送已经超时的调用请求，被调用方不应调用函数并回送错误结果
+        let deadline = crate::now_millis() - 1;
+        let msg = caller.request()
+            .make_request_with_deadline(func, vec![], Some(deadline)).unwrap();
+        callee.handle_message(&msg).unwrap();
+        let reply = reply.lock().unwrap().take().unwrap();
+        caller.handle_message(&reply).unwrap();
+
+        // 检验结果
+        assert!(!called.lock().unwrap().get());
+        let error = result.lock().unwrap().take().unwrap().unwrap_err();
+        assert_eq!(RpcErrorCode::DeadlineExceeded, error.code);
+    }
+
+    #[test]
+    fn test_negotiate_format() {
+        // 偏好 JSON 的 Host 与偏好 postcard 的 WASM 模块握手
+        let mut host = RpcNode::new(SerializeCtx::with_format(SerializeFormat::Json), 0, ());
+        let guest = RpcNode::new(SerializeCtx::with_format(SerializeFormat::Postcard), 1, ());
+        let reply: Arc<Mutex<Cell<Option<Vec<u8>>>>> = Arc::new(Mutex::new(Cell::new(None)));
+        let inner_reply = reply.clone();
+        host.set_reply_cb(move |_, msg| {
+            inner_reply.lock().unwrap().set(Some(msg));
+            Ok(())
+        });
+
+        // WASM 模块通告自身信息，Host 选取双方都支持的格式中自身最偏好的一个
+        host.handle_message(&guest.make_peer_info("guest".to_string())).unwrap();
+        assert_eq!(Some("guest".to_string()), host.get_peer_name());
+        assert_eq!(SerializeFormat::Json, host.serialize_ctx().format());
+
+        // WASM 模块收到协商结果后切换格式
+        let reply = reply.lock().unwrap().take().unwrap();
+        guest.handle_message(&reply).unwrap();
+        assert_eq!(SerializeFormat::Json, guest.serialize_ctx().format());
+    }
+
+    #[test]
+    fn test_peer_manifest() {
+        let host = RpcNode::new(SerializeCtx::new(), 0, ());
+        let mut guest = RpcNode::new(SerializeCtx::new(), 1, ());
+        let mut manifest = ModuleManifest::new("guest", "1.2.3".parse().unwrap());
+        manifest.exports.push("app".to_string());
+        manifest.add_import("service", "^1.0".parse().unwrap());
+        guest.set_manifest(manifest.clone());
+
+        // 模块清单随对端信息发送
+        host.handle_message(&guest.make_peer_info("guest".to_string())).unwrap();
+        assert_eq!(Some(manifest), host.get_peer_manifest());
+        assert_eq!(None, guest.get_peer_manifest());
+    }
+
+    /// 握手及调用测试中的一对节点，被调用方导出 Host 的 `allowed` 与 `secret` 函数
+    struct Peers {
+        caller: RpcNode<()>,
+        callee: RpcNode<()>,
+        /// 被调用方的导出函数被调用的次数
+        count: Arc<Mutex<Cell<i32>>>,
+        result: Arc<Mutex<Cell<Option<RpcCallResult>>>>,
+        reply: Arc<Mutex<Cell<Option<Vec<u8>>>>>,
+    }
+
+    impl Peers {
+        fn new() -> Self {
+            // 调用方
+            let mut caller = RpcNode::new(SerializeCtx::new(), 0, ());
+            let result: Arc<Mutex<Cell<Option<RpcCallResult>>>> = Arc::new(Mutex::new(Cell::new(None)));
+            let inner_result = result.clone();
+            caller.set_result_cb(move |_, res| {
+                inner_result.lock().unwrap().set(Some(res));
+                Ok(())
+            });
+
+            // 被调用方
+            let mut callee = RpcNode::new(SerializeCtx::new(), 1, ());
+            let mut exports = RpcExports::new(Host);
+            let count: Arc<Mutex<Cell<i32>>> = Arc::new(Mutex::new(Cell::new(0)));
+            for name in ["allowed", "secret"] {
+                let inner_count = count.clone();
+                exports.add_exports(ident(name), Box::new(move |_: &'_ RpcResponseCtx<'_, _>, _: &'_ [u8]| {
+                    let count = inner_count.lock().unwrap();
+                    count.set(count.get() + 1);
+                    Ok(())
+                }));
+            }
+            callee.set_exports(exports);
+            let reply: Arc<Mutex<Cell<Option<Vec<u8>>>>> = Arc::new(Mutex::new(Cell::new(None)));
+            let inner_reply = reply.clone();
+            callee.set_reply_cb(move |_, msg| {
+                inner_reply.lock().unwrap().set(Some(msg));
+                Ok(())
+            });
+
+            Peers { caller, callee, count, result, reply }
+        }
+
+        /// 调用方向被调用方发送对端信息，丢弃协商结果
+        fn handshake(&self) -> Result<()> {
+            let ret = self.callee.handle_message(&self.caller.make_peer_info("caller".to_string()));
+            self.reply.lock().unwrap().take();
+            ret
+        }
+
+        /// 调用被调用方的函数，返回被调用方回送的错误码
+        fn call(&self, name: &str) -> Option<RpcErrorCode> {
+            let msg = self.caller.request().make_request(ident(name), vec![]).unwrap();
+            self.callee.handle_message(&msg).unwrap();
+            let reply = self.reply.lock().unwrap().take()?;
+            self.caller.handle_message(&reply).unwrap();
+            let result = self.result.lock().unwrap().take().unwrap();
+            result.err().map(|error| error.code)
+        }
+
+        fn count(&self) -> i32 {
+            self.count.lock().unwrap().get()
+        }
+    }
+
+    fn ident(name: &str) -> abi::FunctionIdent {
+        let mut func = abi::FunctionIdent::new(name);
+        func.set_hint(Host);
+        func
+    }
+
+    fn allowed_imports() -> RpcImports {
+        let mut imports = RpcImports::new();
+        imports.add_imports(ident("allowed"));
+        imports
+    }
+
+    #[test]
+    fn test_undeclared_import() {
+        // 调用方声明只导入 Host 的 allowed 函数
+        let mut peers = Peers::new();
+        peers.caller.set_imports(allowed_imports());
+        peers.handshake().unwrap();
+        assert_eq!(Some(allowed_imports()), peers.callee.get_peer_imports());
+
+        // 已声明的函数正常调用，未声明的函数被拒绝
+        assert_eq!(None, peers.call("allowed"));
+        assert_eq!(Some(RpcErrorCode::UndeclaredImport), peers.call("secret"));
+        assert_eq!(1, peers.count());
+    }
+
+    #[test]
+    fn test_duplicate_peer_info() {
+        let mut peers = Peers::new();
+        peers.caller.set_imports(allowed_imports());
+        peers.handshake().unwrap();
+
+        // 不声明导入表的第二次握手被拒绝，已声明的导入表仍然生效
+        let mut other = RpcNode::new(SerializeCtx::new(), 2, ());
+        other.set_manifest(crate::manifest::ModuleManifest::new("other", "1.0.0".parse().unwrap()));
+        let ret = peers.callee.handle_message(&other.make_peer_info("other".to_string()));
+        assert!(matches!(ret, Err(Error::DuplicatePeerInfo)));
+        assert_eq!(Some("caller".to_string()), peers.callee.get_peer_name());
+        assert_eq!(None, peers.callee.get_peer_manifest());
+        assert_eq!(Some(allowed_imports()), peers.callee.get_peer_imports());
+        assert_eq!(Some(RpcErrorCode::UndeclaredImport), peers.call("secret"));
+        assert_eq!(0, peers.count());
+    }
+
+    #[test]
+    fn test_import_policy() {
+        // 未声明导入表时默认不作限制
+        let peers = Peers::new();
+        peers.handshake().unwrap();
+        assert_eq!(None, peers.call("secret"));
+
+        // 要求声明导入表时，未声明导入表的对端不能调用任何函数
+        let mut peers = Peers::new();
+        peers.callee.set_import_policy(ImportPolicy::Required);
+        peers.handshake().unwrap();
+        assert_eq!(Some(RpcErrorCode::UndeclaredImport), peers.call("allowed"));
+
+        // 使用被调用方提供的导入表，忽略对端声明的导入表
+        let mut peers = Peers::new();
+        let mut declared = allowed_imports();
+        declared.add_imports(ident("secret"));
+        peers.caller.set_imports(declared);
+        peers.callee.set_import_policy(ImportPolicy::Fixed(allowed_imports()));
+        peers.handshake().unwrap();
+        assert_eq!(Some(allowed_imports()), peers.callee.get_peer_imports());
+        assert_eq!(None, peers.call("allowed"));
+        assert_eq!(Some(RpcErrorCode::UndeclaredImport), peers.call("secret"));
+        assert_eq!(1, peers.count());
+    }
+
+    #[test]
+    fn test_permission_denied() {
+        // 只授权调用方调用 Host 的 allowed 函数
+        let mut peers = Peers::new();
+        let mut capabilities = Capabilities::allow_all();
+        capabilities.deny_module(Host);
+        capabilities.allow_function(&ident("allowed"));
+        peers.callee.set_capabilities(capabilities);
+
+        // 被授权的函数正常调用，其余函数不被调用并回送权限错误
+        assert_eq!(None, peers.call("allowed"));
+        assert_eq!(Some(RpcErrorCode::PermissionDenied), peers.call("secret"));
+        assert_eq!(1, peers.count());
+    }
+
+    #[test]
+    fn test_call_with_format() {
+        for format in SerializeFormat::supported() {
+            // 调用方
+            let mut caller = RpcNode::new(SerializeCtx::with_format(format), 0, ());
+            let result = Arc::new(Mutex::new(Cell::new(None)));
+            let inner_result = result.clone();
+            caller.set_result_cb(move |ctx, res| {
+                inner_result.lock().unwrap().set(Some((ctx.serialize_ctx().format(), res)));
+                Ok(())
+            });
+
+            // 被调用方使用默认格式，但应当以调用请求的格式解析参数并回送结果
+            let mut callee = RpcNode::new(SerializeCtx::new(), 1, ());
+            let func = abi::FunctionIdent::new("test");
+            let mut exports = RpcExports::new(func.hint.clone());
+            let reply: Arc<Mutex<Cell<Option<Vec<u8>>>>> = Arc::new(Mutex::new(Cell::new(None)));
+            let inner_reply = reply.clone();
+            exports.add_exports(func.clone(), Box::new(move |ctx: &'_ RpcResponseCtx<'_, _>, args: &'_ [u8]| {
+                let value: String = ctx.serialize_ctx().deserialize(args)?;
+                let result = ctx.serialize_ctx().serialize(&value.len())?;
+                inner_reply.lock().unwrap().set(Some(ctx.make_response(abi::FunctionIdent::new("test"), result)?));
+                Ok(())
+            }));
+            callee.set_exports(exports);
+
+            let req = caller.request();
+            let args = req.serialize_ctx().serialize("hello world").unwrap();
+            callee.handle_message(&req.make_request(func, args).unwrap()).unwrap();
+            let reply = reply.lock().unwrap().take().unwrap();
+            caller.handle_message(&reply).unwrap();
+
+            // 检验结果
+            let (actual_format, res) = result.lock().unwrap().take().unwrap();
+            assert_eq!(format, actual_format);
+            let len: usize = SerializeCtx::with_format(format).deserialize(&res.unwrap()).unwrap();
+            assert_eq!(11, len);
+        }
+    }
 }
@@ -2,7 +2,7 @@
 
 use once_cell::sync::OnceCell;
 
-use rpc::{abi, Result, RpcEndCtx, RpcNode};
+use rpc::{abi, Result, RpcCallResult, RpcEndCtx, RpcNode};
 use rpc::adapter::SendMessageAdapter;
 use serialize::{ArgsBuilder, SerializeCtx};
 
@@ -37,8 +37,9 @@ fn wasm_export_to_host(ctx: &MockHostContext, param: String) {
     // 之后。
 }
 
-fn wasm_export_to_host_return<T>(ret: &RpcEndCtx<T>, data: Vec<u8>) -> Result<()> {
+fn wasm_export_to_host_return<T>(ret: &RpcEndCtx<T>, data: RpcCallResult) -> Result<()> {
     // 解析参数
+    let data = data?;
     let result = ret.serialize_ctx().deserialize::<String>(&data).unwrap();
     // 返回结果
     println!("收到 Wasm 的返回值：{}", result);
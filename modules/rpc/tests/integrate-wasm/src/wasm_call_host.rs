@@ -1,6 +1,6 @@
 //! Wasm 调用函数、Host 导出函数的函数调用部分
 
-use rpc::{abi, Result, RpcEndCtx};
+use rpc::{abi, Result, RpcCallResult, RpcEndCtx};
 use rpc::adapter::SendMessageAdapter;
 use serialize::ArgsBuilder;
 
@@ -31,8 +31,9 @@ fn host_export_to_wasm(param: String) {
     // 之后。
 }
 
-pub fn host_export_to_wasm_return<T>(ret: &RpcEndCtx<T>, data: Vec<u8>) -> Result<()> {
+pub fn host_export_to_wasm_return<T>(ret: &RpcEndCtx<T>, data: RpcCallResult) -> Result<()> {
     // 解析参数
+    let data = data?;
     let result = ret.serialize_ctx().deserialize::<String>(&data).unwrap();
     // 返回结果
     println!("收到 Wasm 的返回值：{}", result);
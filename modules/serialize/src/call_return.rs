@@ -1,12 +1,21 @@
 //! 参数及其返回值等等与函数调用相关的序列化和反序列化
 
-use serde::{Deserialize, Serialize};
+use crate::{Error, HostcallValue, Result, SerializeCtx};
 
-use crate::{HostcallValue, Result, SerializeCtx};
+/// 参数个数及偏移量字段的长度
+const LEN_SIZE: usize = std::mem::size_of::<u32>();
 
-/// 用于构建可序列化的参数的数据结构，其内部应该维护一系列等待序列化的参数的引用
+/// 用于构建可序列化的参数的数据结构，其内部维护已经序列化的参数
 ///
-/// 生命期 `'a` 代表其中待序列化的参数的生命期。
+/// 各参数直接序列化到同一个缓冲区中，不再对参数集合整体进行二次序列化。构建结果的布局为：
+///
+/// ```text
+/// | 参数个数 n (u32) | 各参数的结束偏移量 (u32 * n) | 参数 0 | 参数 1 | ... |
+/// ```
+///
+/// 其中整数均为小端序，偏移量相对于参数数据的起始位置。
+///
+/// 生命期 `'a` 代表序列化上下文的生命期。
 ///
 /// ## 使用示例
 /// ```
@@ -21,7 +30,8 @@ use crate::{HostcallValue, Result, SerializeCtx};
 ///                         .build().unwrap();
 /// ```
 pub struct ArgsBuilder<'a> {
-    args: InnerArgs,
+    buffer: Vec<u8>,
+    ends: Vec<u32>,
     ctx: &'a SerializeCtx,
 }
 
@@ -29,32 +39,51 @@ impl<'a> ArgsBuilder<'a> {
     pub fn new(ctx: &'a SerializeCtx) -> Self {
         ArgsBuilder {
             ctx,
-            args: InnerArgs {
-                arg_buffers: Vec::new(),
-            },
+            buffer: Vec::new(),
+            ends: Vec::new(),
         }
     }
 
     pub fn push<'b, T>(&mut self, value: &'b T) -> Result<&mut Self>
         where T: HostcallValue<'b>,
     {
-        self.args.arg_buffers.push(self.ctx.serialize(value)?.to_vec());
+        self.ctx.serialize_into(&mut self.buffer, value)?;
+        self.ends.push(self.buffer.len() as u32);
         Ok(self)
     }
 
+    /// 追加一个已按 `ctx` 的格式序列化的参数
+    fn push_serialized(&mut self, bytes: &[u8]) -> &mut Self {
+        self.buffer.extend_from_slice(bytes);
+        self.ends.push(self.buffer.len() as u32);
+        self
+    }
+
     pub fn build(&self) -> Result<Vec<u8>> {
-        self.ctx.serialize(&self.args)
+        let header_len = LEN_SIZE * (self.ends.len() + 1);
+        let mut bytes = Vec::with_capacity(header_len + self.buffer.len());
+        bytes.extend((self.ends.len() as u32).to_le_bytes());
+        for end in &self.ends {
+            bytes.extend(end.to_le_bytes());
+        }
+        bytes.extend(&self.buffer);
+        Ok(bytes)
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct InnerArgs {
-    arg_buffers: Vec<Vec<u8>>,
+/// 读取 `bytes` 中第 `index` 个 u32 字段
+fn read_len(bytes: &[u8], index: usize) -> Option<usize> {
+    let start = index * LEN_SIZE;
+    let field = bytes.get(start..start + LEN_SIZE)?;
+    Some(u32::from_le_bytes(field.try_into().unwrap()) as usize)
 }
 
 /// 用于描述已完成序列化的参数集合的数据结构，提供反序列化的 API
 ///
-/// 生命期 `'a` 代表反序列化所依赖的原始数据（如 Vec<u8> 等）的生命期。
+/// 创建时仅检查参数个数，各参数在调用 `get` 时才进行反序列化。
+///
+/// 生命期 `'a` 代表反序列化所依赖的原始数据（如 Vec<u8> 等）及序列化上下文的生命期。
+/// `get` 得到的 `&str`、`Str`、`Bytes` 等参数直接借用原始数据，在 `'a` 内有效。
 ///
 /// ## 使用示例
 /// ```
@@ -72,29 +101,74 @@ struct InnerArgs {
 /// args.get::<i32>(1).unwrap_err();
 /// ```
 pub struct Args<'a> {
-    inner_args: InnerArgs,
+    len: usize,
+    // 各参数的结束偏移量
+    ends: &'a [u8],
+    // 参数数据
+    data: &'a [u8],
     ctx: &'a SerializeCtx,
 }
 
 impl<'a> Args<'a> {
-    pub fn from_bytes(ctx: &'a SerializeCtx, bytes: &[u8]) -> Result<Self> {
+    pub fn from_bytes(ctx: &'a SerializeCtx, bytes: &'a [u8]) -> Result<Self> {
+        let len = read_len(bytes, 0).ok_or(Error::MalformedArgs)?;
+        let header_len = len.checked_add(1)
+            .and_then(|n| n.checked_mul(LEN_SIZE))
+            .filter(|header_len| *header_len <= bytes.len())
+            .ok_or(Error::MalformedArgs)?;
+
         Ok(Args {
-            inner_args: ctx.deserialize(bytes)?,
+            len,
+            ends: &bytes[LEN_SIZE..header_len],
+            data: &bytes[header_len..],
             ctx,
         })
     }
 
-    pub fn get<'b, T>(&'b self, index: usize) -> Result<T>
-        where T: HostcallValue<'b>,
+    /// 参数个数
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// 第 `index` 个参数序列化后的数据
+    fn arg_bytes(&self, index: usize) -> Result<&'a [u8]> {
+        if index >= self.len {
+            return Err(Error::ArgIndex(index));
+        }
+        let start = match index {
+            0 => 0,
+            _ => read_len(self.ends, index - 1).ok_or(Error::MalformedArgs)?,
+        };
+        let end = read_len(self.ends, index).ok_or(Error::MalformedArgs)?;
+        self.data.get(start..end).ok_or(Error::MalformedArgs)
+    }
+
+    pub fn get<T>(&self, index: usize) -> Result<T>
+        where T: HostcallValue<'a>,
     {
-        let args = &self.inner_args.arg_buffers;
-        let bytes = args.get(index).ok_or(format!("index {} out of range", index))?;
-        Ok(self.ctx.deserialize::<T>(bytes)?)
+        let bytes = self.arg_bytes(index)?;
+        self.ctx.deserialize::<T>(bytes)
+    }
+
+    /// 把各参数转码为 `to` 的格式，返回重新构建的参数数据
+    pub fn transcode(&self, to: &SerializeCtx) -> Result<Vec<u8>> {
+        let mut builder = ArgsBuilder::new(to);
+        for index in 0..self.len {
+            let bytes = self.ctx.transcode(to, self.arg_bytes(index)?)?;
+            builder.push_serialized(&bytes);
+        }
+        builder.build()
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::{Bytes, SerializeFormat, Str};
+
     use super::*;
 
     #[test]
@@ -112,4 +186,90 @@ mod tests {
         let actual = args.get::<i32>(1).unwrap();
         assert_eq!(arg2, actual);
     }
+
+    #[test]
+    fn test_arg_errors() {
+        let ctx = SerializeCtx::new();
+        let bytes = ArgsBuilder::new(&ctx)
+            .push(&"hello world".to_string()).unwrap()
+            .build().unwrap();
+        let args = Args::from_bytes(&ctx, &bytes).unwrap();
+        assert!(matches!(args.get::<i32>(0), Err(Error::Decode(_))));
+        assert!(matches!(args.get::<String>(1), Err(Error::ArgIndex(1))));
+    }
+
+    #[test]
+    fn test_arg_formats() {
+        for format in SerializeFormat::supported() {
+            let ctx = SerializeCtx::with_format(format);
+            let bytes = ArgsBuilder::new(&ctx)
+                .push(&"hello world".to_string()).unwrap()
+                .push(&vec![1u8; 1024]).unwrap()
+                .push(&()).unwrap()
+                .push(&-1i64).unwrap()
+                .build().unwrap();
+            let args = Args::from_bytes(&ctx, &bytes).unwrap();
+            assert_eq!(4, args.len());
+            // 可以按任意顺序解析参数
+            assert_eq!(-1i64, args.get::<i64>(3).unwrap());
+            assert_eq!("hello world", args.get::<String>(0).unwrap());
+            assert_eq!(vec![1u8; 1024], args.get::<Vec<u8>>(1).unwrap());
+            args.get::<()>(2).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_arg_borrowed() {
+        for format in SerializeFormat::supported() {
+            let ctx = SerializeCtx::with_format(format);
+            let bytes = ArgsBuilder::new(&ctx)
+                .push(&"hello world").unwrap()
+                .push(&Bytes::from(&[1, 2, 3][..])).unwrap()
+                .push(&"say \"hello\"").unwrap()
+                .build().unwrap();
+            // 借用的参数在 `Args` 被释放后仍然有效
+            let actual = {
+                let args = Args::from_bytes(&ctx, &bytes).unwrap();
+                args.get::<&str>(0).unwrap()
+            };
+            assert_eq!("hello world", actual);
+            assert!(bytes.as_ptr_range().contains(&actual.as_ptr()), "{:?}", format);
+
+            // JSON 无法借用字节串及含有转义字符的字符串，退化为副本
+            let args = Args::from_bytes(&ctx, &bytes).unwrap();
+            assert_eq!(&[1, 2, 3], &*args.get::<Bytes>(1).unwrap());
+            assert_eq!("say \"hello\"", &*args.get::<Str>(2).unwrap());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "json")]
+    fn test_arg_transcode() {
+        let msgpack = SerializeCtx::new();
+        let json = SerializeCtx::with_format(SerializeFormat::Json);
+        let bytes = ArgsBuilder::new(&msgpack)
+            .push(&"hello world".to_string()).unwrap()
+            .push(&vec![1u32, 2, 3]).unwrap()
+            .build().unwrap();
+
+        let transcoded = Args::from_bytes(&msgpack, &bytes).unwrap().transcode(&json).unwrap();
+        let args = Args::from_bytes(&json, &transcoded).unwrap();
+        assert_eq!(2, args.len());
+        assert_eq!("hello world", args.get::<String>(0).unwrap());
+        assert_eq!(vec![1u32, 2, 3], args.get::<Vec<u32>>(1).unwrap());
+    }
+
+    #[test]
+    fn test_malformed_args() {
+        let ctx = SerializeCtx::new();
+        assert!(matches!(Args::from_bytes(&ctx, &[1, 0]), Err(Error::MalformedArgs)));
+        // 声明了 2 个参数，但缺少偏移量
+        assert!(matches!(Args::from_bytes(&ctx, &[2, 0, 0, 0, 1, 0, 0, 0]), Err(Error::MalformedArgs)));
+        // 偏移量超出参数数据
+        let args = Args::from_bytes(&ctx, &[1, 0, 0, 0, 8, 0, 0, 0, 0]).unwrap();
+        assert!(matches!(args.get::<i32>(0), Err(Error::MalformedArgs)));
+
+        let bytes = ArgsBuilder::new(&ctx).build().unwrap();
+        assert!(Args::from_bytes(&ctx, &bytes).unwrap().is_empty());
+    }
 }
@@ -0,0 +1,112 @@
+//! 序列化模块的错误类型
+
+use std::fmt;
+
+use crate::SerializeFormat;
+
+/// 序列化与反序列化中可能发生的错误
+#[derive(Debug)]
+pub enum Error {
+    /// MessagePack 序列化失败
+    Encode(rmp_serde::encode::Error),
+    /// MessagePack 反序列化失败
+    Decode(rmp_serde::decode::Error),
+    /// bincode 序列化或反序列化失败
+    #[cfg(feature = "bincode")]
+    Bincode(bincode::Error),
+    /// postcard 序列化或反序列化失败
+    #[cfg(feature = "postcard")]
+    Postcard(postcard::Error),
+    /// JSON 序列化或反序列化失败
+    #[cfg(feature = "json")]
+    Json(serde_json::Error),
+    /// 当前编译配置不支持该序列化格式
+    Unsupported(SerializeFormat),
+    /// 无法识别的格式标记字节
+    UnknownFormat(u8),
+    /// 数据为空，缺少格式标记字节
+    MissingFormatTag,
+    /// 参数下标超出参数个数
+    ArgIndex(usize),
+    /// 参数数据的结构不完整
+    MalformedArgs,
+    /// 无法在两种格式之间转码，其中至少一种不是自描述格式
+    Untranscodable(SerializeFormat, SerializeFormat),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Encode(e) => write!(f, "serialize error: {}", e),
+            Error::Decode(e) => write!(f, "deserialize error: {}", e),
+            #[cfg(feature = "bincode")]
+            Error::Bincode(e) => write!(f, "bincode error: {}", e),
+            #[cfg(feature = "postcard")]
+            Error::Postcard(e) => write!(f, "postcard error: {}", e),
+            #[cfg(feature = "json")]
+            Error::Json(e) => write!(f, "json error: {}", e),
+            Error::Unsupported(format) => write!(f, "unsupported format {:?}", format),
+            Error::UnknownFormat(tag) => write!(f, "unknown format tag {}", tag),
+            Error::MissingFormatTag => write!(f, "missing format tag"),
+            Error::ArgIndex(index) => write!(f, "index {} out of range", index),
+            Error::MalformedArgs => write!(f, "malformed args"),
+            Error::Untranscodable(from, to) => write!(f, "cannot transcode from {:?} to {:?}", from, to),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Encode(e) => Some(e),
+            Error::Decode(e) => Some(e),
+            #[cfg(feature = "bincode")]
+            Error::Bincode(e) => Some(e),
+            #[cfg(feature = "postcard")]
+            Error::Postcard(e) => Some(e),
+            #[cfg(feature = "json")]
+            Error::Json(e) => Some(e),
+            Error::Unsupported(_)
+            | Error::UnknownFormat(_)
+            | Error::MissingFormatTag
+            | Error::ArgIndex(_)
+            | Error::MalformedArgs
+            | Error::Untranscodable(_, _) => None,
+        }
+    }
+}
+
+impl From<rmp_serde::encode::Error> for Error {
+    fn from(e: rmp_serde::encode::Error) -> Self {
+        Error::Encode(e)
+    }
+}
+
+impl From<rmp_serde::decode::Error> for Error {
+    fn from(e: rmp_serde::decode::Error) -> Self {
+        Error::Decode(e)
+    }
+}
+
+#[cfg(feature = "bincode")]
+impl From<bincode::Error> for Error {
+    fn from(e: bincode::Error) -> Self {
+        Error::Bincode(e)
+    }
+}
+
+#[cfg(feature = "postcard")]
+impl From<postcard::Error> for Error {
+    fn from(e: postcard::Error) -> Self {
+        Error::Postcard(e)
+    }
+}
+
+#[cfg(feature = "json")]
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Json(e)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,82 @@
+//! 可插拔的序列化格式
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Result};
+
+/// 序列化所使用的格式（后端）
+///
+/// 其中 MessagePack 总是可用，其余格式由同名的 feature 控制是否编译。
+/// 每种格式对应一个固定的标记字节，用于在报文中标明报文本身的格式。
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum SerializeFormat {
+    /// MessagePack，基于 `rmp_serde`
+    #[default]
+    MessagePack,
+    /// bincode，定长整数编码，适合数值密集的数据
+    Bincode,
+    /// postcard，变长整数编码，体积较小
+    Postcard,
+    /// JSON，便于调试，体积和性能较差
+    Json,
+}
+
+impl SerializeFormat {
+    /// 所有格式，按照默认的偏好顺序排列
+    pub const ALL: [SerializeFormat; 4] = [
+        SerializeFormat::MessagePack,
+        SerializeFormat::Bincode,
+        SerializeFormat::Postcard,
+        SerializeFormat::Json,
+    ];
+
+    /// 格式对应的标记字节
+    pub fn tag(self) -> u8 {
+        match self {
+            SerializeFormat::MessagePack => 0,
+            SerializeFormat::Bincode => 1,
+            SerializeFormat::Postcard => 2,
+            SerializeFormat::Json => 3,
+        }
+    }
+
+    /// 由标记字节解析格式
+    pub fn from_tag(tag: u8) -> Result<Self> {
+        match tag {
+            0 => Ok(SerializeFormat::MessagePack),
+            1 => Ok(SerializeFormat::Bincode),
+            2 => Ok(SerializeFormat::Postcard),
+            3 => Ok(SerializeFormat::Json),
+            tag => Err(Error::UnknownFormat(tag)),
+        }
+    }
+
+    /// 当前编译配置下是否支持该格式
+    pub fn is_supported(self) -> bool {
+        match self {
+            SerializeFormat::MessagePack => true,
+            SerializeFormat::Bincode => cfg!(feature = "bincode"),
+            SerializeFormat::Postcard => cfg!(feature = "postcard"),
+            SerializeFormat::Json => cfg!(feature = "json"),
+        }
+    }
+
+    /// 当前编译配置下支持的所有格式
+    pub fn supported() -> Vec<SerializeFormat> {
+        Self::ALL.into_iter().filter(|format| format.is_supported()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_tag() {
+        for format in SerializeFormat::ALL {
+            assert_eq!(format, SerializeFormat::from_tag(format.tag()).unwrap());
+        }
+        assert!(matches!(SerializeFormat::from_tag(0xff), Err(Error::UnknownFormat(0xff))));
+        assert_eq!(Some(&SerializeFormat::MessagePack), SerializeFormat::supported().first());
+    }
+}
@@ -1,20 +1,206 @@
 //! 进行基础序列化工作的系列定义
 
-use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::fmt;
+use std::ops::Deref;
 
-use crate::Result;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::{self, SeqAccess, Visitor};
+
+use crate::{Error, Result, SerializeFormat};
 
 /// 可序列化类型的标注 trait
+///
+/// 生命期 `'a` 为反序列化所依赖的原始数据的生命期，如 `&'a str` 可以直接借用原始数据而无需复制。
 pub trait HostcallValue<'a>: Serialize + Deserialize<'a> {}
 
 impl<'a, T> HostcallValue<'a> for T where T: Serialize + Deserialize<'a> {}
 
+/// 以字节串形式序列化的数据，反序列化时尽可能直接借用原始数据
+///
+/// `&[u8]` 本身会被序列化为 `u8` 的序列，在 MessagePack 等格式中无法以借用的方式反序列化。
+/// 使用 `Bytes` 则序列化为格式原生的字节串。JSON 没有字节串，数据以数字数组表示，反序列化时退化为副本。
+///
+/// ## 使用示例
+/// ```
+/// use serialize::{Bytes, SerializeCtx};
+///
+/// let ctx = SerializeCtx::new();
+/// let serialized = ctx.serialize(&Bytes::from(&[1, 2, 3][..])).unwrap();
+/// let actual = ctx.deserialize::<Bytes>(&serialized).unwrap();
+/// assert_eq!(&[1, 2, 3], &*actual);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Bytes<'a>(pub Cow<'a, [u8]>);
+
+impl<'a> Deref for Bytes<'a> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl<'a> From<&'a [u8]> for Bytes<'a> {
+    fn from(bytes: &'a [u8]) -> Self {
+        Bytes(Cow::Borrowed(bytes))
+    }
+}
+
+impl From<Vec<u8>> for Bytes<'_> {
+    fn from(bytes: Vec<u8>) -> Self {
+        Bytes(Cow::Owned(bytes))
+    }
+}
+
+impl Serialize for Bytes<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for Bytes<'a> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        struct BytesVisitor;
+
+        impl<'de> Visitor<'de> for BytesVisitor {
+            type Value = Bytes<'de>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a byte array")
+            }
+
+            fn visit_borrowed_bytes<E: de::Error>(self, v: &'de [u8]) -> std::result::Result<Self::Value, E> {
+                Ok(Bytes(Cow::Borrowed(v)))
+            }
+
+            fn visit_borrowed_str<E: de::Error>(self, v: &'de str) -> std::result::Result<Self::Value, E> {
+                Ok(Bytes(Cow::Borrowed(v.as_bytes())))
+            }
+
+            fn visit_bytes<E: de::Error>(self, v: &[u8]) -> std::result::Result<Self::Value, E> {
+                Ok(Bytes(Cow::Owned(v.to_vec())))
+            }
+
+            fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> std::result::Result<Self::Value, E> {
+                Ok(Bytes(Cow::Owned(v)))
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> std::result::Result<Self::Value, A::Error> {
+                // JSON 等没有字节串的格式
+                let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+                while let Some(byte) = seq.next_element()? {
+                    bytes.push(byte);
+                }
+                Ok(Bytes(Cow::Owned(bytes)))
+            }
+        }
+
+        deserializer.deserialize_bytes(BytesVisitor)
+    }
+}
+
+/// 反序列化时尽可能直接借用原始数据的字符串
+///
+/// `&str` 只能借用原始数据，JSON 中含有转义字符的字符串无法借用，反序列化 `&str` 会失败。
+/// 使用 `Str` 则在无法借用时退化为副本。
+///
+/// ## 使用示例
+/// ```
+/// use serialize::{SerializeCtx, SerializeFormat, Str};
+///
+/// let ctx = SerializeCtx::with_format(SerializeFormat::Json);
+/// let serialized = ctx.serialize("say \"hello\"").unwrap();
+/// let actual = ctx.deserialize::<Str>(&serialized).unwrap();
+/// assert_eq!("say \"hello\"", &*actual);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Str<'a>(pub Cow<'a, str>);
+
+impl<'a> Deref for Str<'a> {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl<'a> From<&'a str> for Str<'a> {
+    fn from(s: &'a str) -> Self {
+        Str(Cow::Borrowed(s))
+    }
+}
+
+impl From<String> for Str<'_> {
+    fn from(s: String) -> Self {
+        Str(Cow::Owned(s))
+    }
+}
+
+impl Serialize for Str<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for Str<'a> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        struct StrVisitor;
+
+        impl<'de> Visitor<'de> for StrVisitor {
+            type Value = Str<'de>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a string")
+            }
+
+            fn visit_borrowed_str<E: de::Error>(self, v: &'de str) -> std::result::Result<Self::Value, E> {
+                Ok(Str(Cow::Borrowed(v)))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> std::result::Result<Self::Value, E> {
+                Ok(Str(Cow::Owned(v.to_string())))
+            }
+
+            fn visit_string<E: de::Error>(self, v: String) -> std::result::Result<Self::Value, E> {
+                Ok(Str(Cow::Owned(v)))
+            }
+        }
+
+        deserializer.deserialize_str(StrVisitor)
+    }
+}
+
 /// 对序列化所需的内部数据结构进行封装
-pub struct SerializeCtx;
+///
+/// 序列化格式在构建时选定，默认为 MessagePack。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SerializeCtx {
+    format: SerializeFormat,
+}
 
 impl SerializeCtx {
     pub fn new() -> Self {
-        SerializeCtx
+        Self::with_format(SerializeFormat::default())
+    }
+
+    /// 使用指定的序列化格式
+    ///
+    /// ## 使用示例
+    /// ```
+    /// use serialize::{SerializeCtx, SerializeFormat};
+    ///
+    /// let ctx = SerializeCtx::with_format(SerializeFormat::Postcard);
+    /// let serialized = ctx.serialize(&123u32).unwrap();
+    /// assert_eq!(123u32, ctx.deserialize::<u32>(&serialized).unwrap());
+    /// ```
+    pub fn with_format(format: SerializeFormat) -> Self {
+        SerializeCtx { format }
+    }
+
+    /// 序列化所使用的格式
+    pub fn format(&self) -> SerializeFormat {
+        self.format
     }
 
     /// 对所给类型的数据进行序列化
@@ -32,13 +218,51 @@ impl SerializeCtx {
     pub fn serialize<T>(&self, value: &T) -> Result<Vec<u8>>
         where T: Serialize + ?Sized,
     {
-        Ok(rmp_serde::to_vec(value)?)
+        match self.format {
+            SerializeFormat::MessagePack => Ok(rmp_serde::to_vec(value)?),
+            #[cfg(feature = "bincode")]
+            SerializeFormat::Bincode => Ok(bincode::serialize(value)?),
+            #[cfg(feature = "postcard")]
+            SerializeFormat::Postcard => Ok(postcard::to_allocvec(value)?),
+            #[cfg(feature = "json")]
+            SerializeFormat::Json => Ok(serde_json::to_vec(value)?),
+            #[allow(unreachable_patterns)]
+            format => Err(Error::Unsupported(format)),
+        }
+    }
+
+    /// 把所给类型的数据序列化并追加到 `buffer` 末尾，避免产生中间缓冲区
+    ///
+    /// ## 使用示例
+    /// ```
+    /// use serialize::SerializeCtx;
+    ///
+    /// let ctx = SerializeCtx::new();
+    /// let mut buffer = vec![0xff];
+    /// ctx.serialize_into(&mut buffer, "hello world").unwrap();
+    /// assert_eq!("hello world", ctx.deserialize::<String>(&buffer[1..]).unwrap());
+    /// ```
+    pub fn serialize_into<T>(&self, buffer: &mut Vec<u8>, value: &T) -> Result<()>
+        where T: Serialize + ?Sized,
+    {
+        match self.format {
+            SerializeFormat::MessagePack => Ok(rmp_serde::encode::write(buffer, value)?),
+            #[cfg(feature = "bincode")]
+            SerializeFormat::Bincode => Ok(bincode::serialize_into(buffer, value)?),
+            #[cfg(feature = "postcard")]
+            SerializeFormat::Postcard => postcard::to_extend(value, BufferExtend(buffer)).map(|_| ()).map_err(Error::from),
+            #[cfg(feature = "json")]
+            SerializeFormat::Json => Ok(serde_json::to_writer(buffer, value)?),
+            #[allow(unreachable_patterns)]
+            format => Err(Error::Unsupported(format)),
+        }
     }
 
     /// 对所给的二进制数据进行反序列化
     ///
     /// 其中，函数返回值并不对返回反序列化后数据的所有权做出保障（考虑到可以通过如 Zero-Copy
-    /// 等手段进行优化），因此仅保证能返回序列化后的数据的引用。
+    /// 等手段进行优化），因此仅保证能返回序列化后的数据的引用。如 `&str`、`Bytes` 等类型
+    /// 直接借用 `bytes` 中的数据。格式无法借用时 `&str` 反序列化失败，`Str`、`Bytes` 则退化为副本。
     ///
     /// ## 使用示例
     /// ```
@@ -55,7 +279,61 @@ impl SerializeCtx {
     pub fn deserialize<'a, 'b, T>(&'b self, bytes: &'a [u8]) -> Result<T>
         where T: Deserialize<'a>,
     {
-        Ok(rmp_serde::from_slice(bytes)?)
+        match self.format {
+            SerializeFormat::MessagePack => Ok(rmp_serde::from_slice(bytes)?),
+            #[cfg(feature = "bincode")]
+            SerializeFormat::Bincode => Ok(bincode::deserialize(bytes)?),
+            #[cfg(feature = "postcard")]
+            SerializeFormat::Postcard => Ok(postcard::from_bytes(bytes)?),
+            #[cfg(feature = "json")]
+            SerializeFormat::Json => Ok(serde_json::from_slice(bytes)?),
+            #[allow(unreachable_patterns)]
+            format => Err(Error::Unsupported(format)),
+        }
+    }
+
+    /// 序列化数据，并在开头附加一个标明格式的标记字节
+    ///
+    /// 用于在格式尚未协商一致时，使对端能够自行判断报文的格式。
+    pub fn serialize_tagged<T>(&self, value: &T) -> Result<Vec<u8>>
+        where T: Serialize + ?Sized,
+    {
+        let mut bytes = vec![self.format.tag()];
+        bytes.extend(self.serialize(value)?);
+        Ok(bytes)
+    }
+
+    /// 反序列化由 `serialize_tagged` 生成的数据，同时返回数据所使用格式的上下文
+    ///
+    /// ## 使用示例
+    /// ```
+    /// use serialize::{SerializeCtx, SerializeFormat};
+    ///
+    /// let ctx = SerializeCtx::with_format(SerializeFormat::Json);
+    /// let serialized = ctx.serialize_tagged("hello world").unwrap();
+    ///
+    /// let (actual_ctx, actual) = SerializeCtx::deserialize_tagged::<String>(&serialized).unwrap();
+    /// assert_eq!(ctx, actual_ctx);
+    /// assert_eq!("hello world", actual);
+    /// ```
+    pub fn deserialize_tagged<'a, T>(bytes: &'a [u8]) -> Result<(SerializeCtx, T)>
+        where T: Deserialize<'a>,
+    {
+        let (tag, body) = bytes.split_first().ok_or(Error::MissingFormatTag)?;
+        let ctx = SerializeCtx::with_format(SerializeFormat::from_tag(*tag)?);
+        let value = ctx.deserialize(body)?;
+        Ok((ctx, value))
+    }
+}
+
+/// 以可变引用向缓冲区追加数据，供 postcard 使用
+#[cfg(feature = "postcard")]
+struct BufferExtend<'a>(&'a mut Vec<u8>);
+
+#[cfg(feature = "postcard")]
+impl Extend<u8> for BufferExtend<'_> {
+    fn extend<I: IntoIterator<Item=u8>>(&mut self, iter: I) {
+        self.0.extend(iter)
     }
 }
 
@@ -82,4 +360,51 @@ mod tests {
         let actual: String = ctx.deserialize::<String>(bytes).unwrap();
         assert_eq!(expected, actual);
     }
+
+    #[test]
+    fn test_formats() {
+        let expected = (String::from("hello world"), 123i32, vec![1u8, 2, 3]);
+        for format in SerializeFormat::supported() {
+            let ctx = SerializeCtx::with_format(format);
+            let serialized = ctx.serialize(&expected).unwrap();
+            let actual: (String, i32, Vec<u8>) = ctx.deserialize(&serialized).unwrap();
+            assert_eq!(expected, actual, "{:?}", format);
+
+            let tagged = ctx.serialize_tagged(&expected).unwrap();
+            let (actual_ctx, actual) = SerializeCtx::deserialize_tagged::<(String, i32, Vec<u8>)>(&tagged).unwrap();
+            assert_eq!(ctx, actual_ctx);
+            assert_eq!(expected, actual);
+        }
+    }
+
+    #[test]
+    fn test_deserialize_borrowed() {
+        for format in SerializeFormat::supported() {
+            let ctx = SerializeCtx::with_format(format);
+            let serialized = ctx.serialize("hello world").unwrap();
+            let actual = ctx.deserialize::<&str>(&serialized).unwrap();
+            assert_eq!("hello world", actual);
+            // 借用原始数据，而非复制
+            assert!(serialized.as_ptr_range().contains(&actual.as_ptr()), "{:?}", format);
+            let actual = ctx.deserialize::<Str>(&serialized).unwrap();
+            assert!(matches!(actual.0, Cow::Borrowed("hello world")), "{:?}", format);
+
+            // JSON 中含有转义字符的字符串以及字节串无法借用，退化为副本
+            let serialized = ctx.serialize("say \"hello\"").unwrap();
+            let actual = ctx.deserialize::<Str>(&serialized).unwrap();
+            assert_eq!("say \"hello\"", &*actual);
+            assert_eq!(format == SerializeFormat::Json, ctx.deserialize::<&str>(&serialized).is_err());
+
+            let serialized = ctx.serialize(&Bytes::from(&[1, 2, 3][..])).unwrap();
+            let actual = ctx.deserialize::<Bytes>(&serialized).unwrap();
+            assert_eq!(&[1, 2, 3], &*actual);
+            assert_eq!(format != SerializeFormat::Json, matches!(actual.0, Cow::Borrowed(_)), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn test_tagged_errors() {
+        assert!(matches!(SerializeCtx::deserialize_tagged::<i32>(&[]), Err(Error::MissingFormatTag)));
+        assert!(matches!(SerializeCtx::deserialize_tagged::<i32>(&[0xff, 0]), Err(Error::UnknownFormat(0xff))));
+    }
 }
@@ -1,13 +1,12 @@
 //! Hostcall 中涉及序列化和反序列化的接口
 
 pub use call_return::*;
+pub use error::*;
+pub use format::*;
 pub use general::*;
 
 mod general;
 mod call_return;
-
-// FIXME: 此处的错误类型仅仅是最简单，可用于容纳任何错误的类型。而实际上好的错误类型
-//        应该囊括更加细节的错误信息。此处仅为适应短时间的开发需求而临时设计。
-pub type Error = Box<dyn std::error::Error + Send + Sync>;
-
-pub type Result<T> = std::result::Result<T, Error>;
+mod error;
+mod format;
+mod transcode;
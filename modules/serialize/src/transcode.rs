@@ -0,0 +1,224 @@
+//! 自描述格式之间的转码，用于在使用不同格式的模块之间转发参数与返回值
+//!
+//! 转码不需要知道数据的类型，因此只支持无需类型即可解析的格式（MessagePack、JSON）。
+
+use std::fmt;
+
+use serde::de::{Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{Serialize, SerializeMap, SerializeSeq, Serializer};
+
+use crate::{Error, Result, SerializeCtx, SerializeFormat};
+
+/// 转码时的中间表示
+enum Value {
+    Unit,
+    Bool(bool),
+    I64(i64),
+    U64(u64),
+    F64(f64),
+    String(String),
+    Bytes(Vec<u8>),
+    Seq(Vec<Value>),
+    Map(Vec<(Value, Value)>),
+}
+
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        match self {
+            Value::Unit => serializer.serialize_unit(),
+            Value::Bool(v) => serializer.serialize_bool(*v),
+            Value::I64(v) => serializer.serialize_i64(*v),
+            Value::U64(v) => serializer.serialize_u64(*v),
+            Value::F64(v) => serializer.serialize_f64(*v),
+            Value::String(v) => serializer.serialize_str(v),
+            Value::Bytes(v) => serializer.serialize_bytes(v),
+            Value::Seq(items) => {
+                let mut seq = serializer.serialize_seq(Some(items.len()))?;
+                for item in items {
+                    seq.serialize_element(item)?;
+                }
+                seq.end()
+            }
+            Value::Map(entries) => {
+                let mut map = serializer.serialize_map(Some(entries.len()))?;
+                for (key, value) in entries {
+                    map.serialize_entry(key, value)?;
+                }
+                map.end()
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("any self-describing value")
+    }
+
+    fn visit_bool<E>(self, v: bool) -> std::result::Result<Value, E> {
+        Ok(Value::Bool(v))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> std::result::Result<Value, E> {
+        Ok(Value::I64(v))
+    }
+
+    fn visit_u64<E>(self, v: u64) -> std::result::Result<Value, E> {
+        Ok(Value::U64(v))
+    }
+
+    fn visit_f64<E>(self, v: f64) -> std::result::Result<Value, E> {
+        Ok(Value::F64(v))
+    }
+
+    fn visit_str<E>(self, v: &str) -> std::result::Result<Value, E> {
+        Ok(Value::String(v.to_string()))
+    }
+
+    fn visit_string<E>(self, v: String) -> std::result::Result<Value, E> {
+        Ok(Value::String(v))
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> std::result::Result<Value, E> {
+        Ok(Value::Bytes(v.to_vec()))
+    }
+
+    fn visit_byte_buf<E>(self, v: Vec<u8>) -> std::result::Result<Value, E> {
+        Ok(Value::Bytes(v))
+    }
+
+    fn visit_none<E>(self) -> std::result::Result<Value, E> {
+        Ok(Value::Unit)
+    }
+
+    fn visit_unit<E>(self) -> std::result::Result<Value, E> {
+        Ok(Value::Unit)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> std::result::Result<Value, D::Error> {
+        Value::deserialize(deserializer)
+    }
+
+    fn visit_newtype_struct<D: Deserializer<'de>>(self, deserializer: D) -> std::result::Result<Value, D::Error> {
+        Value::deserialize(deserializer)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> std::result::Result<Value, A::Error> {
+        let mut items = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(item) = seq.next_element()? {
+            items.push(item);
+        }
+        Ok(Value::Seq(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> std::result::Result<Value, A::Error> {
+        let mut entries = Vec::with_capacity(map.size_hint().unwrap_or(0));
+        while let Some(entry) = map.next_entry()? {
+            entries.push(entry);
+        }
+        Ok(Value::Map(entries))
+    }
+}
+
+impl SerializeFormat {
+    /// 格式是否自描述，即无需知道数据的类型即可解析
+    pub fn is_self_describing(self) -> bool {
+        matches!(self, SerializeFormat::MessagePack | SerializeFormat::Json)
+    }
+}
+
+impl SerializeCtx {
+    /// 把以本格式序列化的单个值转码为 `to` 的格式
+    ///
+    /// 格式相同时原样复制；任一格式不是自描述格式时返回 `Error::Untranscodable`。
+    ///
+    /// ## 使用示例
+    /// ```
+    /// use serialize::{SerializeCtx, SerializeFormat};
+    ///
+    /// let msgpack = SerializeCtx::new();
+    /// let json = SerializeCtx::with_format(SerializeFormat::Json);
+    /// let bytes = msgpack.serialize(&(1u32, "hello")).unwrap();
+    ///
+    /// let transcoded = msgpack.transcode(&json, &bytes).unwrap();
+    /// assert_eq!((1u32, "hello".to_string()), json.deserialize(&transcoded).unwrap());
+    /// ```
+    pub fn transcode(&self, to: &SerializeCtx, bytes: &[u8]) -> Result<Vec<u8>> {
+        if self.format() == to.format() {
+            return Ok(bytes.to_vec());
+        }
+        if !self.format().is_self_describing() || !to.format().is_self_describing() {
+            return Err(Error::Untranscodable(self.format(), to.format()));
+        }
+
+        let value: Value = self.deserialize(bytes)?;
+        to.serialize(&value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Sample {
+        id: u32,
+        name: String,
+        tags: Vec<String>,
+        parent: Option<Box<Sample>>,
+        attrs: HashMap<String, i64>,
+    }
+
+    #[test]
+    #[cfg(feature = "json")]
+    fn test_transcode() {
+        let msgpack = SerializeCtx::new();
+        let json = SerializeCtx::with_format(SerializeFormat::Json);
+        let sample = Sample {
+            id: 1,
+            name: "root".to_string(),
+            tags: vec!["a".to_string(), "b".to_string()],
+            parent: Some(Box::new(Sample {
+                id: 0,
+                name: "parent".to_string(),
+                tags: vec![],
+                parent: None,
+                attrs: HashMap::new(),
+            })),
+            attrs: HashMap::from([("depth".to_string(), -1)]),
+        };
+
+        // 往返转码后与原值相同
+        let bytes = msgpack.serialize(&sample).unwrap();
+        let transcoded = msgpack.transcode(&json, &bytes).unwrap();
+        assert_eq!(sample, json.deserialize::<Sample>(&transcoded).unwrap());
+        let transcoded = json.transcode(&msgpack, &transcoded).unwrap();
+        assert_eq!(sample, msgpack.deserialize::<Sample>(&transcoded).unwrap());
+    }
+
+    #[test]
+    #[cfg(feature = "postcard")]
+    fn test_untranscodable() {
+        let msgpack = SerializeCtx::new();
+        let postcard = SerializeCtx::with_format(SerializeFormat::Postcard);
+        let bytes = msgpack.serialize(&1u32).unwrap();
+
+        assert!(matches!(msgpack.transcode(&postcard, &bytes),
+                         Err(Error::Untranscodable(SerializeFormat::MessagePack, SerializeFormat::Postcard))));
+        assert_eq!(bytes, msgpack.transcode(&msgpack, &bytes).unwrap());
+    }
+}
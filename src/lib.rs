@@ -4,6 +4,7 @@ pub use async_rt;
 #[cfg(target_arch = "wasm32")]
 pub use async_rt::bc_wasm_module;
 pub use low_level;
+pub use macros::{bc_export, bc_export_module, bc_import, bc_import_module};
 #[cfg(target_arch = "wasm32")]
 pub use low_level::set_message_callback;
 #[cfg(not(target_arch = "wasm32"))]
@@ -1,13 +1,18 @@
 use std::io::Write;
 use std::sync::Arc;
+use std::time::Duration;
 
 use tokio::task;
 
+use bc_hostcall::module_api::cache::ModuleCache;
 use bc_hostcall::module_api::manager::ModuleManager;
 use bc_hostcall::module_api::module::WasmModule;
-use bc_hostcall::rpc::abi;
+use bc_hostcall::module_api::wasmtime::Engine;
+use bc_hostcall::rpc::{abi, ImportPolicy};
+use bc_hostcall::rpc::capability::Capabilities;
+use bc_hostcall::rpc::manifest::VersionReq;
 
-use crate::exports::init_exports;
+use crate::exports::__bc_module_export;
 use crate::imports::app;
 
 mod imports;
@@ -15,60 +20,136 @@ mod exports;
 
 fn usage() {
     println!("bc-hostcall CLI Demo");
-    println!("load <*.wasm>            加载/重载 Bc Module");
+    println!("load <*.wasm|*.cwasm> [func ...]");
+    println!("                         加载/重载 Bc Module，不同版本的模块同时存在；");
+    println!("                         指定函数时仅授权模块调用这些 Host 函数");
+    println!("reload <name> <*.wasm>   热重载模块并迁移其状态");
     println!("list                     列出已加载模块");
     println!("call_app <name> <param>  调用模块导出函数 `app`");
     println!("unload <name>            卸载模块");
     println!("help                     显示此信息");
     println!("exit                     退出");
     println!();
+    println!("<name> 可写作 name@版本要求（如 service@^0.1）以指定模块版本，默认使用最高版本");
+    println!();
 }
 
+/// 卸载模块时等待已接受的请求返回结果的时间
+const UNLOAD_TIMEOUT: Duration = Duration::from_secs(5);
+
 pub type Error = Box<dyn std::error::Error + Send + Sync>;
 pub type Result<T> = std::result::Result<T, Error>;
 
 struct CliContext {
     modules: Arc<ModuleManager>,
+    cache: ModuleCache,
+}
+
+/// 解析 `name` 或 `name@版本要求` 形式的模块名
+fn parse_hint(name: &str) -> Result<abi::LinkHint> {
+    match name.split_once('@') {
+        Some((name, req)) => Ok(abi::LinkHint::BcModuleVersion(name.to_string(), req.parse::<VersionReq>()?)),
+        None => Ok(abi::LinkHint::BcModule(name.to_string())),
+    }
 }
 
 /// 加载/重载 Bc Module
-async fn command_load(ctx: &mut CliContext, path: &str) -> Result<()> {
+async fn command_load(ctx: &mut CliContext, path: &str, grants: &[&str]) -> Result<()> {
     let mut module = WasmModule::new();
+    // 模块需要声明其导入表
+    module.set_import_policy(ImportPolicy::Required);
 
-    // 初始化模块
-    module.init(path, init_exports())?;
+    // 仅授权调用指定的 Host 函数，其他模块导出的函数不受限制
+    if !grants.is_empty() {
+        let mut capabilities = Capabilities::allow_all();
+        capabilities.deny_module(abi::LinkHint::Host);
+        for name in grants {
+            capabilities.allow_function(&abi::FunctionIdent::new(name));
+        }
+        module.set_capabilities(capabilities);
+    }
+
+    // 初始化模块，未修改的模块重载时复用缓存的编译结果
+    if path.ends_with(".cwasm") {
+        // SAFETY: CLI 仅用于加载用户自己预编译的模块
+        unsafe { module.init_precompiled(path, __bc_module_export())? };
+    } else {
+        let compiled = ctx.cache.load_file(path)?;
+        module.init_module(&compiled, __bc_module_export())?;
+    }
     println!("[Host] 初始化模块：{}", module.get_name());
 
+    // 启动前审查模块声明的导入表
+    match module.imports() {
+        Some(imports) => {
+            for func in imports.functions() {
+                println!("[Host] 模块导入：{} {:?}", func.name, func.hint);
+            }
+        }
+        None => println!("[Host] 模块未声明导入表，拒绝其所有调用"),
+    }
+
     // 启动模块
     module.start().await;
     println!("[Host] 成功启动模块：{}", module.get_name());
 
-    // 添加到模块管理器
+    // 导入的模块尚未加载时，暂缓注册直至其依赖的模块被加载
     let module = Arc::new(module);
-    let swap_out =
-        ctx.modules.register(module.get_hint(), module.clone());
-    module.attach_to_manager(ctx.modules.clone());
-
-    // 如果发现老模块，则卸载
-    if let Some(old_module) = swap_out {
-        println!("[Host] 卸载已存在的旧模块：{}", old_module.get_name());
-        old_module.kill();
+    module.clone().attach_to_manager(ctx.modules.clone());
+    let unsatisfied = ctx.modules.unsatisfied_imports(&module);
+    if !unsatisfied.is_empty() {
+        for dep in unsatisfied {
+            println!("[Host] 等待依赖的模块：{} {}", dep.name, dep.req);
+        }
+        ctx.modules.defer(module.versioned_hint(), module.clone());
+        return Ok(());
+    }
+
+    // 添加到模块管理器，如果发现同一版本的老模块，则在其处理完已接受的请求后卸载。其他版本继续运行
+    if ctx.modules.replace(module.versioned_hint(), module.clone(), UNLOAD_TIMEOUT).await? {
+        println!("[Host] 卸载已存在的同版本旧模块：{}", module.get_name());
+    }
+    if let Some(version) = module.version() {
+        println!("[Host] 模块版本：{}", version);
     }
 
     Ok(())
 }
 
+/// 热重载模块，新模块在初始化时导入旧模块导出的状态
+async fn command_reload(ctx: &mut CliContext, name: &str, path: &str) -> Result<()> {
+    let hint = parse_hint(name)?;
+    let compiled = ctx.cache.load_file(path)?;
+
+    // 新模块初始化或启动失败时，旧模块继续运行
+    let module = ctx.modules.reload(hint, |state| {
+        let mut module = WasmModule::new();
+        if let Some(state) = state {
+            println!("[Host] 迁移模块状态：{} 字节", state.len());
+            module.set_initial_state(state);
+        }
+        module.init_module(&compiled, __bc_module_export())?;
+        Ok(module)
+    }, UNLOAD_TIMEOUT).await?;
+    println!("[Host] 成功重载模块：{}", module.get_name());
+
+    Ok(())
+}
+
 /// 列出已加载模块
 async fn command_list(ctx: &mut CliContext) -> Result<()> {
     for hint in ctx.modules.list_modules() {
         println!("- {:?}", hint);
     }
+    for hint in ctx.modules.list_deferred() {
+        println!("- {:?}（等待依赖）", hint);
+    }
     Ok(())
 }
 
 /// 调用模块导出函数 `app`
 async fn command_call_app(ctx: &mut CliContext, name: &str, param: String) -> Result<()> {
-    let hint = abi::LinkHint::BcModule(name.to_string());
+    let hint = parse_hint(name)?;
 
     // 寻找模块
     let module = ctx.modules.resolve(&hint);
@@ -88,19 +169,13 @@ async fn command_call_app(ctx: &mut CliContext, name: &str, param: String) -> Re
 
 /// 卸载模块
 async fn command_unload(ctx: &mut CliContext, name: &str) -> Result<()> {
-    // 寻找模块
-    let hint = abi::LinkHint::BcModule(name.to_string());
-    let module = ctx.modules.unregister(&hint);
-    let module = if let Some(module) = module {
-        module
-    } else {
+    // 卸载匹配的全部版本，等待其处理完已接受的请求
+    let hint = parse_hint(name)?;
+    if !ctx.modules.remove(&hint, UNLOAD_TIMEOUT).await {
         println!("[Host] 模块不存在：{}", name);
         return Ok(());
-    };
-
-    // 卸载
-    module.kill();
-    println!("[Host] 成功卸载模块：{}", module.get_name());
+    }
+    println!("[Host] 成功卸载模块：{}", name);
 
     Ok(())
 }
@@ -122,7 +197,17 @@ async fn handle_input(ctx: &mut CliContext, input: String) -> Result<()> {
     };
 
     if cmd == "load" {
-        command_load(ctx, &rest).await?;
+        if parts.len() < 2 {
+            println!("[Host] 参数错误：load <*.wasm|*.cwasm> [func ...]");
+            return Ok(());
+        }
+        command_load(ctx, parts[1], &parts[2..]).await?;
+    } else if cmd == "reload" {
+        if parts.len() < 3 {
+            println!("[Host] 参数错误：reload <name> <*.wasm>");
+            return Ok(());
+        }
+        command_reload(ctx, parts[1], parts[2]).await?;
     } else if cmd == "list" {
         command_list(ctx).await?;
     } else if cmd == "call_app" {
@@ -152,6 +237,7 @@ async fn main() {
 
     let mut ctx = CliContext {
         modules: Arc::new(ModuleManager::new()),
+        cache: ModuleCache::with_user_dir(Engine::default()).unwrap(),
     };
 
     loop {
@@ -1,19 +1,8 @@
-use bc_hostcall::rpc::abi;
-use bc_hostcall::serialize::{ArgsBuilder, SerializeCtx};
+use bc_hostcall::{bc_import, bc_import_module};
 
 use crate::Result;
 
-pub async fn do_service() -> Result<String> {
-    let ser_ctx = SerializeCtx::new();
-    // 函数标识符
-    let mut func = abi::FunctionIdent::new("do_service");
-    func.set_hint(abi::LinkHint::BcModule("service".to_string()));
-    // 参数拼接
-    let args = ArgsBuilder::new(&ser_ctx)
-        .build().unwrap();
-    // 调用函数
-    let ret = bc_hostcall::async_rt::rt::request_api(func, args).await?;
-    // 解析返回值
-    let result = ser_ctx.deserialize::<String>(&ret)?;
-    Ok(result)
-}
+#[bc_import(module = "service")]
+pub fn do_service() -> Result<String>;
+
+bc_import_module!(do_service);
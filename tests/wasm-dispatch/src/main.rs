@@ -13,6 +13,7 @@ pub mod imports;
 
 pub const MODULE_NAME: &str = "dispatch";
 
-bc_wasm_module!(MODULE_NAME, __bc_module_export);
+bc_wasm_module!(MODULE_NAME, __bc_module_export, imports = ["service" => "^0.1"],
+                import_table = imports::__bc_module_import);
 
 fn main() {}
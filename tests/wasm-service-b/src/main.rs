@@ -13,6 +13,6 @@ pub mod imports;
 
 pub const MODULE_NAME: &str = "service";
 
-bc_wasm_module!(MODULE_NAME, __bc_module_export);
+bc_wasm_module!(MODULE_NAME, __bc_module_export, import_table = imports::__bc_module_import);
 
 fn main() {}